@@ -1,6 +1,7 @@
-use std::any::TypeId;
-use std::collections::HashMap;
-use std::ptr::NonNull;
+use crate::Map;
+use alloc::vec::Vec;
+use core::any::TypeId;
+use core::ptr::NonNull;
 
 /// Stores component data for entities with the same component types
 pub(crate) struct Archetype {
@@ -10,6 +11,7 @@ pub(crate) struct Archetype {
 }
 
 struct Column {
+    type_id: TypeId,
     data: NonNull<u8>,
     len: usize,
     capacity: usize,
@@ -39,16 +41,25 @@ impl Archetype {
     }
 
     pub fn add_column<T: 'static>(&mut self) {
+        let type_id = TypeId::of::<T>();
+        if self.columns.iter().any(|c| c.type_id == type_id) {
+            return;
+        }
+
         let column = Column {
+            type_id,
             data: NonNull::dangling(),
             len: 0,
             capacity: 0,
-            item_size: std::mem::size_of::<T>(),
+            item_size: core::mem::size_of::<T>(),
             drop_fn: |ptr| unsafe {
-                std::ptr::drop_in_place(ptr as *mut T);
+                core::ptr::drop_in_place(ptr as *mut T);
             },
         };
-        self.columns.push(column);
+
+        // Keep columns sorted by TypeId so column i always stores types[i]
+        let position = self.columns.partition_point(|c| c.type_id < type_id);
+        self.columns.insert(position, column);
     }
 
     pub fn push_entity(&mut self, entity: crate::Entity) -> usize {
@@ -76,7 +87,7 @@ impl Archetype {
         unsafe {
             let column = &mut self.columns[column_index];
             let ptr = column.data.as_ptr().add(index * column.item_size) as *mut T;
-            std::ptr::write(ptr, component);
+            core::ptr::write(ptr, component);
         }
     }
 
@@ -117,7 +128,7 @@ impl Archetype {
                 if index != last {
                     let src = column.data.as_ptr().add(last * column.item_size);
                     let dst = column.data.as_ptr().add(index * column.item_size);
-                    std::ptr::copy_nonoverlapping(src, dst, column.item_size);
+                    core::ptr::copy_nonoverlapping(src, dst, column.item_size);
                 }
                 column.len -= 1;
             }
@@ -136,19 +147,19 @@ impl Column {
         };
 
         unsafe {
-            let new_layout = std::alloc::Layout::from_size_align_unchecked(
+            let new_layout = alloc::alloc::Layout::from_size_align_unchecked(
                 new_capacity * self.item_size,
-                std::mem::align_of::<u8>(),
+                core::mem::align_of::<u8>(),
             );
 
             let new_ptr = if self.capacity == 0 {
-                std::alloc::alloc(new_layout)
+                alloc::alloc::alloc(new_layout)
             } else {
-                let old_layout = std::alloc::Layout::from_size_align_unchecked(
+                let old_layout = alloc::alloc::Layout::from_size_align_unchecked(
                     self.capacity * self.item_size,
-                    std::mem::align_of::<u8>(),
+                    core::mem::align_of::<u8>(),
                 );
-                std::alloc::realloc(
+                alloc::alloc::realloc(
                     self.data.as_ptr(),
                     old_layout,
                     new_capacity * self.item_size,
@@ -170,11 +181,11 @@ impl Drop for Column {
                     (self.drop_fn)(ptr);
                 }
 
-                let layout = std::alloc::Layout::from_size_align_unchecked(
+                let layout = alloc::alloc::Layout::from_size_align_unchecked(
                     self.capacity * self.item_size,
-                    std::mem::align_of::<u8>(),
+                    core::mem::align_of::<u8>(),
                 );
-                std::alloc::dealloc(self.data.as_ptr(), layout);
+                alloc::alloc::dealloc(self.data.as_ptr(), layout);
             }
         }
     }
@@ -182,14 +193,14 @@ impl Drop for Column {
 
 pub(crate) struct ArchetypeMap {
     archetypes: Vec<Archetype>,
-    type_map: HashMap<Vec<TypeId>, usize>,
+    type_map: Map<Vec<TypeId>, usize>,
 }
 
 impl ArchetypeMap {
     pub fn new() -> Self {
         Self {
             archetypes: Vec::new(),
-            type_map: HashMap::new(),
+            type_map: Map::new(),
         }
     }
 
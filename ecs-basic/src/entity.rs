@@ -1,7 +1,9 @@
-use std::fmt;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::fmt;
 
 /// A unique identifier for an entity
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Entity {
     pub(crate) id: u32,
     pub(crate) generation: u32,
@@ -15,6 +17,20 @@ impl Entity {
     pub fn generation(&self) -> u32 {
         self.generation
     }
+
+    /// Packs the entity as `generation << 32 | id`, the same layout
+    /// `ecs-complete`'s `Entity::to_bits` uses for its version and index
+    pub fn to_bits(self) -> u64 {
+        (u64::from(self.generation) << 32) | u64::from(self.id)
+    }
+
+    /// Inverse of `to_bits`
+    pub fn from_bits(bits: u64) -> Self {
+        Self {
+            id: bits as u32,
+            generation: (bits >> 32) as u32,
+        }
+    }
 }
 
 impl fmt::Debug for Entity {
@@ -23,24 +39,44 @@ impl fmt::Debug for Entity {
     }
 }
 
+/// How freed entity ids are handed out again
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AllocationMode {
+    /// Reuse the most recently freed id first
+    #[default]
+    Lifo,
+    /// Reuse the oldest freed id first, independent of despawn order
+    Fifo,
+    /// Never reuse ids
+    Monotonic,
+}
+
 /// Manages entity allocation and recycling
 pub(crate) struct EntityAllocator {
     generations: Vec<u32>,
-    free_list: Vec<u32>,
+    free_list: VecDeque<u32>,
     next_id: u32,
+    mode: AllocationMode,
 }
 
 impl EntityAllocator {
-    pub fn new() -> Self {
+    pub fn new(mode: AllocationMode) -> Self {
         Self {
             generations: Vec::new(),
-            free_list: Vec::new(),
+            free_list: VecDeque::new(),
             next_id: 0,
+            mode,
         }
     }
 
     pub fn allocate(&mut self) -> Entity {
-        if let Some(id) = self.free_list.pop() {
+        let recycled = match self.mode {
+            AllocationMode::Lifo => self.free_list.pop_back(),
+            AllocationMode::Fifo => self.free_list.pop_front(),
+            AllocationMode::Monotonic => None,
+        };
+
+        if let Some(id) = recycled {
             let generation = self.generations[id as usize];
             Entity { id, generation }
         } else {
@@ -61,7 +97,9 @@ impl EntityAllocator {
         }
 
         self.generations[entity.id as usize] += 1;
-        self.free_list.push(entity.id);
+        if self.mode != AllocationMode::Monotonic {
+            self.free_list.push_back(entity.id);
+        }
         true
     }
 
@@ -1,12 +1,25 @@
+// Tests always link std for the harness; the crate itself only needs
+// `alloc` when the `std` feature is off
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
 pub mod archetype;
 pub mod entity;
 pub mod query;
 pub mod world;
 
-pub use entity::Entity;
+pub use entity::{AllocationMode, Entity};
 pub use query::{Query, QueryBorrow};
 pub use world::World;
 
+// `HashMap` needs std's random hasher, so without std the maps fall back to
+// `BTreeMap`
+#[cfg(feature = "std")]
+pub(crate) type Map<K, V> = std::collections::HashMap<K, V>;
+#[cfg(not(feature = "std"))]
+pub(crate) type Map<K, V> = alloc::collections::BTreeMap<K, V>;
+
 #[cfg(test)]
 mod tests {
     use crate::*;
@@ -128,4 +141,110 @@ mod tests {
             assert!(pos.x >= 11.0);
         }
     }
+
+    #[test]
+    fn test_query_five_tuple() {
+        #[derive(Debug, PartialEq)]
+        struct Mass(f64);
+
+        #[derive(Debug, PartialEq)]
+        struct Tag(u8);
+
+        let mut world = World::new();
+
+        for i in 0..3 {
+            world.spawn((
+                Position {
+                    x: i as f32,
+                    y: 0.0,
+                },
+                Velocity { dx: 1.0, dy: 2.0 },
+                Health(100),
+                Mass(i as f64 * 2.0),
+                Tag(i as u8),
+            ));
+        }
+        world.spawn((Position { x: 9.0, y: 9.0 }, Health(1)));
+
+        let mut count = 0;
+        for (pos, vel, health, mass, tag) in
+            world.query::<(&mut Position, &Velocity, &Health, &Mass, &Tag)>()
+        {
+            pos.x += vel.dx;
+            assert_eq!(health.0, 100);
+            assert_eq!(mass.0, tag.0 as f64 * 2.0);
+            count += 1;
+        }
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn test_allocation_modes() {
+        fn reuse_order(mode: AllocationMode) -> Vec<u32> {
+            let mut world = World::with_allocation_mode(mode);
+            let entities: Vec<Entity> = (0..3).map(|i| world.spawn((Health(i),))).collect();
+
+            world.despawn(entities[0]);
+            world.despawn(entities[2]);
+            world.despawn(entities[1]);
+
+            (0..3).map(|i| world.spawn((Health(i),)).id()).collect()
+        }
+
+        assert_eq!(reuse_order(AllocationMode::Lifo), vec![1, 2, 0]);
+        assert_eq!(reuse_order(AllocationMode::Fifo), vec![0, 2, 1]);
+        assert_eq!(reuse_order(AllocationMode::Monotonic), vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn test_entity_bits_round_trip() {
+        let mut world = World::new();
+        let first = world.spawn((Health(1),));
+        world.despawn(first);
+        let recycled = world.spawn((Health(2),));
+
+        assert_eq!(recycled.id(), first.id());
+        assert_ne!(recycled.generation(), first.generation());
+        assert_eq!(
+            recycled.to_bits(),
+            (u64::from(recycled.generation()) << 32) | u64::from(recycled.id())
+        );
+        assert_eq!(Entity::from_bits(recycled.to_bits()), recycled);
+        assert_eq!(Entity::from_bits(first.to_bits()), first);
+    }
+}
+
+// Exercises the `BTreeMap`-backed storage used without the `std` feature;
+// run with `cargo test -p ecs-basic --no-default-features`
+#[cfg(all(test, not(feature = "std")))]
+mod no_std_tests {
+    use crate::*;
+
+    struct Position(i32);
+    struct Velocity(i32);
+
+    #[test]
+    fn test_storage_maps_are_btree() {
+        let map: Map<u32, u32> = alloc::collections::BTreeMap::new();
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn test_spawn_query_without_std() {
+        let mut world = World::new();
+        let moving = world.spawn((Position(0), Velocity(2)));
+        let still = world.spawn((Position(5),));
+
+        for (pos, vel) in world.query::<(&mut Position, &Velocity)>() {
+            pos.0 += vel.0;
+        }
+
+        assert_eq!(world.get::<Position>(moving).unwrap().0, 2);
+        assert_eq!(world.get::<Position>(still).unwrap().0, 5);
+        assert_eq!(world.query::<&Position>().count(), 2);
+
+        world.despawn(moving);
+        assert!(!world.is_alive(moving));
+        assert_eq!(world.query::<&Position>().count(), 1);
+    }
 }
@@ -1,4 +1,4 @@
-use std::any::TypeId;
+use core::any::TypeId;
 
 /// Trait for querying components from the world
 pub trait Query {
@@ -48,62 +48,30 @@ impl<T: 'static> Query for &mut T {
 }
 
 // Implement Query for tuples of queries
-impl<Q1: Query, Q2: Query> Query for (Q1, Q2) {
-    type Item<'a> = (Q1::Item<'a>, Q2::Item<'a>);
-
-    fn matches_archetype(types: &[TypeId]) -> bool {
-        Q1::matches_archetype(types) && Q2::matches_archetype(types)
-    }
-
-    unsafe fn fetch<'a>(
-        archetype: &'a mut crate::archetype::Archetype,
-        index: usize,
-    ) -> Self::Item<'a> {
-        let ptr = archetype as *mut crate::archetype::Archetype;
-        (Q1::fetch(&mut *ptr, index), Q2::fetch(&mut *ptr, index))
-    }
+macro_rules! impl_query_tuple {
+    ($($q:ident),+) => {
+        impl<$($q: Query),+> Query for ($($q,)+) {
+            type Item<'a> = ($($q::Item<'a>,)+);
+
+            fn matches_archetype(types: &[TypeId]) -> bool {
+                $($q::matches_archetype(types))&&+
+            }
+
+            unsafe fn fetch<'a>(
+                archetype: &'a mut crate::archetype::Archetype,
+                index: usize,
+            ) -> Self::Item<'a> {
+                let ptr = archetype as *mut crate::archetype::Archetype;
+                unsafe { ($($q::fetch(&mut *ptr, index),)+) }
+            }
+        }
+    };
 }
 
-impl<Q1: Query, Q2: Query, Q3: Query> Query for (Q1, Q2, Q3) {
-    type Item<'a> = (Q1::Item<'a>, Q2::Item<'a>, Q3::Item<'a>);
-
-    fn matches_archetype(types: &[TypeId]) -> bool {
-        Q1::matches_archetype(types) && Q2::matches_archetype(types) && Q3::matches_archetype(types)
-    }
-
-    unsafe fn fetch<'a>(
-        archetype: &'a mut crate::archetype::Archetype,
-        index: usize,
-    ) -> Self::Item<'a> {
-        let ptr = archetype as *mut crate::archetype::Archetype;
-        (
-            Q1::fetch(&mut *ptr, index),
-            Q2::fetch(&mut *ptr, index),
-            Q3::fetch(&mut *ptr, index),
-        )
-    }
-}
-
-impl<Q1: Query, Q2: Query, Q3: Query, Q4: Query> Query for (Q1, Q2, Q3, Q4) {
-    type Item<'a> = (Q1::Item<'a>, Q2::Item<'a>, Q3::Item<'a>, Q4::Item<'a>);
-
-    fn matches_archetype(types: &[TypeId]) -> bool {
-        Q1::matches_archetype(types)
-            && Q2::matches_archetype(types)
-            && Q3::matches_archetype(types)
-            && Q4::matches_archetype(types)
-    }
-
-    unsafe fn fetch<'a>(
-        archetype: &'a mut crate::archetype::Archetype,
-        index: usize,
-    ) -> Self::Item<'a> {
-        let ptr = archetype as *mut crate::archetype::Archetype;
-        (
-            Q1::fetch(&mut *ptr, index),
-            Q2::fetch(&mut *ptr, index),
-            Q3::fetch(&mut *ptr, index),
-            Q4::fetch(&mut *ptr, index),
-        )
-    }
-}
+impl_query_tuple!(Q1, Q2);
+impl_query_tuple!(Q1, Q2, Q3);
+impl_query_tuple!(Q1, Q2, Q3, Q4);
+impl_query_tuple!(Q1, Q2, Q3, Q4, Q5);
+impl_query_tuple!(Q1, Q2, Q3, Q4, Q5, Q6);
+impl_query_tuple!(Q1, Q2, Q3, Q4, Q5, Q6, Q7);
+impl_query_tuple!(Q1, Q2, Q3, Q4, Q5, Q6, Q7, Q8);
@@ -1,14 +1,16 @@
+use crate::Map;
 use crate::archetype::ArchetypeMap;
-use crate::entity::{Entity, EntityAllocator};
+use crate::entity::{AllocationMode, Entity, EntityAllocator};
 use crate::query::Query;
-use std::any::TypeId;
-use std::collections::HashMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::TypeId;
 
 /// The main ECS container
 pub struct World {
     entities: EntityAllocator,
     archetypes: ArchetypeMap,
-    entity_locations: HashMap<Entity, EntityLocation>,
+    entity_locations: Map<Entity, EntityLocation>,
 }
 
 #[derive(Clone, Copy)]
@@ -19,10 +21,15 @@ struct EntityLocation {
 
 impl World {
     pub fn new() -> Self {
+        Self::with_allocation_mode(AllocationMode::default())
+    }
+
+    /// Create a world whose entity ids are recycled according to `mode`
+    pub fn with_allocation_mode(mode: AllocationMode) -> Self {
         Self {
-            entities: EntityAllocator::new(),
+            entities: EntityAllocator::new(mode),
             archetypes: ArchetypeMap::new(),
-            entity_locations: HashMap::new(),
+            entity_locations: Map::new(),
         }
     }
 
@@ -61,13 +68,13 @@ impl World {
 
         if let Some(location) = self.entity_locations.remove(&entity) {
             let archetype = self.archetypes.get_mut(location.archetype).unwrap();
-            let swapped_entity = archetype.remove_entity(location.index);
+            archetype.remove_entity(location.index);
 
-            // Update the swapped entity's location
-            if swapped_entity != entity {
-                if let Some(swapped_location) = self.entity_locations.get_mut(&swapped_entity) {
-                    swapped_location.index = location.index;
-                }
+            // Update the location of the entity swapped into the freed row
+            if let Some(&swapped_entity) = archetype.entities().get(location.index)
+                && let Some(swapped_location) = self.entity_locations.get_mut(&swapped_entity)
+            {
+                swapped_location.index = location.index;
             }
 
             true
@@ -101,7 +108,7 @@ impl World {
             archetypes: &mut self.archetypes,
             archetype_index: 0,
             entity_index: 0,
-            _marker: std::marker::PhantomData,
+            _marker: core::marker::PhantomData,
         }
     }
 }
@@ -119,86 +126,39 @@ pub trait ComponentBundle {
     fn insert_into(self, archetype: &mut crate::archetype::Archetype, index: usize);
 }
 
-// Implement ComponentBundle for single components
-impl<T: 'static> ComponentBundle for (T,) {
-    fn type_ids() -> Vec<TypeId> {
-        vec![TypeId::of::<T>()]
-    }
-
-    fn init_archetype(archetype: &mut crate::archetype::Archetype) {
-        archetype.add_column::<T>();
-    }
-
-    fn insert_into(self, archetype: &mut crate::archetype::Archetype, index: usize) {
-        archetype.set_component(index, self.0);
-    }
-}
-
-// Implement ComponentBundle for tuples of 2-4 components
-impl<T1: 'static, T2: 'static> ComponentBundle for (T1, T2) {
-    fn type_ids() -> Vec<TypeId> {
-        vec![TypeId::of::<T1>(), TypeId::of::<T2>()]
-    }
-
-    fn init_archetype(archetype: &mut crate::archetype::Archetype) {
-        archetype.add_column::<T1>();
-        archetype.add_column::<T2>();
-    }
-
-    fn insert_into(self, archetype: &mut crate::archetype::Archetype, index: usize) {
-        archetype.set_component(index, self.0);
-        archetype.set_component(index, self.1);
-    }
-}
-
-impl<T1: 'static, T2: 'static, T3: 'static> ComponentBundle for (T1, T2, T3) {
-    fn type_ids() -> Vec<TypeId> {
-        vec![TypeId::of::<T1>(), TypeId::of::<T2>(), TypeId::of::<T3>()]
-    }
+// Implement ComponentBundle for tuples of 1-8 components
+macro_rules! impl_component_bundle {
+    ($($t:ident: $idx:tt),+) => {
+        impl<$($t: 'static),+> ComponentBundle for ($($t,)+) {
+            fn type_ids() -> Vec<TypeId> {
+                vec![$(TypeId::of::<$t>()),+]
+            }
 
-    fn init_archetype(archetype: &mut crate::archetype::Archetype) {
-        archetype.add_column::<T1>();
-        archetype.add_column::<T2>();
-        archetype.add_column::<T3>();
-    }
+            fn init_archetype(archetype: &mut crate::archetype::Archetype) {
+                $(archetype.add_column::<$t>();)+
+            }
 
-    fn insert_into(self, archetype: &mut crate::archetype::Archetype, index: usize) {
-        archetype.set_component(index, self.0);
-        archetype.set_component(index, self.1);
-        archetype.set_component(index, self.2);
-    }
+            fn insert_into(self, archetype: &mut crate::archetype::Archetype, index: usize) {
+                $(archetype.set_component(index, self.$idx);)+
+            }
+        }
+    };
 }
 
-impl<T1: 'static, T2: 'static, T3: 'static, T4: 'static> ComponentBundle for (T1, T2, T3, T4) {
-    fn type_ids() -> Vec<TypeId> {
-        vec![
-            TypeId::of::<T1>(),
-            TypeId::of::<T2>(),
-            TypeId::of::<T3>(),
-            TypeId::of::<T4>(),
-        ]
-    }
-
-    fn init_archetype(archetype: &mut crate::archetype::Archetype) {
-        archetype.add_column::<T1>();
-        archetype.add_column::<T2>();
-        archetype.add_column::<T3>();
-        archetype.add_column::<T4>();
-    }
-
-    fn insert_into(self, archetype: &mut crate::archetype::Archetype, index: usize) {
-        archetype.set_component(index, self.0);
-        archetype.set_component(index, self.1);
-        archetype.set_component(index, self.2);
-        archetype.set_component(index, self.3);
-    }
-}
+impl_component_bundle!(T1: 0);
+impl_component_bundle!(T1: 0, T2: 1);
+impl_component_bundle!(T1: 0, T2: 1, T3: 2);
+impl_component_bundle!(T1: 0, T2: 1, T3: 2, T4: 3);
+impl_component_bundle!(T1: 0, T2: 1, T3: 2, T4: 3, T5: 4);
+impl_component_bundle!(T1: 0, T2: 1, T3: 2, T4: 3, T5: 4, T6: 5);
+impl_component_bundle!(T1: 0, T2: 1, T3: 2, T4: 3, T5: 4, T6: 5, T7: 6);
+impl_component_bundle!(T1: 0, T2: 1, T3: 2, T4: 3, T5: 4, T6: 5, T7: 6, T8: 7);
 
 pub struct QueryIter<'a, Q: Query> {
     archetypes: &'a mut ArchetypeMap,
     archetype_index: usize,
     entity_index: usize,
-    _marker: std::marker::PhantomData<Q>,
+    _marker: core::marker::PhantomData<Q>,
 }
 
 impl<'a, Q: Query> Iterator for QueryIter<'a, Q> {
@@ -242,7 +202,7 @@ impl<'a, Q: Query> Iterator for QueryIter<'a, Q> {
             // 1. The iterator holds a mutable borrow to archetypes for 'a
             // 2. We're returning references that live as long as that borrow
             // 3. The query system ensures no overlapping mutable borrows
-            return Some(unsafe { std::mem::transmute(item) });
+            return Some(unsafe { core::mem::transmute(item) });
         }
     }
 }
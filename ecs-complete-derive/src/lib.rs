@@ -0,0 +1,124 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::visit_mut::VisitMut;
+use syn::{Data, DeriveInput, Fields, GenericParam, Lifetime, parse_macro_input};
+
+/// Derives `ecs_complete::Query` for a struct whose fields are query terms
+/// borrowing for the struct's single lifetime, e.g.
+/// `struct Movement<'w> { pos: &'w mut Position, vel: &'w Velocity }`.
+/// The struct matches archetypes every field matches, and each field is
+/// fetched through its own term.
+#[proc_macro_derive(QueryData)]
+pub fn derive_query_data(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+// Rewrites the struct's lifetime to `'static`, turning a field type into the
+// query term whose `Item<'a>` is that field type at `'a`
+struct ToStatic<'l>(&'l Lifetime);
+
+impl VisitMut for ToStatic<'_> {
+    fn visit_lifetime_mut(&mut self, lifetime: &mut Lifetime) {
+        if lifetime == self.0 {
+            *lifetime = Lifetime::new("'static", lifetime.span());
+        }
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+
+    let mut params = input.generics.params.iter();
+    let lifetime = match (params.next(), params.next()) {
+        (Some(GenericParam::Lifetime(param)), None) => &param.lifetime,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.generics,
+                "QueryData structs need exactly one lifetime parameter, e.g. `Movement<'w>`",
+            ));
+        }
+    };
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    name,
+                    "QueryData can only be derived for structs with named fields",
+                ));
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                name,
+                "QueryData can only be derived for structs",
+            ));
+        }
+    };
+
+    let idents: Vec<_> = fields.iter().map(|field| &field.ident).collect();
+    let terms: Vec<_> = fields
+        .iter()
+        .map(|field| {
+            let mut ty = field.ty.clone();
+            ToStatic(lifetime).visit_type_mut(&mut ty);
+            ty
+        })
+        .collect();
+
+    Ok(quote! {
+        impl<#lifetime> ::ecs_complete::query::Query for #name<#lifetime> {
+            type Item<'__a> = #name<'__a>;
+
+            fn matches_archetype(types: &[::std::any::TypeId]) -> bool {
+                true #(&& <#terms as ::ecs_complete::query::Query>::matches_archetype(types))*
+            }
+
+            unsafe fn fetch<'__a>(
+                archetype: ::ecs_complete::archetype::ArchetypeView<'__a>,
+                index: usize,
+            ) -> Self::Item<'__a> {
+                unsafe {
+                    #name {
+                        #(#idents: <#terms as ::ecs_complete::query::Query>::fetch(archetype, index),)*
+                    }
+                }
+            }
+
+            unsafe fn try_fetch<'__a>(
+                archetype: ::ecs_complete::archetype::ArchetypeView<'__a>,
+                index: usize,
+            ) -> ::std::option::Option<Self::Item<'__a>> {
+                unsafe {
+                    ::std::option::Option::Some(#name {
+                        #(#idents: <#terms as ::ecs_complete::query::Query>::try_fetch(archetype, index)?,)*
+                    })
+                }
+            }
+
+            fn matches_row(
+                archetype: ::ecs_complete::archetype::ArchetypeView<'_>,
+                index: usize,
+            ) -> bool {
+                true #(&& <#terms as ::ecs_complete::query::Query>::matches_row(archetype, index))*
+            }
+
+            fn read_types() -> ::std::vec::Vec<::std::any::TypeId> {
+                let mut types = ::std::vec::Vec::new();
+                #(types.extend(<#terms as ::ecs_complete::query::Query>::read_types());)*
+                types
+            }
+
+            fn write_types() -> ::std::vec::Vec<::std::any::TypeId> {
+                let mut types = ::std::vec::Vec::new();
+                #(types.extend(<#terms as ::ecs_complete::query::Query>::write_types());)*
+                types
+            }
+        }
+    })
+}
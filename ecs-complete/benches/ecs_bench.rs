@@ -1,5 +1,6 @@
 use criterion::{BenchmarkId, Criterion, black_box, criterion_group, criterion_main};
 use ecs_complete::System;
+use ecs_complete::With;
 use ecs_complete::World;
 
 #[derive(Debug, Clone, Copy)]
@@ -44,6 +45,38 @@ fn spawn_benchmark(c: &mut Criterion) {
     group.finish();
 }
 
+// Run once normally and once with `--no-default-features` to compare spawn
+// cost with and without per-component change ticks
+fn spawn_change_detection_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("spawn_change_detection");
+    let mode = if cfg!(feature = "change_detection") {
+        "tracked"
+    } else {
+        "untracked"
+    };
+
+    for size in [1_000, 10_000].iter() {
+        group.bench_with_input(BenchmarkId::new(mode, size), size, |b, &size| {
+            b.iter(|| {
+                let mut world = World::new();
+                for i in 0..size {
+                    world.spawn((
+                        Position {
+                            x: i as f32,
+                            y: 0.0,
+                        },
+                        Velocity { x: 1.0, y: 1.0 },
+                        Health(100.0),
+                    ));
+                }
+                black_box(world);
+            });
+        });
+    }
+
+    group.finish();
+}
+
 fn query_benchmark(c: &mut Criterion) {
     let mut group = c.benchmark_group("query");
 
@@ -135,6 +168,50 @@ fn insert_component_benchmark(c: &mut Criterion) {
     group.finish();
 }
 
+fn insert_batch_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert_batch");
+    group.sample_size(10);
+
+    let setup = |size: usize| {
+        let mut world = World::new();
+        let entities: Vec<_> = (0..size)
+            .map(|i| {
+                world.spawn((Position {
+                    x: i as f32,
+                    y: 0.0,
+                },))
+            })
+            .collect();
+        (world, entities)
+    };
+
+    for size in [1_000, 10_000].iter() {
+        group.bench_function(BenchmarkId::new("per_entity", size), |b| {
+            b.iter_with_setup(
+                || setup(*size),
+                |(mut world, entities)| {
+                    for &entity in &entities {
+                        let _ = world.insert(entity, Velocity { x: 1.0, y: 1.0 });
+                    }
+                    black_box(world);
+                },
+            );
+        });
+        group.bench_function(BenchmarkId::new("batched", size), |b| {
+            b.iter_with_setup(
+                || setup(*size),
+                |(mut world, entities)| {
+                    let batch = entities.iter().map(|&e| (e, Velocity { x: 1.0, y: 1.0 }));
+                    world.insert_batch(batch).unwrap();
+                    black_box(world);
+                },
+            );
+        });
+    }
+
+    group.finish();
+}
+
 fn remove_component_benchmark(c: &mut Criterion) {
     let mut group = c.benchmark_group("remove_component");
     group.sample_size(10);
@@ -254,6 +331,100 @@ fn fragmented_query_benchmark(c: &mut Criterion) {
     group.finish();
 }
 
+struct Marker<const N: usize>;
+
+macro_rules! spawn_marked {
+    ($world:expr, $($n:literal),+) => {
+        $(
+            $world.spawn((Position { x: $n as f32, y: 0.0 }, Marker::<$n>));
+            $world.spawn((Velocity { x: $n as f32, y: 0.0 }, Marker::<$n>));
+        )+
+    };
+}
+
+// Many archetypes, few entities: dominated by archetype matching, which is
+// cached between repeated queries
+fn repeated_query_benchmark(c: &mut Criterion) {
+    let mut world = World::new();
+    spawn_marked!(
+        world, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22,
+        23, 24, 25, 26, 27, 28, 29, 30, 31
+    );
+
+    c.bench_function("repeated_query_many_archetypes", |b| {
+        b.iter(|| {
+            for _ in 0..100 {
+                for pos in world.query::<&Position>() {
+                    black_box(pos);
+                }
+            }
+        });
+    });
+}
+
+struct HugeComponent([u8; 4096]);
+
+// `With` only inspects archetype types, so filtering on a 4 KiB component
+// should cost the same as the bare `&Health` query rather than the borrowing one
+fn with_filter_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("with_filter");
+
+    let mut world = World::new();
+    for i in 0..10_000 {
+        world.spawn((Health(i as f32), HugeComponent([0; 4096])));
+    }
+
+    group.bench_function("filtered", |b| {
+        b.iter(|| {
+            for health in world.query_filtered::<&Health, With<HugeComponent>>() {
+                black_box(health);
+            }
+        });
+    });
+    group.bench_function("borrowed", |b| {
+        b.iter(|| {
+            for (health, huge) in world.query::<(&Health, &HugeComponent)>() {
+                black_box(health);
+                black_box(huge.0[0]);
+            }
+        });
+    });
+
+    group.finish();
+}
+
+// Transient archetypes left empty after their entities moved on, with a
+// few populated ones in between; empty archetypes are skipped up front
+fn empty_archetypes_query_benchmark(c: &mut Criterion) {
+    let mut world = World::new();
+    macro_rules! spawn_transient {
+        ($($n:literal),+) => {
+            $(
+                let entity = world.spawn((Position { x: $n as f32, y: 0.0 }, Marker::<$n>));
+                world.despawn(entity);
+                if $n % 8 == 0 {
+                    world.spawn((Position { x: $n as f32, y: 0.0 }, Velocity { x: 1.0, y: 0.0 }, Marker::<$n>));
+                }
+            )+
+        };
+    }
+    spawn_transient!(
+        0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24,
+        25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46, 47,
+        48, 49, 50, 51, 52, 53, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63
+    );
+
+    c.bench_function("query_many_empty_archetypes", |b| {
+        b.iter(|| {
+            for _ in 0..100 {
+                for pos in world.query::<&Position>() {
+                    black_box(pos);
+                }
+            }
+        });
+    });
+}
+
 fn system_benchmark(c: &mut Criterion) {
     let mut group = c.benchmark_group("system");
 
@@ -285,12 +456,17 @@ fn system_benchmark(c: &mut Criterion) {
 criterion_group!(
     benches,
     spawn_benchmark,
+    spawn_change_detection_benchmark,
     query_benchmark,
     query_mut_benchmark,
     insert_component_benchmark,
+    insert_batch_benchmark,
     remove_component_benchmark,
     despawn_benchmark,
     fragmented_query_benchmark,
+    repeated_query_benchmark,
+    with_filter_benchmark,
+    empty_archetypes_query_benchmark,
     system_benchmark,
 );
 criterion_main!(benches);
@@ -0,0 +1,52 @@
+use crate::system::{Schedule, Stage, System};
+use crate::world::World;
+
+/// Owns a `World` and the `Schedule` that drives it, one `update` per frame
+pub struct App {
+    pub world: World,
+    pub schedule: Schedule,
+}
+
+impl App {
+    pub fn new() -> Self {
+        Self::from_parts(World::new(), Schedule::new())
+    }
+
+    pub fn from_parts(world: World, schedule: Schedule) -> Self {
+        Self { world, schedule }
+    }
+
+    pub fn add_system(&mut self, stage: Stage, system: impl System + 'static) -> &mut Self {
+        self.schedule.add_system(stage, system);
+        self
+    }
+
+    /// Runs one frame: every stage, then the command flush and tick (which
+    /// also advances world events)
+    pub fn update(&mut self) {
+        self.schedule.run(&mut self.world);
+    }
+
+    pub fn run_frames(&mut self, frames: usize) {
+        for _ in 0..frames {
+            self.update();
+        }
+    }
+
+    /// Runs frames while `condition` holds, checking it before each frame.
+    /// Returns the number of frames run.
+    pub fn run_while(&mut self, mut condition: impl FnMut(&World) -> bool) -> usize {
+        let mut frames = 0;
+        while condition(&self.world) {
+            self.update();
+            frames += 1;
+        }
+        frames
+    }
+}
+
+impl Default for App {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,38 +1,422 @@
+use crate::component::{ComponentId, ComponentIndex, DynComponentId};
 use crate::entity::Entity;
-use std::any::TypeId;
+use crate::registry::CloneIntoFn;
+use crate::sparse_set::{SparseSet, SparseStorage};
+use std::alloc::{GlobalAlloc, Layout};
+use std::any::{Any, TypeId};
+use std::cell::{Cell, UnsafeCell};
 use std::collections::HashMap;
 use std::ptr::NonNull;
+use std::sync::Arc;
 
-pub(crate) struct Archetype {
+/// Allocator backing component column storage, see `World::with_allocator`
+pub type ColumnAllocator = Arc<dyn GlobalAlloc + Send + Sync>;
+
+/// Forwards to whatever `#[global_allocator]` the program uses
+struct GlobalAllocator;
+
+unsafe impl GlobalAlloc for GlobalAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        unsafe { std::alloc::alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { std::alloc::dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        unsafe { std::alloc::realloc(ptr, layout, new_size) }
+    }
+}
+
+pub(crate) fn global_allocator() -> ColumnAllocator {
+    Arc::new(GlobalAllocator)
+}
+
+thread_local! {
+    // System whose writes are being recorded on this thread
+    static WRITER: Cell<u32> = const { Cell::new(0) };
+}
+
+/// Records `writer` as the system making changes on this thread from now on,
+/// returning the previous one. Kept outside the archetypes so switching
+/// systems doesn't touch every archetype, and read whenever a change is
+/// stamped.
+pub(crate) fn set_current_writer(writer: u32) -> u32 {
+    WRITER.replace(writer)
+}
+
+#[cfg(feature = "change_detection")]
+pub(crate) fn current_writer() -> u32 {
+    WRITER.get()
+}
+
+/// A change tick or writer id, one per row. Query items write the stamps of
+/// their own row through a shared borrow of the archetype, so items for many
+/// rows can be alive at once, on one thread or several.
+#[repr(transparent)]
+pub(crate) struct Stamp<T>(UnsafeCell<T>);
+
+// A slot is written through `&self` only by the one item borrowing its row
+unsafe impl<T: Send> Sync for Stamp<T> {}
+
+impl<T: Copy> Stamp<T> {
+    pub(crate) fn new(value: T) -> Self {
+        Self(UnsafeCell::new(value))
+    }
+
+    pub(crate) fn get(&self) -> T {
+        unsafe { *self.0.get() }
+    }
+
+    pub(crate) fn set(&mut self, value: T) {
+        *self.0.get_mut() = value;
+    }
+
+    /// The slot, for the item that borrows this row to write
+    pub(crate) fn as_ptr(&self) -> *mut T {
+        self.0.get()
+    }
+}
+
+impl<T: Copy> Clone for Stamp<T> {
+    fn clone(&self) -> Self {
+        Self::new(self.get())
+    }
+}
+
+/// An archetype together with the world's sparse sets, which is what query
+/// terms and filters fetch from, since an entity's sparse components live
+/// outside its archetype. Mutable fetches go through raw pointers to single
+/// column slots and sparse entries, so items for different rows never alias,
+/// whichever thread fetched them.
+#[derive(Clone, Copy)]
+pub struct ArchetypeView<'a> {
+    archetype: &'a Archetype,
+    sparse: &'a SparseStorage,
+}
+
+impl<'a> ArchetypeView<'a> {
+    pub(crate) fn new(archetype: &'a Archetype, sparse: &'a SparseStorage) -> Self {
+        Self { archetype, sparse }
+    }
+
+    pub fn archetype(&self) -> &'a Archetype {
+        self.archetype
+    }
+
+    /// Whether entities may hold components outside the archetype's columns,
+    /// so a query has to check them one by one
+    pub fn checks_rows(&self) -> bool {
+        self.sparse.has_sets()
+    }
+
+    /// The types queries match this archetype against: its own, plus a
+    /// stand-in for every sparse type since any entity may hold one
+    pub(crate) fn query_types(&self) -> std::borrow::Cow<'a, [TypeId]> {
+        if self.sparse.has_sets() {
+            [self.archetype.types(), self.sparse.markers()]
+                .concat()
+                .into()
+        } else {
+            self.archetype.types().into()
+        }
+    }
+
+    fn sparse_set<T: 'static>(&self, index: usize) -> Option<(&'a SparseSet<T>, Entity)> {
+        let entity = *self.archetype.entities.get(index)?;
+        Some((self.sparse.get::<T>()?, entity))
+    }
+
+    /// Whether the entity at `index` has `T`, in a column or a sparse set
+    pub fn has_component_at<T: 'static>(&self, index: usize) -> bool {
+        if self.archetype.types.contains(&TypeId::of::<T>()) {
+            return index < self.archetype.len();
+        }
+        self.sparse_set::<T>(index)
+            .is_some_and(|(set, entity)| set.contains(entity))
+    }
+
+    pub fn get_component<T: 'static>(&self, index: usize) -> Option<&'a T> {
+        if self.archetype.column::<T>().is_none() {
+            let (set, entity) = self.sparse_set::<T>(index)?;
+            return set.get(entity);
+        }
+        self.archetype.get_component::<T>(index)
+    }
+
+    // Pointers to `T`'s slot at `index` and, with change detection, to the
+    // stamps of its last change
+    fn slot<T: 'static>(&self, index: usize) -> Option<(*mut T, ChangeSlots)> {
+        let Some(column) = self.archetype.column::<T>() else {
+            let (set, entity) = self.sparse_set::<T>(index)?;
+            return set.slot(entity);
+        };
+        column
+            .slot(index)
+            .map(|(_, stamps)| (column.typed_ptr::<T>(index), stamps))
+    }
+
+    /// Erased `get_component_mut` for the component of type `type_id`,
+    /// column or sparse
+    ///
+    /// # Safety
+    ///
+    /// Same contract as `get_component_mut`.
+    pub(crate) unsafe fn get_component_ptr_mut(
+        &self,
+        type_id: TypeId,
+        index: usize,
+    ) -> Option<*mut u8> {
+        let (value, _stamps) = match self.archetype.column_of(type_id) {
+            Some(column) => column.slot(index)?,
+            None => {
+                let entity = *self.archetype.entities.get(index)?;
+                self.sparse.slot(type_id, entity)?
+            }
+        };
+        #[cfg(feature = "change_detection")]
+        unsafe {
+            *_stamps.0 = self.archetype.tick;
+            *_stamps.1 = current_writer();
+        }
+        Some(value)
+    }
+
+    /// Mutable `T` at `index`, marked changed by the current writer
+    ///
+    /// # Safety
+    ///
+    /// Nothing else may reference this entity's `T` while the result lives.
+    pub unsafe fn get_component_mut<T: 'static>(&self, index: usize) -> Option<&'a mut T> {
+        let (value, _stamps) = self.slot::<T>(index)?;
+        #[cfg(feature = "change_detection")]
+        unsafe {
+            *_stamps.0 = self.archetype.tick;
+            *_stamps.1 = current_writer();
+        }
+        Some(unsafe { &mut *value })
+    }
+
+    /// Mutable `T` at `index`, left unmarked, for bookkeeping writes that
+    /// don't change what the component means
+    ///
+    /// # Safety
+    ///
+    /// Same contract as `get_component_mut`.
+    pub(crate) unsafe fn get_component_mut_unmarked<T: 'static>(
+        &self,
+        index: usize,
+    ) -> Option<&'a mut T> {
+        let (value, _) = self.slot::<T>(index)?;
+        Some(unsafe { &mut *value })
+    }
+
+    /// Mutable `T` at `index` with its changed tick and writer, which are
+    /// left to the caller to stamp
+    ///
+    /// # Safety
+    ///
+    /// Same contract as `get_component_mut`.
+    #[cfg(feature = "change_detection")]
+    pub(crate) unsafe fn get_component_mut_untracked<T: 'static>(
+        &self,
+        index: usize,
+    ) -> Option<(&'a mut T, &'a mut u64, &'a mut u32)> {
+        let (value, (changed_tick, changed_by)) = self.slot::<T>(index)?;
+        unsafe { Some((&mut *value, &mut *changed_tick, &mut *changed_by)) }
+    }
+
+    #[cfg(feature = "change_detection")]
+    pub fn component_changed<T: 'static>(&self, index: usize, since_tick: u64) -> bool {
+        if self.archetype.column::<T>().is_some() {
+            return self.archetype.component_changed::<T>(index, since_tick);
+        }
+        self.sparse_set::<T>(index)
+            .is_some_and(|(set, entity)| set.changed_since(entity, since_tick, None))
+    }
+
+    /// Whether `T` at `index` changed at or after `since_tick`, last by `writer`
+    #[cfg(feature = "change_detection")]
+    pub fn component_changed_by<T: 'static>(
+        &self,
+        index: usize,
+        since_tick: u64,
+        writer: u32,
+    ) -> bool {
+        if self.archetype.column::<T>().is_some() {
+            return self
+                .archetype
+                .component_changed_by::<T>(index, since_tick, writer);
+        }
+        self.sparse_set::<T>(index)
+            .is_some_and(|(set, entity)| set.changed_since(entity, since_tick, Some(writer)))
+    }
+
+    #[cfg(feature = "change_detection")]
+    pub fn component_added<T: 'static>(&self, index: usize, since_tick: u64) -> bool {
+        if self.archetype.column::<T>().is_some() {
+            return self.archetype.component_added::<T>(index, since_tick);
+        }
+        self.sparse_set::<T>(index)
+            .is_some_and(|(set, entity)| set.added_since(entity, since_tick))
+    }
+}
+
+impl std::ops::Deref for ArchetypeView<'_> {
+    type Target = Archetype;
+
+    fn deref(&self) -> &Archetype {
+        self.archetype
+    }
+}
+
+/// Pointers to the changed tick and writer of one component, see `Stamp`
+#[cfg(feature = "change_detection")]
+pub(crate) type ChangeSlots = (*mut u64, *mut u32);
+#[cfg(not(feature = "change_detection"))]
+pub(crate) type ChangeSlots = ();
+
+/// An archetype being spawned into together with the world's sparse sets,
+/// which receive a bundle's sparse components
+pub struct ArchetypeViewMut<'a> {
+    archetype: &'a mut Archetype,
+    sparse: &'a mut SparseStorage,
+}
+
+impl ArchetypeViewMut<'_> {
+    /// Writes into an uninitialized slot like `Archetype::set_component`, or
+    /// for a sparse `T` into its sparse set, replacing any old value
+    pub fn set_component<T: 'static>(&mut self, index: usize, component: T) {
+        if self.archetype.types.contains(&TypeId::of::<T>()) {
+            self.archetype.set_component(index, component);
+            return;
+        }
+        let entity = self.archetype.entities[index];
+        self.sparse
+            .get_mut::<T>()
+            .expect("Component type not in archetype")
+            .insert(entity, component);
+    }
+}
+
+impl std::ops::Deref for ArchetypeViewMut<'_> {
+    type Target = Archetype;
+
+    fn deref(&self) -> &Archetype {
+        self.archetype
+    }
+}
+
+impl std::ops::DerefMut for ArchetypeViewMut<'_> {
+    fn deref_mut(&mut self) -> &mut Archetype {
+        self.archetype
+    }
+}
+
+pub struct Archetype {
     id: usize,
     types: Vec<TypeId>,
     type_names: Vec<&'static str>,
+    dynamic: Vec<DynComponentId>,
     pub(crate) columns: Vec<Column>,
     entities: Vec<Entity>,
     tick: u64,
+    allocator: ColumnAllocator,
+    chunk_size: Option<usize>,
 }
 
+/// Reinterprets a pointer into a column as the column's concrete type
+pub(crate) type AsAnyFn = fn(*const u8) -> *const dyn Any;
+
 pub(crate) struct Column {
-    pub(crate) data: NonNull<u8>,
+    pub(crate) id: ComponentId,
+    // One block of `capacity` elements, or with a chunk size one block per
+    // chunk so growing never moves existing rows
+    blocks: Vec<NonNull<u8>>,
+    chunk_size: Option<usize>,
     pub(crate) len: usize,
     pub(crate) capacity: usize,
+    // Layout of one element, its size padded to the alignment
+    pub(crate) layout: Layout,
     pub(crate) item_size: usize,
-    pub(crate) changed_ticks: Vec<u64>,
+    #[cfg(feature = "change_detection")]
+    pub(crate) changed_ticks: Vec<Stamp<u64>>,
+    // Id of the system that made each change, see `ChangedBy`
+    #[cfg(feature = "change_detection")]
+    pub(crate) changed_by: Vec<Stamp<u32>>,
+    // Tick the component was first attached, kept across archetype moves
+    #[cfg(feature = "change_detection")]
+    pub(crate) added_ticks: Vec<u64>,
     pub(crate) drop_fn: unsafe fn(*mut u8),
+    pub(crate) as_any_fn: AsAnyFn,
+    allocator: ColumnAllocator,
 }
 
 impl Archetype {
-    pub fn new(id: usize, types: Vec<TypeId>, type_names: Vec<&'static str>) -> Self {
+    pub fn new(
+        id: usize,
+        types: Vec<TypeId>,
+        type_names: Vec<&'static str>,
+        dynamic: Vec<DynComponentId>,
+    ) -> Self {
         Self {
             id,
             types,
             type_names,
+            dynamic,
             columns: Vec::new(),
             entities: Vec::new(),
             tick: 0,
+            allocator: global_allocator(),
+            chunk_size: None,
         }
     }
 
+    /// Columns added after this call allocate from `allocator`
+    pub(crate) fn with_allocator(mut self, allocator: ColumnAllocator) -> Self {
+        self.allocator = allocator;
+        self
+    }
+
+    // Column holding `T`, if `T` is one of the archetype's types
+    fn column<T: 'static>(&self) -> Option<&Column> {
+        self.column_of(TypeId::of::<T>())
+    }
+
+    fn column_of(&self, type_id: TypeId) -> Option<&Column> {
+        let column_index = self.types.iter().position(|&t| t == type_id)?;
+        self.columns.get(column_index)
+    }
+
+    /// Columns store rows in separate blocks of `chunk_size` entities instead
+    /// of one block that doubles. Only valid before any column has allocated.
+    pub(crate) fn set_chunk_size(&mut self, chunk_size: Option<usize>) {
+        assert!(
+            self.columns.iter().all(|column| column.capacity == 0),
+            "chunk size of archetype {} changed after allocating",
+            self.id
+        );
+        self.chunk_size = chunk_size;
+        for column in &mut self.columns {
+            column.chunk_size = chunk_size;
+        }
+    }
+
+    pub fn chunk_size(&self) -> Option<usize> {
+        self.chunk_size
+    }
+
+    /// Row ranges of at most `chunk_size` entities covering the archetype in
+    /// order; a single range without a chunk size
+    pub fn chunks(&self) -> impl Iterator<Item = std::ops::Range<usize>> + '_ {
+        let len = self.len();
+        let size = self.chunk_size.unwrap_or(len.max(1));
+        (0..len)
+            .step_by(size)
+            .map(move |start| start..(start + size).min(len))
+    }
+
     pub fn id(&self) -> usize {
         self.id
     }
@@ -45,10 +429,53 @@ impl Archetype {
         &self.type_names
     }
 
+    pub fn dynamic_ids(&self) -> &[DynComponentId] {
+        &self.dynamic
+    }
+
+    /// Every component in this archetype, static types first
+    pub fn component_ids(&self) -> impl Iterator<Item = ComponentId> + '_ {
+        self.types
+            .iter()
+            .map(|&t| ComponentId::Static(t))
+            .chain(self.dynamic.iter().map(|&d| ComponentId::Dynamic(d)))
+    }
+
+    pub fn contains(&self, id: ComponentId) -> bool {
+        self.column_index(id).is_some()
+    }
+
+    // Columns are ordered like `component_ids`: static types, then dynamic ones
+    fn column_index(&self, id: ComponentId) -> Option<usize> {
+        match id {
+            ComponentId::Static(type_id) => self.types.iter().position(|&t| t == type_id),
+            ComponentId::Dynamic(dyn_id) => self
+                .dynamic
+                .iter()
+                .position(|&d| d == dyn_id)
+                .map(|i| self.types.len() + i),
+        }
+    }
+
     pub fn len(&self) -> usize {
         self.entities.len()
     }
 
+    /// Whether every component has a column holding a row per entity. Only a
+    /// bug or a half-finished move leaves an archetype incomplete.
+    pub(crate) fn is_complete(&self) -> bool {
+        self.columns.len() == self.types.len() + self.dynamic.len()
+            && self
+                .columns
+                .iter()
+                .all(|column| column.len == self.entities.len())
+    }
+
+    /// False only for the archetype of entities without any components
+    pub fn has_components(&self) -> bool {
+        !self.types.is_empty() || !self.dynamic.is_empty()
+    }
+
     pub fn is_empty(&self) -> bool {
         self.entities.is_empty()
     }
@@ -57,34 +484,110 @@ impl Archetype {
         &self.entities
     }
 
+    pub(crate) fn remap_entities(&mut self, map: impl Fn(Entity) -> Entity) {
+        for entity in &mut self.entities {
+            *entity = map(*entity);
+        }
+    }
+
+    pub fn entity_capacity(&self) -> usize {
+        self.entities.capacity()
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
     pub fn set_tick(&mut self, tick: u64) {
         self.tick = tick;
     }
 
     pub fn add_column<T: 'static>(&mut self) {
+        let id = ComponentId::Static(TypeId::of::<T>());
+        // Types outside the archetype, like sparse ones, never get a column
+        if self.has_column(id) || !self.types.contains(&TypeId::of::<T>()) {
+            return;
+        }
+
+        let layout = Layout::new::<T>();
         let column = Column {
-            data: NonNull::dangling(),
+            id,
+            blocks: Vec::new(),
+            chunk_size: self.chunk_size,
             len: 0,
             capacity: 0,
-            item_size: std::mem::size_of::<T>(),
+            layout,
+            item_size: layout.size(),
+            #[cfg(feature = "change_detection")]
             changed_ticks: Vec::new(),
+            #[cfg(feature = "change_detection")]
+            changed_by: Vec::new(),
+            #[cfg(feature = "change_detection")]
+            added_ticks: Vec::new(),
             drop_fn: |ptr| unsafe {
                 std::ptr::drop_in_place(ptr as *mut T);
             },
+            as_any_fn: |ptr| ptr as *const T as *const dyn Any,
+            allocator: self.allocator.clone(),
         };
-        self.columns.push(column);
+        self.insert_column(column);
     }
 
-    pub fn add_column_raw(&mut self, item_size: usize, drop_fn: unsafe fn(*mut u8)) {
+    /// Adds a column whose elements have `layout`; its size is padded to the
+    /// alignment to get the stride between elements
+    pub fn add_column_raw(
+        &mut self,
+        id: ComponentId,
+        layout: Layout,
+        drop_fn: unsafe fn(*mut u8),
+        as_any_fn: AsAnyFn,
+    ) {
+        if self.has_column(id) {
+            return;
+        }
+
+        let layout = layout.pad_to_align();
         let column = Column {
-            data: NonNull::dangling(),
+            id,
+            blocks: Vec::new(),
+            chunk_size: self.chunk_size,
             len: 0,
             capacity: 0,
-            item_size,
+            layout,
+            item_size: layout.size(),
+            #[cfg(feature = "change_detection")]
             changed_ticks: Vec::new(),
+            #[cfg(feature = "change_detection")]
+            changed_by: Vec::new(),
+            #[cfg(feature = "change_detection")]
+            added_ticks: Vec::new(),
             drop_fn,
+            as_any_fn,
+            allocator: self.allocator.clone(),
         };
-        self.columns.push(column);
+        self.insert_column(column);
+    }
+
+    fn has_column(&self, id: ComponentId) -> bool {
+        self.columns.iter().any(|c| c.id == id)
+    }
+
+    // Columns are kept in `types` order so column i always stores types[i]. A
+    // column added after entities were pushed would be shorter than `len`
+    // (data and tick slots alike), so columns may only be added while empty.
+    fn insert_column(&mut self, column: Column) {
+        assert!(
+            self.entities.is_empty(),
+            "Column {:?} added to archetype {} while it holds {} entities",
+            column.id,
+            self.id,
+            self.entities.len()
+        );
+        let rank = self.column_index(column.id);
+        let position = self
+            .columns
+            .partition_point(|c| self.column_index(c.id) < rank);
+        self.columns.insert(position, column);
     }
 
     pub fn push_entity(&mut self, entity: Entity) {
@@ -92,13 +595,19 @@ impl Archetype {
 
         for column in &mut self.columns {
             column.len += 1;
-            column.changed_ticks.push(self.tick);
+            #[cfg(feature = "change_detection")]
+            {
+                column.changed_ticks.push(Stamp::new(self.tick));
+                column.changed_by.push(Stamp::new(current_writer()));
+                column.added_ticks.push(self.tick);
+            }
             if column.len > column.capacity {
                 column.grow();
             }
         }
     }
 
+    /// Writes into an uninitialized slot; the previous bytes are not dropped
     pub fn set_component<T: 'static>(&mut self, index: usize, component: T) {
         let type_id = TypeId::of::<T>();
         let column_index = self
@@ -109,22 +618,22 @@ impl Archetype {
 
         unsafe {
             let column = &mut self.columns[column_index];
-            let ptr = column.data.as_ptr().add(index * column.item_size) as *mut T;
+            let ptr = column.ptr_at(index) as *mut T;
             std::ptr::write(ptr, component);
-            column.changed_ticks[index] = self.tick;
+            #[cfg(feature = "change_detection")]
+            {
+                column.mark_changed(index, self.tick, current_writer());
+            }
         }
     }
 
     pub fn get_component<T: 'static>(&self, index: usize) -> Option<&T> {
-        let type_id = TypeId::of::<T>();
-        let column_index = self.types.iter().position(|&t| t == type_id)?;
-
         unsafe {
-            let column = &self.columns[column_index];
+            let column = self.column::<T>()?;
             if index >= column.len {
                 return None;
             }
-            let ptr = column.data.as_ptr().add(index * column.item_size) as *const T;
+            let ptr = column.ptr_at(index) as *const T;
             Some(&*ptr)
         }
     }
@@ -134,28 +643,161 @@ impl Archetype {
         let column_index = self.types.iter().position(|&t| t == type_id)?;
 
         unsafe {
-            let column = &mut self.columns[column_index];
+            let column = self.columns.get_mut(column_index)?;
             if index >= column.len {
                 return None;
             }
-            let ptr = column.data.as_ptr().add(index * column.item_size) as *mut T;
-            column.changed_ticks[index] = self.tick;
+            let ptr = column.ptr_at(index) as *mut T;
+            #[cfg(feature = "change_detection")]
+            {
+                column.mark_changed(index, self.tick, current_writer());
+            }
             Some(&mut *ptr)
         }
     }
 
-    pub fn component_changed<T: 'static>(&self, index: usize, since_tick: u64) -> bool {
-        let type_id = TypeId::of::<T>();
-        if let Some(column_index) = self.types.iter().position(|&t| t == type_id) {
-            let column = &self.columns[column_index];
-            if index < column.changed_ticks.len() {
-                return column.changed_ticks[index] > since_tick;
+    pub fn get_component_ptr(&self, id: impl Into<ComponentId>, index: usize) -> Option<*const u8> {
+        let column_index = self.column_index(id.into())?;
+
+        let column = self.columns.get(column_index)?;
+        if index >= column.len {
+            return None;
+        }
+        Some(column.ptr_at(index) as *const u8)
+    }
+
+    pub fn get_component_any(&self, type_id: TypeId, index: usize) -> Option<&dyn Any> {
+        let column_index = self.types.iter().position(|&t| t == type_id)?;
+        let column = &self.columns[column_index];
+        let ptr = self.get_component_ptr(type_id, index)?;
+        Some(unsafe { &*(column.as_any_fn)(ptr) })
+    }
+
+    pub fn get_component_ptr_mut(
+        &mut self,
+        id: impl Into<ComponentId>,
+        index: usize,
+    ) -> Option<*mut u8> {
+        let column_index = self.column_index(id.into())?;
+
+        let column = self.columns.get_mut(column_index)?;
+        if index >= column.len {
+            return None;
+        }
+        #[cfg(feature = "change_detection")]
+        {
+            column.mark_changed(index, self.tick, current_writer());
+        }
+        Some(column.ptr_at(index))
+    }
+
+    /// The whole `T` column, one element per entity in `entities()` order.
+    /// None if the rows span more than one chunk, see `column_chunks`.
+    pub fn column_slice<T: 'static>(&self) -> Option<&[T]> {
+        let column = &self.columns[self.column_index(TypeId::of::<T>().into())?];
+        if column.len > column.contiguous_from(0) {
+            return None;
+        }
+        unsafe {
+            Some(std::slice::from_raw_parts(
+                column.typed_ptr::<T>(0),
+                column.len,
+            ))
+        }
+    }
+
+    /// The `T` column as one slice per chunk, in `entities()` order; a single
+    /// slice without a chunk size
+    pub fn column_chunks<T: 'static>(&self) -> Option<impl Iterator<Item = &[T]> + '_> {
+        let column = &self.columns[self.column_index(TypeId::of::<T>().into())?];
+        Some(self.chunks().map(move |range| unsafe {
+            std::slice::from_raw_parts(column.typed_ptr::<T>(range.start), range.len())
+        }))
+    }
+
+    /// Mutable `column_slice`; marks every element of the column changed
+    pub fn column_slice_mut<T: 'static>(&mut self) -> Option<&mut [T]> {
+        let column_index = self.column_index(TypeId::of::<T>().into())?;
+        let column = &mut self.columns[column_index];
+        if column.len > column.contiguous_from(0) {
+            return None;
+        }
+        #[cfg(feature = "change_detection")]
+        {
+            let writer = current_writer();
+            for (tick, by) in column.changed_ticks.iter_mut().zip(&mut column.changed_by) {
+                tick.set(self.tick);
+                by.set(writer);
             }
         }
-        false
+        unsafe {
+            Some(std::slice::from_raw_parts_mut(
+                column.typed_ptr::<T>(0),
+                column.len,
+            ))
+        }
     }
 
+    /// Stamps the changed tick of column `id` at `index` with the current
+    /// tick, returning false if there is no such slot
+    #[cfg(feature = "change_detection")]
+    pub fn mark_changed(&mut self, id: impl Into<ComponentId>, index: usize) -> bool {
+        let Some(column_index) = self.column_index(id.into()) else {
+            return false;
+        };
+        match self.columns.get_mut(column_index) {
+            Some(column) if index < column.changed_ticks.len() => {
+                column.mark_changed(index, self.tick, current_writer());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    #[cfg(feature = "change_detection")]
+    pub fn component_changed<T: 'static>(&self, index: usize, since_tick: u64) -> bool {
+        self.column::<T>().is_some_and(|column| {
+            index < column.changed_ticks.len() && column.changed_ticks[index].get() >= since_tick
+        })
+    }
+
+    /// Whether `T` at `index` changed at or after `since_tick`, last by `writer`
+    #[cfg(feature = "change_detection")]
+    pub fn component_changed_by<T: 'static>(
+        &self,
+        index: usize,
+        since_tick: u64,
+        writer: u32,
+    ) -> bool {
+        self.column::<T>().is_some_and(|column| {
+            index < column.changed_ticks.len()
+                && column.changed_ticks[index].get() >= since_tick
+                && column.changed_by[index].get() == writer
+        })
+    }
+
+    #[cfg(feature = "change_detection")]
+    pub fn component_added<T: 'static>(&self, index: usize, since_tick: u64) -> bool {
+        self.column::<T>().is_some_and(|column| {
+            index < column.added_ticks.len() && column.added_ticks[index] >= since_tick
+        })
+    }
+
+    /// Removes the entity at `index`, dropping its components
     pub fn remove_entity(&mut self, index: usize) -> (Entity, Option<Entity>) {
+        for column in &mut self.columns {
+            unsafe {
+                let ptr = column.ptr_at(index);
+                (column.drop_fn)(ptr);
+            }
+        }
+
+        self.remove_entity_moved(index)
+    }
+
+    /// Removes the entity at `index` without dropping its components, for
+    /// when they have already been moved or read out
+    pub fn remove_entity_moved(&mut self, index: usize) -> (Entity, Option<Entity>) {
         // Get the entity that will be swapped in (if any)
         let swapped_entity = if index < self.entities.len() - 1 {
             Some(self.entities[self.entities.len() - 1])
@@ -169,13 +811,23 @@ impl Archetype {
             unsafe {
                 let last = column.len - 1;
                 if index != last {
-                    let src = column.data.as_ptr().add(last * column.item_size);
-                    let dst = column.data.as_ptr().add(index * column.item_size);
+                    let src = column.ptr_at(last);
+                    let dst = column.ptr_at(index);
                     std::ptr::copy_nonoverlapping(src, dst, column.item_size);
-                    column.changed_ticks[index] = column.changed_ticks[last];
+                    #[cfg(feature = "change_detection")]
+                    {
+                        column.changed_ticks.swap(index, last);
+                        column.changed_by.swap(index, last);
+                        column.added_ticks[index] = column.added_ticks[last];
+                    }
                 }
                 column.len -= 1;
-                column.changed_ticks.pop();
+                #[cfg(feature = "change_detection")]
+                {
+                    column.changed_ticks.pop();
+                    column.changed_by.pop();
+                    column.added_ticks.pop();
+                }
             }
         }
 
@@ -187,151 +839,633 @@ impl Archetype {
         let column_index = self.types.iter().position(|&t| t == type_id)?;
 
         unsafe {
-            let column = &mut self.columns[column_index];
+            let column = self.columns.get_mut(column_index)?;
             if index >= column.len {
                 return None;
             }
-            let ptr = column.data.as_ptr().add(index * column.item_size) as *mut T;
+            let ptr = column.ptr_at(index) as *mut T;
             Some(std::ptr::read(ptr))
         }
     }
 
+    /// Copies one component of `from_archetype[from_index]` into row `to_index`.
+    /// Returns `false` if either archetype lacks an initialized column for `id`
+    /// or a row is out of range, leaving the destination slot untouched.
     pub fn copy_component_from(
         &mut self,
         to_index: usize,
         from_archetype: &Archetype,
         from_index: usize,
-        type_id: TypeId,
-    ) {
-        // Find column indices in both archetypes
-        let to_column_index = self.types.iter().position(|&t| t == type_id);
-        let from_column_index = from_archetype.types.iter().position(|&t| t == type_id);
+        id: ComponentId,
+    ) -> bool {
+        let (Some(to_col_idx), Some(from_col_idx)) =
+            (self.column_index(id), from_archetype.column_index(id))
+        else {
+            return false;
+        };
+        let (Some(to_column), Some(from_column)) = (
+            self.columns.get_mut(to_col_idx),
+            from_archetype.columns.get(from_col_idx),
+        ) else {
+            return false;
+        };
+        if to_column.id != id
+            || from_column.id != id
+            || to_index >= to_column.len
+            || from_index >= from_column.len
+        {
+            return false;
+        }
+        #[cfg(feature = "change_detection")]
+        if to_index >= to_column.changed_ticks.len()
+            || from_index >= from_column.changed_ticks.len()
+        {
+            return false;
+        }
+
+        unsafe {
+            let src = from_column.ptr_at(from_index);
+            let dst = to_column.ptr_at(to_index);
+            std::ptr::copy_nonoverlapping(src, dst, to_column.item_size);
+        }
 
-        if let (Some(to_col_idx), Some(from_col_idx)) = (to_column_index, from_column_index) {
+        // The tick slots were already pushed by push_entity, just carry them
+        // over so a moved component doesn't look freshly added
+        #[cfg(feature = "change_detection")]
+        {
+            to_column.changed_ticks[to_index].set(from_column.changed_ticks[from_index].get());
+            to_column.changed_by[to_index].set(from_column.changed_by[from_index].get());
+            to_column.added_ticks[to_index] = from_column.added_ticks[from_index];
+        }
+        true
+    }
+
+    /// Copies `count` consecutive rows of one component, starting at
+    /// `from_index`, into rows `to_index..to_index + count`, with one memcpy
+    /// per chunk touched. Same checks and tick handling as `copy_component_from`.
+    pub fn copy_run_from(
+        &mut self,
+        to_index: usize,
+        from_archetype: &Archetype,
+        from_index: usize,
+        count: usize,
+        id: ComponentId,
+    ) -> bool {
+        let (Some(to_col_idx), Some(from_col_idx)) =
+            (self.column_index(id), from_archetype.column_index(id))
+        else {
+            return false;
+        };
+        let (Some(to_column), Some(from_column)) = (
+            self.columns.get_mut(to_col_idx),
+            from_archetype.columns.get(from_col_idx),
+        ) else {
+            return false;
+        };
+        if to_column.id != id
+            || from_column.id != id
+            || to_index + count > to_column.len
+            || from_index + count > from_column.len
+        {
+            return false;
+        }
+        #[cfg(feature = "change_detection")]
+        if to_index + count > to_column.changed_ticks.len()
+            || from_index + count > from_column.changed_ticks.len()
+        {
+            return false;
+        }
+
+        // One memcpy per stretch that is contiguous in both columns
+        let mut copied = 0;
+        while copied < count {
+            let run = (count - copied)
+                .min(from_column.contiguous_from(from_index + copied))
+                .min(to_column.contiguous_from(to_index + copied));
             unsafe {
-                let to_column = &mut self.columns[to_col_idx];
-                let from_column = &from_archetype.columns[from_col_idx];
-
-                // Ensure we have enough space and the indices are valid
-                if to_index < to_column.len && from_index < from_column.len {
-                    let src = from_column
-                        .data
-                        .as_ptr()
-                        .add(from_index * from_column.item_size);
-                    let dst = to_column.data.as_ptr().add(to_index * to_column.item_size);
-
-                    std::ptr::copy_nonoverlapping(src, dst, to_column.item_size);
-
-                    // Update the changed tick - the tick was already added by push_entity
-                    // so we just need to update it
-                    to_column.changed_ticks[to_index] = from_column.changed_ticks[from_index];
-                }
+                let src = from_column.ptr_at(from_index + copied);
+                let dst = to_column.ptr_at(to_index + copied);
+                std::ptr::copy_nonoverlapping(src, dst, run * to_column.item_size);
             }
+            copied += run;
+        }
+
+        #[cfg(feature = "change_detection")]
+        {
+            let from = from_index..from_index + count;
+            let to = to_index..to_index + count;
+            for (to, from) in to.clone().zip(from.clone()) {
+                to_column.changed_ticks[to].set(from_column.changed_ticks[from].get());
+                to_column.changed_by[to].set(from_column.changed_by[from].get());
+            }
+            to_column.added_ticks[to].copy_from_slice(&from_column.added_ticks[from]);
+        }
+        true
+    }
+
+    /// Copies each of `ids` with `copy_component_from`, panicking if any fails
+    /// since the destination row would otherwise hold uninitialized memory
+    pub fn copy_components_from(
+        &mut self,
+        to_index: usize,
+        from_archetype: &Archetype,
+        from_index: usize,
+        ids: &[ComponentId],
+    ) {
+        for &id in ids {
+            assert!(
+                self.copy_component_from(to_index, from_archetype, from_index, id),
+                "Component {:?} could not be moved from archetype {} to archetype {}",
+                id,
+                from_archetype.id,
+                self.id
+            );
         }
     }
 
     pub fn reserve(&mut self, additional: usize) {
         for column in &mut self.columns {
-            column.reserve(additional);
+            let needed = column.len + additional;
+            column.reserve(needed.saturating_sub(column.capacity));
         }
         self.entities.reserve(additional);
     }
+
+    /// Appends `entity` with a copy of row `index`, each column cloned through
+    /// `clone_fn`, and returns the new row. Its components count as just
+    /// added. Panics if a column has no clone function.
+    pub(crate) fn clone_row(
+        &mut self,
+        index: usize,
+        entity: Entity,
+        clone_fn: &dyn Fn(ComponentId) -> Option<CloneIntoFn>,
+    ) -> usize {
+        let clones: Vec<CloneIntoFn> = self
+            .columns
+            .iter()
+            .map(|column| {
+                clone_fn(column.id)
+                    .unwrap_or_else(|| panic!("Component {:?} can't be cloned", column.id))
+            })
+            .collect();
+
+        // Clone into reserved slots before pushing, so a panicking clone
+        // leaves no uninitialized row behind
+        let row = self.len();
+        self.reserve(1);
+        for (column, clone) in self.columns.iter().zip(clones) {
+            unsafe { clone(column.ptr_at(index), column.ptr_at(row)) };
+        }
+        self.push_entity(entity);
+        row
+    }
+
+    /// Copy with the same id and entities, each column cloned through
+    /// `clone_fn` with its change ticks. Panics if a non-empty column has no
+    /// clone function.
+    pub(crate) fn clone_with(&self, clone_fn: &dyn Fn(ComponentId) -> Option<CloneIntoFn>) -> Self {
+        Self {
+            id: self.id,
+            types: self.types.clone(),
+            type_names: self.type_names.clone(),
+            dynamic: self.dynamic.clone(),
+            columns: self
+                .columns
+                .iter()
+                .map(|column| column.clone_with(clone_fn(column.id)))
+                .collect(),
+            entities: self.entities.clone(),
+            tick: self.tick,
+            allocator: self.allocator.clone(),
+            chunk_size: self.chunk_size,
+        }
+    }
+
+    /// Number of entities this archetype can hold before any column or the
+    /// entity list has to reallocate.
+    pub fn capacity(&self) -> usize {
+        self.columns
+            .iter()
+            .map(|column| column.capacity)
+            .fold(self.entities.capacity(), usize::min)
+    }
+}
+
+// Non-null pointer aligned for `layout`, used until (or, for zero-sized
+// elements, instead of) an allocation
+fn dangling_for(layout: Layout) -> NonNull<u8> {
+    NonNull::new(layout.align() as *mut u8).unwrap()
 }
 
+// The blocks hold components, which are `Send + Sync`, and are only
+// reached through the column
+unsafe impl Send for Column {}
+unsafe impl Sync for Column {}
+
 impl Column {
-    fn grow(&mut self) {
-        let new_capacity = if self.capacity == 0 {
-            4
-        } else {
-            self.capacity * 2
+    fn typed_ptr<T>(&self, index: usize) -> *mut T {
+        debug_assert_eq!(self.item_size, std::mem::size_of::<T>());
+        self.ptr_at(index) as *mut T
+    }
+
+    // Address of row `index`, which must be below `capacity`
+    fn ptr_at(&self, index: usize) -> *mut u8 {
+        if self.item_size == 0 {
+            return dangling_for(self.layout).as_ptr();
+        }
+        let (block, offset) = match self.chunk_size {
+            Some(chunk_size) => (index / chunk_size, index % chunk_size),
+            None => (0, index),
         };
-        self.reserve(new_capacity - self.capacity);
+        unsafe { self.blocks[block].as_ptr().add(offset * self.item_size) }
     }
 
-    fn reserve(&mut self, additional: usize) {
-        if additional == 0 {
-            return;
+    fn slot(&self, index: usize) -> Option<(*mut u8, ChangeSlots)> {
+        if index >= self.len {
+            return None;
+        }
+        #[cfg(feature = "change_detection")]
+        let stamps = (
+            self.changed_ticks[index].as_ptr(),
+            self.changed_by[index].as_ptr(),
+        );
+        #[cfg(not(feature = "change_detection"))]
+        let stamps = ();
+        Some((self.ptr_at(index), stamps))
+    }
+
+    // Rows from `index` on that share its block
+    fn contiguous_from(&self, index: usize) -> usize {
+        match self.chunk_size {
+            Some(chunk_size) if self.item_size > 0 => chunk_size - index % chunk_size,
+            _ => usize::MAX,
         }
+    }
 
-        let new_capacity = self.capacity + additional;
+    // Layout of `capacity` elements
+    fn array_layout(&self, capacity: usize) -> Layout {
+        self.item_size
+            .checked_mul(capacity)
+            .and_then(|size| Layout::from_size_align(size, self.layout.align()).ok())
+            .expect("Column capacity overflow")
+    }
 
-        unsafe {
-            let new_layout = std::alloc::Layout::from_size_align_unchecked(
-                new_capacity * self.item_size,
-                std::mem::align_of::<u8>(),
-            );
+    #[cfg(feature = "change_detection")]
+    fn mark_changed(&mut self, index: usize, tick: u64, writer: u32) {
+        self.changed_ticks[index].set(tick);
+        self.changed_by[index].set(writer);
+    }
 
-            let new_ptr = if self.capacity == 0 {
-                std::alloc::alloc(new_layout)
-            } else {
-                let old_layout = std::alloc::Layout::from_size_align_unchecked(
-                    self.capacity * self.item_size,
-                    std::mem::align_of::<u8>(),
-                );
-                std::alloc::realloc(
-                    self.data.as_ptr(),
-                    old_layout,
-                    new_capacity * self.item_size,
-                )
-            };
+    // Copy with each row cloned by `clone`, which only an empty column may lack
+    fn clone_with(&self, clone: Option<CloneIntoFn>) -> Column {
+        let mut column = Column {
+            id: self.id,
+            blocks: Vec::new(),
+            chunk_size: self.chunk_size,
+            len: 0,
+            capacity: 0,
+            layout: self.layout,
+            item_size: self.item_size,
+            #[cfg(feature = "change_detection")]
+            changed_ticks: self.changed_ticks.clone(),
+            #[cfg(feature = "change_detection")]
+            changed_by: self.changed_by.clone(),
+            #[cfg(feature = "change_detection")]
+            added_ticks: self.added_ticks.clone(),
+            drop_fn: self.drop_fn,
+            as_any_fn: self.as_any_fn,
+            allocator: self.allocator.clone(),
+        };
+        if self.len == 0 {
+            return column;
+        }
 
-            self.data = NonNull::new(new_ptr).expect("Allocation failed");
-            self.capacity = new_capacity;
+        let clone = clone.unwrap_or_else(|| panic!("Component {:?} can't be cloned", self.id));
+        column.reserve(self.len);
+        for index in 0..self.len {
+            unsafe { clone(self.ptr_at(index), column.ptr_at(index)) };
+            // Counted as it goes so a panicking clone drops only cloned rows
+            column.len += 1;
         }
+        column
+    }
 
-        self.changed_ticks.reserve(additional);
+    fn grow(&mut self) {
+        let additional = match self.chunk_size {
+            Some(_) => 1,
+            None if self.capacity == 0 => 4,
+            None => self.capacity,
+        };
+        self.reserve(additional);
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        if additional == 0 {
+            return;
+        }
+
+        let mut new_capacity = self.capacity + additional;
+
+        // Zero-sized elements never allocate; `GlobalAlloc` must never see a
+        // zero-sized layout
+        if self.item_size > 0 {
+            match self.chunk_size {
+                Some(chunk_size) => {
+                    let layout = self.array_layout(chunk_size);
+                    while self.blocks.len() * chunk_size < new_capacity {
+                        let ptr = unsafe { self.allocator.alloc(layout) };
+                        let block = NonNull::new(ptr)
+                            .unwrap_or_else(|| std::alloc::handle_alloc_error(layout));
+                        self.blocks.push(block);
+                    }
+                    new_capacity = self.blocks.len() * chunk_size;
+                }
+                None => {
+                    let new_layout = self.array_layout(new_capacity);
+                    let new_ptr = unsafe {
+                        match self.blocks.first() {
+                            None => self.allocator.alloc(new_layout),
+                            Some(block) => {
+                                let old_layout = self.array_layout(self.capacity);
+                                self.allocator.realloc(
+                                    block.as_ptr(),
+                                    old_layout,
+                                    new_layout.size(),
+                                )
+                            }
+                        }
+                    };
+                    let block = NonNull::new(new_ptr)
+                        .unwrap_or_else(|| std::alloc::handle_alloc_error(new_layout));
+                    self.blocks.clear();
+                    self.blocks.push(block);
+                }
+            }
+        }
+        #[cfg(feature = "change_detection")]
+        {
+            let additional = new_capacity - self.len;
+            self.changed_ticks.reserve(additional);
+            self.changed_by.reserve(additional);
+            self.added_ticks.reserve(additional);
+        }
+        self.capacity = new_capacity;
     }
 }
 
 impl Drop for Column {
     fn drop(&mut self) {
-        if self.capacity > 0 {
-            unsafe {
-                for i in 0..self.len {
-                    let ptr = self.data.as_ptr().add(i * self.item_size);
-                    (self.drop_fn)(ptr);
-                }
+        unsafe {
+            for i in 0..self.len {
+                (self.drop_fn)(self.ptr_at(i));
+            }
 
-                let layout = std::alloc::Layout::from_size_align_unchecked(
-                    self.capacity * self.item_size,
-                    std::mem::align_of::<u8>(),
-                );
-                std::alloc::dealloc(self.data.as_ptr(), layout);
+            let layout = self.array_layout(self.chunk_size.unwrap_or(self.capacity));
+            for block in &self.blocks {
+                self.allocator.dealloc(block.as_ptr(), layout);
             }
         }
     }
 }
 
+struct CachedMatches {
+    generation: u64,
+    all: Arc<[usize]>,
+    structure_generation: u64,
+    non_empty: Arc<[usize]>,
+}
+
+type QueryCache = HashMap<(TypeId, bool), CachedMatches>;
+
 pub(crate) struct ArchetypeMap {
     archetypes: Vec<Archetype>,
-    type_map: HashMap<Vec<TypeId>, usize>,
+    type_map: HashMap<(Vec<TypeId>, Vec<DynComponentId>), usize>,
     graph: ArchetypeGraph,
+    /// Bumped whenever an archetype is created, invalidating `query_cache`
+    generation: u64,
+    query_cache: QueryCache,
+    /// Bumped on archetype creation and by `World` on every spawn, despawn,
+    /// archetype move or reserve, i.e. anything that may move component data
+    structure_generation: u64,
+    allocator: ColumnAllocator,
+    /// World tick, handed to archetypes created after the first `set_tick`
+    tick: u64,
+    chunk_size: Option<usize>,
+    /// Registration order of component types, which orders archetype types
+    /// and columns independently of `TypeId`
+    component_indices: HashMap<TypeId, ComponentIndex>,
+    // Components stored outside archetypes, see `World::register_sparse`
+    sparse: SparseStorage,
 }
 
 impl ArchetypeMap {
     pub fn new() -> Self {
+        Self::with_allocator(global_allocator())
+    }
+
+    pub fn with_allocator(allocator: ColumnAllocator) -> Self {
         Self {
             archetypes: Vec::new(),
             type_map: HashMap::new(),
             graph: ArchetypeGraph::new(),
+            generation: 0,
+            query_cache: HashMap::new(),
+            structure_generation: 0,
+            allocator,
+            tick: 0,
+            chunk_size: None,
+            component_indices: HashMap::new(),
+            sparse: SparseStorage::new(),
         }
     }
 
-    pub fn get_or_create(
+    pub(crate) fn sparse(&self) -> &SparseStorage {
+        &self.sparse
+    }
+
+    pub(crate) fn sparse_mut(&mut self) -> &mut SparseStorage {
+        &mut self.sparse
+    }
+
+    /// Archetype `index` as query terms see it, see `ArchetypeView`
+    pub(crate) fn view(&self, index: usize) -> Option<ArchetypeView<'_>> {
+        let archetype = self.archetypes.get(index)?;
+        Some(ArchetypeView::new(archetype, &self.sparse))
+    }
+
+    pub(crate) fn views(&self) -> impl Iterator<Item = ArchetypeView<'_>> {
+        self.archetypes
+            .iter()
+            .map(|archetype| ArchetypeView::new(archetype, &self.sparse))
+    }
+
+    /// Archetype `index` for writing a bundle into, see `ArchetypeViewMut`
+    pub(crate) fn view_mut(&mut self, index: usize) -> Option<ArchetypeViewMut<'_>> {
+        let archetype = self.archetypes.get_mut(index)?;
+        Some(ArchetypeViewMut {
+            archetype,
+            sparse: &mut self.sparse,
+        })
+    }
+
+    /// Copy of every archetype and sparse set, with components cloned
+    /// through `clone_fn` and change ticks kept, so archetype ids and entity
+    /// locations carry over unchanged. See `Archetype::clone_with`.
+    pub(crate) fn clone_with(&self, clone_fn: &dyn Fn(ComponentId) -> Option<CloneIntoFn>) -> Self {
+        let sparse = self
+            .sparse
+            .clone_with(&|type_id| clone_fn(ComponentId::Static(type_id)));
+        let archetypes = self
+            .archetypes
+            .iter()
+            .map(|archetype| archetype.clone_with(clone_fn))
+            .collect();
+        Self {
+            archetypes,
+            type_map: self.type_map.clone(),
+            graph: self.graph.clone(),
+            generation: self.generation,
+            query_cache: QueryCache::new(),
+            structure_generation: self.structure_generation,
+            allocator: self.allocator.clone(),
+            tick: self.tick,
+            chunk_size: self.chunk_size,
+            component_indices: self.component_indices.clone(),
+            sparse,
+        }
+    }
+
+    /// Stores `T` in a sparse set from now on. Archetypes match queries
+    /// differently once any type is sparse, so cached matches are dropped.
+    pub(crate) fn register_sparse<T: crate::component::Component>(&mut self) {
+        if self.sparse.register::<T>() {
+            self.generation += 1;
+        }
+    }
+
+    /// Index of `type_id`, registering it after every known type if new
+    pub fn register_component(&mut self, type_id: TypeId) -> ComponentIndex {
+        let next = ComponentIndex(self.component_indices.len() as u32);
+        *self.component_indices.entry(type_id).or_insert(next)
+    }
+
+    pub fn component_index(&self, type_id: TypeId) -> Option<ComponentIndex> {
+        self.component_indices.get(&type_id).copied()
+    }
+
+    pub fn chunk_size(&self) -> Option<usize> {
+        self.chunk_size
+    }
+
+    pub fn set_chunk_size(&mut self, chunk_size: Option<usize>) {
+        self.chunk_size = chunk_size;
+        for archetype in &mut self.archetypes {
+            archetype.set_chunk_size(chunk_size);
+        }
+    }
+
+    pub fn set_tick(&mut self, tick: u64) {
+        self.tick = tick;
+        for archetype in &mut self.archetypes {
+            archetype.set_tick(tick);
+        }
+        #[cfg(feature = "change_detection")]
+        self.sparse.set_tick(tick);
+    }
+
+    pub fn mark_structural_change(&mut self) {
+        self.structure_generation += 1;
+    }
+
+    pub fn structure_generation(&self) -> u64 {
+        self.structure_generation
+    }
+
+    /// Ids of the non-empty archetypes accepted by `matches`. The full match
+    /// list is cached under `key` until the next archetype is created, and its
+    /// non-empty subset until the next structural change.
+    pub fn matching_archetypes(
+        &mut self,
+        key: (TypeId, bool),
+        matches: impl Fn(ArchetypeView<'_>) -> bool,
+    ) -> Arc<[usize]> {
+        let all = match self.query_cache.get(&key) {
+            Some(cached) if cached.generation == self.generation => {
+                if cached.structure_generation == self.structure_generation {
+                    return cached.non_empty.clone();
+                }
+                cached.all.clone()
+            }
+            _ => self
+                .views()
+                .filter(|&archetype| matches(archetype))
+                .map(|archetype| archetype.id())
+                .collect(),
+        };
+
+        let non_empty: Arc<[usize]> = all
+            .iter()
+            .copied()
+            .filter(|&id| !self.archetypes[id].is_empty())
+            .collect();
+        self.query_cache.insert(
+            key,
+            CachedMatches {
+                generation: self.generation,
+                all,
+                structure_generation: self.structure_generation,
+                non_empty: non_empty.clone(),
+            },
+        );
+        non_empty
+    }
+
+    pub fn get_or_create(&mut self, types: Vec<TypeId>, type_names: Vec<&'static str>) -> usize {
+        self.get_or_create_with_dynamic(types, type_names, Vec::new())
+    }
+
+    pub fn get_or_create_with_dynamic(
         &mut self,
-        mut types: Vec<TypeId>,
+        types: Vec<TypeId>,
         type_names: Vec<&'static str>,
+        mut dynamic: Vec<DynComponentId>,
     ) -> usize {
-        types.sort_unstable();
-
-        if let Some(&index) = self.type_map.get(&types) {
+        // Sparse types live in their sets, never in an archetype
+        let (types, type_names): (Vec<TypeId>, Vec<&'static str>) = types
+            .into_iter()
+            .zip(type_names)
+            .filter(|(type_id, _)| !self.sparse.contains(*type_id))
+            .unzip();
+
+        // Sort names alongside their types so type_names[i] describes types[i].
+        // Types are ordered by registration rather than `TypeId`, so column
+        // layout only depends on the order types were first seen.
+        let mut pairs: Vec<_> = types
+            .into_iter()
+            .zip(type_names)
+            .map(|(type_id, name)| (self.register_component(type_id), type_id, name))
+            .collect();
+        pairs.sort_unstable_by_key(|&(index, _, _)| index);
+        let (types, type_names): (Vec<TypeId>, Vec<&'static str>) = pairs
+            .into_iter()
+            .map(|(_, type_id, name)| (type_id, name))
+            .unzip();
+        dynamic.sort_unstable();
+
+        let key = (types, dynamic);
+        if let Some(&index) = self.type_map.get(&key) {
             return index;
         }
 
         let index = self.archetypes.len();
-        self.archetypes
-            .push(Archetype::new(index, types.clone(), type_names));
-        self.type_map.insert(types, index);
+        let mut archetype = Archetype::new(index, key.0.clone(), type_names, key.1.clone())
+            .with_allocator(self.allocator.clone());
+        archetype.set_tick(self.tick);
+        archetype.set_chunk_size(self.chunk_size);
+        self.archetypes.push(archetype);
+        self.type_map.insert(key, index);
+        self.generation += 1;
+        self.structure_generation += 1;
         index
     }
 
@@ -391,8 +1525,9 @@ impl ArchetypeMap {
 
         new_types.push(add);
         new_names.push(add_name);
+        let dynamic = from_arch.dynamic.clone();
 
-        let to = self.get_or_create(new_types, new_names);
+        let to = self.get_or_create_with_dynamic(new_types, new_names, dynamic);
         self.graph.add_edge(from, to, add, true);
         to
     }
@@ -406,13 +1541,15 @@ impl ArchetypeMap {
             new_types.remove(pos);
             new_names.remove(pos);
         }
+        let dynamic = from_arch.dynamic.clone();
 
-        let to = self.get_or_create(new_types, new_names);
+        let to = self.get_or_create_with_dynamic(new_types, new_names, dynamic);
         self.graph.add_edge(from, to, remove, false);
         to
     }
 }
 
+#[derive(Clone)]
 struct ArchetypeGraph {
     edges: HashMap<(usize, TypeId, bool), usize>,
 }
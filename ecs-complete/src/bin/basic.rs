@@ -31,14 +31,12 @@ fn main() {
     // Create a schedule
     let mut schedule = Schedule::new();
 
-    // Add a movement system with explicit type annotation
+    // Add a movement system; the closure's types come from the query
     schedule.add_update_system(
-        QuerySystem::<(&mut Position, &Velocity), _>::new(
-            |(pos, vel): (&mut Position, &Velocity)| {
-                pos.x += vel.x;
-                pos.y += vel.y;
-            },
-        )
+        QuerySystem::<(&mut Position, &Velocity), _>::new(|(pos, vel)| {
+            pos.x += vel.x;
+            pos.y += vel.y;
+        })
         .with_name("movement_system"),
     );
 
@@ -3,11 +3,11 @@ use crate::entity::Entity;
 use std::any::TypeId;
 
 pub enum Command {
-    Spawn(Box<dyn FnOnce(&mut crate::world::World) -> Entity + Send>),
+    Spawn(Box<dyn FnOnce(&mut crate::world::World) -> Entity + Send + Sync>),
     Despawn(Entity),
     Insert(
         Entity,
-        Box<dyn FnOnce(&mut crate::world::World, Entity) + Send>,
+        Box<dyn FnOnce(&mut crate::world::World, Entity) + Send + Sync>,
     ),
     Remove(Entity, TypeId),
 }
@@ -16,6 +16,9 @@ pub struct Commands {
     queue: Vec<Command>,
 }
 
+/// Buffer handed to `World::for_each_mut` callbacks; applied once iteration ends.
+pub type EntityCommandBuffer = Commands;
+
 impl Commands {
     pub fn new() -> Self {
         Self { queue: Vec::new() }
@@ -1,112 +1,199 @@
-use std::any::TypeId;
+use std::any::{Any, TypeId};
 
 pub trait Component: 'static + Send + Sync {}
 
 impl<T: 'static + Send + Sync> Component for T {}
 
-pub fn type_name<T: 'static>() -> &'static str {
-    std::any::type_name::<T>()
+/// Handle to a component registered at runtime with
+/// `World::register_dynamic_component`, known only by its layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DynComponentId(pub(crate) u32);
+
+/// Position of a Rust component type in a world's registration order, see
+/// `World::register_component`. Unlike `TypeId` it is the same across builds
+/// as long as types are registered in the same order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ComponentIndex(pub(crate) u32);
+
+impl ComponentIndex {
+    pub fn get(self) -> u32 {
+        self.0
+    }
 }
 
-pub trait Bundle: Send + Sync + 'static {
-    fn type_ids() -> Vec<TypeId>;
-    fn type_names() -> Vec<&'static str>;
-    fn init_archetype(archetype: &mut crate::archetype::Archetype);
-    fn insert_into(self, archetype: &mut crate::archetype::Archetype, index: usize);
+/// Identifies a column: either a Rust component type or a dynamic component
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ComponentId {
+    Static(TypeId),
+    Dynamic(DynComponentId),
 }
 
-// Implement Bundle for single components
-impl<T: Component> Bundle for (T,) {
-    fn type_ids() -> Vec<TypeId> {
-        vec![TypeId::of::<T>()]
+impl From<TypeId> for ComponentId {
+    fn from(type_id: TypeId) -> Self {
+        ComponentId::Static(type_id)
     }
+}
 
-    fn type_names() -> Vec<&'static str> {
-        vec![type_name::<T>()]
+impl From<DynComponentId> for ComponentId {
+    fn from(id: DynComponentId) -> Self {
+        ComponentId::Dynamic(id)
     }
+}
 
-    fn init_archetype(archetype: &mut crate::archetype::Archetype) {
-        archetype.add_column::<T>();
-    }
+/// Runtime tag distinguishing the logical components stored as `DynValue`,
+/// registered with `World::register_dyn_value_tag`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DynValueTag(pub(crate) u32);
 
-    fn insert_into(self, archetype: &mut crate::archetype::Archetype, index: usize) {
-        archetype.set_component(index, self.0);
-    }
+/// A boxed value with a runtime tag, letting one Rust component type back
+/// many script-defined components. Query by tag with `World::query_tagged`.
+pub struct DynValue {
+    tag: DynValueTag,
+    value: Box<dyn Any + Send + Sync>,
 }
 
-// Implement Bundle for tuples
-impl<T1: Component, T2: Component> Bundle for (T1, T2) {
-    fn type_ids() -> Vec<TypeId> {
-        vec![TypeId::of::<T1>(), TypeId::of::<T2>()]
+impl DynValue {
+    pub fn new<T: Any + Send + Sync>(tag: DynValueTag, value: T) -> Self {
+        Self {
+            tag,
+            value: Box::new(value),
+        }
     }
 
-    fn type_names() -> Vec<&'static str> {
-        vec![type_name::<T1>(), type_name::<T2>()]
+    pub fn tag(&self) -> DynValueTag {
+        self.tag
     }
 
-    fn init_archetype(archetype: &mut crate::archetype::Archetype) {
-        archetype.add_column::<T1>();
-        archetype.add_column::<T2>();
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.value.downcast_ref()
     }
 
-    fn insert_into(self, archetype: &mut crate::archetype::Archetype, index: usize) {
-        archetype.set_component(index, self.0);
-        archetype.set_component(index, self.1);
+    pub fn downcast_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.value.downcast_mut()
     }
 }
 
-impl<T1: Component, T2: Component, T3: Component> Bundle for (T1, T2, T3) {
-    fn type_ids() -> Vec<TypeId> {
-        vec![TypeId::of::<T1>(), TypeId::of::<T2>(), TypeId::of::<T3>()]
+pub fn type_name<T: 'static>() -> &'static str {
+    std::any::type_name::<T>()
+}
+
+pub trait Bundle: Send + Sync + 'static {
+    fn type_ids() -> Vec<TypeId>;
+    fn type_names() -> Vec<&'static str>;
+    fn init_archetype(archetype: &mut crate::archetype::Archetype);
+    /// Writes the components to row `index`, sparse ones to their sets
+    fn insert_into(self, archetype: &mut crate::archetype::ArchetypeViewMut<'_>, index: usize);
+}
+
+// Implement Bundle for tuples of 1-8 components
+macro_rules! impl_bundle {
+    ($($t:ident: $idx:tt),+) => {
+        impl<$($t: Component),+> Bundle for ($($t,)+) {
+            fn type_ids() -> Vec<TypeId> {
+                vec![$(TypeId::of::<$t>()),+]
+            }
+
+            fn type_names() -> Vec<&'static str> {
+                vec![$(type_name::<$t>()),+]
+            }
+
+            fn init_archetype(archetype: &mut crate::archetype::Archetype) {
+                $(archetype.add_column::<$t>();)+
+            }
+
+            fn insert_into(
+                self,
+                archetype: &mut crate::archetype::ArchetypeViewMut<'_>,
+                index: usize,
+            ) {
+                $(archetype.set_component(index, self.$idx);)+
+            }
+        }
+    };
+}
+
+impl_bundle!(T1: 0);
+impl_bundle!(T1: 0, T2: 1);
+impl_bundle!(T1: 0, T2: 1, T3: 2);
+impl_bundle!(T1: 0, T2: 1, T3: 2, T4: 3);
+impl_bundle!(T1: 0, T2: 1, T3: 2, T4: 3, T5: 4);
+impl_bundle!(T1: 0, T2: 1, T3: 2, T4: 3, T5: 4, T6: 5);
+impl_bundle!(T1: 0, T2: 1, T3: 2, T4: 3, T5: 4, T6: 5, T7: 6);
+impl_bundle!(T1: 0, T2: 1, T3: 2, T4: 3, T5: 4, T6: 5, T7: 6, T8: 7);
+
+type StagedValue = Box<dyn Any + Send + Sync>;
+
+struct StagedComponent {
+    type_id: TypeId,
+    type_name: &'static str,
+    value: StagedValue,
+    init: fn(&mut crate::archetype::Archetype),
+    write: fn(StagedValue, &mut crate::archetype::ArchetypeViewMut<'_>, usize),
+}
+
+/// Components staged one at a time for `World::spawn_dynamic_bundle`, for
+/// entities assembled at runtime or wider than the tuple `Bundle` impls
+#[derive(Default)]
+pub struct DynamicBundle {
+    components: Vec<StagedComponent>,
+}
+
+impl DynamicBundle {
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    fn type_names() -> Vec<&'static str> {
-        vec![type_name::<T1>(), type_name::<T2>(), type_name::<T3>()]
+    /// Stages `value`, replacing any `T` staged earlier
+    pub fn add<T: Component>(&mut self, value: T) -> &mut Self {
+        let staged = StagedComponent {
+            type_id: TypeId::of::<T>(),
+            type_name: type_name::<T>(),
+            value: Box::new(value),
+            init: |archetype| archetype.add_column::<T>(),
+            write: |value, archetype, index| {
+                archetype.set_component::<T>(index, *value.downcast().unwrap());
+            },
+        };
+        match self
+            .components
+            .iter_mut()
+            .find(|c| c.type_id == staged.type_id)
+        {
+            Some(existing) => *existing = staged,
+            None => self.components.push(staged),
+        }
+        self
     }
 
-    fn init_archetype(archetype: &mut crate::archetype::Archetype) {
-        archetype.add_column::<T1>();
-        archetype.add_column::<T2>();
-        archetype.add_column::<T3>();
+    pub fn len(&self) -> usize {
+        self.components.len()
     }
 
-    fn insert_into(self, archetype: &mut crate::archetype::Archetype, index: usize) {
-        archetype.set_component(index, self.0);
-        archetype.set_component(index, self.1);
-        archetype.set_component(index, self.2);
+    pub fn is_empty(&self) -> bool {
+        self.components.is_empty()
     }
-}
 
-impl<T1: Component, T2: Component, T3: Component, T4: Component> Bundle for (T1, T2, T3, T4) {
-    fn type_ids() -> Vec<TypeId> {
-        vec![
-            TypeId::of::<T1>(),
-            TypeId::of::<T2>(),
-            TypeId::of::<T3>(),
-            TypeId::of::<T4>(),
-        ]
+    pub(crate) fn type_ids(&self) -> Vec<TypeId> {
+        self.components.iter().map(|c| c.type_id).collect()
     }
 
-    fn type_names() -> Vec<&'static str> {
-        vec![
-            type_name::<T1>(),
-            type_name::<T2>(),
-            type_name::<T3>(),
-            type_name::<T4>(),
-        ]
+    pub(crate) fn type_names(&self) -> Vec<&'static str> {
+        self.components.iter().map(|c| c.type_name).collect()
     }
 
-    fn init_archetype(archetype: &mut crate::archetype::Archetype) {
-        archetype.add_column::<T1>();
-        archetype.add_column::<T2>();
-        archetype.add_column::<T3>();
-        archetype.add_column::<T4>();
+    pub(crate) fn init_archetype(&self, archetype: &mut crate::archetype::Archetype) {
+        for component in &self.components {
+            (component.init)(archetype);
+        }
     }
 
-    fn insert_into(self, archetype: &mut crate::archetype::Archetype, index: usize) {
-        archetype.set_component(index, self.0);
-        archetype.set_component(index, self.1);
-        archetype.set_component(index, self.2);
-        archetype.set_component(index, self.3);
+    pub(crate) fn insert_into(
+        self,
+        archetype: &mut crate::archetype::ArchetypeViewMut<'_>,
+        index: usize,
+    ) {
+        for component in self.components {
+            (component.write)(component.value, archetype, index);
+        }
     }
 }
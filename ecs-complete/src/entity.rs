@@ -1,9 +1,25 @@
-use slotmap::new_key_type;
+use slotmap::{Key, KeyData, new_key_type};
+use std::any::TypeId;
 
 new_key_type! {
     pub struct Entity;
 }
 
+impl Entity {
+    /// Packs the entity as `version << 32 | index`, slotmap's FFI layout and
+    /// the same one `ecs-basic`'s `Entity::to_bits` uses for its generation
+    /// and id. Versions of live entities are always odd.
+    pub fn to_bits(self) -> u64 {
+        self.data().as_ffi()
+    }
+
+    /// Inverse of `to_bits`. An even version is rounded up to the next odd
+    /// one, so bits from `ecs-basic` map generation `g` to version `g | 1`.
+    pub fn from_bits(bits: u64) -> Self {
+        KeyData::from_ffi(bits).into()
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct EntityMeta {
     pub generation: u32,
@@ -16,4 +32,169 @@ pub struct EntityInfo {
     pub entity: Entity,
     pub archetype_id: usize,
     pub component_types: Vec<&'static str>,
+    /// Same order as `component_types`
+    pub component_type_ids: Vec<TypeId>,
+}
+
+/// Generational storage keyed by `Entity`. Behaves like a `SlotMap` but can
+/// also place a value at a caller-chosen key, which is needed to mirror ids
+/// assigned elsewhere.
+#[derive(Clone)]
+pub(crate) struct EntityMap<V> {
+    slots: Vec<EntitySlot<V>>,
+    // May hold slots since taken by `insert_at`, which `insert` skips
+    free: Vec<u32>,
+    len: usize,
+}
+
+/// Furthest `insert_at` may place an entity past the last slot, so a bogus
+/// id can't make it allocate billions of padding slots
+const MAX_INSERT_GAP: usize = 1 << 16;
+
+#[derive(Clone)]
+struct EntitySlot<V> {
+    // Odd while occupied, matching slotmap's key versions
+    version: u32,
+    value: Option<V>,
+}
+
+fn make_key(idx: u32, version: u32) -> Entity {
+    KeyData::from_ffi((u64::from(version) << 32) | u64::from(idx)).into()
+}
+
+fn split_key(entity: Entity) -> (u32, u32) {
+    let bits = entity.data().as_ffi();
+    (bits as u32, (bits >> 32) as u32)
+}
+
+/// Slot index of an entity, stable for the entity's lifetime
+pub(crate) fn entity_index(entity: Entity) -> usize {
+    split_key(entity).0 as usize
+}
+
+impl<V> EntityMap<V> {
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Bytes allocated for slots and the free list
+    pub fn allocated_bytes(&self) -> usize {
+        self.slots.capacity() * std::mem::size_of::<EntitySlot<V>>()
+            + self.free.capacity() * std::mem::size_of::<u32>()
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        let needed = additional.saturating_sub(self.free.len());
+        self.slots.reserve(needed);
+    }
+
+    pub fn insert(&mut self, value: V) -> Entity {
+        self.len += 1;
+
+        while let Some(idx) = self.free.pop() {
+            let slot = &mut self.slots[idx as usize];
+            if slot.value.is_some() {
+                continue;
+            }
+            slot.version = slot.version.wrapping_add(1) | 1;
+            slot.value = Some(value);
+            return make_key(idx, slot.version);
+        }
+
+        let idx = self.slots.len() as u32;
+        self.slots.push(EntitySlot {
+            version: 1,
+            value: Some(value),
+        });
+        make_key(idx, 1)
+    }
+
+    /// Inserts `value` at exactly `entity`, returning `false` if that slot is
+    /// already occupied (by this or any other generation), if `entity` is
+    /// older than the slot's current generation, or if it lies more than
+    /// `MAX_INSERT_GAP` slots past the end
+    pub fn insert_at(&mut self, entity: Entity, value: V) -> bool {
+        if entity.is_null() {
+            return false;
+        }
+
+        let (idx, version) = split_key(entity);
+        if idx as usize >= self.slots.len() + MAX_INSERT_GAP {
+            return false;
+        }
+        while self.slots.len() <= idx as usize {
+            let new_idx = self.slots.len() as u32;
+            self.slots.push(EntitySlot {
+                version: 0,
+                value: None,
+            });
+            if new_idx != idx {
+                self.free.push(new_idx);
+            }
+        }
+
+        let slot = &mut self.slots[idx as usize];
+        // Reusing an older version would make stale handles valid again.
+        // The slot stays on the free list; `insert` skips it while occupied.
+        if slot.value.is_some() || (version | 1) < slot.version {
+            return false;
+        }
+
+        slot.version = version | 1;
+        slot.value = Some(value);
+        self.len += 1;
+        true
+    }
+
+    pub fn remove(&mut self, entity: Entity) -> Option<V> {
+        let (idx, version) = split_key(entity);
+        let slot = self.slots.get_mut(idx as usize)?;
+        if slot.version != version {
+            return None;
+        }
+
+        let value = slot.value.take()?;
+        slot.version = slot.version.wrapping_add(1);
+        self.free.push(idx);
+        self.len -= 1;
+        Some(value)
+    }
+
+    pub fn get(&self, entity: Entity) -> Option<&V> {
+        let (idx, version) = split_key(entity);
+        let slot = self.slots.get(idx as usize)?;
+        if slot.version != version {
+            return None;
+        }
+        slot.value.as_ref()
+    }
+
+    pub fn get_mut(&mut self, entity: Entity) -> Option<&mut V> {
+        let (idx, version) = split_key(entity);
+        let slot = self.slots.get_mut(idx as usize)?;
+        if slot.version != version {
+            return None;
+        }
+        slot.value.as_mut()
+    }
+
+    pub fn contains_key(&self, entity: Entity) -> bool {
+        self.get(entity).is_some()
+    }
+
+    /// Live entries in slot order
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &V)> {
+        self.slots.iter().enumerate().filter_map(|(idx, slot)| {
+            let value = slot.value.as_ref()?;
+            Some((make_key(idx as u32, slot.version), value))
+        })
+    }
 }
@@ -1,11 +1,13 @@
 use crate::entity::Entity;
-use std::any::TypeId;
 use std::fmt;
 
 #[derive(Debug, Clone)]
 pub enum EcsError {
     EntityNotFound(Entity),
-    ComponentNotFound(TypeId),
+    ComponentNotFound {
+        entity: Entity,
+        type_name: &'static str,
+    },
     ArchetypeNotFound(usize),
     InvalidOperation(String),
 }
@@ -14,7 +16,13 @@ impl fmt::Display for EcsError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             EcsError::EntityNotFound(e) => write!(f, "Entity {:?} not found", e),
-            EcsError::ComponentNotFound(t) => write!(f, "Component {:?} not found", t),
+            EcsError::ComponentNotFound { entity, type_name } => {
+                write!(
+                    f,
+                    "Component {} not found on entity {:?}",
+                    type_name, entity
+                )
+            }
             EcsError::ArchetypeNotFound(a) => write!(f, "Archetype {} not found", a),
             EcsError::InvalidOperation(s) => write!(f, "Invalid operation: {}", s),
         }
@@ -1,34 +1,141 @@
+use std::any::Any;
+use std::collections::VecDeque;
 use std::marker::PhantomData;
 
+/// What `Events::send` does when the buffer is already at its limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Evict the oldest stored event to make room
+    DropOldest,
+    /// Discard the event being sent
+    DropNewest,
+    Panic,
+}
+
+#[derive(Clone)]
 pub struct Events<T> {
-    events: Vec<T>,
+    events: VecDeque<T>,
+    // Priority of each stored event; the current events (from `start_index`)
+    // are kept in descending priority, oldest first among equals
+    priorities: VecDeque<i32>,
+    // Send order of each stored event, so `DropOldest` can find the oldest
+    // one whatever its priority
+    seqs: VecDeque<u64>,
+    next_seq: u64,
     start_index: usize,
+    limit: Option<(usize, OverflowPolicy)>,
 }
 
 impl<T> Events<T> {
     pub fn new() -> Self {
         Self {
-            events: Vec::new(),
+            events: VecDeque::new(),
+            priorities: VecDeque::new(),
+            seqs: VecDeque::new(),
+            next_seq: 0,
             start_index: 0,
+            limit: None,
+        }
+    }
+
+    /// Buffer that stores at most `max` events, applying `policy` on overflow
+    pub fn with_capacity_policy(max: usize, policy: OverflowPolicy) -> Self {
+        Self {
+            events: VecDeque::with_capacity(max),
+            priorities: VecDeque::with_capacity(max),
+            seqs: VecDeque::with_capacity(max),
+            next_seq: 0,
+            start_index: 0,
+            limit: Some((max, policy)),
         }
     }
 
     pub fn send(&mut self, event: T) {
-        self.events.push(event);
+        self.send_prioritized(event, 0);
+    }
+
+    /// Sends `event` ahead of every current event with a lower priority.
+    /// `send` uses priority 0. `DropOldest` evicts the event sent first,
+    /// whatever its priority.
+    pub fn send_prioritized(&mut self, event: T, priority: i32) {
+        if let Some((max, policy)) = self.limit
+            && self.events.len() >= max
+        {
+            match policy {
+                OverflowPolicy::DropOldest => {
+                    if !self.evict_oldest() {
+                        return;
+                    }
+                }
+                OverflowPolicy::DropNewest => return,
+                OverflowPolicy::Panic => panic!("Event buffer exceeded its limit of {}", max),
+            }
+        }
+        let current = &self.priorities.make_contiguous()[self.start_index..];
+        let position = self.start_index + current.partition_point(|&p| p >= priority);
+        self.events.insert(position, event);
+        self.priorities.insert(position, priority);
+        self.seqs.insert(position, self.next_seq);
+        self.next_seq += 1;
+    }
+
+    // Old events all predate the current ones and sit in send order at the
+    // front; current events are in priority order, so search them by seq
+    fn evict_oldest(&mut self) -> bool {
+        let index = if self.start_index > 0 {
+            self.start_index -= 1;
+            0
+        } else {
+            match (0..self.seqs.len()).min_by_key(|&i| self.seqs[i]) {
+                Some(index) => index,
+                None => return false,
+            }
+        };
+        self.events.remove(index);
+        self.priorities.remove(index);
+        self.seqs.remove(index);
+        true
     }
 
     pub fn clear(&mut self) {
         self.events.clear();
+        self.priorities.clear();
+        self.seqs.clear();
         self.start_index = 0;
     }
 
     pub fn update(&mut self) {
-        // Mark current events as "old" - they'll be available until next update
+        // Events that were already old are dropped; current events become old
+        // and stay stored (for `drain_all`) until the next update
+        self.events.drain(..self.start_index);
+        self.priorities.drain(..self.start_index);
+        self.seqs.drain(..self.start_index);
         self.start_index = self.events.len();
     }
 
     pub fn iter(&self) -> impl Iterator<Item = &T> {
-        self.events[self.start_index..].iter()
+        self.events.range(self.start_index..)
+    }
+
+    /// Removes the current events and yields them by value
+    pub fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.priorities.truncate(self.start_index);
+        self.seqs.truncate(self.start_index);
+        self.events.drain(self.start_index..)
+    }
+
+    /// Removes both the current events and those made old by the last
+    /// `update`, oldest first
+    pub fn drain_all(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.start_index = 0;
+        self.priorities.clear();
+        self.seqs.clear();
+        self.events.drain(..)
+    }
+
+    /// Like `drain`, but appends into a caller-owned buffer so it can be reused
+    pub fn drain_into(&mut self, buffer: &mut Vec<T>) {
+        buffer.extend(self.drain());
     }
 
     pub fn len(&self) -> usize {
@@ -46,6 +153,51 @@ impl<T> Default for Events<T> {
     }
 }
 
+/// Type-erased handle to a world-owned `Events<T>`
+pub(crate) trait EventStorage: Send + Sync {
+    fn update(&mut self);
+    fn type_name(&self) -> &'static str;
+    /// Whether no event is stored, current or old
+    fn is_drained(&self) -> bool;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+/// Copies a type-erased `Events<T>`, see `World::register_clonable_event`
+pub(crate) type CloneEventsFn = fn(&dyn EventStorage) -> Box<dyn EventStorage>;
+
+pub(crate) fn clone_events<T: Clone + Send + Sync + 'static>(
+    events: &dyn EventStorage,
+) -> Box<dyn EventStorage> {
+    let events = events
+        .as_any()
+        .downcast_ref::<Events<T>>()
+        .expect("Event storage has the wrong type");
+    Box::new(events.clone())
+}
+
+impl<T: Send + Sync + 'static> EventStorage for Events<T> {
+    fn update(&mut self) {
+        Events::update(self);
+    }
+
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<T>()
+    }
+
+    fn is_drained(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
 pub struct EventReader<'a, T> {
     events: &'a Events<T>,
     last_read: usize,
@@ -62,7 +214,7 @@ impl<'a, T> EventReader<'a, T> {
     pub fn iter(&mut self) -> impl Iterator<Item = &'a T> {
         let start = self.last_read;
         self.last_read = self.events.events.len();
-        self.events.events[start..self.last_read].iter()
+        self.events.events.range(start..self.last_read)
     }
 
     pub fn len(&self) -> usize {
@@ -90,4 +242,8 @@ impl<'a, T> EventWriter<'a, T> {
     pub fn send(&mut self, event: T) {
         self.events.send(event);
     }
+
+    pub fn send_prioritized(&mut self, event: T, priority: i32) {
+        self.events.send_prioritized(event, priority);
+    }
 }
@@ -10,6 +10,17 @@ pub struct Children(pub Vec<Entity>);
 
 // Component is automatically implemented via the blanket impl
 
+/// What `World::despawn` does with a despawned entity's `Children` when
+/// hierarchy cleanup is on, see `World::set_hierarchy_cleanup`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChildPolicy {
+    /// Remove the children's `Parent` and keep them alive
+    #[default]
+    Orphan,
+    /// Despawn the whole subtree
+    Despawn,
+}
+
 impl Children {
     pub fn new() -> Self {
         Self(Vec::new())
@@ -1,3 +1,7 @@
+// Lets `QueryData` derive output, which names `::ecs_complete`, compile here too
+extern crate self as ecs_complete;
+
+pub mod app;
 pub mod archetype;
 pub mod command;
 pub mod component;
@@ -6,26 +10,50 @@ pub mod entity;
 pub mod error;
 pub mod events;
 pub mod hierarchy;
+pub mod prefab;
+pub mod prelude;
 pub mod query;
+pub mod registry;
 pub mod resource;
+pub mod sparse_set;
+pub mod state;
 pub mod system;
+pub mod trait_query;
 pub mod world;
 
-pub use command::Commands;
-pub use component::{Bundle, Component};
+pub use app::App;
+pub use command::{Commands, EntityCommandBuffer};
+pub use component::{
+    Bundle, Component, ComponentId, ComponentIndex, DynComponentId, DynValue, DynValueTag,
+    DynamicBundle,
+};
 pub use ecs_bench::*;
+pub use ecs_complete_derive::QueryData;
 pub use entity::Entity;
 pub use error::{EcsError, Result};
-pub use hierarchy::{Children, Parent};
-pub use query::{Changed, Query, With, Without};
-pub use resource::{Res, ResMut, Resources};
-pub use system::{IntoSystem, ParallelSchedule, Schedule, Stage, System};
-pub use world::World;
+pub use hierarchy::{ChildPolicy, Children, Parent};
+pub use prefab::Prefab;
+#[cfg(feature = "change_detection")]
+pub use query::{Added, Changed, ChangedBy, Mut};
+pub use query::{Disabled, Has, Query, QueryFilter, ReadOnlyQuery, RowFilter, With, Without};
+pub use registry::ComponentRegistry;
+pub use resource::{Res, ResMut, ResourceCheckpoint, Resources};
+pub use sparse_set::SparseSet;
+pub use state::State;
+pub use system::{
+    BatchDiagnostic, IntoSystem, ParallelSchedule, ParamSystem, PipeSystem, QuerySystem, Schedule,
+    ScheduleBuilder, Stage, System, SystemId, SystemParam, Tick,
+};
+pub use trait_query::TraitRegistry;
+pub use world::{
+    ArchetypeInfo, ArchetypeWriter, ComponentHandle, EntityMut, EntityRef, MemoryStats, QueryWorld,
+    World, WorldLike,
+};
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::events::{EventReader, Events};
+    use crate::events::{EventReader, Events, OverflowPolicy};
 
     #[derive(Debug, Clone, Copy, PartialEq)]
     struct Position {
@@ -82,6 +110,44 @@ mod tests {
         assert_eq!(count, 1);
     }
 
+    #[test]
+    fn test_despawn_drops_components() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct Handle;
+
+        impl Drop for Handle {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let mut world = World::new();
+        let a = world.spawn((Handle, Position { x: 0.0, y: 0.0 }));
+        let b = world.spawn((Handle, Position { x: 1.0, y: 0.0 }));
+        let c = world.spawn((Handle, Position { x: 2.0, y: 0.0 }));
+
+        assert!(world.despawn(a));
+        assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+
+        // Moving between archetypes must not drop the moved component
+        world.insert(b, Velocity { x: 0.0, y: 0.0 }).unwrap();
+        world.remove::<Velocity>(b).unwrap();
+        assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+
+        assert!(world.despawn(b));
+        assert!(!world.despawn(b));
+        assert_eq!(DROPS.load(Ordering::SeqCst), 2);
+
+        assert_eq!(world.despawn_batch(&[c]), 1);
+        assert_eq!(DROPS.load(Ordering::SeqCst), 3);
+
+        drop(world);
+        assert_eq!(DROPS.load(Ordering::SeqCst), 3);
+    }
+
     #[test]
     fn test_get_component() {
         let mut world = World::new();
@@ -113,6 +179,20 @@ mod tests {
         assert_eq!(vel.x, 1.0);
     }
 
+    #[test]
+    fn test_insert_if_absent() {
+        let mut world = World::new();
+
+        let entity = world.spawn((Position { x: 0.0, y: 0.0 },));
+
+        assert!(world.insert_if_absent(entity, Health(100.0)).unwrap());
+        assert!(!world.insert_if_absent(entity, Health(1.0)).unwrap());
+        assert_eq!(world.get::<Health>(entity), Some(&Health(100.0)));
+
+        world.despawn(entity);
+        assert!(world.insert_if_absent(entity, Health(1.0)).is_err());
+    }
+
     #[test]
     fn test_remove_component() {
         let mut world = World::new();
@@ -170,6 +250,25 @@ mod tests {
         assert!(events.is_empty());
     }
 
+    #[test]
+    fn test_events_drain() {
+        let mut events = Events::<String>::new();
+
+        events.send("spawn".to_string());
+        events.send("despawn".to_string());
+
+        let drained: Vec<String> = events.drain().collect();
+        assert_eq!(drained, vec!["spawn".to_string(), "despawn".to_string()]);
+        assert!(events.is_empty());
+
+        events.send("move".to_string());
+        let mut buffer = Vec::with_capacity(4);
+        events.drain_into(&mut buffer);
+        events.drain_into(&mut buffer);
+        assert_eq!(buffer, vec!["move".to_string()]);
+        assert!(events.is_empty());
+    }
+
     #[test]
     fn test_event_reader() {
         let mut events = Events::<i32>::new();
@@ -291,6 +390,38 @@ mod tests {
     }
 
     #[test]
+    fn test_schedule_builder() {
+        struct Log(Vec<&'static str>);
+
+        fn record(world: &mut World, name: &'static str) {
+            world.get_resource_mut::<Log>().unwrap().0.push(name);
+        }
+
+        let mut world = World::new();
+        world.insert_resource(Log(Vec::new()));
+
+        let mut schedule = Schedule::builder()
+            .system(
+                Stage::PostUpdate,
+                (|world: &mut World| record(world, "late")).into_system(),
+            )
+            .system(
+                Stage::Update,
+                (|world: &mut World| record(world, "first")).into_system(),
+            )
+            .system(
+                Stage::Update,
+                (|world: &mut World| record(world, "second")).into_system(),
+            )
+            .build();
+        schedule.run(&mut world);
+
+        let log = world.remove_resource::<Log>().unwrap().0;
+        assert_eq!(log, vec!["first", "second", "late"]);
+    }
+
+    #[test]
+    #[cfg(feature = "change_detection")]
     fn test_change_detection() {
         let mut world = World::new();
 
@@ -306,7 +437,7 @@ mod tests {
         // Check if it was changed
         let location = world.entity_meta(entity).unwrap();
         let archetype = world.archetypes.get(location.archetype).unwrap();
-        assert!(archetype.component_changed::<Position>(location.index, 0));
+        assert!(archetype.component_changed::<Position>(location.index, 1));
     }
 
     #[test]
@@ -320,6 +451,27 @@ mod tests {
         assert_eq!(info.component_types.len(), 2);
     }
 
+    #[test]
+    fn test_entity_info_type_ids() {
+        use std::any::TypeId;
+
+        let mut world = World::new();
+        let entity = world.spawn((Position { x: 0.0, y: 0.0 }, Velocity { x: 1.0, y: 1.0 }));
+
+        let info = world.entity_info(entity).unwrap();
+        assert_eq!(info.component_type_ids.len(), 2);
+        assert!(info.component_type_ids.contains(&TypeId::of::<Position>()));
+        assert!(info.component_type_ids.contains(&TypeId::of::<Velocity>()));
+        for (name, type_id) in info.component_types.iter().zip(&info.component_type_ids) {
+            let expected = if name.ends_with("Position") {
+                TypeId::of::<Position>()
+            } else {
+                TypeId::of::<Velocity>()
+            };
+            assert_eq!(*type_id, expected);
+        }
+    }
+
     #[test]
     fn test_error_handling() {
         let mut world = World::new();
@@ -395,4 +547,4120 @@ mod tests {
             assert!(world.get::<Velocity>(entity).is_some());
         }
     }
+
+    #[test]
+    fn test_world_debug() {
+        let mut world = World::new();
+
+        world.spawn((Position { x: 0.0, y: 0.0 }, Velocity { x: 1.0, y: 1.0 }));
+        world.spawn((Health(100.0),));
+
+        let output = format!("{:?}", world);
+        assert!(output.contains("entity_count: 2"));
+        assert!(output.contains("archetype_count: 2"));
+        assert!(output.contains(std::any::type_name::<Position>()));
+        assert!(output.contains(std::any::type_name::<Velocity>()));
+        assert!(output.contains(std::any::type_name::<Health>()));
+    }
+
+    #[test]
+    fn test_trait_query() {
+        trait Drawable {
+            fn draw(&mut self) -> &'static str;
+        }
+
+        struct Sprite {
+            draws: u32,
+        }
+
+        struct Mesh {
+            draws: u32,
+        }
+
+        impl Drawable for Sprite {
+            fn draw(&mut self) -> &'static str {
+                self.draws += 1;
+                "sprite"
+            }
+        }
+
+        impl Drawable for Mesh {
+            fn draw(&mut self) -> &'static str {
+                self.draws += 1;
+                "mesh"
+            }
+        }
+
+        let mut world = World::new();
+        world.register_trait::<dyn Drawable, Sprite>(|sprite| sprite);
+        world.register_trait::<dyn Drawable, Mesh>(|mesh| mesh);
+
+        let sprite = world.spawn((Sprite { draws: 0 }, Position { x: 0.0, y: 0.0 }));
+        let mesh = world.spawn((Mesh { draws: 0 },));
+        world.spawn((Position { x: 1.0, y: 1.0 },));
+
+        let mut drawn: Vec<_> = world
+            .query_trait::<dyn Drawable>()
+            .map(|drawable| drawable.draw())
+            .collect();
+        drawn.sort();
+        assert_eq!(drawn, vec!["mesh", "sprite"]);
+
+        assert_eq!(world.get::<Sprite>(sprite).unwrap().draws, 1);
+        assert_eq!(world.get::<Mesh>(mesh).unwrap().draws, 1);
+
+        // Sparse components count, disabled entities don't
+        world.register_sparse::<Mesh>();
+        let sparse = world.spawn((Position { x: 2.0, y: 2.0 }, Mesh { draws: 0 }));
+        world.insert(sprite, Disabled).unwrap();
+        let mut drawn: Vec<_> = world
+            .query_trait::<dyn Drawable>()
+            .map(|drawable| drawable.draw())
+            .collect();
+        drawn.sort();
+        assert_eq!(drawn, vec!["mesh", "mesh"]);
+        assert_eq!(world.get::<Mesh>(sparse).unwrap().draws, 1);
+        assert_eq!(world.get::<Sprite>(sprite).unwrap().draws, 1);
+    }
+
+    #[test]
+    #[cfg(feature = "change_detection")]
+    fn test_query_trait_marks_only_reached_components() {
+        trait Named {
+            fn name(&mut self) -> &'static str;
+        }
+
+        impl Named for Position {
+            fn name(&mut self) -> &'static str {
+                "position"
+            }
+        }
+
+        let mut world = World::new();
+        world.register_trait::<dyn Named, Position>(|pos| pos);
+        world.spawn((Position { x: 0.0, y: 0.0 },));
+        world.spawn((Position { x: 1.0, y: 0.0 },));
+        world.tick();
+
+        assert_eq!(
+            world.query_trait::<dyn Named>().next().unwrap().name(),
+            "position"
+        );
+        assert_eq!(
+            world
+                .query_filtered::<&Position, (Changed<Position>,)>()
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_component_not_found_error() {
+        let mut world = World::new();
+
+        let entity = world.spawn((Position { x: 0.0, y: 0.0 },));
+
+        let err = world.try_get_mut::<Velocity>(entity).unwrap_err();
+        assert!(matches!(
+            err,
+            EcsError::ComponentNotFound { entity: e, .. } if e == entity
+        ));
+        assert!(err.to_string().contains(std::any::type_name::<Velocity>()));
+
+        let err = world.remove::<Health>(entity).unwrap_err();
+        assert!(err.to_string().contains(std::any::type_name::<Health>()));
+    }
+
+    #[test]
+    fn test_despawn_batch() {
+        let mut world = World::new();
+
+        let mut entities = Vec::new();
+        for i in 0..20 {
+            let pos = Position {
+                x: i as f32,
+                y: 0.0,
+            };
+            let entity = if i % 2 == 0 {
+                world.spawn((pos,))
+            } else {
+                world.spawn((pos, Velocity { x: 1.0, y: 1.0 }))
+            };
+            entities.push(entity);
+        }
+
+        // Mixed order, spanning both archetypes, with a duplicate
+        let doomed = [
+            entities[3],
+            entities[18],
+            entities[0],
+            entities[19],
+            entities[7],
+            entities[10],
+            entities[3],
+        ];
+        assert_eq!(world.despawn_batch(&doomed), 6);
+
+        for (i, &entity) in entities.iter().enumerate() {
+            if doomed.contains(&entity) {
+                assert!(!world.is_alive(entity));
+            } else {
+                assert!(world.is_alive(entity));
+                assert_eq!(world.get::<Position>(entity).unwrap().x, i as f32);
+            }
+        }
+
+        assert_eq!(world.query::<&Position>().count(), 14);
+        assert_eq!(world.query::<(&Position, &Velocity)>().count(), 7);
+    }
+
+    #[test]
+    #[cfg(feature = "change_detection")]
+    fn test_query_filtered_changed_with() {
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        struct Visible;
+
+        let mut world = World::new();
+
+        let visible_changed = world.spawn((Position { x: 1.0, y: 0.0 }, Visible));
+        let visible_unchanged = world.spawn((Position { x: 2.0, y: 0.0 }, Visible));
+        let invisible_changed = world.spawn((Position { x: 3.0, y: 0.0 },));
+
+        world.tick();
+
+        world.get_mut::<Position>(visible_changed).unwrap().x = 10.0;
+        world.get_mut::<Position>(invisible_changed).unwrap().x = 30.0;
+
+        let results: Vec<f32> = world
+            .query_filtered::<&Position, (Changed<Position>, With<Visible>)>()
+            .map(|pos| pos.x)
+            .collect();
+        assert_eq!(results, vec![10.0]);
+
+        assert!(world.is_alive(visible_unchanged));
+        assert_eq!(
+            world
+                .query_filtered::<&Position, (With<Visible>,)>()
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_get_or_spawn() {
+        use slotmap::{Key, KeyData};
+
+        let mut server = World::new();
+        server.spawn((Health(1.0),));
+        let remote = server.spawn((Position { x: 4.0, y: 2.0 },));
+
+        let mut client = World::new();
+        let id = Entity::from(KeyData::from_ffi(remote.data().as_ffi()));
+
+        let mut entity = client.get_or_spawn(id).unwrap();
+        assert_eq!(entity.id(), remote);
+        assert!(entity.get::<Position>().is_none());
+        entity.insert(Position { x: 4.0, y: 2.0 }).unwrap();
+
+        assert!(client.is_alive(remote));
+        assert_eq!(client.get::<Position>(remote).unwrap().x, 4.0);
+
+        // Fetching again returns the existing entity rather than a new one
+        let entity = client.get_or_spawn(remote).unwrap();
+        assert_eq!(entity.get::<Position>().unwrap().y, 2.0);
+
+        // Local spawns don't collide with the mirrored id
+        let local = client.spawn((Health(5.0),));
+        assert_ne!(local, remote);
+        assert!(client.is_alive(local));
+        assert_eq!(client.query::<&Position>().count(), 1);
+    }
+
+    #[test]
+    fn test_mixed_size_components() {
+        let mut world = World::new();
+
+        let entity = world.spawn((Health(7.0), Position { x: 1.0, y: 2.0 }, Player));
+        world.spawn((Position { x: 3.0, y: 4.0 }, Health(9.0), Player));
+        world.insert(entity, Velocity { x: 5.0, y: 6.0 }).unwrap();
+
+        assert_eq!(world.get::<Health>(entity), Some(&Health(7.0)));
+        assert_eq!(
+            world.get::<Position>(entity),
+            Some(&Position { x: 1.0, y: 2.0 })
+        );
+        assert_eq!(
+            world.get::<Velocity>(entity),
+            Some(&Velocity { x: 5.0, y: 6.0 })
+        );
+
+        let mut healths: Vec<f32> = world.query::<&Health>().map(|h| h.0).collect();
+        healths.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(healths, vec![7.0, 9.0]);
+    }
+
+    #[test]
+    fn test_sparse_components() {
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        struct Selected(u32);
+
+        let mut world = World::new();
+        world.register_sparse::<Selected>();
+
+        let entities: Vec<_> = (0..100)
+            .map(|i| {
+                world.spawn((
+                    Position {
+                        x: i as f32,
+                        y: 0.0,
+                    },
+                    Velocity { x: 0.0, y: 0.0 },
+                ))
+            })
+            .collect();
+        let archetype = world.entity_meta(entities[0]).unwrap().archetype;
+        let archetype_count = world.archetypes.iter().count();
+
+        for (i, &entity) in entities.iter().enumerate() {
+            if i % 2 == 0 {
+                world.insert(entity, Selected(i as u32)).unwrap();
+            }
+        }
+        for &entity in entities.iter().step_by(4) {
+            assert_eq!(world.remove::<Selected>(entity).unwrap().0 % 4, 0);
+        }
+
+        // Toggling the flag never moved anything
+        assert_eq!(world.archetypes.iter().count(), archetype_count);
+        for &entity in &entities {
+            assert_eq!(world.entity_meta(entity).unwrap().archetype, archetype);
+        }
+
+        assert_eq!(world.query_sparse::<Selected>().count(), 25);
+        assert!(world.get::<Selected>(entities[2]).is_some());
+        assert!(world.get::<Selected>(entities[4]).is_none());
+        assert!(world.remove::<Selected>(entities[1]).is_err());
+
+        let mut flagged: Vec<f32> = world
+            .query_with_sparse::<&Position, Selected>()
+            .map(|(pos, selected)| {
+                assert_eq!(pos.x as u32, selected.0);
+                pos.x
+            })
+            .collect();
+        flagged.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let expected: Vec<f32> = (0..100).filter(|i| i % 4 == 2).map(|i| i as f32).collect();
+        assert_eq!(flagged, expected);
+
+        world.disable(entities[6]).unwrap();
+        assert_eq!(
+            world.query_with_sparse::<&Position, Selected>().count(),
+            expected.len() - 1
+        );
+
+        world.despawn(entities[2]);
+        assert_eq!(world.query_sparse::<Selected>().count(), 24);
+    }
+
+    #[test]
+    fn test_query_five_tuple() {
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        struct Sprite(u8);
+
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        struct Collider {
+            radius: f64,
+        }
+
+        let mut world = World::new();
+
+        for i in 0..3 {
+            world.spawn((
+                Position {
+                    x: i as f32,
+                    y: 0.0,
+                },
+                Velocity { x: 1.0, y: 2.0 },
+                Health(100.0),
+                Sprite(i as u8),
+                Collider {
+                    radius: i as f64 * 0.5,
+                },
+            ));
+        }
+        world.spawn((Position { x: 9.0, y: 9.0 }, Health(1.0)));
+
+        let mut count = 0;
+        for (pos, vel, health, sprite, collider) in
+            world.query::<(&mut Position, &Velocity, &Health, &Sprite, &Collider)>()
+        {
+            pos.x += vel.x;
+            assert_eq!(health.0, 100.0);
+            assert_eq!(collider.radius, sprite.0 as f64 * 0.5);
+            count += 1;
+        }
+        assert_eq!(count, 3);
+
+        type Access = (
+            &'static mut Position,
+            &'static Velocity,
+            &'static Health,
+            &'static Sprite,
+            &'static Collider,
+        );
+        assert_eq!(
+            <Access as Query>::write_types(),
+            vec![std::any::TypeId::of::<Position>()]
+        );
+        assert_eq!(<Access as Query>::read_types().len(), 4);
+    }
+
+    #[test]
+    fn test_archetype_change_hook() {
+        struct Moves(Vec<(Entity, usize, usize)>);
+
+        let mut world = World::new();
+        world.insert_resource(Moves(Vec::new()));
+        world.register_on_archetype_change(|world, entity, from, to| {
+            world
+                .get_resource_mut::<Moves>()
+                .unwrap()
+                .0
+                .push((entity, from, to));
+        });
+
+        let entity = world.spawn((Position { x: 0.0, y: 0.0 },));
+        let before = world.entity_meta(entity).unwrap().archetype;
+
+        // Overwriting an existing component doesn't move the entity
+        world.insert(entity, Position { x: 1.0, y: 1.0 }).unwrap();
+        assert!(world.get_resource::<Moves>().unwrap().0.is_empty());
+
+        world.insert(entity, Velocity { x: 1.0, y: 1.0 }).unwrap();
+        let after = world.entity_meta(entity).unwrap().archetype;
+        assert_ne!(before, after);
+
+        world.remove::<Velocity>(entity).unwrap();
+
+        let moves = world.remove_resource::<Moves>().unwrap().0;
+        assert_eq!(
+            moves,
+            vec![(entity, before, after), (entity, after, before)]
+        );
+    }
+
+    #[test]
+    fn test_prefab_with_children() {
+        #[derive(Debug, Clone, PartialEq)]
+        struct Name(String);
+
+        let mut world = World::new();
+        world.register_clonable::<Position>();
+        world.register_clonable::<Name>();
+
+        let parent = world.spawn((Position { x: 1.0, y: 2.0 }, Children::new()));
+        let child = world.spawn((Name("wheel".to_string()), Parent(parent)));
+        world.get_mut::<Children>(parent).unwrap().add(child);
+
+        let prefab = world.export_prefab(parent, true).unwrap();
+        assert_eq!(prefab.len(), 2);
+        assert_eq!(
+            prefab.component_names(),
+            vec![std::any::type_name::<Position>()]
+        );
+
+        // Each instance entity is spawned whole, never moved between archetypes
+        let moves = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = moves.clone();
+        world.register_on_archetype_change(move |_, _, _, _| {
+            counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        });
+
+        let first = world.spawn_prefab(&prefab);
+        let second = world.spawn_prefab(&prefab);
+        assert_eq!(moves.load(std::sync::atomic::Ordering::Relaxed), 0);
+        assert_ne!(first, parent);
+        assert_ne!(first, second);
+
+        for root in [first, second] {
+            assert_eq!(
+                world.get::<Position>(root),
+                Some(&Position { x: 1.0, y: 2.0 })
+            );
+            assert!(world.get::<Parent>(root).is_none());
+
+            let children = world.get::<Children>(root).unwrap().0.clone();
+            assert_eq!(children.len(), 1);
+            assert_ne!(children[0], child);
+            assert_eq!(world.get::<Parent>(children[0]), Some(&Parent(root)));
+            assert_eq!(world.get::<Name>(children[0]).unwrap().0, "wheel");
+        }
+
+        // Instances are independent of each other and of the source
+        let first_child = world.get::<Children>(first).unwrap().0[0];
+        world
+            .get_mut::<Name>(first_child)
+            .unwrap()
+            .0
+            .push_str("-left");
+        let second_child = world.get::<Children>(second).unwrap().0[0];
+        assert_eq!(world.get::<Name>(second_child).unwrap().0, "wheel");
+        assert_eq!(world.get::<Name>(child).unwrap().0, "wheel");
+
+        // Non-recursive export only captures the root
+        assert_eq!(world.export_prefab(parent, false).unwrap().len(), 1);
+
+        let unregistered = world.spawn((Velocity { x: 0.0, y: 0.0 },));
+        assert!(matches!(
+            world.export_prefab(unregistered, false),
+            Err(EcsError::InvalidOperation(_))
+        ));
+    }
+
+    #[test]
+    fn test_memory_stats() {
+        let mut world = World::new();
+        assert_eq!(world.memory_stats(), MemoryStats::default());
+
+        for i in 0..100 {
+            world.spawn((Position {
+                x: i as f32,
+                y: 0.0,
+            },));
+        }
+
+        let stats = world.memory_stats();
+        let used = 100 * std::mem::size_of::<Position>();
+        assert_eq!(stats.archetype_count, 1);
+        assert!(stats.column_bytes >= used);
+        assert!(stats.column_bytes <= used * 2);
+        assert_eq!(stats.wasted_bytes, stats.column_bytes - used);
+        assert!(stats.entity_metadata_bytes >= 100 * std::mem::size_of::<Entity>());
+    }
+
+    #[test]
+    #[cfg(feature = "change_detection")]
+    fn test_mut_query_change_detection() {
+        let mut world = World::new();
+
+        world.spawn((Position { x: 0.0, y: 0.0 }, Velocity { x: 0.0, y: 0.0 }));
+        world.spawn((Position { x: 1.0, y: 0.0 }, Velocity { x: 2.0, y: 0.0 }));
+
+        world.tick();
+
+        // Reading through a mutable term doesn't count as a change
+        let mut total = 0.0;
+        for (pos, vel) in world.query::<(Mut<Position>, &Velocity)>() {
+            total += pos.x + vel.x;
+        }
+        assert_eq!(total, 3.0);
+        assert_eq!(
+            world
+                .query_filtered::<&Position, (Changed<Position>,)>()
+                .count(),
+            0
+        );
+
+        for (mut pos, vel) in world.query::<(Mut<Position>, &Velocity)>() {
+            if vel.x > 0.0 {
+                pos.x += vel.x;
+            }
+        }
+        let changed: Vec<f32> = world
+            .query_filtered::<&Position, (Changed<Position>,)>()
+            .map(|pos| pos.x)
+            .collect();
+        assert_eq!(changed, vec![3.0]);
+    }
+
+    #[test]
+    fn test_disabled_entities_hidden_from_query() {
+        let mut world = World::new();
+        let visible = world.spawn((Position { x: 1.0, y: 0.0 },));
+        let hidden = world.spawn((Position { x: 2.0, y: 0.0 },));
+
+        world.disable(hidden).unwrap();
+        assert!(world.is_disabled(hidden));
+
+        let xs: Vec<f32> = world.query::<&Position>().map(|p| p.x).collect();
+        assert_eq!(xs, vec![1.0]);
+
+        let mut xs: Vec<f32> = world
+            .query_including_disabled::<&Position>()
+            .map(|p| p.x)
+            .collect();
+        xs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(xs, vec![1.0, 2.0]);
+
+        assert!(world.enable(hidden));
+        assert!(!world.enable(visible));
+        assert_eq!(world.query::<&Position>().count(), 2);
+    }
+
+    #[test]
+    fn test_despawn_get_info() {
+        let mut world = World::new();
+        let entity = world.spawn((Position { x: 1.0, y: 2.0 }, Velocity { x: 0.0, y: 0.0 }));
+
+        let info = world.despawn_get_info(entity).unwrap();
+        assert_eq!(info.entity, entity);
+        let mut names = info.component_types.clone();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                std::any::type_name::<Position>(),
+                std::any::type_name::<Velocity>()
+            ]
+        );
+        assert!(!world.is_alive(entity));
+        assert!(world.despawn_get_info(entity).is_none());
+    }
+
+    #[test]
+    fn test_reserve_in_avoids_reallocation() {
+        let mut world = World::new();
+        world.reserve_in::<(Position, Velocity)>(10_000);
+
+        let first = world.spawn((Position { x: 0.0, y: 0.0 }, Velocity { x: 1.0, y: 1.0 }));
+        let location = world.entity_meta(first).unwrap();
+        let capacity = world.archetypes.get(location.archetype).unwrap().capacity();
+        assert!(capacity >= 10_000);
+
+        for i in 1..10_000 {
+            world.spawn((
+                Position {
+                    x: i as f32,
+                    y: 0.0,
+                },
+                Velocity { x: 1.0, y: 1.0 },
+            ));
+        }
+
+        let archetype = world.archetypes.get(location.archetype).unwrap();
+        assert_eq!(archetype.len(), 10_000);
+        assert_eq!(archetype.capacity(), capacity);
+    }
+
+    #[test]
+    fn test_for_each_mut_defers_despawn() {
+        let mut world = World::new();
+        let weak = world.spawn((Health(5.0),));
+        let strong = world.spawn((Health(50.0),));
+
+        world.for_each_mut::<&mut Health>(|entity, health, commands| {
+            health.0 -= 10.0;
+            if health.0 <= 0.0 {
+                commands.despawn(entity);
+            }
+        });
+
+        assert!(!world.is_alive(weak));
+        assert!(world.is_alive(strong));
+        assert_eq!(world.get::<Health>(strong).unwrap().0, 40.0);
+    }
+
+    #[test]
+    fn test_clone_entity() {
+        #[derive(Clone, Debug, PartialEq)]
+        struct Inventory(Vec<u32>);
+
+        let mut world = World::new();
+        let original = world.spawn((Position { x: 1.0, y: 2.0 }, Inventory(vec![1, 2])));
+
+        assert!(world.clone_entity(original).is_err());
+
+        world.register_clonable::<Position>();
+        world.register_clonable::<Inventory>();
+        let copy = world.clone_entity(original).unwrap();
+
+        world.get_mut::<Inventory>(copy).unwrap().0.push(3);
+        assert_eq!(world.get::<Inventory>(original).unwrap().0, vec![1, 2]);
+        assert_eq!(world.get::<Inventory>(copy).unwrap().0, vec![1, 2, 3]);
+        assert_eq!(world.get::<Position>(copy), world.get::<Position>(original));
+        assert_eq!(
+            world.entity_meta(copy).unwrap().archetype,
+            world.entity_meta(original).unwrap().archetype
+        );
+    }
+
+    #[test]
+    fn test_clone_entity_unwinds_a_panicking_clone() {
+        #[derive(Debug, PartialEq)]
+        struct Fragile(u32);
+
+        impl Clone for Fragile {
+            fn clone(&self) -> Self {
+                panic!("can't clone");
+            }
+        }
+
+        let mut world = World::new();
+        world.register_clonable::<Position>();
+        world.register_clonable::<Fragile>();
+        let original = world.spawn((Position { x: 1.0, y: 0.0 }, Fragile(1)));
+        world.register_sparse::<Fragile>();
+        let sparse = world.spawn((Position { x: 2.0, y: 0.0 }, Fragile(2)));
+
+        // Failing on a column, then on a sparse set after the row was pushed
+        for entity in [original, sparse] {
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                world.clone_entity(entity)
+            }));
+            assert!(result.is_err());
+            assert_eq!(world.query::<&Position>().count(), 2);
+            assert_eq!(world.query_sparse::<Fragile>().count(), 1);
+        }
+
+        let copy = world.spawn((Position { x: 3.0, y: 0.0 },));
+        assert_eq!(world.query::<&Position>().count(), 3);
+        assert_eq!(
+            world.get::<Position>(copy),
+            Some(&Position { x: 3.0, y: 0.0 })
+        );
+        assert_eq!(world.get::<Fragile>(original), Some(&Fragile(1)));
+        // No id was left behind for the failed clones
+        assert_eq!(world.compact().len(), 3);
+    }
+
+    #[test]
+    fn test_clone_entity_keeps_hierarchy() {
+        let mut world = World::new();
+        world.register_clonable::<Position>();
+        let parent = world.spawn((Position { x: 0.0, y: 0.0 },));
+        let original = world.spawn((Position { x: 1.0, y: 0.0 }, Parent(parent)));
+        let child = world.spawn((Position { x: 2.0, y: 0.0 }, Parent(original)));
+        world.insert(parent, Children(vec![original])).unwrap();
+        world.insert(original, Children(vec![child])).unwrap();
+
+        let copy = world.clone_entity(original).unwrap();
+        assert_eq!(
+            world.entity_meta(copy).unwrap().archetype,
+            world.entity_meta(original).unwrap().archetype
+        );
+        assert_eq!(world.get::<Parent>(copy), Some(&Parent(parent)));
+        assert_eq!(
+            world.get::<Children>(parent),
+            Some(&Children(vec![original, copy]))
+        );
+        // The child stays with the original
+        assert_eq!(world.get::<Children>(copy), Some(&Children::new()));
+        assert_eq!(
+            world.get::<Children>(original),
+            Some(&Children(vec![child]))
+        );
+        assert_eq!(
+            world.get::<Position>(copy),
+            Some(&Position { x: 1.0, y: 0.0 })
+        );
+    }
+
+    #[test]
+    fn test_schedule_batches_within_stage() {
+        let mut world = World::new();
+        world.spawn((
+            Position { x: 0.0, y: 0.0 },
+            Velocity { x: 0.0, y: 0.0 },
+            Health(100.0),
+        ));
+
+        let mut schedule = Schedule::new();
+        schedule.add_update_system(
+            QuerySystem::<&mut Position, _>::new(|pos: &mut Position| pos.x += 1.0)
+                .with_name("move"),
+        );
+        schedule.add_update_system(
+            QuerySystem::<&mut Velocity, _>::new(|vel: &mut Velocity| vel.y += 2.0)
+                .with_name("accelerate"),
+        );
+        schedule.add_update_system(
+            QuerySystem::<&mut Health, _>::new(|health: &mut Health| health.0 -= 10.0)
+                .with_name("damage"),
+        );
+        schedule.add_update_system(
+            QuerySystem::<&mut Health, _>::new(|health: &mut Health| health.0 *= 2.0)
+                .with_name("double"),
+        );
+
+        assert_eq!(
+            schedule.stage_batches(Stage::Update),
+            vec![vec!["move", "accelerate", "damage"], vec!["double"]]
+        );
+
+        schedule.run(&mut world);
+
+        let (pos, vel, health) = world
+            .query::<(&Position, &Velocity, &Health)>()
+            .next()
+            .unwrap();
+        assert_eq!(pos.x, 1.0);
+        assert_eq!(vel.y, 2.0);
+        assert_eq!(health.0, 180.0);
+    }
+
+    #[test]
+    fn test_query_dynamic_matches_static_query() {
+        use std::any::TypeId;
+
+        let mut world = World::new();
+        world.spawn((Position { x: 0.0, y: 0.0 }, Velocity { x: 1.0, y: 0.0 }));
+        world.spawn((
+            Position { x: 1.0, y: 0.0 },
+            Velocity { x: 1.0, y: 0.0 },
+            Health(10.0),
+        ));
+        world.spawn((Position { x: 2.0, y: 0.0 }, Health(10.0)));
+        world.spawn((Velocity { x: 3.0, y: 0.0 },));
+
+        let include = [
+            TypeId::of::<Position>().into(),
+            TypeId::of::<Velocity>().into(),
+        ];
+        let exclude = [TypeId::of::<Health>().into()];
+        let dynamic: Vec<f32> = world
+            .query_dynamic(&include, &exclude)
+            .map(|entity| world.get::<Position>(entity).unwrap().x)
+            .collect();
+
+        let expected: Vec<f32> = world
+            .query_filtered::<(&Position, &Velocity), Without<Health>>()
+            .map(|(pos, _)| pos.x)
+            .collect();
+
+        assert_eq!(dynamic, vec![0.0]);
+        assert_eq!(dynamic, expected);
+    }
+
+    #[test]
+    fn test_visit_components() {
+        let mut world = World::new();
+        let entity = world.spawn((Position { x: 3.0, y: 4.0 }, Health(7.0)));
+
+        let mut names = Vec::new();
+        let mut position = None;
+        world.visit(&mut |visited, name, component| {
+            assert_eq!(visited, entity);
+            names.push(name);
+            if let Some(pos) = component.downcast_ref::<Position>() {
+                position = Some((pos.x, pos.y));
+            }
+        });
+
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                std::any::type_name::<Health>(),
+                std::any::type_name::<Position>()
+            ]
+        );
+        assert_eq!(position, Some((3.0, 4.0)));
+    }
+
+    #[test]
+    fn test_events_drop_oldest() {
+        let mut events = Events::with_capacity_policy(3, OverflowPolicy::DropOldest);
+        for i in 0..5 {
+            events.send(i);
+        }
+        assert_eq!(events.iter().copied().collect::<Vec<_>>(), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn test_events_drop_oldest_ignores_priority() {
+        let mut events = Events::with_capacity_policy(3, OverflowPolicy::DropOldest);
+        events.send_prioritized("urgent", 10);
+        events.send("a");
+        events.send("b");
+        events.send("c");
+        assert_eq!(
+            events.iter().copied().collect::<Vec<_>>(),
+            vec!["a", "b", "c"]
+        );
+
+        events.send_prioritized("urgent", 10);
+        events.send("d");
+        assert_eq!(
+            events.iter().copied().collect::<Vec<_>>(),
+            vec!["urgent", "c", "d"]
+        );
+
+        // Events made old by `update` go first
+        events.update();
+        events.send("e");
+        assert_eq!(events.iter().copied().collect::<Vec<_>>(), vec!["e"]);
+        assert_eq!(events.drain_all().collect::<Vec<_>>(), vec!["c", "d", "e"]);
+    }
+
+    #[test]
+    fn test_events_drop_newest() {
+        let mut events = Events::with_capacity_policy(3, OverflowPolicy::DropNewest);
+        for i in 0..5 {
+            events.send(i);
+        }
+        assert_eq!(events.iter().copied().collect::<Vec<_>>(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "exceeded its limit")]
+    fn test_events_overflow_panics() {
+        let mut events = Events::with_capacity_policy(3, OverflowPolicy::Panic);
+        for i in 0..4 {
+            events.send(i);
+        }
+    }
+
+    #[test]
+    fn test_world_events_lifecycle() {
+        let mut world = World::new();
+        assert!(world.drain_events::<u32>().is_empty());
+
+        world.send_event(1u32);
+        world.tick();
+        world.send_event(2u32);
+        assert_eq!(world.drain_events::<u32>(), vec![1, 2]);
+
+        // Undrained events survive one tick, then are dropped
+        world.send_event(3u32);
+        world.tick();
+        world.tick();
+        world.send_event(4u32);
+        world.tick();
+        assert_eq!(world.drain_events::<u32>(), vec![4]);
+    }
+
+    #[test]
+    fn test_query_with_parent() {
+        struct Constraints {
+            max_width: f32,
+        }
+        struct LocalRect {
+            width: f32,
+        }
+
+        let mut world = World::new();
+        let root = world.spawn((Constraints { max_width: 50.0 },));
+        for width in [30.0, 80.0] {
+            world.spawn((LocalRect { width }, Parent(root)));
+        }
+        world.spawn((LocalRect { width: 200.0 },));
+
+        let mut orphans = 0;
+        for (rect, constraints) in world.query_with_parent::<&mut LocalRect, Constraints>() {
+            match constraints {
+                Some(constraints) => rect.width = rect.width.min(constraints.max_width),
+                None => orphans += 1,
+            }
+        }
+        assert_eq!(orphans, 1);
+
+        let mut widths: Vec<f32> = world.query::<&LocalRect>().map(|r| r.width).collect();
+        widths.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(widths, vec![30.0, 50.0, 200.0]);
+    }
+
+    #[test]
+    #[cfg(feature = "change_detection")]
+    fn test_fill_overwrites_and_marks_changed() {
+        let mut world = World::new();
+        for i in 0..3 {
+            world.spawn((Health(i as f32),));
+        }
+        world.spawn((Position { x: 0.0, y: 0.0 }, Health(-5.0)));
+        world.spawn((Position { x: 0.0, y: 0.0 },));
+        world.tick();
+
+        world.fill(Health(100.0));
+
+        assert!(world.query::<&Health>().all(|health| health.0 == 100.0));
+        assert_eq!(
+            world
+                .query_filtered::<&Health, (Changed<Health>,)>()
+                .count(),
+            4
+        );
+    }
+
+    #[test]
+    fn test_insert_overwrite_drops_previous_value() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct Buffer(Vec<u8>);
+
+        impl Drop for Buffer {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let mut world = World::new();
+        let entity = world.spawn((Buffer(vec![1, 2, 3]),));
+
+        world.insert(entity, Buffer(vec![4, 5])).unwrap();
+        assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+        assert_eq!(world.get::<Buffer>(entity).unwrap().0, vec![4, 5]);
+
+        world.despawn(entity);
+        assert_eq!(DROPS.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_query_combinations() {
+        #[derive(Debug, PartialEq)]
+        struct Id(u32);
+
+        let mut world = World::new();
+        for i in 0..4 {
+            world.spawn((Id(i),));
+        }
+
+        let pairs: Vec<[u32; 2]> = world
+            .query_combinations::<&Id, 2>()
+            .map(|[a, b]| [a.0, b.0])
+            .collect();
+        assert_eq!(pairs.len(), 6);
+        assert!(pairs.iter().all(|[a, b]| a != b));
+
+        let triples: Vec<[u32; 3]> = world
+            .query_combinations::<&Id, 3>()
+            .map(|items| items.map(|id| id.0))
+            .collect();
+        assert_eq!(triples.len(), 4);
+        for [a, b, c] in triples {
+            assert!(a != b && b != c && a != c);
+        }
+
+        assert_eq!(world.query_combinations::<&Id, 5>().count(), 0);
+    }
+
+    #[test]
+    fn test_checkpoint_and_restore_resources() {
+        #[derive(Clone, Debug, PartialEq)]
+        struct Settings {
+            volume: u8,
+            difficulty: String,
+        }
+
+        let mut world = World::new();
+        world.insert_resource(Settings {
+            volume: 5,
+            difficulty: "normal".to_string(),
+        });
+        world.insert_resource(42u64);
+        world.register_clonable_resource::<Settings>();
+
+        let checkpoint = world.checkpoint_resources();
+        assert_eq!(checkpoint.len(), 1);
+
+        {
+            let mut settings = world.get_resource_mut::<Settings>().unwrap();
+            settings.volume = 11;
+            settings.difficulty = "hard".to_string();
+        }
+        *world.get_resource_mut::<u64>().unwrap() = 7;
+
+        world.restore_resources(checkpoint);
+
+        let settings = world.get_resource::<Settings>().unwrap();
+        assert_eq!(settings.volume, 5);
+        assert_eq!(settings.difficulty, "normal");
+        assert_eq!(*world.get_resource::<u64>().unwrap(), 7);
+    }
+
+    #[test]
+    fn test_query_cache_invalidated_by_new_archetype() {
+        let mut world = World::new();
+        world.spawn((Position { x: 0.0, y: 0.0 },));
+        world.spawn((Velocity { x: 0.0, y: 0.0 },));
+
+        let archetype_count = world.archetypes.iter().count();
+        assert_eq!(world.query::<&Position>().count(), 1);
+        assert_eq!(world.query::<&Position>().count(), 1);
+
+        // Reusing an archetype keeps the cached match list valid
+        world.spawn((Position { x: 1.0, y: 0.0 },));
+        assert_eq!(world.archetypes.iter().count(), archetype_count);
+        assert_eq!(world.query::<&Position>().count(), 2);
+
+        let healthy = world.spawn((Position { x: 2.0, y: 0.0 }, Health(1.0)));
+        assert_eq!(world.query::<&Position>().count(), 3);
+        assert_eq!(world.query::<&Position>().size_hint(), (3, Some(3)));
+
+        // Emptied archetypes drop out of the iteration set and come back
+        // once they hold entities again
+        world.despawn(healthy);
+        assert_eq!(world.query::<&Position>().count(), 2);
+        assert_eq!(world.query::<&Health>().count(), 0);
+        world.spawn((Position { x: 3.0, y: 0.0 }, Health(1.0)));
+        assert_eq!(world.query::<&Health>().count(), 1);
+        assert_eq!(world.query::<&Position>().count(), 3);
+    }
+
+    #[test]
+    fn test_function_system_access_batching() {
+        use std::any::TypeId;
+
+        let mut schedule = Schedule::new();
+        schedule.add_update_system(
+            (|world: &mut World| {
+                for pos in world.query::<&mut Position>() {
+                    pos.x += 1.0;
+                }
+            })
+            .into_system()
+            .with_name("move")
+            .with_access(vec![], vec![TypeId::of::<Position>()]),
+        );
+        schedule.add_update_system(
+            (|world: &mut World| {
+                for health in world.query::<&mut Health>() {
+                    health.0 -= 1.0;
+                }
+            })
+            .into_system()
+            .with_name("decay")
+            .with_access(vec![], vec![TypeId::of::<Health>()]),
+        );
+        schedule.add_update_system((|_: &mut World| {}).into_system().with_name("opaque"));
+
+        assert_eq!(
+            schedule.stage_batches(Stage::Update),
+            vec![vec!["move", "decay"], vec!["opaque"]]
+        );
+
+        let mut world = World::new();
+        world.spawn((Position { x: 0.0, y: 0.0 }, Health(10.0)));
+        schedule.run(&mut world);
+        let (pos, health) = world.query::<(&Position, &Health)>().next().unwrap();
+        assert_eq!((pos.x, health.0), (1.0, 9.0));
+    }
+
+    #[test]
+    fn test_dynamic_components() {
+        use std::alloc::Layout;
+
+        let mut world = World::new();
+        let speed = world.register_dynamic_component("speed", Layout::new::<u32>(), None);
+        let tag = world.register_dynamic_component("tag", Layout::new::<[u8; 3]>(), None);
+        assert_eq!(world.dynamic_component_name(speed), Some("speed"));
+
+        let entity = world
+            .spawn_dynamic(&[(speed, &7u32.to_ne_bytes()), (tag, b"abc")])
+            .unwrap();
+        assert!(world.spawn_dynamic(&[(speed, &[1, 2])]).is_err());
+
+        // Static components can be mixed in without losing the dynamic ones
+        world.insert(entity, Position { x: 1.0, y: 2.0 }).unwrap();
+        world
+            .insert_dynamic(entity, speed, &9u32.to_ne_bytes())
+            .unwrap();
+
+        let bytes = world.get_dynamic(entity, speed).unwrap();
+        assert_eq!(u32::from_ne_bytes(bytes.try_into().unwrap()), 9);
+        assert_eq!(world.get_dynamic(entity, tag), Some(&b"abc"[..]));
+        assert_eq!(world.get::<Position>(entity).unwrap().x, 1.0);
+
+        world.spawn((Position { x: 0.0, y: 0.0 },));
+        let matches: Vec<Entity> = world
+            .query_dynamic(
+                &[tag.into(), std::any::TypeId::of::<Position>().into()],
+                &[],
+            )
+            .collect();
+        assert_eq!(matches, vec![entity]);
+    }
+
+    #[test]
+    fn test_app_run_while() {
+        struct FrameCounter(u32);
+
+        let mut app = App::new();
+        app.world.insert_resource(FrameCounter(0));
+        app.add_system(
+            Stage::Update,
+            (|world: &mut World| {
+                world.get_resource_mut::<FrameCounter>().unwrap().0 += 1;
+            })
+            .into_system(),
+        );
+
+        let frames = app.run_while(|world| world.get_resource::<FrameCounter>().unwrap().0 < 5);
+        assert_eq!(frames, 5);
+        assert_eq!(app.world.current_tick(), 5);
+
+        app.run_frames(3);
+        assert_eq!(app.world.get_resource::<FrameCounter>().unwrap().0, 8);
+    }
+
+    #[test]
+    #[should_panic(expected = "could not be moved")]
+    fn test_move_with_missing_source_component_panics() {
+        use crate::archetype::Archetype;
+        use std::any::TypeId;
+
+        let mut world = World::new();
+        let entity = world.spawn((Position { x: 0.0, y: 0.0 },));
+
+        let mut from = Archetype::new(
+            0,
+            vec![TypeId::of::<Position>()],
+            vec!["Position"],
+            Vec::new(),
+        );
+        from.add_column::<Position>();
+        from.push_entity(entity);
+        from.set_component(0, Position { x: 1.0, y: 1.0 });
+
+        let mut to = Archetype::new(
+            1,
+            vec![TypeId::of::<Position>(), TypeId::of::<Velocity>()],
+            vec!["Position", "Velocity"],
+            Vec::new(),
+        );
+        to.add_column::<Position>();
+        to.add_column::<Velocity>();
+        to.push_entity(entity);
+
+        // The source has no Velocity, so the destination slot would be garbage
+        to.copy_components_from(
+            0,
+            &from,
+            0,
+            &[
+                TypeId::of::<Position>().into(),
+                TypeId::of::<Velocity>().into(),
+            ],
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "could not be moved")]
+    fn test_world_move_with_missing_source_column_panics() {
+        let mut world = World::new();
+        let entity = world.spawn((Position { x: 0.0, y: 0.0 }, Health(1.0)));
+
+        // Corrupt the source: it still lists Health but lost the column
+        let archetype = world.entity_meta(entity).unwrap().archetype;
+        world.archetypes.get_mut(archetype).unwrap().columns.pop();
+
+        let _ = world.remove::<Position>(entity);
+    }
+
+    #[test]
+    fn test_archetype_histogram() {
+        let mut world = World::new();
+        assert!(world.archetype_histogram().is_empty());
+        assert_eq!(world.fragmentation_ratio(), 0.0);
+
+        spawn_entities_fragmented(&mut world, 10);
+        world.spawn((BenchPosition::new(0.0, 0.0, 0.0),));
+
+        let histogram = world.archetype_histogram();
+        let counts: Vec<usize> = histogram.iter().map(|(_, count)| *count).collect();
+        assert_eq!(counts, vec![4, 3, 2, 2]);
+        assert_eq!(histogram[0].0.len(), 1);
+        assert!(histogram[0].0[0].ends_with("BenchPosition"));
+        assert_eq!(world.fragmentation_ratio(), 4.0 / 11.0);
+    }
+
+    #[test]
+    fn test_query_iter_rev() {
+        let mut world = World::new();
+        for i in 0..6 {
+            world.spawn((Health(i as f32),));
+            world.spawn((Health(10.0 + i as f32), Position { x: 0.0, y: 0.0 }));
+        }
+
+        let forward: Vec<f32> = world.query::<&Health>().map(|h| h.0).collect();
+        let mut backward: Vec<f32> = world.query::<&Health>().rev().map(|h| h.0).collect();
+        backward.reverse();
+        assert_eq!(forward.len(), 12);
+        assert_eq!(forward, backward);
+
+        // Alternating ends never yields an entity twice
+        let mut iter = world.query::<&Health>();
+        let mut seen = Vec::new();
+        loop {
+            let (lower, _) = iter.size_hint();
+            assert_eq!(lower, 12 - seen.len());
+            let item = if seen.len() % 2 == 0 {
+                iter.next()
+            } else {
+                iter.next_back()
+            };
+            match item {
+                Some(health) => seen.push(health.0),
+                None => break,
+            }
+        }
+        seen.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let mut expected = forward.clone();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(seen, expected);
+    }
+
+    #[test]
+    fn test_resource_change_detection() {
+        struct Settings {
+            volume: u32,
+        }
+
+        let mut world = World::new();
+        world.insert_resource(Settings { volume: 5 });
+        assert!(world.is_resource_changed::<Settings>());
+        assert!(!world.is_resource_changed::<u32>());
+
+        world.tick();
+        assert!(!world.is_resource_changed::<Settings>());
+
+        // Reading through ResMut doesn't count as a change
+        assert_eq!(world.get_resource_mut::<Settings>().unwrap().volume, 5);
+        assert!(!world.get_resource::<Settings>().unwrap().is_changed());
+
+        world.get_resource_mut::<Settings>().unwrap().volume = 8;
+        assert!(world.is_resource_changed::<Settings>());
+        assert!(world.get_resource::<Settings>().unwrap().is_changed());
+
+        world.tick();
+        assert!(!world.is_resource_changed::<Settings>());
+        assert!(!world.get_resource::<Settings>().unwrap().is_changed());
+    }
+
+    #[test]
+    fn test_archetype_writer() {
+        let mut world = World::new();
+        world.spawn((Position { x: -1.0, y: 0.0 },));
+
+        let entities: Vec<Entity> = {
+            let mut writer = world.archetype_writer::<(Position, Velocity)>();
+            writer.reserve(1000);
+            (0..1000)
+                .map(|i| {
+                    writer.push((
+                        Position {
+                            x: i as f32,
+                            y: 0.0,
+                        },
+                        Velocity { x: 1.0, y: 0.0 },
+                    ))
+                })
+                .collect()
+        };
+
+        assert_eq!(world.query::<(&Position, &Velocity)>().count(), 1000);
+        assert_eq!(world.query::<&Position>().count(), 1001);
+
+        let archetype_id = world.entity_info(entities[0]).unwrap().archetype_id;
+        for (i, &entity) in entities.iter().enumerate() {
+            assert_eq!(world.get::<Position>(entity).unwrap().x, i as f32);
+            let meta = world.entity_meta(entity).unwrap();
+            assert_eq!(meta.archetype, archetype_id);
+            assert_eq!(meta.index, i);
+        }
+
+        // Spawning the same bundle normally lands in the writer's archetype
+        let extra = world.spawn((Position { x: 0.0, y: 0.0 }, Velocity { x: 0.0, y: 0.0 }));
+        assert_eq!(world.entity_info(extra).unwrap().archetype_id, archetype_id);
+    }
+
+    #[test]
+    fn test_has_query_term() {
+        let mut world = World::new();
+        world.spawn((Position { x: 0.0, y: 0.0 },));
+        world.spawn((Position { x: 1.0, y: 0.0 }, Velocity { x: 1.0, y: 0.0 }));
+        world.spawn((Position { x: 2.0, y: 0.0 }, Health(1.0)));
+        world.spawn((Velocity { x: 3.0, y: 0.0 },));
+
+        let mut results: Vec<(f32, bool)> = world
+            .query::<(&Position, Has<Velocity>)>()
+            .map(|(pos, has_velocity)| (pos.x, has_velocity))
+            .collect();
+        results.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        assert_eq!(results, vec![(0.0, false), (1.0, true), (2.0, false)]);
+
+        // On its own it matches every entity
+        assert_eq!(world.query::<Has<Health>>().filter(|&has| has).count(), 1);
+        assert_eq!(world.query::<Has<Health>>().count(), 4);
+    }
+
+    #[test]
+    fn test_schedule_flush_after_each_system() {
+        struct Seen(usize);
+
+        fn frame(flush: bool) -> usize {
+            let mut world = World::new();
+            world.insert_resource(Seen(0));
+
+            let mut schedule = Schedule::new();
+            schedule.flush_after_each_system(flush);
+            schedule.add_update_system(
+                (|world: &mut World| {
+                    world.commands().spawn((Position { x: 0.0, y: 0.0 },));
+                })
+                .into_system(),
+            );
+            schedule.add_update_system(
+                (|world: &mut World| {
+                    let count = world.query::<&Position>().count();
+                    world.get_resource_mut::<Seen>().unwrap().0 = count;
+                })
+                .into_system(),
+            );
+            schedule.run(&mut world);
+
+            assert_eq!(world.query::<&Position>().count(), 1);
+            world.get_resource::<Seen>().unwrap().0
+        }
+
+        assert_eq!(frame(false), 0);
+        assert_eq!(frame(true), 1);
+    }
+
+    #[test]
+    fn test_system_pipe() {
+        struct Counted(usize);
+
+        let mut world = World::new();
+        for i in 0..3 {
+            world.spawn((Position {
+                x: i as f32,
+                y: 0.0,
+            },));
+        }
+
+        let mut system = (|world: &mut World| world.query::<&Position>().count()).pipe(
+            |count: usize, world: &mut World| {
+                assert_eq!(count, 3);
+                world.insert_resource(Counted(count));
+            },
+        );
+        system.run(&mut world);
+        assert_eq!(world.get_resource::<Counted>().unwrap().0, 3);
+        assert!(system.name().contains(" | "));
+
+        // Fallible systems can be piped into an error handler and scheduled
+        struct Failures(u32);
+        world.insert_resource(Failures(0));
+
+        let mut schedule = Schedule::new();
+        schedule.add_update_system(
+            (|world: &mut World| -> std::result::Result<(), String> {
+                if world.query::<&Position>().count() > 2 {
+                    Err("too many".to_string())
+                } else {
+                    Ok(())
+                }
+            })
+            .pipe(
+                |result: std::result::Result<(), String>, world: &mut World| {
+                    if result.is_err() {
+                        world.get_resource_mut::<Failures>().unwrap().0 += 1;
+                    }
+                },
+            ),
+        );
+        schedule.run(&mut world);
+        schedule.run(&mut world);
+        assert_eq!(world.get_resource::<Failures>().unwrap().0, 2);
+    }
+
+    #[test]
+    fn test_query_ref() {
+        fn render(world: &World) -> Vec<(f32, bool)> {
+            world
+                .query_ref::<(&Position, Has<Velocity>)>()
+                .map(|(pos, moving)| (pos.x, moving))
+                .collect()
+        }
+
+        let mut world = World::new();
+        let player = world.spawn((Position { x: 1.0, y: 0.0 }, Health(10.0)));
+        world.spawn((Position { x: 2.0, y: 0.0 }, Velocity { x: 1.0, y: 0.0 }));
+        let hidden = world.spawn((Position { x: 3.0, y: 0.0 },));
+        world.disable(hidden).unwrap();
+
+        let shared = &world;
+        let health = shared.get::<Health>(player).unwrap();
+        let mut positions = render(shared);
+        positions.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        assert_eq!(positions, vec![(1.0, false), (2.0, true)]);
+
+        let velocities: Vec<Option<&Velocity>> = shared.query_ref::<Option<&Velocity>>().collect();
+        assert_eq!(velocities.iter().filter(|v| v.is_some()).count(), 1);
+        assert_eq!(health.0, 10.0);
+    }
+
+    #[test]
+    fn test_entity_age() {
+        let mut world = World::new();
+        let first = world.spawn((Health(1.0),));
+        world.tick();
+        world.tick();
+        let second = world.spawn((Health(2.0),));
+        world.tick();
+
+        assert_eq!(world.entity_age(first), Some(3));
+        assert_eq!(world.entity_age(second), Some(1));
+
+        // Archetype moves keep the original spawn tick
+        world.insert(first, Position { x: 0.0, y: 0.0 }).unwrap();
+        assert_eq!(world.entity_age(first), Some(3));
+
+        world.despawn(second);
+        assert_eq!(world.entity_age(second), None);
+    }
+
+    #[test]
+    fn test_removed_components() {
+        let mut world = World::new();
+        let a = world.spawn((Health(1.0), Position { x: 0.0, y: 0.0 }));
+        let b = world.spawn((Health(2.0),));
+        let c = world.spawn((Health(3.0),));
+
+        world.remove::<Health>(a).unwrap();
+        world.despawn(b);
+
+        let mut removed: Vec<Entity> = world.removed::<Health>().collect();
+        removed.sort();
+        let mut expected = vec![a, b];
+        expected.sort();
+        assert_eq!(removed, expected);
+        assert!(!world.removed::<Health>().any(|e| e == c));
+        assert_eq!(world.removed::<Position>().count(), 0);
+
+        world.tick();
+        assert_eq!(world.removed::<Health>().count(), 0);
+    }
+
+    #[test]
+    fn test_schedule_generic_world() {
+        // A second world type: wraps `World` and counts what the schedule does
+        struct TracedWorld {
+            inner: World,
+            flushes: usize,
+            ticks: usize,
+        }
+
+        impl WorldLike for TracedWorld {
+            fn flush_commands(&mut self) {
+                self.flushes += 1;
+                self.inner.flush_commands();
+            }
+
+            fn tick(&mut self) {
+                self.ticks += 1;
+                self.inner.tick();
+            }
+        }
+
+        impl QueryWorld for TracedWorld {
+            fn query<Q: Query + 'static>(&mut self) -> impl Iterator<Item = Q::Item<'_>> {
+                self.inner.query::<Q>()
+            }
+        }
+
+        fn movement<W: QueryWorld>() -> impl System<(), (), W> {
+            system::QuerySystem::<(&mut Position, &Velocity), _>::new(
+                |(pos, vel): (&mut Position, &Velocity)| {
+                    pos.x += vel.x;
+                },
+            )
+        }
+
+        let mut world = World::new();
+        let plain = world.spawn((Position { x: 0.0, y: 0.0 }, Velocity { x: 1.0, y: 0.0 }));
+        let mut schedule = Schedule::new();
+        schedule.add_update_system(movement());
+        schedule.run(&mut world);
+        assert_eq!(world.get::<Position>(plain).unwrap().x, 1.0);
+
+        let mut traced = TracedWorld {
+            inner: World::new(),
+            flushes: 0,
+            ticks: 0,
+        };
+        let entity = traced
+            .inner
+            .spawn((Position { x: 0.0, y: 0.0 }, Velocity { x: 2.0, y: 0.0 }));
+        let mut schedule = ScheduleBuilder::<TracedWorld>::default()
+            .flush_after_each_system(true)
+            .system(Stage::Update, movement())
+            .system(
+                Stage::PostUpdate,
+                (|world: &mut TracedWorld| {
+                    world.inner.commands().spawn((Health(1.0),));
+                })
+                .into_system(),
+            )
+            .build();
+        schedule.run(&mut traced);
+        schedule.run(&mut traced);
+
+        assert_eq!(traced.inner.get::<Position>(entity).unwrap().x, 4.0);
+        assert_eq!(traced.inner.query::<&Health>().count(), 2);
+        assert_eq!(traced.ticks, 2);
+        // Two systems plus the end-of-frame flush, per frame
+        assert_eq!(traced.flushes, 6);
+    }
+
+    #[test]
+    fn test_query_data_derive() {
+        #[derive(QueryData)]
+        struct MovementQuery<'w> {
+            pos: &'w mut Position,
+            vel: &'w Velocity,
+            health: Option<&'w Health>,
+        }
+
+        let mut world = World::new();
+        let mover = world.spawn((Position { x: 0.0, y: 0.0 }, Velocity { x: 1.0, y: 2.0 }));
+        let healthy = world.spawn((
+            Position { x: 5.0, y: 5.0 },
+            Velocity { x: 1.0, y: 1.0 },
+            Health(3.0),
+        ));
+        world.spawn((Position { x: 9.0, y: 9.0 },));
+
+        let mut with_health = 0;
+        for movement in world.query::<MovementQuery>() {
+            movement.pos.x += movement.vel.x;
+            movement.pos.y += movement.vel.y;
+            with_health += movement.health.is_some() as usize;
+        }
+        assert_eq!(with_health, 1);
+        assert_eq!(world.get::<Position>(mover).unwrap().x, 1.0);
+        assert_eq!(world.get::<Position>(healthy).unwrap().y, 6.0);
+
+        assert_eq!(
+            MovementQuery::write_types(),
+            vec![std::any::TypeId::of::<Position>()]
+        );
+        assert_eq!(MovementQuery::read_types().len(), 2);
+    }
+
+    #[test]
+    fn test_par_query() {
+        use rayon::prelude::*;
+
+        let mut world = World::new();
+        for i in 0..1000 {
+            world.spawn((Health(i as f32),));
+            if i % 3 == 0 {
+                world.spawn((Health(i as f32), Position { x: 0.0, y: 0.0 }));
+            }
+        }
+
+        let sequential: f64 = world.query::<&Health>().map(|h| h.0 as f64).sum();
+        let parallel: f64 = world.par_query::<&Health>().map(|h| h.0 as f64).sum();
+        assert_eq!(parallel, sequential);
+
+        let large = world
+            .par_query::<&Health>()
+            .filter(|h| h.0 >= 500.0)
+            .count();
+        assert_eq!(
+            large,
+            world.query::<&Health>().filter(|h| h.0 >= 500.0).count()
+        );
+
+        world
+            .par_query::<&mut Health>()
+            .for_each(|health| health.0 *= 2.0);
+        let doubled: f64 = world.query::<&Health>().map(|h| h.0 as f64).sum();
+        assert_eq!(doubled, sequential * 2.0);
+    }
+
+    #[test]
+    fn test_par_query_is_indexed() {
+        use rayon::prelude::*;
+
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        struct Selected;
+
+        // Two threads so the rows are really split, the world built inside
+        // since it can't be sent to the pool
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        pool.install(|| {
+            let mut world = World::new();
+            world.register_sparse::<Selected>();
+            for i in 0..500 {
+                let entity = world.spawn((Health(i as f32),));
+                if i % 7 < 3 {
+                    world.insert(entity, Selected).unwrap();
+                }
+                if i % 4 == 0 {
+                    world.spawn((Health(i as f32), Position { x: 0.0, y: 0.0 }));
+                }
+            }
+
+            let sequential: Vec<f32> = world.query::<&Health>().map(|h| h.0).collect();
+            let parallel = world.par_query::<&Health>().with_min_len(1);
+            assert_eq!(parallel.len(), sequential.len());
+            let parallel: Vec<f32> = parallel.map(|h| h.0).collect();
+            assert_eq!(parallel, sequential);
+
+            let reversed: Vec<f32> = world.par_query::<&Health>().rev().map(|h| h.0).collect();
+            assert!(reversed.iter().rev().eq(sequential.iter()));
+
+            // Rows without the sparse term are left out of the runs
+            let selected: Vec<f32> = world
+                .query::<(&Health, &Selected)>()
+                .map(|(h, _)| h.0)
+                .collect();
+            let parallel = world.par_query::<(&Health, &Selected)>().with_min_len(1);
+            assert_eq!(parallel.len(), selected.len());
+            let positions: Vec<(usize, f32)> =
+                parallel.enumerate().map(|(i, (h, _))| (i, h.0)).collect();
+            assert_eq!(
+                positions,
+                selected.into_iter().enumerate().collect::<Vec<_>>()
+            );
+        });
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "structurally modified")]
+    fn test_query_iter_detects_structural_change() {
+        let mut world = World::new();
+        world.spawn((Position { x: 0.0, y: 0.0 },));
+        world.spawn((Position { x: 1.0, y: 0.0 },));
+
+        // Sidestep the borrow checker the way a careless unsafe caller might
+        let world_ptr = &mut world as *mut World;
+        let mut iter = unsafe { (*world_ptr).query::<&Position>() };
+        iter.next();
+        unsafe {
+            (*world_ptr).spawn((Position { x: 2.0, y: 0.0 },));
+        }
+        iter.next();
+    }
+
+    #[test]
+    fn test_dyn_value_tags() {
+        let mut world = World::new();
+        let mana = world.register_dyn_value_tag("Mana");
+        let quest = world.register_dyn_value_tag("QuestState");
+        assert_eq!(world.dyn_value_tag_name(quest), Some("QuestState"));
+
+        let wizard = world.spawn((Position { x: 0.0, y: 0.0 }, DynValue::new(mana, 40u32)));
+        let npc = world.spawn((DynValue::new(quest, String::from("started")),));
+        let hidden = world.spawn((DynValue::new(mana, 5u32),));
+        world.disable(hidden).unwrap();
+
+        let tagged: Vec<(Entity, u32)> = world
+            .query_tagged(mana)
+            .map(|(entity, value)| (entity, *value.downcast_ref::<u32>().unwrap()))
+            .collect();
+        assert_eq!(tagged, vec![(wizard, 40)]);
+
+        let quests: Vec<Entity> = world.query_tagged(quest).map(|(e, _)| e).collect();
+        assert_eq!(quests, vec![npc]);
+
+        let value = world.get_mut::<DynValue>(npc).unwrap();
+        assert!(value.downcast_ref::<u32>().is_none());
+        value
+            .downcast_mut::<String>()
+            .unwrap()
+            .push_str(" and finished");
+        assert_eq!(
+            world
+                .get::<DynValue>(npc)
+                .unwrap()
+                .downcast_ref::<String>()
+                .unwrap(),
+            "started and finished"
+        );
+    }
+
+    #[test]
+    fn test_query_helpers_on_empty_world() {
+        use rayon::prelude::*;
+
+        let mut world = World::new();
+        assert_eq!(world.query::<&Position>().size_hint(), (0, Some(0)));
+        assert_eq!(world.query::<&Position>().rev().next(), None);
+        assert!(world.query_single::<&Position>().is_none());
+        assert_eq!(world.query_count::<(&Position, &Velocity)>(), 0);
+        assert!(!world.query_any::<&mut Position>());
+        assert_eq!(world.query_ref::<&Position>().count(), 0);
+        assert_eq!(world.par_query::<&Position>().count(), 0);
+        assert!(world.archetype_histogram().is_empty());
+    }
+
+    #[test]
+    fn test_query_helpers() {
+        let mut world = World::new();
+        let player = world.spawn((Position { x: 1.0, y: 0.0 }, Health(5.0)));
+        world.spawn((Position { x: 2.0, y: 0.0 },));
+
+        assert_eq!(
+            world.query_single::<(&Position, &Health)>().unwrap().0.x,
+            1.0
+        );
+        assert!(world.query_single::<&Position>().is_none());
+        assert_eq!(world.query_count::<&Position>(), 2);
+        assert!(world.query_any::<&Health>());
+        let mut partly_used = world.query::<&Position>();
+        partly_used.next();
+        assert_eq!(partly_used.count(), 1);
+        assert_eq!(world.query_filtered::<&Position, With<Health>>().count(), 1);
+
+        world.disable(player).unwrap();
+        assert!(world.query_single::<&Health>().is_none());
+        assert_eq!(world.query_count::<&Position>(), 1);
+    }
+
+    #[test]
+    fn test_world_with_allocator() {
+        use std::alloc::{GlobalAlloc, Layout, System};
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        #[derive(Default)]
+        struct Tracking {
+            allocated: Arc<AtomicUsize>,
+            freed: Arc<AtomicUsize>,
+        }
+
+        unsafe impl GlobalAlloc for Tracking {
+            unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+                self.allocated.fetch_add(layout.size(), Ordering::SeqCst);
+                unsafe { System.alloc(layout) }
+            }
+
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+                self.freed.fetch_add(layout.size(), Ordering::SeqCst);
+                unsafe { System.dealloc(ptr, layout) }
+            }
+        }
+
+        let tracking = Tracking::default();
+        let allocated = tracking.allocated.clone();
+        let freed = tracking.freed.clone();
+
+        let mut world = World::with_allocator(tracking);
+        assert_eq!(allocated.load(Ordering::SeqCst), 0);
+
+        for i in 0..100 {
+            world.spawn((
+                Position {
+                    x: i as f32,
+                    y: 0.0,
+                },
+                Velocity { x: 1.0, y: 0.0 },
+            ));
+        }
+        let column_bytes =
+            100 * (std::mem::size_of::<Position>() + std::mem::size_of::<Velocity>());
+        assert!(allocated.load(Ordering::SeqCst) >= column_bytes);
+        assert_eq!(world.query::<&Position>().map(|p| p.x).sum::<f32>(), 4950.0);
+
+        drop(world);
+        assert_eq!(
+            freed.load(Ordering::SeqCst),
+            allocated.load(Ordering::SeqCst)
+        );
+    }
+
+    #[test]
+    fn test_with_filter_skips_unmatched_archetypes() {
+        struct Huge([u8; 4096]);
+
+        let mut world = World::new();
+        let tagged = world.spawn((Health(1.0), Huge([7; 4096])));
+        world.spawn((Health(2.0),));
+        world.spawn((Huge([0; 4096]),));
+
+        let found: Vec<f32> = world
+            .query_filtered::<&Health, With<Huge>>()
+            .map(|h| h.0)
+            .collect();
+        assert_eq!(found, vec![1.0]);
+        assert_eq!(world.query_filtered::<&Health, With<Huge>>().count(), 1);
+        assert_eq!(world.get::<Huge>(tagged).unwrap().0[4095], 7);
+    }
+
+    #[test]
+    fn test_tick_system_param() {
+        use std::sync::{Arc, Mutex};
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorded = seen.clone();
+
+        let mut world = World::new();
+        let mut schedule = Schedule::new();
+        schedule.add_update_system(
+            (move |tick: Tick| recorded.lock().unwrap().push(tick.get())).into_system(),
+        );
+
+        for _ in 0..3 {
+            schedule.run(&mut world);
+        }
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 3);
+        assert_eq!(seen[1], seen[0] + 1);
+        assert_eq!(seen[2], seen[1] + 1);
+        assert_eq!(world.last_frame_tick(), seen[2]);
+    }
+
+    #[test]
+    #[cfg(feature = "change_detection")]
+    fn test_added_survives_archetype_move() {
+        let mut world = World::new();
+        world.tick();
+        let entity = world.spawn((Position { x: 1.0, y: 0.0 },));
+        assert_eq!(world.current_tick(), 1);
+
+        while world.current_tick() < 5 {
+            world.tick();
+        }
+        world.insert(entity, Velocity { x: 2.0, y: 0.0 }).unwrap();
+
+        assert_eq!(
+            world
+                .query_filtered::<&Position, (Added<Position>,)>()
+                .count(),
+            0
+        );
+        assert_eq!(
+            world
+                .query_filtered::<&Velocity, (Added<Velocity>,)>()
+                .count(),
+            1
+        );
+        // Moving archetypes doesn't count as a write either
+        assert_eq!(
+            world
+                .query_filtered::<&Position, (Changed<Position>,)>()
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_component_slice() {
+        let mut world = World::new();
+        for i in 0..10 {
+            world.spawn((Position {
+                x: i as f32,
+                y: 1.0,
+            },));
+            world.spawn((
+                Position {
+                    x: i as f32,
+                    y: 2.0,
+                },
+                Velocity { x: 0.0, y: 0.0 },
+            ));
+        }
+
+        let archetype_id = world
+            .archetypes_info()
+            .into_iter()
+            .find(|info| info.type_names.len() == 1 && info.len > 0)
+            .unwrap()
+            .id;
+
+        let slice = world.component_slice::<Position>(archetype_id).unwrap();
+        assert_eq!(slice.len(), 10);
+        let sum: f32 = slice.iter().map(|p| p.x + p.y).sum();
+        let expected: f32 = world
+            .query_filtered::<&Position, Without<Velocity>>()
+            .map(|p| p.x + p.y)
+            .sum();
+        assert_eq!(sum, expected);
+
+        for pos in world.component_slice_mut::<Position>(archetype_id).unwrap() {
+            pos.y = 0.0;
+        }
+        assert!(
+            world
+                .query_filtered::<&Position, Without<Velocity>>()
+                .all(|p| p.y == 0.0)
+        );
+        assert!(world.component_slice::<Velocity>(archetype_id).is_none());
+        assert!(world.component_slice::<Position>(usize::MAX).is_none());
+    }
+
+    #[test]
+    fn test_parallel_schedule_batch_diagnostics() {
+        let position = std::any::TypeId::of::<Position>();
+        let velocity = std::any::TypeId::of::<Velocity>();
+        let health = std::any::TypeId::of::<Health>();
+
+        let mut schedule = ParallelSchedule::new();
+        schedule.add_system(
+            (|_world: &mut World| {})
+                .into_system()
+                .with_name("mover")
+                .with_access(vec![velocity], vec![position]),
+        );
+        schedule.add_system(
+            (|_world: &mut World| {})
+                .into_system()
+                .with_name("healer")
+                .with_access(vec![], vec![health]),
+        );
+        schedule.add_system(
+            (|_world: &mut World| {})
+                .into_system()
+                .with_name("renderer")
+                .with_access(vec![position], vec![]),
+        );
+        schedule.add_system((|_world: &mut World| {}).into_system().with_name("opaque"));
+
+        let mut world = World::new();
+        assert!(schedule.last_batch_diagnostics().is_empty());
+        schedule.run(&mut world);
+
+        // `healer` shares the first batch, so only the systems after a
+        // boundary are reported, each with the conflict behind it
+        assert_eq!(
+            schedule.stage_batches(Stage::Update),
+            vec![vec!["mover", "healer"], vec!["renderer"], vec!["opaque"]]
+        );
+        assert_eq!(
+            schedule.last_batch_diagnostics(),
+            &[
+                BatchDiagnostic {
+                    system: "renderer".to_string(),
+                    batch: 1,
+                    after: "mover".to_string(),
+                    component: Some(position),
+                },
+                BatchDiagnostic {
+                    system: "opaque".to_string(),
+                    batch: 2,
+                    after: "renderer".to_string(),
+                    component: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "change_detection")]
+    fn test_optional_mut_query_is_lazy() {
+        let mut world = World::new();
+        world.spawn((Position { x: 0.0, y: 0.0 }, Velocity { x: 1.0, y: 0.0 }));
+        world.spawn((Position { x: 0.0, y: 0.0 },));
+        world.tick();
+
+        let mut present = 0;
+        for (_pos, vel) in world.query::<(&Position, Option<&mut Velocity>)>() {
+            if let Some(vel) = vel {
+                present += 1;
+                assert_eq!(vel.x, 1.0);
+            }
+        }
+        assert_eq!(present, 1);
+        assert_eq!(
+            world
+                .query_filtered::<&Velocity, (Changed<Velocity>,)>()
+                .count(),
+            0
+        );
+
+        for (_pos, vel) in world.query::<(&Position, Option<&mut Velocity>)>() {
+            if let Some(mut vel) = vel {
+                vel.x = 2.0;
+            }
+        }
+        assert_eq!(
+            world
+                .query_filtered::<&Velocity, (Changed<Velocity>,)>()
+                .map(|v| v.x)
+                .collect::<Vec<_>>(),
+            vec![2.0]
+        );
+    }
+
+    #[test]
+    fn test_spawn_at_preserves_ids() {
+        struct Target(Entity);
+
+        let mut source = World::new();
+        let _gap = source.spawn((Health(0.0),));
+        let target = source.spawn((Position { x: 4.0, y: 2.0 },));
+        let follower = source.spawn((Target(target), Health(1.0)));
+
+        let mut restored = World::new();
+        restored
+            .spawn_at(follower, (Target(target), Health(1.0)))
+            .unwrap();
+        restored
+            .spawn_at(target, (Position { x: 4.0, y: 2.0 },))
+            .unwrap();
+
+        let pointed = restored.get::<Target>(follower).unwrap().0;
+        assert_eq!(pointed, target);
+        assert_eq!(restored.get::<Position>(pointed).unwrap().x, 4.0);
+        assert!(restored.is_alive(target) && restored.is_alive(follower));
+
+        assert!(matches!(
+            restored.spawn_at(target, (Health(2.0),)),
+            Err(EcsError::InvalidOperation(_))
+        ));
+        assert!(restored.get::<Health>(target).is_none());
+
+        // Fresh spawns don't collide with the restored ids
+        let fresh = restored.spawn((Health(3.0),));
+        assert!(fresh != target && fresh != follower);
+    }
+
+    #[test]
+    fn test_entity_bits_round_trip() {
+        let mut world = World::new();
+        let first = world.spawn((Health(1.0),));
+        world.despawn(first);
+        let recycled = world.spawn((Health(2.0),));
+
+        assert_ne!(first, recycled);
+        assert_eq!(Entity::from_bits(first.to_bits()), first);
+        assert_eq!(Entity::from_bits(recycled.to_bits()), recycled);
+        assert_eq!(first.to_bits() as u32, recycled.to_bits() as u32);
+
+        let restored = Entity::from_bits(recycled.to_bits());
+        assert_eq!(world.get::<Health>(restored).unwrap().0, 2.0);
+        assert!(
+            world
+                .get::<Health>(Entity::from_bits(first.to_bits()))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_insert_with_derived_component() {
+        #[derive(Debug, PartialEq)]
+        struct NextPosition {
+            x: f32,
+            y: f32,
+        }
+
+        let mut world = World::new();
+        let moving = world.spawn((Position { x: 1.0, y: 2.0 }, Velocity { x: 0.5, y: -1.0 }));
+        let still = world.spawn((Position { x: 3.0, y: 3.0 },));
+
+        for entity in [moving, still] {
+            world
+                .insert_with(entity, |e| {
+                    let pos = e.get::<Position>().unwrap();
+                    let vel = e.get::<Velocity>().map_or((0.0, 0.0), |v| (v.x, v.y));
+                    NextPosition {
+                        x: pos.x + vel.0,
+                        y: pos.y + vel.1,
+                    }
+                })
+                .unwrap();
+        }
+
+        assert_eq!(
+            world.get::<NextPosition>(moving),
+            Some(&NextPosition { x: 1.5, y: 1.0 })
+        );
+        assert_eq!(
+            world.get::<NextPosition>(still),
+            Some(&NextPosition { x: 3.0, y: 3.0 })
+        );
+        assert_eq!(world.get::<Position>(moving).unwrap().x, 1.0);
+
+        world.despawn(still);
+        assert!(matches!(
+            world.insert_with(still, |_| Health(1.0)),
+            Err(EcsError::EntityNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_insert_and_remove_in_populated_archetypes() {
+        let mut world = World::new();
+        let entities: Vec<_> = (0..5)
+            .map(|i| {
+                world.spawn((Position {
+                    x: i as f32,
+                    y: 0.0,
+                },))
+            })
+            .collect();
+        // Populate the destination archetype before any moves into it
+        world.spawn((Position { x: 10.0, y: 0.0 }, Velocity { x: 1.0, y: 0.0 }));
+        world.tick();
+
+        world
+            .insert(entities[2], Velocity { x: 2.0, y: 0.0 })
+            .unwrap();
+        world
+            .insert(entities[0], Velocity { x: 3.0, y: 0.0 })
+            .unwrap();
+        world.remove::<Velocity>(entities[2]).unwrap();
+
+        for (i, &entity) in entities.iter().enumerate() {
+            assert_eq!(world.get::<Position>(entity).unwrap().x, i as f32);
+        }
+        assert_eq!(world.get::<Velocity>(entities[0]).unwrap().x, 3.0);
+        assert!(world.get::<Velocity>(entities[2]).is_none());
+        assert_eq!(world.query::<(&Position, &Velocity)>().count(), 2);
+
+        #[cfg(feature = "change_detection")]
+        assert_eq!(
+            world
+                .query_filtered::<&Velocity, (Changed<Velocity>,)>()
+                .map(|v| v.x)
+                .collect::<Vec<_>>(),
+            vec![3.0]
+        );
+    }
+
+    #[test]
+    fn test_spawn_dynamic_bundle() {
+        struct Name(&'static str);
+        struct Level(u32);
+        struct Mana(f32);
+        struct Team(u8);
+
+        let mut bundle = DynamicBundle::new();
+        bundle
+            .add(Position { x: 1.0, y: 2.0 })
+            .add(Velocity { x: 3.0, y: 4.0 })
+            .add(Health(50.0))
+            .add(Name("mage"))
+            .add(Level(1))
+            .add(Mana(10.0))
+            .add(Team(2))
+            .add(Level(7));
+        assert_eq!(bundle.len(), 7);
+
+        let mut world = World::new();
+        let entity = world.spawn_dynamic_bundle(bundle);
+        world.spawn((Position { x: 0.0, y: 0.0 },));
+
+        let found: Vec<_> = world
+            .query::<(&Position, &Velocity, &Health, &Name, &Level, &Mana)>()
+            .map(|(p, v, h, n, l, m)| (p.x + p.y, v.x + v.y, h.0, n.0, l.0, m.0))
+            .collect();
+        assert_eq!(found, vec![(3.0, 7.0, 50.0, "mage", 7, 10.0)]);
+        assert_eq!(world.get::<Team>(entity).unwrap().0, 2);
+
+        // A second bundle with the same types reuses the archetype
+        let mut other = DynamicBundle::new();
+        other.add(Team(1)).add(Mana(0.0)).add(Level(2));
+        other
+            .add(Name("rogue"))
+            .add(Health(1.0))
+            .add(Velocity { x: 0.0, y: 0.0 })
+            .add(Position { x: 0.0, y: 0.0 });
+        let before = world.archetypes.iter().count();
+        world.spawn_dynamic_bundle(other);
+        assert_eq!(world.archetypes.iter().count(), before);
+        assert_eq!(world.query::<&Team>().count(), 2);
+    }
+
+    #[test]
+    fn test_resource_introspection() {
+        struct Gravity(f32);
+        #[derive(Clone)]
+        struct Score(u32);
+
+        let mut world = World::new();
+        assert!(world.resource_type_names().is_empty());
+
+        world.insert_resource(Gravity(9.8));
+        world.insert_resource(Score(0));
+        assert!(world.contains_resource::<Gravity>());
+        assert!(world.contains_resource::<Score>());
+        assert!(!world.contains_resource::<u64>());
+
+        let names = world.resource_type_names();
+        assert_eq!(names.len(), 2);
+        assert!(names.iter().any(|name| name.ends_with("Gravity")));
+        assert!(names.iter().any(|name| name.ends_with("Score")));
+
+        // Names survive a checkpoint round trip through a removal
+        world.register_clonable_resource::<Score>();
+        let checkpoint = world.checkpoint_resources();
+        world.remove_resource::<Score>();
+        assert!(!world.contains_resource::<Score>());
+        world.restore_resources(checkpoint);
+        assert_eq!(world.resource_type_names(), names);
+    }
+
+    #[test]
+    fn test_scan_despawn() {
+        let mut world = World::new();
+        let entities: Vec<_> = (0..1000)
+            .map(|i| {
+                world.spawn((
+                    Position {
+                        x: i as f32,
+                        y: 0.0,
+                    },
+                    Health(1.0),
+                ))
+            })
+            .collect();
+        world.spawn((Velocity { x: 0.0, y: 0.0 },));
+
+        let mut visited = Vec::new();
+        let despawned = world.scan_despawn::<&Position>(|entity, pos| {
+            visited.push(entity);
+            pos.x as u32 % 2 == 0
+        });
+        assert_eq!(despawned, 500);
+
+        visited.sort();
+        let mut expected = entities.clone();
+        expected.sort();
+        assert_eq!(visited, expected);
+
+        for (i, &entity) in entities.iter().enumerate() {
+            assert_eq!(world.is_alive(entity), i % 2 == 1);
+            if i % 2 == 1 {
+                assert_eq!(world.get::<Position>(entity).unwrap().x, i as f32);
+            }
+        }
+        assert_eq!(world.query::<&Health>().count(), 500);
+        assert_eq!(world.query::<&Velocity>().count(), 1);
+    }
+
+    #[test]
+    fn test_component_handle_survives_moves() {
+        let mut world = World::new();
+        let other = world.spawn((Position { x: -1.0, y: 0.0 },));
+        let entity = world.spawn((Position { x: 1.0, y: 2.0 },));
+        let handle = world.track::<Position>(entity);
+        assert_eq!(handle.get(&world).unwrap().x, 1.0);
+
+        // Moving archetypes swap-removes `entity` out of its old row
+        world.insert(entity, Velocity { x: 0.0, y: 0.0 }).unwrap();
+        world.despawn(other);
+        assert_eq!(handle.get(&world).unwrap().y, 2.0);
+
+        handle.get_mut(&mut world).unwrap().x = 5.0;
+        assert_eq!(world.get::<Position>(entity).unwrap().x, 5.0);
+
+        world.remove::<Position>(entity).unwrap();
+        assert!(handle.get(&world).is_none());
+        world.despawn(entity);
+        assert!(handle.get(&world).is_none());
+    }
+
+    #[test]
+    fn test_swap_components() {
+        #[derive(Debug, PartialEq)]
+        struct Equipment(Vec<&'static str>);
+
+        let mut world = World::new();
+        let knight = world.spawn((Equipment(vec!["sword", "shield"]),));
+        let archer = world.spawn((Equipment(vec!["bow"]), Velocity { x: 0.0, y: 0.0 }));
+        let squire = world.spawn((Equipment(vec![]),));
+        let peasant = world.spawn((Health(1.0),));
+        world.tick();
+
+        // Across archetypes
+        world.swap::<Equipment>(knight, archer).unwrap();
+        assert_eq!(world.get::<Equipment>(knight).unwrap().0, vec!["bow"]);
+        assert_eq!(
+            world.get::<Equipment>(archer).unwrap().0,
+            vec!["sword", "shield"]
+        );
+
+        #[cfg(feature = "change_detection")]
+        assert_eq!(
+            world
+                .query_filtered::<&Equipment, (Changed<Equipment>,)>()
+                .count(),
+            2
+        );
+
+        // Within one archetype
+        world.swap::<Equipment>(knight, squire).unwrap();
+        assert!(world.get::<Equipment>(knight).unwrap().0.is_empty());
+        assert_eq!(world.get::<Equipment>(squire).unwrap().0, vec!["bow"]);
+
+        assert!(matches!(
+            world.swap::<Equipment>(knight, peasant),
+            Err(EcsError::ComponentNotFound { .. })
+        ));
+        world.despawn(squire);
+        assert!(matches!(
+            world.swap::<Equipment>(squire, knight),
+            Err(EcsError::EntityNotFound(_))
+        ));
+        assert_eq!(
+            world.get::<Equipment>(archer).unwrap().0,
+            vec!["sword", "shield"]
+        );
+    }
+
+    #[test]
+    fn test_query_hierarchical() {
+        struct Node(u32);
+
+        let mut world = World::new();
+        // Spawn children before parents so archetype order alone is wrong
+        let leaf = world.spawn((Node(3),));
+        let mid = world.spawn((Node(2), Health(1.0)));
+        let root = world.spawn((Node(1),));
+        let sibling = world.spawn((Node(4),));
+        world.insert(leaf, Parent(mid)).unwrap();
+        world.insert(mid, Parent(root)).unwrap();
+        world.insert(sibling, Parent(root)).unwrap();
+
+        // A two-entity cycle hanging a third entity below it
+        let x = world.spawn((Node(10),));
+        let y = world.spawn((Node(11),));
+        let below = world.spawn((Node(12),));
+        world.insert(x, Parent(y)).unwrap();
+        world.insert(y, Parent(x)).unwrap();
+        world.insert(below, Parent(y)).unwrap();
+
+        let order: Vec<u32> = world.query_hierarchical::<&Node>().map(|n| n.0).collect();
+        assert_eq!(order.len(), 7);
+        let position = |id: u32| order.iter().position(|&n| n == id).unwrap();
+        assert!(position(1) < position(2));
+        assert!(position(2) < position(3));
+        assert!(position(1) < position(4));
+
+        // The cycle is cut above its smallest entity
+        let (first, second) = if x < y { (10, 11) } else { (11, 10) };
+        assert!(position(first) < position(second));
+        if second == 11 {
+            assert!(position(11) < position(12));
+        }
+        assert_eq!(
+            world
+                .query_hierarchical::<&Node>()
+                .map(|n| n.0)
+                .collect::<Vec<_>>(),
+            order
+        );
+    }
+
+    #[test]
+    fn test_insert_batch_moves_runs() {
+        let mut world = World::new();
+        let entities: Vec<_> = (0..1000)
+            .map(|i| {
+                world.spawn((
+                    Position {
+                        x: i as f32,
+                        y: -(i as f32),
+                    },
+                    Health(i as f32),
+                ))
+            })
+            .collect();
+        let mixed = world.spawn((Position { x: 0.0, y: 0.0 }, Velocity { x: 9.0, y: 0.0 }));
+
+        // Every entity but a few gaps, so the source splits into several runs
+        let mut batch: Vec<_> = entities
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| i % 250 != 7)
+            .map(|(i, &e)| {
+                (
+                    e,
+                    Velocity {
+                        x: i as f32,
+                        y: 0.0,
+                    },
+                )
+            })
+            .collect();
+        batch.push((mixed, Velocity { x: -1.0, y: 0.0 }));
+        world.insert_batch(batch).unwrap();
+
+        for (i, &entity) in entities.iter().enumerate() {
+            let pos = world.get::<Position>(entity).unwrap();
+            assert_eq!((pos.x, pos.y), (i as f32, -(i as f32)));
+            assert_eq!(world.get::<Health>(entity).unwrap().0, i as f32);
+            let velocity = world.get::<Velocity>(entity).map(|v| v.x);
+            if i % 250 == 7 {
+                assert_eq!(velocity, None);
+            } else {
+                assert_eq!(velocity, Some(i as f32));
+            }
+        }
+        assert_eq!(world.get::<Velocity>(mixed).unwrap().x, -1.0);
+        assert_eq!(
+            world.query::<(&Position, &Health, &Velocity)>().count(),
+            996
+        );
+        assert_eq!(
+            world.query_filtered::<&Health, Without<Velocity>>().count(),
+            4
+        );
+
+        let dup = entities[7];
+        assert!(matches!(
+            world.insert_batch([
+                (dup, Velocity { x: 0.0, y: 0.0 }),
+                (dup, Velocity { x: 1.0, y: 0.0 })
+            ]),
+            Err(EcsError::InvalidOperation(_))
+        ));
+        assert!(world.get::<Velocity>(dup).is_none());
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn test_query_skips_partially_initialized_archetype() {
+        let mut world = World::new();
+        world.spawn((Position { x: 1.0, y: 2.0 },));
+
+        // An archetype that claims Position but never got its column
+        let ghost = world.spawn_dynamic_bundle(DynamicBundle::new());
+        let id = world.archetypes.get_or_create(
+            vec![
+                std::any::TypeId::of::<Position>(),
+                std::any::TypeId::of::<Health>(),
+            ],
+            vec!["Position", "Health"],
+        );
+        world.archetypes.get_mut(id).unwrap().push_entity(ghost);
+
+        assert_eq!(world.query::<&Position>().size_hint(), (1, Some(1)));
+        assert_eq!(world.query_count::<&Position>(), 1);
+        let xs: Vec<f32> = world.query::<&Position>().map(|pos| pos.x).collect();
+        assert_eq!(xs, vec![1.0]);
+        assert_eq!(world.query::<(&mut Position, Option<&Health>)>().count(), 1);
+        assert_eq!(world.query::<&Position>().rev().count(), 1);
+    }
+
+    #[test]
+    fn test_for_each_in_archetype() {
+        let mut world = World::new();
+        let a = world.spawn((Position { x: 1.0, y: 0.0 }, Velocity { x: 0.0, y: 0.0 }));
+        let b = world.spawn((Position { x: 2.0, y: 0.0 }, Velocity { x: 0.0, y: 0.0 }));
+        world.spawn((Position { x: 3.0, y: 0.0 },));
+
+        let id = world
+            .archetypes_info()
+            .into_iter()
+            .find(|info| info.type_names.len() == 2 && info.len == 2)
+            .unwrap()
+            .id;
+
+        let mut entities = Vec::new();
+        world.for_each_in_archetype(id, |entity| entities.push(entity));
+        assert_eq!(entities, vec![a, b]);
+
+        let mut xs = Vec::new();
+        world.for_each_component_in_archetype::<Position>(id, |entity, pos| {
+            xs.push((entity, pos.x))
+        });
+        assert_eq!(xs, vec![(a, 1.0), (b, 2.0)]);
+
+        let mut visited = 0;
+        world.for_each_component_in_archetype::<Health>(id, |_, _| visited += 1);
+        world.for_each_in_archetype(usize::MAX, |_| visited += 1);
+        assert_eq!(visited, 0);
+    }
+
+    #[test]
+    fn test_res_mut_system_param() {
+        struct Score(u32);
+
+        let mut world = World::new();
+        world.insert_resource(Score(1));
+        world.spawn((Position { x: 0.0, y: 0.0 },));
+        let mut schedule = Schedule::new();
+        schedule.add_update_system(
+            (|mut score: ResMut<Score>| {
+                score.0 += 1;
+                // Reads through the same handle don't take the lock again
+                let doubled = score.0 * 2;
+                score.0 = doubled;
+            })
+            .into_system(),
+        );
+        schedule.run(&mut world);
+        schedule.run(&mut world);
+
+        assert_eq!(world.get_resource::<Score>().unwrap().0, 10);
+        // The lock is released once the run is over
+        world.get_resource_mut::<Score>().unwrap().0 = 0;
+        assert_eq!(world.get_resource::<Score>().unwrap().0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "borrows a resource mutably")]
+    fn test_res_and_res_mut_of_same_type_panics() {
+        struct Score(u32);
+
+        let _ = (|_: Res<Score>, mut score: ResMut<Score>| score.0 += 1).into_system();
+    }
+
+    #[test]
+    fn test_chunked_archetypes() {
+        let mut world = World::with_chunk_size(1024);
+        assert_eq!(world.chunk_size(), Some(1024));
+        let entities: Vec<Entity> = (0..10_000)
+            .map(|i| {
+                world.spawn((Position {
+                    x: i as f32,
+                    y: 0.0,
+                },))
+            })
+            .collect();
+
+        let xs: Vec<f32> = world.query::<&Position>().map(|pos| pos.x).collect();
+        assert_eq!(xs, (0..10_000).map(|i| i as f32).collect::<Vec<_>>());
+
+        let location = world.entity_meta(entities[0]).unwrap();
+        let archetype = world.archetypes.get(location.archetype).unwrap();
+        assert_eq!(archetype.columns[0].capacity, 10 * 1024);
+
+        let lens: Vec<usize> = world
+            .component_chunks::<Position>(location.archetype)
+            .unwrap()
+            .map(|chunk| chunk.len())
+            .collect();
+        assert_eq!(lens.len(), 10);
+        assert!(lens[..9].iter().all(|&len| len == 1024));
+        assert_eq!(lens[9], 10_000 - 9 * 1024);
+        assert!(
+            world
+                .component_slice::<Position>(location.archetype)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_chunks_are_separate_allocations() {
+        let mut world = World::with_chunk_size(4);
+        let first = world.spawn((Position { x: 0.0, y: 0.0 },));
+        let address = world.get::<Position>(first).unwrap() as *const Position;
+
+        let entities: Vec<Entity> = (1..10)
+            .map(|i| {
+                world.spawn((Position {
+                    x: i as f32,
+                    y: 0.0,
+                },))
+            })
+            .collect();
+        // Growing added chunks instead of reallocating the first one
+        assert_eq!(
+            world.get::<Position>(first).unwrap() as *const Position,
+            address
+        );
+
+        // Swap-removes and moves copy rows across chunk boundaries
+        assert!(world.despawn(entities[0]));
+        world
+            .insert(entities[1], Velocity { x: 1.0, y: 0.0 })
+            .unwrap();
+        for &entity in &entities[2..] {
+            world.insert(entity, Velocity { x: 2.0, y: 0.0 }).unwrap();
+        }
+
+        let mut xs: Vec<f32> = world
+            .query::<(&Position, &Velocity)>()
+            .map(|(pos, _)| pos.x)
+            .collect();
+        xs.sort_by(f32::total_cmp);
+        assert_eq!(xs, (2..10).map(|i| i as f32).collect::<Vec<_>>());
+        assert_eq!(world.get::<Position>(first).unwrap().x, 0.0);
+        assert_eq!(world.get::<Velocity>(entities[1]).unwrap().x, 1.0);
+    }
+
+    #[test]
+    fn test_replace_resource() {
+        #[derive(Debug, PartialEq)]
+        struct Config(&'static str);
+
+        let mut world = World::new();
+        assert_eq!(world.replace_resource(Config("a")), None);
+        assert_eq!(world.replace_resource(Config("b")), Some(Config("a")));
+        assert_eq!(world.replace_resource(Config("c")), Some(Config("b")));
+        assert_eq!(world.get_resource::<Config>().unwrap().0, "c");
+    }
+
+    #[test]
+    fn test_query_zip() {
+        let mut world = World::new();
+        for i in 0..4 {
+            world.spawn((Position {
+                x: i as f32,
+                y: 0.0,
+            },));
+        }
+        world.spawn((Position { x: 4.0, y: 0.0 }, Velocity { x: 0.0, y: 0.0 }));
+
+        let mut forces: Vec<f32> = world.query::<&Position>().map(|pos| pos.x * 10.0).collect();
+        for (pos, force) in world.query_zip::<&mut Position, _>(&mut forces) {
+            pos.y = *force;
+            *force = 0.0;
+        }
+
+        for pos in world.query::<&Position>() {
+            assert_eq!(pos.y, pos.x * 10.0);
+        }
+        assert!(forces.iter().all(|&force| force == 0.0));
+    }
+
+    #[test]
+    #[should_panic(expected = "query_zip")]
+    fn test_query_zip_length_mismatch() {
+        let mut world = World::new();
+        world.spawn((Position { x: 0.0, y: 0.0 },));
+        let mut data = [0u8; 2];
+        let _ = world.query_zip::<&Position, _>(&mut data);
+    }
+
+    #[test]
+    fn test_despawn_hierarchy_cleanup() {
+        let mut world = World::new();
+        world.set_hierarchy_cleanup(true);
+        let parent = world.spawn((Position { x: 0.0, y: 0.0 },));
+        let a = world.spawn((Position { x: 1.0, y: 0.0 }, Parent(parent)));
+        let b = world.spawn((Position { x: 2.0, y: 0.0 }, Parent(parent)));
+        let grandchild = world.spawn((Position { x: 3.0, y: 0.0 }, Parent(b)));
+        world.insert(parent, Children(vec![a, b])).unwrap();
+        world.insert(b, Children(vec![grandchild])).unwrap();
+
+        assert!(world.despawn(a));
+        assert_eq!(world.get::<Children>(parent), Some(&Children(vec![b])));
+
+        // Orphan is the default policy
+        world.despawn(parent);
+        assert!(world.is_alive(b));
+        assert!(world.get::<Parent>(b).is_none());
+
+        world.set_child_policy(ChildPolicy::Despawn);
+        world.despawn(b);
+        assert!(!world.is_alive(grandchild));
+    }
+
+    #[test]
+    fn test_batch_despawns_clean_up_hierarchy() {
+        let mut world = World::new();
+        world.set_hierarchy_cleanup(true);
+        let parent = world.spawn((Position { x: 0.0, y: 0.0 },));
+        let a = world.spawn((Position { x: 1.0, y: 0.0 }, Parent(parent)));
+        let b = world.spawn((Position { x: 2.0, y: 0.0 }, Parent(parent)));
+        let c = world.spawn((Position { x: 3.0, y: 0.0 }, Parent(parent)));
+        world.insert(parent, Children(vec![a, b, c])).unwrap();
+
+        assert_eq!(world.despawn_batch(&[a]), 1);
+        assert_eq!(world.get::<Children>(parent), Some(&Children(vec![b, c])));
+
+        let despawned = world.scan_despawn::<&Position>(|_, pos| pos.x == 2.0);
+        assert_eq!(despawned, 1);
+        assert_eq!(world.get::<Children>(parent), Some(&Children(vec![c])));
+
+        // Orphaned rather than left pointing at a dead parent
+        assert_eq!(world.despawn_batch(&[parent]), 1);
+        assert!(world.is_alive(c));
+        assert!(world.get::<Parent>(c).is_none());
+
+        world.set_child_policy(ChildPolicy::Despawn);
+        let root = world.spawn((Position { x: 4.0, y: 0.0 },));
+        let leaf = world.spawn((Position { x: 5.0, y: 0.0 }, Parent(root)));
+        world.insert(root, Children(vec![leaf])).unwrap();
+        world.scan_despawn::<&Position>(|entity, _| entity == root);
+        assert!(!world.is_alive(leaf));
+    }
+
+    #[test]
+    fn test_scan_despawn_visits_each_entity_once_with_hierarchy() {
+        for policy in [ChildPolicy::Orphan, ChildPolicy::Despawn] {
+            let mut world = World::new();
+            world.set_hierarchy_cleanup(true);
+            world.set_child_policy(policy);
+            // Parents and children share an archetype, so applying the policy
+            // mid-pass would move rows the pass hasn't reached
+            let root = world.spawn((Position { x: 0.0, y: 0.0 },));
+            let parents: Vec<_> = (0..4)
+                .map(|i| {
+                    world.spawn((
+                        Position {
+                            x: i as f32,
+                            y: 0.0,
+                        },
+                        Parent(root),
+                    ))
+                })
+                .collect();
+            let mut children = Vec::new();
+            for &parent in &parents {
+                let kids: Vec<_> = (0..3)
+                    .map(|i| {
+                        world.spawn((
+                            Position {
+                                x: 10.0 + i as f32,
+                                y: 0.0,
+                            },
+                            Parent(parent),
+                        ))
+                    })
+                    .collect();
+                world.insert(parent, Children(kids.clone())).unwrap();
+                children.extend(kids);
+            }
+            world.insert(root, Children(parents.clone())).unwrap();
+
+            let mut visited = Vec::new();
+            let despawned = world.scan_despawn::<&Position>(|entity, pos| {
+                visited.push(entity);
+                pos.x < 10.0 && entity != root
+            });
+            assert_eq!(despawned, parents.len());
+            visited.sort();
+            let mut expected: Vec<_> = [root]
+                .into_iter()
+                .chain(parents)
+                .chain(children.clone())
+                .collect();
+            expected.sort();
+            assert_eq!(visited, expected);
+
+            assert_eq!(world.get::<Children>(root), Some(&Children(Vec::new())));
+            for child in children {
+                match policy {
+                    ChildPolicy::Orphan => assert!(world.get::<Parent>(child).is_none()),
+                    ChildPolicy::Despawn => assert!(!world.is_alive(child)),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_filters_macro() {
+        struct A;
+        struct B;
+        struct C;
+        struct D;
+
+        let mut world = World::new();
+        world.spawn((Position { x: 0.0, y: 0.0 }, A, B));
+        world.spawn((Position { x: 1.0, y: 0.0 }, A, B, C));
+        world.spawn((Position { x: 2.0, y: 0.0 }, A, B, D));
+        world.spawn((Position { x: 3.0, y: 0.0 }, A));
+
+        let by_macro: Vec<f32> = world
+            .query_filtered::<&Position, filters!(With<A>, With<B>, Without<C>, Without<D>)>()
+            .map(|pos| pos.x)
+            .collect();
+        let by_hand: Vec<f32> = world
+            .query_filtered::<&Position, (With<A>, With<B>, Without<C>, Without<D>)>()
+            .map(|pos| pos.x)
+            .collect();
+        assert_eq!(by_macro, vec![0.0]);
+        assert_eq!(by_macro, by_hand);
+    }
+
+    #[test]
+    fn test_world_try_clone() {
+        #[derive(Debug, Clone, PartialEq)]
+        struct Gravity(f32);
+
+        let mut world = World::new();
+        world.register_clonable::<Position>();
+        world.register_clonable::<Velocity>();
+        world.register_clonable_resource::<Gravity>();
+        world.insert_resource(Gravity(9.8));
+        let a = world.spawn((Position { x: 1.0, y: 0.0 }, Velocity { x: 1.0, y: 1.0 }));
+        let b = world.spawn((Position { x: 2.0, y: 0.0 },));
+
+        let mut copy = world.try_clone().unwrap();
+        assert_eq!(copy.get::<Position>(a).unwrap().x, 1.0);
+        assert_eq!(copy.get::<Velocity>(a).unwrap().y, 1.0);
+        copy.get_mut::<Position>(a).unwrap().x = 100.0;
+        copy.despawn(b);
+        copy.get_resource_mut::<Gravity>().unwrap().0 = 0.0;
+        copy.spawn((Position { x: 3.0, y: 0.0 },));
+
+        assert_eq!(world.get::<Position>(a).unwrap().x, 1.0);
+        assert!(world.is_alive(b));
+        assert_eq!(world.get_resource::<Gravity>().unwrap().0, 9.8);
+        assert_eq!(world.query::<&Position>().count(), 2);
+
+        world.spawn((Health(10.0),));
+        match world.try_clone() {
+            Err(EcsError::InvalidOperation(message)) => assert!(message.contains("Health")),
+            _ => panic!("expected an unclonable component error"),
+        }
+    }
+
+    #[test]
+    fn test_world_try_clone_keeps_storage_state() {
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        struct Selected(u32);
+        #[derive(Debug, Clone, PartialEq)]
+        struct Hit(u32);
+
+        let mut world = World::new();
+        world.register_clonable::<Position>();
+        world.register_clonable::<Selected>();
+        world.register_sparse::<Selected>();
+        world.register_clonable_event::<Hit>();
+
+        let a = world.spawn((Position { x: 1.0, y: 0.0 },));
+        let freed = world.spawn((Position { x: 2.0, y: 0.0 },));
+        world.spawn((Position { x: 5.0, y: 0.0 },));
+        world.tick();
+        let b = world.spawn((Position { x: 3.0, y: 0.0 }, Selected(1)));
+        world.get_mut::<Position>(a).unwrap().x = 10.0;
+        assert!(world.despawn(freed));
+        world.send_event(Hit(7));
+
+        let mut copy = world.try_clone().unwrap();
+
+        // Changed, added and spawn ticks carry over
+        #[cfg(feature = "change_detection")]
+        {
+            let changed = |world: &mut World| -> Vec<f32> {
+                world
+                    .query_filtered::<&Position, Changed<Position>>()
+                    .map(|p| p.x)
+                    .collect()
+            };
+            assert_eq!(changed(&mut world), vec![10.0, 3.0]);
+            assert_eq!(changed(&mut copy), vec![10.0, 3.0]);
+            assert_eq!(
+                copy.query_filtered::<&Selected, Added<Selected>>().count(),
+                1
+            );
+        }
+        assert_eq!(copy.entity_age(a), Some(1));
+        assert_eq!(copy.entity_age(b), Some(0));
+
+        // Sparse components and events are copied
+        assert_eq!(copy.get::<Selected>(b), Some(&Selected(1)));
+        assert_eq!(copy.query::<(&Position, &Selected)>().count(), 1);
+        assert_eq!(copy.drain_events::<Hit>(), vec![Hit(7)]);
+        assert_eq!(world.drain_events::<Hit>(), vec![Hit(7)]);
+
+        // The freed slot is reused with the same version in both worlds
+        assert!(!copy.is_alive(freed));
+        let reused = world.spawn((Position { x: 0.0, y: 0.0 },));
+        assert_eq!(copy.spawn((Position { x: 0.0, y: 0.0 },)), reused);
+
+        copy.get_mut::<Selected>(b).unwrap().0 = 2;
+        assert_eq!(world.get::<Selected>(b), Some(&Selected(1)));
+
+        world.send_event(Position { x: 0.0, y: 0.0 });
+        match world.try_clone() {
+            Err(EcsError::InvalidOperation(message)) => assert!(message.contains("Position")),
+            _ => panic!("expected an unclonable event error"),
+        }
+    }
+
+    #[test]
+    fn test_component_index_orders_archetypes() {
+        fn build(register: impl Fn(&mut World)) -> World {
+            let mut world = World::new();
+            register(&mut world);
+            world.spawn((Position { x: 0.0, y: 0.0 }, Velocity { x: 0.0, y: 0.0 }));
+            world.spawn((Health(1.0),));
+            world.spawn((
+                Velocity { x: 0.0, y: 0.0 },
+                Position { x: 0.0, y: 0.0 },
+                Health(1.0),
+            ));
+            world
+        }
+
+        let velocity_first = |world: &mut World| {
+            assert_eq!(world.register_component::<Velocity>().get(), 0);
+            assert_eq!(world.register_component::<Position>().get(), 1);
+            assert_eq!(world.register_component::<Health>().get(), 2);
+        };
+        let a = build(velocity_first);
+        let b = build(velocity_first);
+        assert_eq!(a.archetypes_info(), b.archetypes_info());
+        assert_eq!(
+            a.component_index::<Position>(),
+            b.component_index::<Position>()
+        );
+
+        let names = |world: &World| {
+            let info = world.archetypes_info();
+            info.iter()
+                .find(|i| i.type_names.len() == 3)
+                .unwrap()
+                .type_names
+                .clone()
+        };
+        assert!(names(&a)[0].ends_with("Velocity"));
+        assert!(names(&a)[2].ends_with("Health"));
+
+        let c = build(|world| {
+            world.register_component::<Health>();
+            world.register_component::<Position>();
+        });
+        assert!(names(&c)[0].ends_with("Health"));
+        assert!(names(&c)[1].ends_with("Position"));
+        assert!(names(&c)[2].ends_with("Velocity"));
+    }
+
+    #[test]
+    fn test_state_transition_systems() {
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        enum GameState {
+            Menu,
+            Playing,
+        }
+
+        #[derive(Default)]
+        struct Log(Vec<&'static str>);
+
+        fn record(world: &mut World, event: &'static str) {
+            world.get_resource_mut::<Log>().unwrap().0.push(event);
+        }
+
+        let mut world = World::new();
+        world.insert_resource(Log::default());
+        world.insert_resource(State::new(GameState::Menu));
+
+        let mut schedule = Schedule::new();
+        schedule.add_enter_system(
+            GameState::Playing,
+            (|world: &mut World| record(world, "enter playing")).into_system(),
+        );
+        schedule.add_exit_system(
+            GameState::Playing,
+            (|world: &mut World| record(world, "exit playing")).into_system(),
+        );
+        schedule.add_enter_system(
+            GameState::Menu,
+            (|world: &mut World| record(world, "enter menu")).into_system(),
+        );
+        schedule.add_exit_system(
+            GameState::Menu,
+            (|world: &mut World| record(world, "exit menu")).into_system(),
+        );
+
+        schedule.run(&mut world);
+        assert!(world.get_resource::<Log>().unwrap().0.is_empty());
+
+        world
+            .get_resource_mut::<State<GameState>>()
+            .unwrap()
+            .set(GameState::Playing);
+        schedule.run(&mut world);
+        schedule.run(&mut world);
+        assert_eq!(
+            world.get_resource::<State<GameState>>().unwrap().get(),
+            &GameState::Playing
+        );
+        assert_eq!(
+            world.get_resource::<Log>().unwrap().0,
+            vec!["exit menu", "enter playing"]
+        );
+
+        // Setting the current value again is not a transition
+        let mut state = world.get_resource_mut::<State<GameState>>().unwrap();
+        state.set(GameState::Playing);
+        state.set(GameState::Menu);
+        drop(state);
+        schedule.run(&mut world);
+        assert_eq!(
+            world.get_resource::<Log>().unwrap().0,
+            vec!["exit menu", "enter playing", "exit playing", "enter menu"]
+        );
+    }
+
+    #[test]
+    fn test_for_each_resource() {
+        struct Gravity(f32);
+        struct Seed(u64);
+
+        let mut world = World::new();
+        world.insert_resource(Gravity(9.8));
+        world.insert_resource(Seed(42));
+
+        let mut visited = Vec::new();
+        let mut seed = None;
+        world.for_each_resource(|type_id, name, value| {
+            visited.push((type_id, name));
+            if let Some(value) = value.downcast_ref::<Seed>() {
+                seed = Some(value.0);
+            }
+        });
+
+        assert_eq!(visited.len(), 2);
+        assert!(visited.contains(&(
+            std::any::TypeId::of::<Gravity>(),
+            std::any::type_name::<Gravity>()
+        )));
+        assert!(visited.contains(&(
+            std::any::TypeId::of::<Seed>(),
+            std::any::type_name::<Seed>()
+        )));
+        assert_eq!(seed, Some(42));
+    }
+
+    #[test]
+    fn test_get_checked() {
+        let mut world = World::new();
+        let entity = world.spawn((Position { x: 1.0, y: 0.0 },));
+
+        assert_eq!(world.get_checked::<Position>(entity).unwrap().x, 1.0);
+        match world.get_checked::<Velocity>(entity) {
+            Err(EcsError::ComponentNotFound {
+                entity: e,
+                type_name,
+            }) => {
+                assert_eq!(e, entity);
+                assert!(type_name.contains("Velocity"));
+            }
+            _ => panic!("expected ComponentNotFound"),
+        }
+
+        world.despawn(entity);
+        assert!(matches!(
+            world.get_checked::<Position>(entity),
+            Err(EcsError::EntityNotFound(e)) if e == entity
+        ));
+    }
+
+    #[test]
+    fn test_parallel_schedule_stages() {
+        use std::sync::{Arc, Mutex};
+
+        let observed = Arc::new(Mutex::new(Vec::new()));
+        let sink = observed.clone();
+
+        let mut world = World::new();
+        world.spawn((
+            Position { x: 0.0, y: 0.0 },
+            Velocity { x: 1.0, y: 0.0 },
+            Health(10.0),
+        ));
+
+        let mut schedule = ParallelSchedule::new();
+        schedule.add_stage_system(
+            Stage::PostUpdate,
+            (move |world: &mut World| {
+                for (pos, health) in world.query::<(&Position, &Health)>() {
+                    sink.lock().unwrap().push((pos.x, health.0));
+                }
+            })
+            .into_system(),
+        );
+        schedule.add_system(
+            QuerySystem::<(&mut Position, &Velocity), _>::new(
+                |(pos, vel): (&mut Position, &Velocity)| pos.x += vel.x,
+            )
+            .with_name("move"),
+        );
+        schedule.add_stage_system(
+            Stage::Update,
+            QuerySystem::<&mut Health, _>::new(|health: &mut Health| health.0 -= 1.0)
+                .with_name("decay"),
+        );
+
+        assert_eq!(
+            schedule.stage_batches(Stage::Update),
+            vec![vec!["move", "decay"]]
+        );
+        assert_eq!(schedule.stage_batches(Stage::PostUpdate).len(), 1);
+
+        schedule.run(&mut world);
+        schedule.run(&mut world);
+        assert_eq!(*observed.lock().unwrap(), vec![(1.0, 9.0), (2.0, 8.0)]);
+    }
+
+    #[test]
+    fn test_query_checked_rejects_aliasing() {
+        let mut world = World::new();
+        world.spawn((Position { x: 0.0, y: 0.0 }, Velocity { x: 1.0, y: 0.0 }));
+
+        for (pos, vel) in world.query_checked::<(&mut Position, &Velocity)>().unwrap() {
+            pos.x += vel.x;
+        }
+        assert_eq!(world.query::<&Position>().next().unwrap().x, 1.0);
+        assert!(world.query_checked::<(&Position, &Position)>().is_ok());
+
+        assert!(matches!(
+            world.query_checked::<(&mut Position, &Position)>(),
+            Err(EcsError::InvalidOperation(_))
+        ));
+        assert!(
+            world
+                .query_checked::<(&mut Position, Option<&mut Position>)>()
+                .is_err()
+        );
+        assert_eq!(
+            query::aliased_component::<(&Velocity, &mut Position, &mut Position)>(),
+            Some(std::any::TypeId::of::<Position>())
+        );
+    }
+
+    #[cfg(feature = "change_detection")]
+    #[test]
+    fn test_mark_changed_after_raw_write() {
+        let mut world = World::new();
+        let moved = world.spawn((Position { x: 0.0, y: 0.0 },));
+        world.spawn((Position { x: 0.0, y: 0.0 },));
+        world.tick();
+        assert_eq!(
+            world
+                .query_filtered::<&Position, (Changed<Position>,)>()
+                .count(),
+            0
+        );
+
+        // Stand-in for external code writing through a raw column pointer
+        let location = world.entity_meta(moved).unwrap();
+        let ptr = world
+            .archetypes
+            .get(location.archetype)
+            .unwrap()
+            .get_component_ptr(std::any::TypeId::of::<Position>(), location.index)
+            .unwrap() as *mut Position;
+        unsafe { (*ptr).x = 5.0 };
+        assert_eq!(
+            world
+                .query_filtered::<&Position, (Changed<Position>,)>()
+                .count(),
+            0
+        );
+
+        world.mark_changed::<Position>(moved).unwrap();
+        let changed: Vec<f32> = world
+            .query_filtered::<&Position, (Changed<Position>,)>()
+            .map(|pos| pos.x)
+            .collect();
+        assert_eq!(changed, vec![5.0]);
+
+        assert!(matches!(
+            world.mark_changed::<Velocity>(moved),
+            Err(EcsError::ComponentNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_events_send_prioritized() {
+        let mut events = Events::new();
+        events.send("spawn a");
+        events.send_prioritized("despawn a", 10);
+        events.send("spawn b");
+        events.send_prioritized("log", -5);
+        events.send_prioritized("despawn b", 10);
+
+        assert_eq!(
+            events.iter().copied().collect::<Vec<_>>(),
+            vec!["despawn a", "despawn b", "spawn a", "spawn b", "log"]
+        );
+
+        events.update();
+        events.send_prioritized("late", 1);
+        assert_eq!(events.drain().collect::<Vec<_>>(), vec!["late"]);
+        assert_eq!(events.drain_all().count(), 5);
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn test_compact_entities() {
+        let mut world = World::new();
+        let spawned: Vec<Entity> = (0..10)
+            .map(|i| {
+                world.spawn((Position {
+                    x: i as f32,
+                    y: 0.0,
+                },))
+            })
+            .collect();
+        for &entity in spawned.iter().step_by(2) {
+            world.despawn(entity);
+        }
+        // Reuse a freed slot so one id carries a later generation
+        let reused = world.spawn((Position { x: 10.0, y: 0.0 },));
+        world.insert(spawned[3], Parent(spawned[1])).unwrap();
+        world
+            .insert(spawned[1], Children(vec![spawned[3], spawned[0]]))
+            .unwrap();
+
+        let map = world.compact();
+        assert_eq!(map.len(), 6);
+
+        let mut bits: Vec<u64> = map.values().map(|e| e.to_bits() & 0xFFFF_FFFF).collect();
+        bits.sort_unstable();
+        assert_eq!(bits, (0..6).collect::<Vec<u64>>());
+
+        for old in spawned.iter().skip(1).step_by(2).chain([&reused]) {
+            let new = map[old];
+            assert!(world.is_alive(new));
+            let expected = match spawned.iter().position(|e| e == old) {
+                Some(i) => i as f32,
+                None => 10.0,
+            };
+            assert_eq!(world.get::<Position>(new).unwrap().x, expected);
+        }
+        assert_eq!(
+            world.get::<Parent>(map[&spawned[3]]),
+            Some(&Parent(map[&spawned[1]]))
+        );
+        assert_eq!(
+            world.get::<Children>(map[&spawned[1]]),
+            Some(&Children(vec![map[&spawned[3]]]))
+        );
+        assert_eq!(world.query::<&Position>().count(), 6);
+    }
+
+    #[test]
+    fn test_compact_flushes_commands_and_drops_dead_parents() {
+        let mut world = World::new();
+        let parent = world.spawn((Position { x: 0.0, y: 0.0 },));
+        let child = world.spawn((Position { x: 1.0, y: 0.0 }, Parent(parent)));
+        let hidden = world.spawn((Position { x: 2.0, y: 0.0 }, Parent(parent)));
+        world.disable(hidden).unwrap();
+        let doomed = world.spawn((Position { x: 3.0, y: 0.0 },));
+        world.despawn(parent);
+
+        world.commands().despawn(doomed);
+        let map = world.compact();
+        assert!(!map.contains_key(&doomed));
+        assert_eq!(map.len(), 2);
+
+        for old in [child, hidden] {
+            let new = map[&old];
+            assert!(world.get::<Parent>(new).is_none());
+            assert!(world.get::<Position>(new).is_some());
+        }
+        assert!(world.is_disabled(map[&hidden]));
+    }
+
+    #[test]
+    #[cfg(feature = "change_detection")]
+    fn test_compact_leaves_hierarchy_unchanged() {
+        let mut world = World::new();
+        let gap = world.spawn((Position { x: 0.0, y: 0.0 },));
+        let parent = world.spawn((Position { x: 1.0, y: 0.0 },));
+        let child = world.spawn((Position { x: 2.0, y: 0.0 }, Parent(parent)));
+        world.insert(parent, Children(vec![child])).unwrap();
+        world.despawn(gap);
+        world.tick();
+
+        let map = world.compact();
+        let (parent, child) = (map[&parent], map[&child]);
+        assert_eq!(world.get::<Parent>(child), Some(&Parent(parent)));
+        assert_eq!(world.get::<Children>(parent), Some(&Children(vec![child])));
+        assert_eq!(
+            world
+                .query_filtered::<&Parent, (Changed<Parent>,)>()
+                .count(),
+            0
+        );
+        assert_eq!(
+            world
+                .query_filtered::<&Children, (Changed<Children>,)>()
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_get_resource_mut2() {
+        struct Gold(u32);
+        struct Wood(u32);
+
+        let mut world = World::new();
+        world.insert_resource(Gold(10));
+        assert!(world.get_resource_mut2::<Gold, Wood>().is_none());
+        world.insert_resource(Wood(0));
+
+        let (mut gold, mut wood) = world.get_resource_mut2::<Gold, Wood>().unwrap();
+        gold.0 -= 3;
+        wood.0 += 3;
+        drop((gold, wood));
+
+        assert_eq!(world.get_resource::<Gold>().unwrap().0, 7);
+        assert_eq!(world.get_resource::<Wood>().unwrap().0, 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "two different resource types")]
+    fn test_get_resource_mut2_same_type_panics() {
+        struct Gold(u32);
+
+        let mut world = World::new();
+        world.insert_resource(Gold(10));
+        let _ = world.get_resource_mut2::<Gold, Gold>();
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_system_tracing_spans() {
+        use std::sync::{Arc, Mutex};
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata, Subscriber};
+
+        struct NameVisitor(Option<String>);
+
+        impl Visit for NameVisitor {
+            fn record_str(&mut self, field: &Field, value: &str) {
+                if field.name() == "name" {
+                    self.0 = Some(value.to_string());
+                }
+            }
+
+            fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+        }
+
+        struct SpanRecorder(Arc<Mutex<Vec<(String, String)>>>);
+
+        impl Subscriber for SpanRecorder {
+            fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut visitor = NameVisitor(None);
+                span.record(&mut visitor);
+                let mut spans = self.0.lock().unwrap();
+                spans.push((
+                    span.metadata().name().to_string(),
+                    visitor.0.unwrap_or_default(),
+                ));
+                Id::from_u64(spans.len() as u64)
+            }
+
+            fn record(&self, _span: &Id, _values: &Record<'_>) {}
+            fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+            fn event(&self, _event: &Event<'_>) {}
+            fn enter(&self, _span: &Id) {}
+            fn exit(&self, _span: &Id) {}
+        }
+
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let mut world = World::new();
+        world.spawn((Position { x: 0.0, y: 0.0 }, Velocity { x: 1.0, y: 0.0 }));
+
+        let mut schedule = Schedule::new();
+        schedule.add_update_system(
+            QuerySystem::<&mut Position, _>::new(|pos: &mut Position| pos.x += 1.0)
+                .with_name("move"),
+        );
+        let mut parallel = ParallelSchedule::new();
+        parallel.add_system(
+            QuerySystem::<&mut Velocity, _>::new(|vel: &mut Velocity| vel.x *= 2.0)
+                .with_name("accelerate"),
+        );
+
+        tracing::subscriber::with_default(SpanRecorder(spans.clone()), || {
+            schedule.run(&mut world);
+            parallel.run(&mut world);
+        });
+
+        assert_eq!(
+            *spans.lock().unwrap(),
+            vec![
+                ("system".to_string(), "move".to_string()),
+                ("system".to_string(), "accelerate".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "change_detection")]
+    fn test_query_changed_by_system() {
+        use std::sync::{Arc, Mutex};
+
+        let mut world = World::new();
+        let a = world.spawn((Position { x: 0.0, y: 0.0 },));
+        let b = world.spawn((Position { x: 10.0, y: 0.0 },));
+        let both = world.spawn((Position { x: 20.0, y: 0.0 },));
+        world.spawn((Position { x: 30.0, y: 0.0 },));
+        world.tick();
+
+        let mut schedule = Schedule::new();
+        let physics = schedule.add_update_system(
+            (move |world: &mut World| {
+                world.get_mut::<Position>(a).unwrap().y = 1.0;
+                world.get_mut::<Position>(both).unwrap().y = 1.0;
+            })
+            .into_system(),
+        );
+        let input = schedule.add_update_system(
+            (move |world: &mut World| {
+                world.get_mut::<Position>(b).unwrap().y = 2.0;
+                world.get_mut::<Position>(both).unwrap().y = 2.0;
+            })
+            .into_system(),
+        );
+        assert_ne!(physics, input);
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorded = seen.clone();
+        schedule.add_update_system(
+            (move |world: &mut World| {
+                for id in [physics, input] {
+                    let mut xs: Vec<f32> = world
+                        .query::<&Position>()
+                        .filter_by(ChangedBy::<Position>::new(id))
+                        .map(|pos| pos.x)
+                        .collect();
+                    xs.sort_by(f32::total_cmp);
+                    recorded.lock().unwrap().push(xs);
+                }
+            })
+            .into_system(),
+        );
+        schedule.run(&mut world);
+
+        // `both` was written by each system, so it belongs to the last writer
+        assert_eq!(*seen.lock().unwrap(), vec![vec![0.0], vec![10.0, 20.0]]);
+
+        // Writes outside a schedule aren't attributed to any system
+        world.get_mut::<Position>(a).unwrap().x = 5.0;
+        assert_eq!(
+            world
+                .query::<&Position>()
+                .filter_by(ChangedBy::<Position>::new(physics))
+                .count(),
+            0
+        );
+        assert_eq!(
+            world
+                .query::<&mut Position>()
+                .filter_by(ChangedBy::<Position>::new(SystemId::NONE))
+                .map(|pos| pos.x)
+                .collect::<Vec<_>>(),
+            vec![5.0]
+        );
+        // Rejected rows were never fetched, so `&mut` didn't mark them
+        assert_eq!(
+            world
+                .query::<&Position>()
+                .filter_by(ChangedBy::<Position>::new(SystemId::NONE))
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "change_detection")]
+    fn test_schedule_restores_previous_writer() {
+        use std::sync::{Arc, Mutex};
+
+        let mut world = World::new();
+        let a = world.spawn((Position { x: 0.0, y: 0.0 },));
+        let b = world.spawn((Position { x: 1.0, y: 0.0 },));
+        world.tick();
+
+        // A schedule run from inside a system hands the writer back to it
+        let mut outer = Schedule::new();
+        let mut inner = Some(Schedule::new());
+        inner.as_mut().unwrap().add_update_system(
+            (move |world: &mut World| {
+                world.get_mut::<Position>(a).unwrap().y = 1.0;
+            })
+            .into_system(),
+        );
+        let outer_id = outer.add_update_system(
+            (move |world: &mut World| {
+                inner.as_mut().unwrap().run(world);
+                world.get_mut::<Position>(b).unwrap().y = 1.0;
+            })
+            .into_system(),
+        );
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorded = seen.clone();
+        outer.add_update_system(
+            (move |world: &mut World| {
+                let xs: Vec<f32> = world
+                    .query::<&Position>()
+                    .filter_by(ChangedBy::<Position>::new(outer_id))
+                    .map(|pos| pos.x)
+                    .collect();
+                recorded.lock().unwrap().push(xs);
+            })
+            .into_system(),
+        );
+        outer.run(&mut world);
+        assert_eq!(*seen.lock().unwrap(), vec![vec![1.0]]);
+
+        // A panicking system still restores it
+        let mut failing = Schedule::new();
+        failing.add_update_system((|_: &mut World| panic!("system failed")).into_system());
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            failing.run(&mut world);
+        }));
+        assert!(result.is_err());
+        world.tick();
+        world.get_mut::<Position>(a).unwrap().x = 5.0;
+        assert_eq!(
+            world
+                .query::<&Position>()
+                .filter_by(ChangedBy::<Position>::new(SystemId::NONE))
+                .map(|pos| pos.x)
+                .collect::<Vec<_>>(),
+            vec![5.0]
+        );
+    }
+
+    #[test]
+    fn test_spawn_empty_entity() {
+        let mut world = World::new();
+        world.spawn((Position { x: 1.0, y: 0.0 },));
+        let empty = world.spawn_empty();
+        let other = world.spawn_empty();
+
+        assert!(world.is_alive(empty));
+        assert_eq!(world.query::<Option<&Position>>().count(), 1);
+        assert_eq!(world.query::<Has<Velocity>>().count(), 1);
+        assert_eq!(world.query_ref::<Option<&Position>>().count(), 1);
+
+        world.insert(empty, Velocity { x: 2.0, y: 0.0 }).unwrap();
+        assert_eq!(world.get::<Velocity>(empty).unwrap().x, 2.0);
+        assert_eq!(world.query::<&Velocity>().count(), 1);
+        assert!(world.is_alive(other));
+
+        // Despawning `other` swaps `last` into its row of the empty archetype
+        let last = world.spawn_empty();
+        assert!(world.despawn(other));
+        assert!(!world.is_alive(other));
+        assert!(world.is_alive(last));
+        world.insert(last, Position { x: 3.0, y: 0.0 }).unwrap();
+        assert_eq!(world.query::<Option<&Position>>().count(), 3);
+    }
+
+    #[test]
+    fn test_query_system_closure_types_inferred() {
+        fn movement<W: QueryWorld>() -> impl System<(), (), W> {
+            QuerySystem::<(&mut Position, &Velocity), _>::new(|(pos, vel)| {
+                pos.x += vel.x;
+                pos.y += vel.y;
+            })
+        }
+
+        let mut world = World::new();
+        world.spawn((Position { x: 0.0, y: 0.0 }, Velocity { x: 1.0, y: 2.0 }));
+        world.spawn((Position { x: 5.0, y: 5.0 },));
+
+        let mut schedule = Schedule::new();
+        schedule.add_update_system(movement());
+        schedule.add_update_system(
+            QuerySystem::<(&mut Position, Option<&Velocity>), _>::new(|(pos, vel)| {
+                if vel.is_none() {
+                    pos.y -= 1.0;
+                }
+            })
+            .with_name("fall"),
+        );
+        #[cfg(feature = "change_detection")]
+        schedule.add_update_system(QuerySystem::<(Mut<Position>, &Velocity), _>::new(
+            |(mut pos, vel)| pos.x *= vel.y,
+        ));
+        schedule.run(&mut world);
+
+        let mut positions: Vec<(f32, f32)> = world
+            .query::<&Position>()
+            .map(|pos| (pos.x, pos.y))
+            .collect();
+        positions.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let moved_x = if cfg!(feature = "change_detection") {
+            2.0
+        } else {
+            1.0
+        };
+        assert_eq!(positions, vec![(moved_x, 2.0), (5.0, 4.0)]);
+    }
+
+    #[test]
+    #[cfg(feature = "change_detection")]
+    fn test_change_filters_match_at_tick_zero() {
+        let mut world = World::new();
+        let a = world.spawn((Position { x: 0.0, y: 0.0 },));
+        world.spawn((Position { x: 1.0, y: 0.0 },));
+        world.get_mut::<Position>(a).unwrap().y = 1.0;
+
+        // Spawns and writes before the first `tick` are in the current window
+        assert_eq!(
+            world
+                .query_filtered::<&Position, (Changed<Position>,)>()
+                .count(),
+            2
+        );
+        assert_eq!(
+            world
+                .query_filtered::<&Position, (Added<Position>,)>()
+                .count(),
+            2
+        );
+        assert_eq!(
+            world
+                .query::<&Position>()
+                .filter_by(ChangedBy::<Position>::new(SystemId::NONE))
+                .count(),
+            2
+        );
+
+        world.tick();
+        assert_eq!(
+            world
+                .query_filtered::<&Position, (Changed<Position>,)>()
+                .count(),
+            0
+        );
+        assert_eq!(
+            world
+                .query_filtered::<&Position, (Added<Position>,)>()
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "change_detection")]
+    fn test_changed_window_is_current_tick() {
+        let mut world = World::new();
+        let entity = world.spawn((Position { x: 0.0, y: 0.0 },));
+        world.tick();
+
+        // Spawned in an earlier tick, so not changed in this one
+        assert_eq!(
+            world
+                .query_filtered::<&Position, (Changed<Position>,)>()
+                .count(),
+            0
+        );
+
+        world.get_mut::<Position>(entity).unwrap().x = 1.0;
+        assert_eq!(
+            world
+                .query_filtered::<&Position, (Changed<Position>,)>()
+                .count(),
+            1
+        );
+
+        // The write falls out of the window once the tick advances
+        world.tick();
+        assert_eq!(
+            world
+                .query_filtered::<&Position, (Changed<Position>,)>()
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_get_or_spawn_rejects_stale_and_distant_ids() {
+        let mut world = World::new();
+        let stale = world.spawn((Health(1.0),));
+        world.despawn(stale);
+        let current = world.spawn((Health(2.0),));
+        world.despawn(current);
+
+        // An older generation of a free slot must not come back to life
+        assert!(world.get_or_spawn(stale).is_none());
+        assert!(!world.is_alive(stale));
+        assert!(world.get_or_spawn(current).is_none());
+
+        let distant = Entity::from_bits((1 << 32) | u64::from(u32::MAX - 1));
+        assert!(world.get_or_spawn(distant).is_none());
+
+        // The slot skipped by `insert_at` is reused by later spawns exactly once
+        let mirrored = Entity::from_bits((7 << 32) | 3);
+        assert!(world.get_or_spawn(mirrored).is_some());
+        let spawned: Vec<Entity> = (0..4).map(|_| world.spawn((Health(0.0),))).collect();
+        assert!(!spawned.contains(&mirrored));
+        let mut unique = spawned.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), spawned.len());
+        assert_eq!(world.query::<&Health>().count(), 4);
+    }
+
+    #[test]
+    fn test_allocator_sees_real_layouts() {
+        use std::alloc::{GlobalAlloc, Layout, System};
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        #[derive(Default)]
+        struct Checking {
+            calls: Arc<AtomicUsize>,
+        }
+
+        unsafe impl GlobalAlloc for Checking {
+            unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+                assert!(layout.size() > 0, "zero-sized allocation");
+                self.calls.fetch_add(1, Ordering::SeqCst);
+                let ptr = unsafe { System.alloc(layout) };
+                assert_eq!(ptr as usize % layout.align(), 0);
+                ptr
+            }
+
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+                assert!(layout.size() > 0, "zero-sized deallocation");
+                unsafe { System.dealloc(ptr, layout) }
+            }
+        }
+
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        #[repr(align(64))]
+        struct Aligned(u8);
+
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        struct Marker;
+
+        let checking = Checking::default();
+        let calls = checking.calls.clone();
+        let mut world = World::with_allocator(checking);
+
+        let marker = world.spawn((Marker,));
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        assert_eq!(world.get::<Marker>(marker), Some(&Marker));
+
+        for i in 0..20 {
+            world.spawn((Aligned(i), Marker));
+        }
+        assert!(calls.load(Ordering::SeqCst) > 0);
+        for aligned in world.query::<&Aligned>() {
+            assert_eq!(aligned as *const Aligned as usize % 64, 0);
+        }
+        assert_eq!(
+            world.query::<&Aligned>().map(|a| a.0 as u32).sum::<u32>(),
+            190
+        );
+    }
+
+    #[test]
+    fn test_dynamic_component_layouts() {
+        use std::alloc::Layout;
+
+        let mut world = World::new();
+        let aligned = world.register_dynamic_component(
+            "Aligned",
+            Layout::from_size_align(4, 32).unwrap(),
+            None,
+        );
+        let tag = world.register_dynamic_component("Tag", Layout::new::<()>(), None);
+
+        let entities: Vec<_> = (0..10u32)
+            .map(|i| {
+                world
+                    .spawn_dynamic(&[(aligned, &i.to_ne_bytes()), (tag, &[])])
+                    .unwrap()
+            })
+            .collect();
+
+        for (i, &entity) in entities.iter().enumerate() {
+            let bytes = world.get_dynamic(entity, aligned).unwrap();
+            assert_eq!(bytes.as_ptr() as usize % 32, 0);
+            assert_eq!(u32::from_ne_bytes(bytes.try_into().unwrap()), i as u32);
+            assert_eq!(world.get_dynamic(entity, tag), Some(&[][..]));
+        }
+    }
+
+    #[test]
+    fn test_custom_filter_is_checked_per_entity() {
+        use crate::archetype::ArchetypeView;
+        use std::any::TypeId;
+
+        struct PositiveX;
+
+        impl QueryFilter for PositiveX {
+            fn matches_archetype(types: &[TypeId]) -> bool {
+                types.contains(&TypeId::of::<Position>())
+            }
+
+            fn matches_component(archetype: ArchetypeView<'_>, index: usize) -> bool {
+                archetype
+                    .get_component::<Position>(index)
+                    .is_some_and(|pos| pos.x > 0.0)
+            }
+        }
+
+        let mut world = World::new();
+        for x in [-1.0, 2.0, -3.0, 4.0] {
+            world.spawn((Position { x, y: 0.0 },));
+        }
+
+        let query = world.query_filtered::<&Position, PositiveX>();
+        assert_eq!(query.size_hint(), (0, Some(4)));
+        let xs: Vec<f32> = query.map(|pos| pos.x).collect();
+        assert_eq!(xs, vec![2.0, 4.0]);
+    }
+
+    #[test]
+    fn test_state_transitions_queued_by_systems_wait_a_run() {
+        #[derive(Debug, Clone, PartialEq)]
+        enum Light {
+            Red,
+            Green,
+        }
+
+        #[derive(Default)]
+        struct Flips(u32);
+
+        let mut world = World::new();
+        world.insert_resource(Flips::default());
+        world.insert_resource(State::new(Light::Red));
+
+        // Each enter system immediately asks for the other state
+        let mut schedule = Schedule::new();
+        schedule.add_enter_system(
+            Light::Green,
+            (|world: &mut World| {
+                world.get_resource_mut::<Flips>().unwrap().0 += 1;
+                world
+                    .get_resource_mut::<State<Light>>()
+                    .unwrap()
+                    .set(Light::Red);
+            })
+            .into_system(),
+        );
+        schedule.add_enter_system(
+            Light::Red,
+            (|world: &mut World| {
+                world.get_resource_mut::<Flips>().unwrap().0 += 1;
+                world
+                    .get_resource_mut::<State<Light>>()
+                    .unwrap()
+                    .set(Light::Green);
+            })
+            .into_system(),
+        );
+
+        world
+            .get_resource_mut::<State<Light>>()
+            .unwrap()
+            .set(Light::Green);
+        for run in 1..=3 {
+            schedule.run(&mut world);
+            assert_eq!(world.get_resource::<Flips>().unwrap().0, run);
+        }
+        assert_eq!(
+            world.get_resource::<State<Light>>().unwrap().get(),
+            &Light::Green
+        );
+    }
+
+    #[test]
+    fn test_resource_handle_holds_lock() {
+        let mut world = World::new();
+
+        struct Score(u32);
+
+        world.insert_resource(Score(3));
+        let read = world.get_resource::<Score>().unwrap();
+        let second = world.get_resource::<Score>().unwrap();
+        assert_eq!(read.0 + second.0, 6);
+
+        let conflict = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            world.get_resource_mut::<Score>().map(|_| ())
+        }));
+        assert!(conflict.is_err());
+        drop((read, second));
+
+        let mut score = world.get_resource_mut::<Score>().unwrap();
+        score.0 = 4;
+        let conflict = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            world.get_resource::<Score>().map(|_| ())
+        }));
+        assert!(conflict.is_err());
+        drop(score);
+        assert_eq!(world.get_resource::<Score>().unwrap().0, 4);
+    }
+
+    #[test]
+    fn test_schedule_runs_batch_concurrently() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::time::{Duration, Instant};
+
+        // Waits for the other system to start, which only happens in time if
+        // both run at once
+        fn wait_for(flag: &AtomicBool) -> bool {
+            let start = Instant::now();
+            while !flag.load(Ordering::SeqCst) {
+                if start.elapsed() > Duration::from_secs(5) {
+                    return false;
+                }
+                std::thread::yield_now();
+            }
+            true
+        }
+
+        // Two threads even on a single core machine
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let (pos, vel) = pool.install(|| {
+            let mut world = World::new();
+            let entity = world.spawn((Position { x: 0.0, y: 0.0 }, Velocity { x: 0.0, y: 0.0 }));
+
+            let moving = Arc::new(AtomicBool::new(false));
+            let accelerating = Arc::new(AtomicBool::new(false));
+            let (started, other) = (moving.clone(), accelerating.clone());
+            let mut schedule = Schedule::new();
+            schedule.add_update_system(QuerySystem::<&mut Position, _>::new(
+                move |pos: &mut Position| {
+                    started.store(true, Ordering::SeqCst);
+                    if wait_for(&other) {
+                        pos.x = 1.0;
+                    }
+                },
+            ));
+            let (started, other) = (accelerating, moving);
+            schedule.add_update_system(QuerySystem::<&mut Velocity, _>::new(
+                move |vel: &mut Velocity| {
+                    started.store(true, Ordering::SeqCst);
+                    if wait_for(&other) {
+                        vel.x = 2.0;
+                    }
+                },
+            ));
+            schedule.run(&mut world);
+
+            (
+                world.get::<Position>(entity).unwrap().x,
+                world.get::<Velocity>(entity).unwrap().x,
+            )
+        });
+        assert_eq!((pos, vel), (1.0, 2.0));
+    }
+
+    #[test]
+    fn test_sparse_components_in_bundles_and_queries() {
+        use rayon::prelude::*;
+
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        struct Selected(u32);
+
+        let mut world = World::new();
+        world.register_sparse::<Selected>();
+
+        let plain = world.spawn((Position { x: 0.0, y: 0.0 },));
+        let selected = world.spawn((Position { x: 1.0, y: 0.0 }, Selected(7)));
+
+        // The bundle's sparse component went to the set, not a column
+        let location = world.entity_meta(selected).unwrap();
+        assert_eq!(
+            location.archetype,
+            world.entity_meta(plain).unwrap().archetype
+        );
+        assert_eq!(world.get::<Selected>(selected), Some(&Selected(7)));
+        assert_eq!(world.query_sparse::<Selected>().count(), 1);
+
+        let found: Vec<_> = world.query::<(&Position, &Selected)>().collect();
+        assert_eq!(found, vec![(&Position { x: 1.0, y: 0.0 }, &Selected(7))]);
+        assert_eq!(world.query_count::<&Selected>(), 1);
+        assert_eq!(world.query_ref::<&Selected>().count(), 1);
+
+        let with: Vec<f32> = world
+            .query_filtered::<&Position, With<Selected>>()
+            .map(|p| p.x)
+            .collect();
+        assert_eq!(with, vec![1.0]);
+        let without: Vec<f32> = world
+            .query_filtered::<&Position, Without<Selected>>()
+            .map(|p| p.x)
+            .collect();
+        assert_eq!(without, vec![0.0]);
+        let has: Vec<bool> = world.query::<Has<Selected>>().collect();
+        assert_eq!(has, vec![false, true]);
+
+        for selection in world.query::<&mut Selected>() {
+            selection.0 += 1;
+        }
+        assert_eq!(world.get::<Selected>(selected), Some(&Selected(8)));
+
+        let many: Vec<_> = (0..64)
+            .map(|i| {
+                world.spawn((
+                    Position {
+                        x: i as f32,
+                        y: 0.0,
+                    },
+                    Selected(i),
+                ))
+            })
+            .collect();
+        world
+            .par_query::<(&Position, &mut Selected)>()
+            .with_min_len(1)
+            .for_each(|(_, selection)| selection.0 += 1);
+        let total: u32 = world.query::<&Selected>().map(|s| s.0).sum();
+        assert_eq!(total, 9 + (1..=64).sum::<u32>());
+        assert_eq!(world.despawn_batch(&many), 64);
+
+        assert!(world.despawn(selected));
+        assert_eq!(world.query_count::<&Selected>(), 0);
+    }
+
+    #[cfg(feature = "change_detection")]
+    #[test]
+    fn test_sparse_components_change_detection() {
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        struct Selected(u32);
+
+        let mut world = World::new();
+        world.register_sparse::<Selected>();
+        world.tick();
+        let a = world.spawn((Position { x: 0.0, y: 0.0 }, Selected(0)));
+        let b = world.spawn((Position { x: 1.0, y: 0.0 }, Selected(1)));
+        assert_eq!(
+            world.query_filtered::<&Selected, Added<Selected>>().count(),
+            2
+        );
+
+        world.tick();
+        assert_eq!(
+            world
+                .query_filtered::<&Selected, Changed<Selected>>()
+                .count(),
+            0
+        );
+        world.get_mut::<Selected>(a).unwrap().0 = 5;
+        world.mark_changed::<Selected>(b).unwrap();
+        world.tick();
+        world.get_mut::<Selected>(b).unwrap().0 = 6;
+        let changed: Vec<_> = world
+            .query_filtered::<&Selected, Changed<Selected>>()
+            .collect();
+        assert_eq!(changed, vec![&Selected(6)]);
+        assert_eq!(
+            world.query_filtered::<&Selected, Added<Selected>>().count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_sparse_components_visited_and_cloned() {
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        struct Selected(u32);
+
+        let mut world = World::new();
+        world.register_sparse::<Selected>();
+        world.register_clonable::<Position>();
+        world.register_clonable::<Selected>();
+        let original = world.spawn((Position { x: 2.0, y: 0.0 }, Selected(3)));
+
+        let mut names = Vec::new();
+        world.visit(&mut |entity, name, _| {
+            assert_eq!(entity, original);
+            names.push(name);
+        });
+        assert!(names.contains(&std::any::type_name::<Selected>()));
+        assert_eq!(names.len(), 2);
+
+        let prefab = world.export_prefab(original, false).unwrap();
+        let from_prefab = world.spawn_prefab(&prefab);
+        assert_eq!(world.get::<Selected>(from_prefab), Some(&Selected(3)));
+
+        let copy = world.clone_entity(original).unwrap();
+        assert_eq!(world.get::<Selected>(copy), Some(&Selected(3)));
+        assert_eq!(
+            world.get::<Position>(copy),
+            Some(&Position { x: 2.0, y: 0.0 })
+        );
+        assert_eq!(world.query_sparse::<Selected>().count(), 3);
+    }
 }
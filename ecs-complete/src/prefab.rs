@@ -0,0 +1,41 @@
+use crate::registry::BoxedComponent;
+use std::any::TypeId;
+
+/// A detached copy of an entity, and optionally its `Children` subtree, that
+/// can be instantiated any number of times with `World::spawn_prefab`
+pub struct Prefab {
+    pub(crate) entities: Vec<PrefabEntity>,
+}
+
+pub(crate) struct PrefabEntity {
+    // Index of the parent within the prefab; the root has none
+    pub(crate) parent: Option<usize>,
+    // Type, name and value of each component. Instances are cloned from the
+    // values through the spawning world's `ComponentRegistry`.
+    pub(crate) components: Vec<(TypeId, &'static str, BoxedComponent)>,
+}
+
+impl Prefab {
+    pub(crate) fn new() -> Self {
+        Self {
+            entities: Vec::new(),
+        }
+    }
+
+    /// Number of entities spawned per instance
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// Type names of the root entity's components
+    pub fn component_names(&self) -> Vec<&'static str> {
+        self.entities
+            .first()
+            .map(|root| root.components.iter().map(|&(_, name, _)| name).collect())
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,52 @@
+//! Commonly used types, so `use ecs_complete::prelude::*;` is enough to get started.
+//!
+//! ```
+//! use ecs_complete::prelude::*;
+//!
+//! #[derive(Debug, Clone, Copy)]
+//! struct Position {
+//!     x: f32,
+//!     y: f32,
+//! }
+//!
+//! #[derive(Debug, Clone, Copy)]
+//! struct Velocity {
+//!     x: f32,
+//!     y: f32,
+//! }
+//!
+//! let mut world = World::new();
+//! let mut schedule = Schedule::new();
+//!
+//! world.spawn((Position { x: 0.0, y: 0.0 }, Velocity { x: 1.0, y: 2.0 }));
+//!
+//! schedule.add_system(
+//!     Stage::Update,
+//!     QuerySystem::<(&mut Position, &Velocity), _>::new(|(pos, vel)| {
+//!         pos.x += vel.x;
+//!         pos.y += vel.y;
+//!     }),
+//! );
+//! schedule.run(&mut world);
+//!
+//! let pos = world.query::<&Position>().next().unwrap();
+//! assert_eq!((pos.x, pos.y), (1.0, 2.0));
+//! ```
+
+pub use crate::QueryData;
+pub use crate::app::App;
+pub use crate::command::Commands;
+pub use crate::component::{Bundle, Component};
+pub use crate::entity::Entity;
+pub use crate::events::{EventReader, EventWriter, Events, OverflowPolicy};
+pub use crate::filters;
+pub use crate::hierarchy::{ChildPolicy, Children, Parent};
+#[cfg(feature = "change_detection")]
+pub use crate::query::{Added, Changed, ChangedBy, Mut};
+pub use crate::query::{Disabled, Has, Query, ReadOnlyQuery, With, Without};
+pub use crate::resource::{Res, ResMut};
+pub use crate::state::State;
+pub use crate::system::{
+    IntoSystem, QuerySystem, Schedule, Stage, System, SystemId, SystemParam, Tick,
+};
+pub use crate::world::{QueryWorld, World, WorldLike};
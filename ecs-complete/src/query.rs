@@ -1,3 +1,4 @@
+use crate::archetype::ArchetypeView;
 use std::any::TypeId;
 use std::marker::PhantomData;
 
@@ -5,10 +6,31 @@ pub trait Query: Send {
     type Item<'a>;
 
     fn matches_archetype(types: &[TypeId]) -> bool;
-    unsafe fn fetch<'a>(
-        archetype: &'a mut crate::archetype::Archetype,
-        index: usize,
-    ) -> Self::Item<'a>;
+
+    /// The item for the entity at `index`
+    ///
+    /// # Safety
+    ///
+    /// While the item lives, nothing else may reference a component it
+    /// borrows mutably, or write one it reads.
+    unsafe fn fetch<'a>(archetype: ArchetypeView<'a>, index: usize) -> Self::Item<'a>;
+
+    /// Like `fetch`, but `None` instead of a panic when the archetype is
+    /// missing a column or row for one of the terms
+    ///
+    /// # Safety
+    ///
+    /// Same contract as `fetch`.
+    unsafe fn try_fetch<'a>(archetype: ArchetypeView<'a>, index: usize) -> Option<Self::Item<'a>> {
+        Some(unsafe { Self::fetch(archetype, index) })
+    }
+
+    /// Whether the entity at `index` has every component the terms require.
+    /// Matching the archetype settles this unless its entities may hold
+    /// sparse components, see `Archetype::checks_rows`.
+    fn matches_row(_archetype: ArchetypeView<'_>, _index: usize) -> bool {
+        true
+    }
 
     fn read_types() -> Vec<TypeId> {
         Vec::new()
@@ -19,9 +41,39 @@ pub trait Query: Send {
     }
 }
 
+/// Whether an archetype with `types` (see `Archetype::query_types`) may hold
+/// `T`, either in a column or, for a sparse `T`, on some of its entities
+fn has_type<T: 'static>(types: &[TypeId]) -> bool {
+    types.contains(&TypeId::of::<T>()) || types.contains(&crate::sparse_set::marker::<T>())
+}
+
+/// A component `Q` writes through more than one term, or both reads and
+/// writes, which would hand out aliasing references
+pub fn aliased_component<Q: Query>() -> Option<TypeId> {
+    let reads = Q::read_types();
+    let writes = Q::write_types();
+    writes
+        .iter()
+        .enumerate()
+        .find(|&(i, write)| reads.contains(write) || writes[i + 1..].contains(write))
+        .map(|(_, &write)| write)
+}
+
+/// Queries that only read, so they can be fetched through a shared archetype
+/// borrow (see `World::query_ref`)
+pub trait ReadOnlyQuery: Query {
+    fn fetch_ref<'a>(archetype: ArchetypeView<'a>, index: usize) -> Self::Item<'a>;
+}
+
 pub trait QueryFilter: Send {
+    /// Whether the filter is decided by archetype alone, so every entity of a
+    /// matching archetype passes `matches_component`. Off by default so a
+    /// per-entity filter can't be skipped by forgetting to clear it. Ignored
+    /// for archetypes whose entities may hold sparse components.
+    const ARCHETYPAL: bool = false;
+
     fn matches_archetype(types: &[TypeId]) -> bool;
-    fn matches_component(archetype: &crate::archetype::Archetype, index: usize) -> bool;
+    fn matches_component(archetype: ArchetypeView<'_>, index: usize) -> bool;
 }
 
 // Basic component queries
@@ -29,34 +81,121 @@ impl<T: 'static + Send + Sync> Query for &T {
     type Item<'a> = &'a T;
 
     fn matches_archetype(types: &[TypeId]) -> bool {
-        types.contains(&TypeId::of::<T>())
+        has_type::<T>(types)
+    }
+
+    fn matches_row(archetype: ArchetypeView<'_>, index: usize) -> bool {
+        archetype.has_component_at::<T>(index)
     }
 
-    unsafe fn fetch<'a>(
-        archetype: &'a mut crate::archetype::Archetype,
-        index: usize,
-    ) -> Self::Item<'a> {
+    unsafe fn fetch<'a>(archetype: ArchetypeView<'a>, index: usize) -> Self::Item<'a> {
         archetype.get_component::<T>(index).unwrap()
     }
 
+    unsafe fn try_fetch<'a>(archetype: ArchetypeView<'a>, index: usize) -> Option<Self::Item<'a>> {
+        archetype.get_component::<T>(index)
+    }
+
     fn read_types() -> Vec<TypeId> {
         vec![TypeId::of::<T>()]
     }
 }
 
+impl<T: 'static + Send + Sync> ReadOnlyQuery for &T {
+    fn fetch_ref<'a>(archetype: ArchetypeView<'a>, index: usize) -> Self::Item<'a> {
+        archetype.get_component::<T>(index).unwrap()
+    }
+}
+
 impl<T: 'static + Send + Sync> Query for &mut T {
     type Item<'a> = &'a mut T;
 
     fn matches_archetype(types: &[TypeId]) -> bool {
-        types.contains(&TypeId::of::<T>())
+        has_type::<T>(types)
+    }
+
+    fn matches_row(archetype: ArchetypeView<'_>, index: usize) -> bool {
+        archetype.has_component_at::<T>(index)
+    }
+
+    unsafe fn fetch<'a>(archetype: ArchetypeView<'a>, index: usize) -> Self::Item<'a> {
+        unsafe { archetype.get_component_mut::<T>(index).unwrap() }
     }
 
-    unsafe fn fetch<'a>(
-        archetype: &'a mut crate::archetype::Archetype,
-        index: usize,
-    ) -> Self::Item<'a> {
-        let ptr = archetype as *mut crate::archetype::Archetype;
-        unsafe { (*ptr).get_component_mut::<T>(index).unwrap() }
+    unsafe fn try_fetch<'a>(archetype: ArchetypeView<'a>, index: usize) -> Option<Self::Item<'a>> {
+        unsafe { archetype.get_component_mut::<T>(index) }
+    }
+
+    fn write_types() -> Vec<TypeId> {
+        vec![TypeId::of::<T>()]
+    }
+}
+
+#[cfg(feature = "change_detection")]
+/// Mutable query term that only marks the component changed when it is
+/// actually written through `DerefMut`, unlike `&mut T` which marks on fetch
+pub struct Mut<'a, T> {
+    value: &'a mut T,
+    changed_tick: &'a mut u64,
+    changed_by: &'a mut u32,
+    tick: u64,
+    writer: u32,
+}
+
+#[cfg(feature = "change_detection")]
+impl<T> std::ops::Deref for Mut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.value
+    }
+}
+
+#[cfg(feature = "change_detection")]
+impl<T> std::ops::DerefMut for Mut<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        *self.changed_tick = self.tick;
+        *self.changed_by = self.writer;
+        self.value
+    }
+}
+
+#[cfg(feature = "change_detection")]
+impl<T: 'static + Send + Sync> Query for Mut<'_, T> {
+    type Item<'a> = Mut<'a, T>;
+
+    fn matches_archetype(types: &[TypeId]) -> bool {
+        has_type::<T>(types)
+    }
+
+    fn matches_row(archetype: ArchetypeView<'_>, index: usize) -> bool {
+        archetype.has_component_at::<T>(index)
+    }
+
+    unsafe fn fetch<'a>(archetype: ArchetypeView<'a>, index: usize) -> Self::Item<'a> {
+        let (tick, writer) = (archetype.tick(), crate::archetype::current_writer());
+        let (value, changed_tick, changed_by) =
+            unsafe { archetype.get_component_mut_untracked::<T>(index).unwrap() };
+        Mut {
+            value,
+            changed_tick,
+            changed_by,
+            tick,
+            writer,
+        }
+    }
+
+    unsafe fn try_fetch<'a>(archetype: ArchetypeView<'a>, index: usize) -> Option<Self::Item<'a>> {
+        let (tick, writer) = (archetype.tick(), crate::archetype::current_writer());
+        let (value, changed_tick, changed_by) =
+            unsafe { archetype.get_component_mut_untracked::<T>(index)? };
+        Some(Mut {
+            value,
+            changed_tick,
+            changed_by,
+            tick,
+            writer,
+        })
     }
 
     fn write_types() -> Vec<TypeId> {
@@ -72,10 +211,7 @@ impl<T: 'static + Send + Sync> Query for Option<&T> {
         true // Always matches
     }
 
-    unsafe fn fetch<'a>(
-        archetype: &'a mut crate::archetype::Archetype,
-        index: usize,
-    ) -> Self::Item<'a> {
+    unsafe fn fetch<'a>(archetype: ArchetypeView<'a>, index: usize) -> Self::Item<'a> {
         archetype.get_component::<T>(index)
     }
 
@@ -84,19 +220,40 @@ impl<T: 'static + Send + Sync> Query for Option<&T> {
     }
 }
 
+impl<T: 'static + Send + Sync> ReadOnlyQuery for Option<&T> {
+    fn fetch_ref<'a>(archetype: ArchetypeView<'a>, index: usize) -> Self::Item<'a> {
+        archetype.get_component::<T>(index)
+    }
+}
+
+// With change detection the item is a `Mut`, so fetching an entity that
+// has `T` doesn't mark it changed until it is written
 impl<T: 'static + Send + Sync> Query for Option<&mut T> {
+    #[cfg(feature = "change_detection")]
+    type Item<'a> = Option<Mut<'a, T>>;
+    #[cfg(not(feature = "change_detection"))]
     type Item<'a> = Option<&'a mut T>;
 
     fn matches_archetype(_types: &[TypeId]) -> bool {
         true
     }
 
-    unsafe fn fetch<'a>(
-        archetype: &'a mut crate::archetype::Archetype,
-        index: usize,
-    ) -> Self::Item<'a> {
-        let ptr = archetype as *mut crate::archetype::Archetype;
-        unsafe { (*ptr).get_component_mut::<T>(index) }
+    #[cfg(feature = "change_detection")]
+    unsafe fn fetch<'a>(archetype: ArchetypeView<'a>, index: usize) -> Self::Item<'a> {
+        let (tick, writer) = (archetype.tick(), crate::archetype::current_writer());
+        let component = unsafe { archetype.get_component_mut_untracked::<T>(index) };
+        component.map(|(value, changed_tick, changed_by)| Mut {
+            value,
+            changed_tick,
+            changed_by,
+            tick,
+            writer,
+        })
+    }
+
+    #[cfg(not(feature = "change_detection"))]
+    unsafe fn fetch<'a>(archetype: ArchetypeView<'a>, index: usize) -> Self::Item<'a> {
+        unsafe { archetype.get_component_mut::<T>(index) }
     }
 
     fn write_types() -> Vec<TypeId> {
@@ -104,148 +261,251 @@ impl<T: 'static + Send + Sync> Query for Option<&mut T> {
     }
 }
 
-// Tuple queries
-impl<Q1: Query, Q2: Query> Query for (Q1, Q2) {
-    type Item<'a> = (Q1::Item<'a>, Q2::Item<'a>);
+/// Query term yielding whether the entity has `T`
+pub struct Has<T>(PhantomData<T>);
 
-    fn matches_archetype(types: &[TypeId]) -> bool {
-        Q1::matches_archetype(types) && Q2::matches_archetype(types)
-    }
+impl<T: 'static + Send + Sync> Query for Has<T> {
+    type Item<'a> = bool;
 
-    unsafe fn fetch<'a>(
-        archetype: &'a mut crate::archetype::Archetype,
-        index: usize,
-    ) -> Self::Item<'a> {
-        let ptr = archetype as *mut crate::archetype::Archetype;
-        unsafe { (Q1::fetch(&mut *ptr, index), Q2::fetch(&mut *ptr, index)) }
+    fn matches_archetype(_types: &[TypeId]) -> bool {
+        true
     }
 
-    fn read_types() -> Vec<TypeId> {
-        let mut types = Q1::read_types();
-        types.extend(Q2::read_types());
-        types
+    unsafe fn fetch<'a>(archetype: ArchetypeView<'a>, index: usize) -> Self::Item<'a> {
+        archetype.has_component_at::<T>(index)
     }
+}
 
-    fn write_types() -> Vec<TypeId> {
-        let mut types = Q1::write_types();
-        types.extend(Q2::write_types());
-        types
+impl<T: 'static + Send + Sync> ReadOnlyQuery for Has<T> {
+    fn fetch_ref<'a>(archetype: ArchetypeView<'a>, index: usize) -> Self::Item<'a> {
+        archetype.has_component_at::<T>(index)
     }
 }
 
-impl<Q1: Query, Q2: Query, Q3: Query> Query for (Q1, Q2, Q3) {
-    type Item<'a> = (Q1::Item<'a>, Q2::Item<'a>, Q3::Item<'a>);
+// Tuple queries
+macro_rules! impl_query_tuple {
+    ($($q:ident),+) => {
+        impl<$($q: Query),+> Query for ($($q,)+) {
+            type Item<'a> = ($($q::Item<'a>,)+);
+
+            fn matches_archetype(types: &[TypeId]) -> bool {
+                $($q::matches_archetype(types))&&+
+            }
+
+            unsafe fn fetch<'a>(
+                archetype: ArchetypeView<'a>,
+                index: usize,
+            ) -> Self::Item<'a> {
+                unsafe { ($($q::fetch(archetype, index),)+) }
+            }
+
+            unsafe fn try_fetch<'a>(
+                archetype: ArchetypeView<'a>,
+                index: usize,
+            ) -> Option<Self::Item<'a>> {
+                unsafe { Some(($($q::try_fetch(archetype, index)?,)+)) }
+            }
+
+            fn matches_row(archetype: ArchetypeView<'_>, index: usize) -> bool {
+                $($q::matches_row(archetype, index))&&+
+            }
+
+            fn read_types() -> Vec<TypeId> {
+                let mut types = Vec::new();
+                $(types.extend($q::read_types());)+
+                types
+            }
+
+            fn write_types() -> Vec<TypeId> {
+                let mut types = Vec::new();
+                $(types.extend($q::write_types());)+
+                types
+            }
+        }
 
-    fn matches_archetype(types: &[TypeId]) -> bool {
-        Q1::matches_archetype(types) && Q2::matches_archetype(types) && Q3::matches_archetype(types)
-    }
-
-    unsafe fn fetch<'a>(
-        archetype: &'a mut crate::archetype::Archetype,
-        index: usize,
-    ) -> Self::Item<'a> {
-        let ptr = archetype as *mut crate::archetype::Archetype;
-        unsafe {
-            (
-                Q1::fetch(&mut *ptr, index),
-                Q2::fetch(&mut *ptr, index),
-                Q3::fetch(&mut *ptr, index),
-            )
+        impl<$($q: ReadOnlyQuery),+> ReadOnlyQuery for ($($q,)+) {
+            fn fetch_ref<'a>(
+                archetype: ArchetypeView<'a>,
+                index: usize,
+            ) -> Self::Item<'a> {
+                ($($q::fetch_ref(archetype, index),)+)
+            }
         }
-    }
+    };
+}
 
-    fn read_types() -> Vec<TypeId> {
-        let mut types = Q1::read_types();
-        types.extend(Q2::read_types());
-        types.extend(Q3::read_types());
-        types
+impl_query_tuple!(Q1, Q2);
+impl_query_tuple!(Q1, Q2, Q3);
+impl_query_tuple!(Q1, Q2, Q3, Q4);
+impl_query_tuple!(Q1, Q2, Q3, Q4, Q5);
+impl_query_tuple!(Q1, Q2, Q3, Q4, Q5, Q6);
+impl_query_tuple!(Q1, Q2, Q3, Q4, Q5, Q6, Q7);
+impl_query_tuple!(Q1, Q2, Q3, Q4, Q5, Q6, Q7, Q8);
+
+// Query filters
+/// Requires `T` without borrowing it: only the archetype's type list is
+/// checked, so `T`'s column is never read during iteration
+pub struct With<T>(PhantomData<T>);
+pub struct Without<T>(PhantomData<T>);
+/// Matches components written during the current tick, i.e. since the last
+/// `World::tick`. Writes from earlier ticks, including the spawn of entities
+/// spawned before that tick, don't count.
+#[cfg(feature = "change_detection")]
+pub struct Changed<T>(PhantomData<T>);
+/// Matches components attached during the current tick. Unlike `Changed`,
+/// writes and archetype moves caused by other inserts don't count.
+#[cfg(feature = "change_detection")]
+pub struct Added<T>(PhantomData<T>);
+
+/// Marker component that hides an entity from `World::query` and
+/// `World::query_filtered`. Use `World::query_including_disabled` to see it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Disabled;
+
+impl<T: 'static + Send + Sync> QueryFilter for With<T> {
+    const ARCHETYPAL: bool = true;
+
+    fn matches_archetype(types: &[TypeId]) -> bool {
+        has_type::<T>(types)
     }
 
-    fn write_types() -> Vec<TypeId> {
-        let mut types = Q1::write_types();
-        types.extend(Q2::write_types());
-        types.extend(Q3::write_types());
-        types
+    // Only reached when entities may hold a sparse `T`
+    fn matches_component(archetype: ArchetypeView<'_>, index: usize) -> bool {
+        archetype.has_component_at::<T>(index)
     }
 }
 
-impl<Q1: Query, Q2: Query, Q3: Query, Q4: Query> Query for (Q1, Q2, Q3, Q4) {
-    type Item<'a> = (Q1::Item<'a>, Q2::Item<'a>, Q3::Item<'a>, Q4::Item<'a>);
+impl<T: 'static + Send + Sync> QueryFilter for Without<T> {
+    const ARCHETYPAL: bool = true;
 
     fn matches_archetype(types: &[TypeId]) -> bool {
-        Q1::matches_archetype(types)
-            && Q2::matches_archetype(types)
-            && Q3::matches_archetype(types)
-            && Q4::matches_archetype(types)
-    }
-
-    unsafe fn fetch<'a>(
-        archetype: &'a mut crate::archetype::Archetype,
-        index: usize,
-    ) -> Self::Item<'a> {
-        let ptr = archetype as *mut crate::archetype::Archetype;
-        unsafe {
-            (
-                Q1::fetch(&mut *ptr, index),
-                Q2::fetch(&mut *ptr, index),
-                Q3::fetch(&mut *ptr, index),
-                Q4::fetch(&mut *ptr, index),
-            )
-        }
+        !types.contains(&TypeId::of::<T>())
     }
 
-    fn read_types() -> Vec<TypeId> {
-        let mut types = Q1::read_types();
-        types.extend(Q2::read_types());
-        types.extend(Q3::read_types());
-        types.extend(Q4::read_types());
-        types
+    // Only reached when entities may hold a sparse `T`
+    fn matches_component(archetype: ArchetypeView<'_>, index: usize) -> bool {
+        !archetype.has_component_at::<T>(index)
     }
+}
 
-    fn write_types() -> Vec<TypeId> {
-        let mut types = Q1::write_types();
-        types.extend(Q2::write_types());
-        types.extend(Q3::write_types());
-        types.extend(Q4::write_types());
-        types
+#[cfg(feature = "change_detection")]
+impl<T: 'static + Send + Sync> QueryFilter for Changed<T> {
+    fn matches_archetype(types: &[TypeId]) -> bool {
+        has_type::<T>(types)
     }
-}
 
-// Query filters
-pub struct With<T>(PhantomData<T>);
-pub struct Without<T>(PhantomData<T>);
-pub struct Changed<T>(PhantomData<T>);
+    // Matches components written during the archetype's current tick
+    fn matches_component(archetype: ArchetypeView<'_>, index: usize) -> bool {
+        archetype.component_changed::<T>(index, archetype.tick())
+    }
+}
 
-impl<T: 'static + Send + Sync> QueryFilter for With<T> {
+#[cfg(feature = "change_detection")]
+impl<T: 'static + Send + Sync> QueryFilter for Added<T> {
     fn matches_archetype(types: &[TypeId]) -> bool {
-        types.contains(&TypeId::of::<T>())
+        has_type::<T>(types)
     }
 
-    fn matches_component(_archetype: &crate::archetype::Archetype, _index: usize) -> bool {
-        true
+    fn matches_component(archetype: ArchetypeView<'_>, index: usize) -> bool {
+        archetype.component_added::<T>(index, archetype.tick())
     }
 }
 
-impl<T: 'static + Send + Sync> QueryFilter for Without<T> {
-    fn matches_archetype(types: &[TypeId]) -> bool {
-        !types.contains(&TypeId::of::<T>())
+// No filter
+impl QueryFilter for () {
+    const ARCHETYPAL: bool = true;
+
+    fn matches_archetype(_types: &[TypeId]) -> bool {
+        true
     }
 
-    fn matches_component(_archetype: &crate::archetype::Archetype, _index: usize) -> bool {
+    fn matches_component(_archetype: ArchetypeView<'_>, _index: usize) -> bool {
         true
     }
 }
 
-impl<T: 'static + Send + Sync> QueryFilter for Changed<T> {
-    fn matches_archetype(types: &[TypeId]) -> bool {
-        types.contains(&TypeId::of::<T>())
+// Filter tuples require every member filter to match
+macro_rules! impl_query_filter_tuple {
+    ($($f:ident),+) => {
+        impl<$($f: QueryFilter),+> QueryFilter for ($($f,)+) {
+            const ARCHETYPAL: bool = $($f::ARCHETYPAL)&&+;
+
+            fn matches_archetype(types: &[TypeId]) -> bool {
+                $($f::matches_archetype(types))&&+
+            }
+
+            fn matches_component(archetype: ArchetypeView<'_>, index: usize) -> bool {
+                $($f::matches_component(archetype, index))&&+
+            }
+        }
+    };
+}
+
+impl_query_filter_tuple!(F1);
+impl_query_filter_tuple!(F1, F2);
+impl_query_filter_tuple!(F1, F2, F3);
+impl_query_filter_tuple!(F1, F2, F3, F4);
+impl_query_filter_tuple!(F1, F2, F3, F4, F5);
+impl_query_filter_tuple!(F1, F2, F3, F4, F5, F6);
+impl_query_filter_tuple!(F1, F2, F3, F4, F5, F6, F7);
+impl_query_filter_tuple!(F1, F2, F3, F4, F5, F6, F7, F8);
+
+/// Per-entity filter holding a runtime value, which a `QueryFilter` type
+/// can't, e.g. `ChangedBy`. Applied lazily with `QueryIter::filter_by`.
+pub trait RowFilter: Send {
+    /// Whether every row passes, so the iterator's size hint stays exact
+    const ALL: bool = false;
+
+    fn matches_row(&self, archetype: ArchetypeView<'_>, index: usize) -> bool;
+}
+
+impl RowFilter for () {
+    const ALL: bool = true;
+
+    fn matches_row(&self, _archetype: ArchetypeView<'_>, _index: usize) -> bool {
+        true
     }
+}
 
-    fn matches_component(archetype: &crate::archetype::Archetype, index: usize) -> bool {
-        archetype.component_changed::<T>(index, 0)
+/// Matches like `Changed<T>`, but only where `system` made the last write to
+/// `T`, e.g. positions moved by the physics system:
+/// `world.query::<&Position>().filter_by(ChangedBy::<Position>::new(physics))`.
+///
+/// Enter and exit systems of state transitions, and commands applied when a
+/// schedule flushes, run outside any scheduled system, so their writes are
+/// attributed to `SystemId::NONE`.
+#[cfg(feature = "change_detection")]
+pub struct ChangedBy<T> {
+    system: crate::system::SystemId,
+    _marker: PhantomData<fn() -> T>,
+}
+
+#[cfg(feature = "change_detection")]
+impl<T> ChangedBy<T> {
+    pub fn new(system: crate::system::SystemId) -> Self {
+        Self {
+            system,
+            _marker: PhantomData,
+        }
     }
 }
 
+#[cfg(feature = "change_detection")]
+impl<T: 'static> RowFilter for ChangedBy<T> {
+    fn matches_row(&self, archetype: ArchetypeView<'_>, index: usize) -> bool {
+        archetype.component_changed_by::<T>(index, archetype.tick(), self.system.get())
+    }
+}
+
+/// Combines any number of filters into one `QueryFilter` type by nesting
+/// pairs, e.g. `world.query_filtered::<&A, filters!(With<B>, Without<C>)>()`
+#[macro_export]
+macro_rules! filters {
+    () => { () };
+    ($f:ty $(,)?) => { ($f,) };
+    ($f:ty, $($rest:ty),+ $(,)?) => { ($f, $crate::filters!($($rest),+)) };
+}
+
 pub trait QueryBorrow {
     type Query: Query;
 }
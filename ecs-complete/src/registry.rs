@@ -0,0 +1,73 @@
+use crate::component::{Component, DynamicBundle};
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+pub(crate) type BoxedComponent = Box<dyn Any + Send + Sync>;
+
+/// Clones the component behind the first pointer into the uninitialized slot
+/// behind the second, so storage can be copied without boxing each value
+pub(crate) type CloneIntoFn = unsafe fn(*const u8, *mut u8);
+
+/// Type-erased operations for a component registered as clonable
+#[derive(Clone, Copy)]
+pub(crate) struct CloneFns {
+    pub read: unsafe fn(*const u8) -> BoxedComponent,
+    pub clone_into: CloneIntoFn,
+    /// Adds a clone of the boxed value to a bundle, so a whole entity can be
+    /// spawned at once
+    pub stage: fn(&BoxedComponent, &mut DynamicBundle),
+}
+
+unsafe fn read_component<T: Component + Clone>(ptr: *const u8) -> BoxedComponent {
+    Box::new(unsafe { &*(ptr as *const T) }.clone())
+}
+
+pub(crate) unsafe fn clone_into<T: Component + Clone>(src: *const u8, dst: *mut u8) {
+    unsafe { (dst as *mut T).write((*(src as *const T)).clone()) }
+}
+
+fn stage_clone<T: Component + Clone>(value: &BoxedComponent, bundle: &mut DynamicBundle) {
+    let value = value
+        .downcast_ref::<T>()
+        .expect("Boxed component has the wrong type");
+    bundle.add(value.clone());
+}
+
+/// Per-type operations that can't be expressed through the raw column bytes alone
+#[derive(Clone)]
+pub struct ComponentRegistry {
+    clone_fns: HashMap<TypeId, CloneFns>,
+}
+
+impl ComponentRegistry {
+    pub fn new() -> Self {
+        Self {
+            clone_fns: HashMap::new(),
+        }
+    }
+
+    pub fn register_clonable<T: Component + Clone>(&mut self) {
+        self.clone_fns.insert(
+            TypeId::of::<T>(),
+            CloneFns {
+                read: read_component::<T>,
+                clone_into: clone_into::<T>,
+                stage: stage_clone::<T>,
+            },
+        );
+    }
+
+    pub fn is_clonable(&self, type_id: TypeId) -> bool {
+        self.clone_fns.contains_key(&type_id)
+    }
+
+    pub(crate) fn clone_fns(&self, type_id: TypeId) -> Option<&CloneFns> {
+        self.clone_fns.get(&type_id)
+    }
+}
+
+impl Default for ComponentRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
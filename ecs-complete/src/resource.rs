@@ -1,41 +1,190 @@
-use parking_lot::RwLock;
+use crate::system::SystemParam;
+use crate::world::World;
+use parking_lot::{ArcRwLockReadGuard, ArcRwLockWriteGuard, RawRwLock, RwLock};
 use std::any::{Any, TypeId};
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+type BoxedResource = Box<dyn Any + Send + Sync>;
+
+fn clone_resource<T: Clone + Send + Sync + 'static>(value: &BoxedResource) -> BoxedResource {
+    Box::new(
+        value
+            .downcast_ref::<T>()
+            .expect("Resource has the wrong type")
+            .clone(),
+    )
+}
+
+/// Copies of the clonable resources taken by `Resources::snapshot`
+pub struct ResourceCheckpoint {
+    data: HashMap<TypeId, (&'static str, BoxedResource)>,
+}
+
+impl ResourceCheckpoint {
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+struct ResourceEntry {
+    value: Arc<RwLock<BoxedResource>>,
+    // Tick of the last insert or mutable deref, shared with outstanding handles
+    changed_tick: Arc<AtomicU64>,
+    type_name: &'static str,
+}
+
+impl ResourceEntry {
+    fn new(value: BoxedResource, type_name: &'static str, tick: u64) -> Self {
+        Self {
+            value: Arc::new(RwLock::new(value)),
+            changed_tick: Arc::new(AtomicU64::new(tick)),
+            type_name,
+        }
+    }
+}
 
 pub struct Resources {
-    data: HashMap<TypeId, Arc<RwLock<Box<dyn Any + Send + Sync>>>>,
+    data: HashMap<TypeId, ResourceEntry>,
+    clone_fns: HashMap<TypeId, fn(&BoxedResource) -> BoxedResource>,
+    tick: u64,
 }
 
 impl Resources {
     pub fn new() -> Self {
         Self {
             data: HashMap::new(),
+            clone_fns: HashMap::new(),
+            tick: 0,
         }
     }
 
-    pub fn insert<T: Send + Sync + 'static>(&mut self, resource: T) {
+    /// Sets the tick stamped on resources when they're inserted or mutated
+    pub fn set_tick(&mut self, tick: u64) {
+        self.tick = tick;
+    }
+
+    /// Whether `T` was inserted or mutably dereferenced during the current tick
+    pub fn is_changed<T: 'static>(&self) -> bool {
+        self.data
+            .get(&TypeId::of::<T>())
+            .is_some_and(|entry| entry.changed_tick.load(Ordering::Relaxed) == self.tick)
+    }
+
+    /// Includes `T` in future snapshots
+    pub fn register_clonable<T: Clone + Send + Sync + 'static>(&mut self) {
+        self.clone_fns
+            .insert(TypeId::of::<T>(), clone_resource::<T>);
+    }
+
+    /// Clones every registered resource that is currently present
+    pub fn snapshot(&self) -> ResourceCheckpoint {
+        let data = self
+            .clone_fns
+            .iter()
+            .filter_map(|(type_id, clone)| {
+                let resource = self.data.get(type_id)?;
+                Some((
+                    *type_id,
+                    (resource.type_name, clone(&resource.value.read())),
+                ))
+            })
+            .collect();
+        ResourceCheckpoint { data }
+    }
+
+    /// Name of a present resource not registered with `register_clonable`
+    pub fn unclonable_type_name(&self) -> Option<&'static str> {
         self.data
-            .insert(TypeId::of::<T>(), Arc::new(RwLock::new(Box::new(resource))));
+            .iter()
+            .find(|(type_id, _)| !self.clone_fns.contains_key(type_id))
+            .map(|(_, entry)| entry.type_name)
+    }
+
+    /// Copies of every registered resource under the same registrations and
+    /// tick. Unregistered resources are left out.
+    pub fn clone_registered(&self) -> Resources {
+        let mut resources = Resources {
+            data: HashMap::new(),
+            clone_fns: self.clone_fns.clone(),
+            tick: self.tick,
+        };
+        resources.restore(self.snapshot());
+        resources
+    }
+
+    /// Puts back the values captured by `snapshot`. Resources that weren't in
+    /// the checkpoint are left untouched.
+    pub fn restore(&mut self, checkpoint: ResourceCheckpoint) {
+        for (type_id, (type_name, value)) in checkpoint.data {
+            match self.data.get(&type_id) {
+                // Write through the existing lock so outstanding handles see it
+                Some(resource) => {
+                    *resource.value.write() = value;
+                    resource.changed_tick.store(self.tick, Ordering::Relaxed);
+                }
+                None => {
+                    self.data
+                        .insert(type_id, ResourceEntry::new(value, type_name, self.tick));
+                }
+            }
+        }
     }
 
-    pub fn get<T: 'static>(&self) -> Option<Res<T>> {
+    pub fn insert<T: Send + Sync + 'static>(&mut self, resource: T) {
+        self.data.insert(
+            TypeId::of::<T>(),
+            ResourceEntry::new(Box::new(resource), std::any::type_name::<T>(), self.tick),
+        );
+    }
+
+    /// Like `insert`, returning the previous `T` if there was one
+    pub fn replace<T: Send + Sync + 'static>(&mut self, resource: T) -> Option<T> {
+        let Some(entry) = self.data.get(&TypeId::of::<T>()) else {
+            self.insert(resource);
+            return None;
+        };
+        let old = std::mem::replace(&mut *entry.value.write(), Box::new(resource));
+        entry.changed_tick.store(self.tick, Ordering::Relaxed);
+        old.downcast::<T>().ok().map(|boxed| *boxed)
+    }
+
+    /// Read handle to `T`, holding its read lock until dropped. Panics if a
+    /// `ResMut<T>` is alive.
+    pub fn get<T: 'static>(&self) -> Option<Res<'_, T>> {
         self.data.get(&TypeId::of::<T>()).map(|r| Res {
-            inner: r.clone(),
+            guard: r
+                .value
+                .try_read_arc()
+                .unwrap_or_else(|| already_borrowed::<T>()),
+            changed_tick: r.changed_tick.clone(),
+            tick: self.tick,
             _marker: std::marker::PhantomData,
         })
     }
 
-    pub fn get_mut<T: 'static>(&self) -> Option<ResMut<T>> {
+    /// Write handle to `T`, holding its write lock until dropped. Panics if
+    /// any other handle to `T` is alive.
+    pub fn get_mut<T: 'static>(&self) -> Option<ResMut<'_, T>> {
         self.data.get(&TypeId::of::<T>()).map(|r| ResMut {
-            inner: r.clone(),
+            guard: r
+                .value
+                .try_write_arc()
+                .unwrap_or_else(|| already_borrowed::<T>()),
+            changed_tick: r.changed_tick.clone(),
+            tick: self.tick,
             _marker: std::marker::PhantomData,
         })
     }
 
     pub fn remove<T: 'static>(&mut self) -> Option<T> {
         self.data.remove(&TypeId::of::<T>()).and_then(|r| {
-            Arc::try_unwrap(r)
+            Arc::try_unwrap(r.value)
                 .ok()
                 .and_then(|lock| lock.into_inner().downcast::<T>().ok())
                 .map(|boxed| *boxed)
@@ -45,6 +194,23 @@ impl Resources {
     pub fn contains<T: 'static>(&self) -> bool {
         self.data.contains_key(&TypeId::of::<T>())
     }
+
+    /// Visits every resource in type name order, e.g. for a save pass driven
+    /// by a registry keyed on `TypeId`. Each one is read-locked during `f`.
+    pub fn for_each(&self, mut f: impl FnMut(TypeId, &'static str, &dyn Any)) {
+        let mut entries: Vec<_> = self.data.iter().collect();
+        entries.sort_unstable_by_key(|(_, entry)| entry.type_name);
+        for (&type_id, entry) in entries {
+            f(type_id, entry.type_name, &**entry.value.read());
+        }
+    }
+
+    /// Type names of every present resource, sorted
+    pub fn type_names(&self) -> Vec<&'static str> {
+        let mut names: Vec<_> = self.data.values().map(|entry| entry.type_name).collect();
+        names.sort_unstable();
+        names
+    }
 }
 
 impl Default for Resources {
@@ -53,46 +219,110 @@ impl Default for Resources {
     }
 }
 
+fn already_borrowed<T>() -> ! {
+    panic!(
+        "resource `{}` is already borrowed mutably, or borrowed while taking it mutably",
+        std::any::type_name::<T>()
+    )
+}
+
+/// Shared handle to a resource. It owns the resource's read lock, so the
+/// references it hands out can't outlive the lock.
 pub struct Res<'a, T: 'static> {
-    inner: Arc<RwLock<Box<dyn Any + Send + Sync>>>,
+    guard: ArcRwLockReadGuard<RawRwLock, BoxedResource>,
+    changed_tick: Arc<AtomicU64>,
+    tick: u64,
     _marker: std::marker::PhantomData<&'a T>,
 }
 
+impl<T: 'static> Res<'_, T> {
+    /// Whether the resource was inserted or mutated during the tick this
+    /// handle was fetched in
+    pub fn is_changed(&self) -> bool {
+        self.changed_tick.load(Ordering::Relaxed) == self.tick
+    }
+}
+
 impl<'a, T: 'static> std::ops::Deref for Res<'a, T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
-        unsafe {
-            let guard = self.inner.read();
-            let ptr = &**guard as *const (dyn Any + Send + Sync) as *const T;
-            &*ptr
+        self.guard
+            .downcast_ref()
+            .expect("Resource has the wrong type")
+    }
+}
+
+impl<T: Send + Sync + 'static> SystemParam for Res<'_, T> {
+    fn fetch(world: &World) -> Self {
+        let res = world
+            .resources()
+            .get::<T>()
+            .unwrap_or_else(|| panic!("resource `{}` not found", std::any::type_name::<T>()));
+        // The handle owns its lock guard, so only the marker lifetime changes
+        Res {
+            guard: res.guard,
+            changed_tick: res.changed_tick,
+            tick: res.tick,
+            _marker: std::marker::PhantomData,
         }
     }
+
+    fn resource_reads() -> Vec<TypeId> {
+        vec![TypeId::of::<T>()]
+    }
 }
 
+/// Exclusive handle to a resource, owning its write lock like `Res` owns the
+/// read lock
 pub struct ResMut<'a, T: 'static> {
-    inner: Arc<RwLock<Box<dyn Any + Send + Sync>>>,
+    guard: ArcRwLockWriteGuard<RawRwLock, BoxedResource>,
+    changed_tick: Arc<AtomicU64>,
+    tick: u64,
     _marker: std::marker::PhantomData<&'a mut T>,
 }
 
+/// As a system argument the write lock is held for the whole run
+impl<T: Send + Sync + 'static> SystemParam for ResMut<'_, T> {
+    fn fetch(world: &World) -> Self {
+        let res = world
+            .resources()
+            .get_mut::<T>()
+            .unwrap_or_else(|| panic!("resource `{}` not found", std::any::type_name::<T>()));
+        ResMut {
+            guard: res.guard,
+            changed_tick: res.changed_tick,
+            tick: res.tick,
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    fn resource_writes() -> Vec<TypeId> {
+        vec![TypeId::of::<T>()]
+    }
+}
+
+impl<T: 'static> ResMut<'_, T> {
+    pub fn is_changed(&self) -> bool {
+        self.changed_tick.load(Ordering::Relaxed) == self.tick
+    }
+}
+
 impl<'a, T: 'static> std::ops::Deref for ResMut<'a, T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
-        unsafe {
-            let guard = self.inner.read();
-            let ptr = &**guard as *const (dyn Any + Send + Sync) as *const T;
-            &*ptr
-        }
+        self.guard
+            .downcast_ref()
+            .expect("Resource has the wrong type")
     }
 }
 
 impl<'a, T: 'static> std::ops::DerefMut for ResMut<'a, T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe {
-            let mut guard = self.inner.write();
-            let ptr = &mut **guard as *mut (dyn Any + Send + Sync) as *mut T;
-            &mut *ptr
-        }
+        self.changed_tick.store(self.tick, Ordering::Relaxed);
+        self.guard
+            .downcast_mut()
+            .expect("Resource has the wrong type")
     }
 }
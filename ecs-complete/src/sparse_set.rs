@@ -0,0 +1,519 @@
+use crate::archetype::ChangeSlots;
+#[cfg(feature = "change_detection")]
+use crate::archetype::Stamp;
+use crate::component::Component;
+use crate::entity::{Entity, entity_index};
+use crate::registry::CloneIntoFn;
+use std::any::{Any, TypeId};
+use std::cell::UnsafeCell;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+
+/// Component storage outside of archetypes. Adding or removing a sparse
+/// component never moves the entity, which suits rarely shared flags that
+/// are toggled often.
+pub struct SparseSet<T> {
+    sparse: Vec<Option<usize>>,
+    // Written through shared borrows by query items, like archetype columns,
+    // each value only by the item borrowing its entity
+    dense: Vec<UnsafeCell<T>>,
+    entities: Vec<Entity>,
+    // Parallel to `dense`, like the tick vectors of an archetype column
+    #[cfg(feature = "change_detection")]
+    changed_ticks: Vec<Stamp<u64>>,
+    #[cfg(feature = "change_detection")]
+    changed_by: Vec<Stamp<u32>>,
+    #[cfg(feature = "change_detection")]
+    added_ticks: Vec<u64>,
+    // World tick stamped on inserts and writes
+    #[cfg(feature = "change_detection")]
+    tick: u64,
+}
+
+impl<T> SparseSet<T> {
+    pub fn new() -> Self {
+        Self {
+            sparse: Vec::new(),
+            dense: Vec::new(),
+            entities: Vec::new(),
+            #[cfg(feature = "change_detection")]
+            changed_ticks: Vec::new(),
+            #[cfg(feature = "change_detection")]
+            changed_by: Vec::new(),
+            #[cfg(feature = "change_detection")]
+            added_ticks: Vec::new(),
+            #[cfg(feature = "change_detection")]
+            tick: 0,
+        }
+    }
+
+    fn dense_index(&self, entity: Entity) -> Option<usize> {
+        let index = (*self.sparse.get(entity_index(entity))?)?;
+        (self.entities[index] == entity).then_some(index)
+    }
+
+    #[cfg(feature = "change_detection")]
+    fn mark_changed(&mut self, index: usize) {
+        self.changed_ticks[index].set(self.tick);
+        self.changed_by[index].set(crate::archetype::current_writer());
+    }
+
+    pub fn insert(&mut self, entity: Entity, value: T) -> Option<T> {
+        if let Some(index) = self.dense_index(entity) {
+            #[cfg(feature = "change_detection")]
+            self.mark_changed(index);
+            return Some(std::mem::replace(self.dense[index].get_mut(), value));
+        }
+
+        let slot = entity_index(entity);
+        if self.sparse.len() <= slot {
+            self.sparse.resize(slot + 1, None);
+        }
+        self.sparse[slot] = Some(self.dense.len());
+        self.dense.push(UnsafeCell::new(value));
+        self.entities.push(entity);
+        #[cfg(feature = "change_detection")]
+        {
+            self.changed_ticks.push(Stamp::new(self.tick));
+            self.changed_by
+                .push(Stamp::new(crate::archetype::current_writer()));
+            self.added_ticks.push(self.tick);
+        }
+        None
+    }
+
+    // Removes the entry at `index` without touching `sparse`
+    fn swap_remove_at(&mut self, index: usize) -> T {
+        self.entities.swap_remove(index);
+        #[cfg(feature = "change_detection")]
+        {
+            self.changed_ticks.swap_remove(index);
+            self.changed_by.swap_remove(index);
+            self.added_ticks.swap_remove(index);
+        }
+        self.dense.swap_remove(index).into_inner()
+    }
+
+    pub fn remove(&mut self, entity: Entity) -> Option<T> {
+        let index = self.dense_index(entity)?;
+        self.sparse[entity_index(entity)] = None;
+
+        let value = self.swap_remove_at(index);
+        if let Some(&moved) = self.entities.get(index) {
+            self.sparse[entity_index(moved)] = Some(index);
+        }
+        Some(value)
+    }
+
+    pub fn get(&self, entity: Entity) -> Option<&T> {
+        // Values are only written through `&mut self` or by the one query
+        // item borrowing the entity, which excludes this shared borrow
+        self.dense_index(entity)
+            .map(|index| unsafe { &*self.dense[index].get() })
+    }
+
+    /// Marks the value changed, like `World::get_mut`
+    pub fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+        let index = self.dense_index(entity)?;
+        #[cfg(feature = "change_detection")]
+        self.mark_changed(index);
+        Some(self.dense[index].get_mut())
+    }
+
+    /// Pointers to `entity`'s value and, with change detection, the stamps of
+    /// its last change, for a query item to borrow, see `ArchetypeView`
+    pub(crate) fn slot(&self, entity: Entity) -> Option<(*mut T, ChangeSlots)> {
+        let index = self.dense_index(entity)?;
+        #[cfg(feature = "change_detection")]
+        let stamps = (
+            self.changed_ticks[index].as_ptr(),
+            self.changed_by[index].as_ptr(),
+        );
+        #[cfg(not(feature = "change_detection"))]
+        let stamps = ();
+        Some((self.dense[index].get(), stamps))
+    }
+
+    /// Whether `entity`'s value changed at or after `since_tick`, and if `writer`
+    /// is given, was last written by it
+    #[cfg(feature = "change_detection")]
+    pub(crate) fn changed_since(
+        &self,
+        entity: Entity,
+        since_tick: u64,
+        writer: Option<u32>,
+    ) -> bool {
+        self.dense_index(entity).is_some_and(|index| {
+            self.changed_ticks[index].get() >= since_tick
+                && writer.is_none_or(|writer| self.changed_by[index].get() == writer)
+        })
+    }
+
+    #[cfg(feature = "change_detection")]
+    pub(crate) fn added_since(&self, entity: Entity, since_tick: u64) -> bool {
+        self.dense_index(entity)
+            .is_some_and(|index| self.added_ticks[index] >= since_tick)
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.dense_index(entity).is_some()
+    }
+
+    /// Exchanges the values of `a` and `b`; returns `false` if either is missing
+    pub fn swap(&mut self, a: Entity, b: Entity) -> bool {
+        match (self.dense_index(a), self.dense_index(b)) {
+            (Some(a), Some(b)) => {
+                self.dense.swap(a, b);
+                #[cfg(feature = "change_detection")]
+                {
+                    self.mark_changed(a);
+                    self.mark_changed(b);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.entities
+            .iter()
+            .copied()
+            .zip(self.dense.iter().map(|value| unsafe { &*value.get() }))
+    }
+
+    /// Marks every value changed as it is yielded
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Entity, &mut T)> {
+        #[cfg(feature = "change_detection")]
+        {
+            let (tick, writer) = (self.tick, crate::archetype::current_writer());
+            self.entities
+                .iter()
+                .copied()
+                .zip(self.dense.iter_mut().map(UnsafeCell::get_mut))
+                .zip(
+                    self.changed_ticks
+                        .iter_mut()
+                        .zip(self.changed_by.iter_mut()),
+                )
+                .map(move |(entry, (changed_tick, changed_by))| {
+                    changed_tick.set(tick);
+                    changed_by.set(writer);
+                    entry
+                })
+        }
+        #[cfg(not(feature = "change_detection"))]
+        self.entities
+            .iter()
+            .copied()
+            .zip(self.dense.iter_mut().map(UnsafeCell::get_mut))
+    }
+
+    /// Rewrites every stored entity through `map`, dropping those it lacks
+    pub fn remap(&mut self, map: &HashMap<Entity, Entity>) {
+        let mut index = 0;
+        while index < self.entities.len() {
+            match map.get(&self.entities[index]) {
+                Some(&new) => {
+                    self.entities[index] = new;
+                    index += 1;
+                }
+                None => {
+                    self.swap_remove_at(index);
+                }
+            }
+        }
+
+        self.sparse.clear();
+        for (index, &entity) in self.entities.iter().enumerate() {
+            let slot = entity_index(entity);
+            if self.sparse.len() <= slot {
+                self.sparse.resize(slot + 1, None);
+            }
+            self.sparse[slot] = Some(index);
+        }
+    }
+
+    // Copy with each value cloned by `clone`, ticks included
+    fn clone_with(&self, clone: CloneIntoFn) -> Self {
+        let dense = self
+            .dense
+            .iter()
+            .map(|value| UnsafeCell::new(unsafe { clone_value(&*value.get(), clone) }))
+            .collect();
+        Self {
+            sparse: self.sparse.clone(),
+            dense,
+            entities: self.entities.clone(),
+            #[cfg(feature = "change_detection")]
+            changed_ticks: self.changed_ticks.clone(),
+            #[cfg(feature = "change_detection")]
+            changed_by: self.changed_by.clone(),
+            #[cfg(feature = "change_detection")]
+            added_ticks: self.added_ticks.clone(),
+            #[cfg(feature = "change_detection")]
+            tick: self.tick,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.dense.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dense.is_empty()
+    }
+}
+
+// `clone` must clone a `T`
+unsafe fn clone_value<T>(value: &T, clone: CloneIntoFn) -> T {
+    let mut slot = MaybeUninit::<T>::uninit();
+    unsafe {
+        clone(value as *const T as *const u8, slot.as_mut_ptr() as *mut u8);
+        slot.assume_init()
+    }
+}
+
+// Like `Vec<T>`, apart from the cells, whose values are only written through
+// a shared borrow by the query item that borrows them
+unsafe impl<T: Sync> Sync for SparseSet<T> {}
+
+impl<T> Default for SparseSet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Stands in for a sparse `T` in the type list queries match archetypes
+/// against, see `SparseStorage::markers`
+pub(crate) struct Sparse<T>(PhantomData<T>);
+
+pub(crate) fn marker<T: 'static>() -> TypeId {
+    TypeId::of::<Sparse<T>>()
+}
+
+trait ErasedSparseSet: Send + Sync {
+    fn remove_entity(&mut self, entity: Entity) -> bool;
+    fn get_any(&self, entity: Entity) -> Option<&dyn Any>;
+    fn slot(&self, entity: Entity) -> Option<(*mut u8, ChangeSlots)>;
+    fn type_name(&self) -> &'static str;
+    fn is_empty(&self) -> bool;
+    fn remap(&mut self, map: &HashMap<Entity, Entity>);
+    fn clone_with(&self, clone: CloneIntoFn) -> Box<dyn ErasedSparseSet>;
+    fn clone_value(&mut self, from: Entity, to: Entity, clone: CloneIntoFn);
+    #[cfg(feature = "change_detection")]
+    fn set_tick(&mut self, tick: u64);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Component> ErasedSparseSet for SparseSet<T> {
+    fn remove_entity(&mut self, entity: Entity) -> bool {
+        self.remove(entity).is_some()
+    }
+
+    fn get_any(&self, entity: Entity) -> Option<&dyn Any> {
+        self.get(entity).map(|value| value as &dyn Any)
+    }
+
+    fn slot(&self, entity: Entity) -> Option<(*mut u8, ChangeSlots)> {
+        SparseSet::slot(self, entity).map(|(value, stamps)| (value.cast(), stamps))
+    }
+
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<T>()
+    }
+
+    fn is_empty(&self) -> bool {
+        SparseSet::is_empty(self)
+    }
+
+    fn remap(&mut self, map: &HashMap<Entity, Entity>) {
+        SparseSet::remap(self, map);
+    }
+
+    fn clone_with(&self, clone: CloneIntoFn) -> Box<dyn ErasedSparseSet> {
+        Box::new(SparseSet::clone_with(self, clone))
+    }
+
+    fn clone_value(&mut self, from: Entity, to: Entity, clone: CloneIntoFn) {
+        if let Some(value) = self.get(from) {
+            let value = unsafe { clone_value(value, clone) };
+            self.insert(to, value);
+        }
+    }
+
+    #[cfg(feature = "change_detection")]
+    fn set_tick(&mut self, tick: u64) {
+        self.tick = tick;
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// All sparse sets in a world, keyed by component type. Owned by the
+/// `ArchetypeMap`, and reached by archetypes for the sparse components of
+/// their entities.
+pub(crate) struct SparseStorage {
+    sets: HashMap<TypeId, Box<dyn ErasedSparseSet>>,
+    // `marker::<T>()` for every registered `T`
+    markers: Vec<TypeId>,
+    #[cfg(feature = "change_detection")]
+    tick: u64,
+}
+
+impl SparseStorage {
+    pub fn new() -> Self {
+        Self {
+            sets: HashMap::new(),
+            markers: Vec::new(),
+            #[cfg(feature = "change_detection")]
+            tick: 0,
+        }
+    }
+
+    /// Returns false if `T` was already registered
+    pub fn register<T: Component>(&mut self) -> bool {
+        if self.contains(TypeId::of::<T>()) {
+            return false;
+        }
+        self.sets
+            .insert(TypeId::of::<T>(), Box::new(SparseSet::<T>::new()));
+        self.markers.push(marker::<T>());
+        #[cfg(feature = "change_detection")]
+        self.set_tick(self.tick);
+        true
+    }
+
+    pub fn contains(&self, type_id: TypeId) -> bool {
+        self.sets.contains_key(&type_id)
+    }
+
+    /// Whether any type is registered, so entities may hold sparse components
+    pub fn has_sets(&self) -> bool {
+        !self.sets.is_empty()
+    }
+
+    /// Stand-ins for the registered types, which queries match as if every
+    /// archetype had them and then check entity by entity
+    pub fn markers(&self) -> &[TypeId] {
+        &self.markers
+    }
+
+    pub fn get<T: 'static>(&self) -> Option<&SparseSet<T>> {
+        self.sets
+            .get(&TypeId::of::<T>())?
+            .as_any()
+            .downcast_ref::<SparseSet<T>>()
+    }
+
+    pub fn get_mut<T: 'static>(&mut self) -> Option<&mut SparseSet<T>> {
+        self.sets
+            .get_mut(&TypeId::of::<T>())?
+            .as_any_mut()
+            .downcast_mut::<SparseSet<T>>()
+    }
+
+    /// Erased `SparseSet::slot` for the set of type `type_id`
+    pub(crate) fn slot(&self, type_id: TypeId, entity: Entity) -> Option<(*mut u8, ChangeSlots)> {
+        self.sets.get(&type_id)?.slot(entity)
+    }
+
+    /// Type, name and value of every sparse component `entity` holds
+    pub fn components_of(
+        &self,
+        entity: Entity,
+    ) -> impl Iterator<Item = (TypeId, &'static str, &dyn Any)> {
+        self.sets.iter().filter_map(move |(&type_id, set)| {
+            set.get_any(entity)
+                .map(|value| (type_id, set.type_name(), value))
+        })
+    }
+
+    /// Type and name of every set holding at least one component
+    pub fn occupied_types(&self) -> impl Iterator<Item = (TypeId, &'static str)> + '_ {
+        self.sets
+            .iter()
+            .filter(|(_, set)| !set.is_empty())
+            .map(|(&type_id, set)| (type_id, set.type_name()))
+    }
+
+    /// Copy with every value cloned through `clone_fn`, ticks included.
+    /// Panics if an occupied set's type has no clone function.
+    pub fn clone_with(&self, clone_fn: &dyn Fn(TypeId) -> Option<CloneIntoFn>) -> SparseStorage {
+        let sets = self
+            .sets
+            .iter()
+            .map(|(&type_id, set)| {
+                let set = match clone_fn(type_id) {
+                    Some(clone) => set.clone_with(clone),
+                    None => {
+                        assert!(
+                            set.is_empty(),
+                            "Component {} can't be cloned",
+                            set.type_name()
+                        );
+                        // An empty set clones without calling `clone`
+                        set.clone_with(|_, _| unreachable!())
+                    }
+                };
+                (type_id, set)
+            })
+            .collect();
+        SparseStorage {
+            sets,
+            markers: self.markers.clone(),
+            #[cfg(feature = "change_detection")]
+            tick: self.tick,
+        }
+    }
+
+    /// Gives `to` a copy of every sparse component `from` holds, cloned
+    /// through `clone_fn`. Panics if one has no clone function.
+    pub fn clone_components(
+        &mut self,
+        from: Entity,
+        to: Entity,
+        clone_fn: &dyn Fn(TypeId) -> Option<CloneIntoFn>,
+    ) {
+        for (&type_id, set) in self.sets.iter_mut() {
+            if set.get_any(from).is_none() {
+                continue;
+            }
+            let clone = clone_fn(type_id)
+                .unwrap_or_else(|| panic!("Component {} can't be cloned", set.type_name()));
+            set.clone_value(from, to, clone);
+        }
+    }
+
+    #[cfg(feature = "change_detection")]
+    pub fn set_tick(&mut self, tick: u64) {
+        self.tick = tick;
+        for set in self.sets.values_mut() {
+            set.set_tick(tick);
+        }
+    }
+
+    pub fn remap(&mut self, map: &HashMap<Entity, Entity>) {
+        for set in self.sets.values_mut() {
+            set.remap(map);
+        }
+    }
+
+    /// Removes `entity` from every set, calling `on_removed` with the type of
+    /// each component it actually had
+    pub fn remove_entity(&mut self, entity: Entity, mut on_removed: impl FnMut(TypeId)) {
+        for (&type_id, set) in self.sets.iter_mut() {
+            if set.remove_entity(entity) {
+                on_removed(type_id);
+            }
+        }
+    }
+}
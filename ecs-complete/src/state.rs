@@ -0,0 +1,111 @@
+use crate::system::System;
+use crate::world::World;
+use std::any::Any;
+use std::collections::VecDeque;
+
+/// Resource holding the current value of a state machine, e.g.
+/// `Menu -> Playing -> Paused`. `set` only queues the transition;
+/// `Schedule::run` applies queued transitions before its first stage and
+/// runs the matching exit and enter systems. Transitions queued by those
+/// systems are applied on the next run.
+pub struct State<S> {
+    current: S,
+    pending: VecDeque<S>,
+}
+
+impl<S: Clone + PartialEq + Send + Sync + 'static> State<S> {
+    pub fn new(initial: S) -> Self {
+        Self {
+            current: initial,
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn get(&self) -> &S {
+        &self.current
+    }
+
+    /// Queues a transition to `next`
+    pub fn set(&mut self, next: S) {
+        self.pending.push_back(next);
+    }
+
+    pub fn pending(&self) -> impl Iterator<Item = &S> {
+        self.pending.iter()
+    }
+
+    // Makes `next` current if it differs, returning `(from, to)`
+    fn apply(&mut self, next: S) -> Option<(S, S)> {
+        if next == self.current {
+            return None;
+        }
+        let previous = std::mem::replace(&mut self.current, next.clone());
+        Some((previous, next))
+    }
+}
+
+type BoxedSystem = Box<dyn System<(), (), World>>;
+
+/// Type-erased transition systems of one state type, kept by `Schedule`
+pub(crate) trait StateDriver<W>: Send {
+    fn run_transitions(&mut self, world: &mut W);
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+pub(crate) struct StateSystems<S> {
+    on_enter: Vec<(S, BoxedSystem)>,
+    on_exit: Vec<(S, BoxedSystem)>,
+}
+
+impl<S> StateSystems<S> {
+    pub fn new() -> Self {
+        Self {
+            on_enter: Vec::new(),
+            on_exit: Vec::new(),
+        }
+    }
+
+    pub fn add_enter(&mut self, value: S, system: BoxedSystem) {
+        self.on_enter.push((value, system));
+    }
+
+    pub fn add_exit(&mut self, value: S, system: BoxedSystem) {
+        self.on_exit.push((value, system));
+    }
+}
+
+impl<S: Clone + PartialEq + Send + Sync + 'static> StateDriver<World> for StateSystems<S> {
+    fn run_transitions(&mut self, world: &mut World) {
+        // Only transitions queued before this run are applied; ones queued by
+        // the exit and enter systems wait for the next run, so systems that
+        // flip between two states can't loop forever
+        let Some(queued) = world
+            .get_resource_mut::<State<S>>()
+            .map(|mut state| std::mem::take(&mut state.pending))
+        else {
+            return;
+        };
+        for next in queued {
+            let Some((from, to)) = world
+                .get_resource_mut::<State<S>>()
+                .and_then(|mut state| state.apply(next))
+            else {
+                continue;
+            };
+            for (value, system) in &mut self.on_exit {
+                if *value == from {
+                    system.run(world);
+                }
+            }
+            for (value, system) in &mut self.on_enter {
+                if *value == to {
+                    system.run(world);
+                }
+            }
+        }
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -1,8 +1,38 @@
-use crate::world::World;
+use crate::state::{StateDriver, StateSystems};
+use crate::world::{QueryWorld, World, WorldLike};
+use rayon::prelude::*;
 use std::any::TypeId;
+use std::ops::Deref;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// A unit of work over a world of type `W`. `In` is passed in by whatever
+/// runs the system and `Out` is handed back; schedules run
+/// `System<(), (), W>`, while `IntoSystem::pipe` threads one system's output
+/// into the next.
+pub trait System<In = (), Out = (), W: WorldLike = World>: Send {
+    fn run_with(&mut self, input: In, world: &mut W) -> Out;
+
+    fn run(&mut self, world: &mut W) -> Out
+    where
+        In: Default,
+    {
+        self.run_with(In::default(), world)
+    }
+
+    /// Runs the system through a world shared with the rest of its batch,
+    /// which runs at the same time on other threads. Returns false, having
+    /// done nothing, if the system needs the world exclusively; the schedule
+    /// then runs it on its own after the others.
+    ///
+    /// # Safety
+    ///
+    /// No system running at the same time may write a component this one
+    /// reads or writes, or read one it writes.
+    unsafe fn run_shared(&mut self, _world: &W) -> bool {
+        false
+    }
 
-pub trait System: Send {
-    fn run(&mut self, world: &mut World);
     fn reads(&self) -> &[TypeId];
     fn writes(&self) -> &[TypeId];
     fn name(&self) -> &str;
@@ -20,6 +50,9 @@ impl<Q: crate::query::Query, F> QuerySystem<Q, F>
 where
     F: FnMut(Q::Item<'_>) + Send,
 {
+    /// Name the query up front, as in `QuerySystem::<(&mut A, &B), _>::new`,
+    /// and the closure's argument types are inferred from it. Leaving `Q` to
+    /// be inferred from the closure instead needs annotated arguments.
     pub fn new(func: F) -> Self {
         Self {
             func,
@@ -36,16 +69,27 @@ where
     }
 }
 
-impl<Q: crate::query::Query, F> System for QuerySystem<Q, F>
+impl<Q: crate::query::Query + 'static, F, W: QueryWorld> System<(), (), W> for QuerySystem<Q, F>
 where
     F: FnMut(Q::Item<'_>) + Send,
 {
-    fn run(&mut self, world: &mut World) {
+    fn run_with(&mut self, _input: (), world: &mut W) {
         for item in world.query::<Q>() {
             (self.func)(item);
         }
     }
 
+    unsafe fn run_shared(&mut self, world: &W) -> bool {
+        // The caller keeps every other system off `Q`'s components
+        let Some(items) = (unsafe { world.query_shared::<Q>() }) else {
+            return false;
+        };
+        for item in items {
+            (self.func)(item);
+        }
+        true
+    }
+
     fn reads(&self) -> &[TypeId] {
         &self.reads
     }
@@ -59,22 +103,79 @@ where
     }
 }
 
-pub struct FunctionSystem<F> {
+/// System wrapping a closure. `M` records the closure's signature so the
+/// same wrapper serves both `FnMut(&mut World) -> Out` and
+/// `FnMut(In, &mut World) -> Out`.
+pub struct FunctionSystem<F, M = fn(&mut World)> {
     func: F,
+    reads: Vec<TypeId>,
+    writes: Vec<TypeId>,
     name: String,
+    _marker: std::marker::PhantomData<M>,
+}
+
+impl<F, M> FunctionSystem<F, M> {
+    fn new(func: F) -> Self {
+        Self {
+            func,
+            reads: Vec::new(),
+            writes: Vec::new(),
+            name: std::any::type_name::<F>().to_string(),
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Declares the components this system touches. Without it the system is
+    /// assumed to access anything and never shares a batch with another.
+    /// It still needs the world exclusively, so it runs after the rest of its
+    /// batch rather than alongside it.
+    pub fn with_access(mut self, reads: Vec<TypeId>, writes: Vec<TypeId>) -> Self {
+        self.reads = reads;
+        self.writes = writes;
+        self
+    }
 }
 
-impl<F: FnMut(&mut World) + Send> System for FunctionSystem<F> {
-    fn run(&mut self, world: &mut World) {
-        (self.func)(world);
+impl<F, Out, W: WorldLike> System<(), Out, W> for FunctionSystem<F, fn(&mut W) -> Out>
+where
+    F: FnMut(&mut W) -> Out + Send,
+{
+    fn run_with(&mut self, _input: (), world: &mut W) -> Out {
+        (self.func)(world)
     }
 
     fn reads(&self) -> &[TypeId] {
-        &[]
+        &self.reads
     }
 
     fn writes(&self) -> &[TypeId] {
-        &[]
+        &self.writes
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<F, In, Out, W: WorldLike> System<In, Out, W> for FunctionSystem<F, fn(In, &mut W) -> Out>
+where
+    F: FnMut(In, &mut W) -> Out + Send,
+{
+    fn run_with(&mut self, input: In, world: &mut W) -> Out {
+        (self.func)(input, world)
+    }
+
+    fn reads(&self) -> &[TypeId] {
+        &self.reads
+    }
+
+    fn writes(&self) -> &[TypeId] {
+        &self.writes
     }
 
     fn name(&self) -> &str {
@@ -83,21 +184,251 @@ impl<F: FnMut(&mut World) + Send> System for FunctionSystem<F> {
 }
 
 pub trait IntoSystem<Marker> {
-    type System: System;
+    type In;
+    type Out;
+    type World: WorldLike;
+    type System: System<Self::In, Self::Out, Self::World>;
+
     fn into_system(self) -> Self::System;
+
+    /// Runs `self`, then `next` with `self`'s output as its input
+    fn pipe<B, BMarker>(self, next: B) -> PipeSystem<Self::System, B::System, Self::Out>
+    where
+        Self: Sized,
+        B: IntoSystem<BMarker, In = Self::Out, World = Self::World>,
+    {
+        PipeSystem::new(self.into_system(), next.into_system())
+    }
+}
+
+impl<F, Out, W: WorldLike> IntoSystem<fn(&mut W) -> Out> for F
+where
+    F: FnMut(&mut W) -> Out + Send + 'static,
+{
+    type In = ();
+    type Out = Out;
+    type World = W;
+    type System = FunctionSystem<F, fn(&mut W) -> Out>;
+
+    fn into_system(self) -> Self::System {
+        FunctionSystem::new(self)
+    }
 }
 
-impl<F: FnMut(&mut World) + Send + 'static> IntoSystem<()> for F {
-    type System = FunctionSystem<F>;
+impl<F, In, Out, W: WorldLike> IntoSystem<fn(In, &mut W) -> Out> for F
+where
+    F: FnMut(In, &mut W) -> Out + Send + 'static,
+{
+    type In = In;
+    type Out = Out;
+    type World = W;
+    type System = FunctionSystem<F, fn(In, &mut W) -> Out>;
 
     fn into_system(self) -> Self::System {
-        FunctionSystem {
-            func: self,
+        FunctionSystem::new(self)
+    }
+}
+
+/// A value a system can take as an argument, fetched from the world before
+/// every run, e.g. `|tick: Tick| ...`
+pub trait SystemParam: Sized {
+    fn fetch(world: &World) -> Self;
+
+    fn resource_reads() -> Vec<TypeId> {
+        Vec::new()
+    }
+
+    fn resource_writes() -> Vec<TypeId> {
+        Vec::new()
+    }
+}
+
+// A write lock held for the whole run would deadlock against any other
+// borrow of the same resource in that system, so refuse to build it
+fn check_resource_access(system: &str, reads: &[TypeId], writes: &[TypeId]) {
+    for (i, write) in writes.iter().enumerate() {
+        if reads.contains(write) || writes[i + 1..].contains(write) {
+            panic!("system `{system}` borrows a resource mutably alongside another borrow of it");
+        }
+    }
+}
+
+/// The world's tick at the time the system runs, see `World::current_tick`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Tick(pub u64);
+
+impl Tick {
+    pub fn get(self) -> u64 {
+        self.0
+    }
+}
+
+impl SystemParam for Tick {
+    fn fetch(world: &World) -> Self {
+        Tick(world.current_tick())
+    }
+}
+
+/// Identifies a system added to a schedule, unique across all schedules.
+/// Writes made while it runs are attributed to it, see `ChangedBy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SystemId(pub(crate) u32);
+
+impl SystemId {
+    /// Attributed to writes made outside any scheduled system
+    pub const NONE: SystemId = SystemId(0);
+
+    fn next() -> Self {
+        static NEXT: AtomicU32 = AtomicU32::new(1);
+        SystemId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn get(self) -> u32 {
+        self.0
+    }
+}
+
+/// `IntoSystem` marker for closures taking only `SystemParam` arguments
+pub struct ParamMarker<P>(std::marker::PhantomData<fn(P)>);
+
+/// System wrapping a closure whose arguments are all `SystemParam`s
+pub struct ParamSystem<F, P> {
+    func: F,
+    name: String,
+    _marker: std::marker::PhantomData<fn() -> P>,
+}
+
+impl<F, P> ParamSystem<F, P> {
+    fn new(func: F) -> Self {
+        Self {
+            func,
             name: std::any::type_name::<F>().to_string(),
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+}
+
+macro_rules! impl_param_system {
+    ($($p:ident $v:ident),+) => {
+        impl<F, Out, $($p: SystemParam),+> System<(), Out> for ParamSystem<F, ($($p,)+)>
+        where
+            F: FnMut($($p),+) -> Out + Send,
+        {
+            fn run_with(&mut self, _input: (), world: &mut World) -> Out {
+                $(let $v = $p::fetch(world);)+
+                (self.func)($($v),+)
+            }
+
+            fn reads(&self) -> &[TypeId] {
+                &[]
+            }
+
+            fn writes(&self) -> &[TypeId] {
+                &[]
+            }
+
+            fn name(&self) -> &str {
+                &self.name
+            }
+        }
+
+        impl<F, Out, $($p: SystemParam),+> IntoSystem<ParamMarker<($($p,)+)>> for F
+        where
+            F: FnMut($($p),+) -> Out + Send + 'static,
+        {
+            type In = ();
+            type Out = Out;
+            type World = World;
+            type System = ParamSystem<F, ($($p,)+)>;
+
+            fn into_system(self) -> Self::System {
+                check_resource_access(
+                    std::any::type_name::<F>(),
+                    &[$($p::resource_reads()),+].concat(),
+                    &[$($p::resource_writes()),+].concat(),
+                );
+                ParamSystem::new(self)
+            }
+        }
+    };
+}
+
+impl_param_system!(P1 p1);
+impl_param_system!(P1 p1, P2 p2);
+impl_param_system!(P1 p1, P2 p2, P3 p3);
+impl_param_system!(P1 p1, P2 p2, P3 p3, P4 p4);
+
+/// Two systems run back to back, the first one's output feeding the second,
+/// see `IntoSystem::pipe`. Its access is the union of both systems' access.
+pub struct PipeSystem<A, B, Mid> {
+    first: A,
+    second: B,
+    reads: Vec<TypeId>,
+    writes: Vec<TypeId>,
+    name: String,
+    _marker: std::marker::PhantomData<fn(Mid)>,
+}
+
+impl<A, B, Mid> PipeSystem<A, B, Mid> {
+    pub fn new<In, Out, W: WorldLike>(first: A, second: B) -> Self
+    where
+        A: System<In, Mid, W>,
+        B: System<Mid, Out, W>,
+    {
+        // An empty access list means "anything", which must survive the union
+        let (reads, writes) = if access_unknown(&first) || access_unknown(&second) {
+            (Vec::new(), Vec::new())
+        } else {
+            (
+                [first.reads(), second.reads()].concat(),
+                [first.writes(), second.writes()].concat(),
+            )
+        };
+        let name = format!("{} | {}", first.name(), second.name());
+
+        Self {
+            first,
+            second,
+            reads,
+            writes,
+            name,
+            _marker: std::marker::PhantomData,
         }
     }
 }
 
+impl<A, B, In, Mid, Out, W: WorldLike> System<In, Out, W> for PipeSystem<A, B, Mid>
+where
+    A: System<In, Mid, W>,
+    B: System<Mid, Out, W>,
+{
+    fn run_with(&mut self, input: In, world: &mut W) -> Out {
+        let output = self.first.run_with(input, world);
+        self.second.run_with(output, world)
+    }
+
+    fn reads(&self) -> &[TypeId] {
+        &self.reads
+    }
+
+    fn writes(&self) -> &[TypeId] {
+        &self.writes
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+fn access_unknown<In, Out, W: WorldLike>(system: &(impl System<In, Out, W> + ?Sized)) -> bool {
+    system.reads().is_empty() && system.writes().is_empty()
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Stage {
     PreUpdate,
@@ -106,142 +437,432 @@ pub enum Stage {
     Render,
 }
 
-pub struct Schedule {
-    stages: Vec<(Stage, Vec<Box<dyn System>>)>,
+type BoxedSystem<W> = Box<dyn System<(), (), W>>;
+
+type ScheduledSystem<W> = (SystemId, BoxedSystem<W>);
+
+type Stages<W> = Vec<(Stage, Vec<ScheduledSystem<W>>)>;
+
+fn empty_stages<W: WorldLike>() -> Stages<W> {
+    vec![
+        (Stage::PreUpdate, Vec::new()),
+        (Stage::Update, Vec::new()),
+        (Stage::PostUpdate, Vec::new()),
+        (Stage::Render, Vec::new()),
+    ]
+}
+
+fn batch_names<W: WorldLike>(stages: &Stages<W>, stage: Stage) -> Vec<Vec<&str>> {
+    stages
+        .iter()
+        .filter(|(s, _)| *s == stage)
+        .flat_map(|(_, systems)| {
+            compute_batches(systems)
+                .into_iter()
+                .map(|batch| batch.iter().map(|&i| systems[i].1.name()).collect())
+        })
+        .collect()
+}
+
+/// Runs systems stage by stage against a world of type `W`. Use
+/// `Schedule::new` for the crate's `World` and `Schedule::<W>::default()` for
+/// any other `WorldLike`.
+pub struct Schedule<W: WorldLike = World> {
+    stages: Stages<W>,
+    flush_after_each_system: bool,
+    states: Vec<Box<dyn StateDriver<W>>>,
 }
 
 impl Schedule {
     pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn builder() -> ScheduleBuilder {
+        ScheduleBuilder::new()
+    }
+
+    /// Runs `system` whenever the `State<S>` resource transitions to `value`
+    pub fn add_enter_system<S: Clone + PartialEq + Send + Sync + 'static>(
+        &mut self,
+        value: S,
+        system: impl System<(), (), World> + 'static,
+    ) {
+        self.state_systems::<S>().add_enter(value, Box::new(system));
+    }
+
+    /// Runs `system` whenever the `State<S>` resource transitions away from
+    /// `value`, before the enter systems of the new value
+    pub fn add_exit_system<S: Clone + PartialEq + Send + Sync + 'static>(
+        &mut self,
+        value: S,
+        system: impl System<(), (), World> + 'static,
+    ) {
+        self.state_systems::<S>().add_exit(value, Box::new(system));
+    }
+
+    fn state_systems<S: Clone + PartialEq + Send + Sync + 'static>(
+        &mut self,
+    ) -> &mut StateSystems<S> {
+        let position = self
+            .states
+            .iter_mut()
+            .position(|driver| driver.as_any_mut().is::<StateSystems<S>>());
+        let index = position.unwrap_or_else(|| {
+            self.states.push(Box::new(StateSystems::<S>::new()));
+            self.states.len() - 1
+        });
+        self.states[index]
+            .as_any_mut()
+            .downcast_mut()
+            .expect("State systems have the wrong type")
+    }
+}
+
+impl<W: WorldLike> Schedule<W> {
+    fn empty() -> Self {
         Self {
-            stages: vec![
-                (Stage::PreUpdate, Vec::new()),
-                (Stage::Update, Vec::new()),
-                (Stage::PostUpdate, Vec::new()),
-                (Stage::Render, Vec::new()),
-            ],
+            stages: empty_stages(),
+            flush_after_each_system: false,
+            states: Vec::new(),
         }
     }
 
-    pub fn add_system(&mut self, stage: Stage, system: impl System + 'static) {
-        for (s, systems) in &mut self.stages {
-            if *s == stage {
-                systems.push(Box::new(system));
-                return;
-            }
+    /// When enabled, queued commands are applied after every system instead of
+    /// only at the end of `run`, so later systems see entities spawned earlier
+    /// in the same frame
+    pub fn flush_after_each_system(&mut self, enabled: bool) {
+        self.flush_after_each_system = enabled;
+    }
+
+    /// Builds a schedule from `(stage, system)` pairs; systems keep their
+    /// relative order within each stage
+    pub fn from_systems(systems: impl IntoIterator<Item = (Stage, BoxedSystem<W>)>) -> Self {
+        let mut schedule = Self::empty();
+        for (stage, system) in systems {
+            schedule.add_boxed_system(stage, system);
+        }
+        schedule
+    }
+
+    pub fn add_system(
+        &mut self,
+        stage: Stage,
+        system: impl System<(), (), W> + 'static,
+    ) -> SystemId {
+        self.add_boxed_system(stage, Box::new(system))
+    }
+
+    fn add_boxed_system(&mut self, stage: Stage, system: BoxedSystem<W>) -> SystemId {
+        let id = SystemId::next();
+        if let Some((_, systems)) = self.stages.iter_mut().find(|(s, _)| *s == stage) {
+            systems.push((id, system));
         }
+        id
     }
 
-    pub fn add_update_system(&mut self, system: impl System + 'static) {
-        self.add_system(Stage::Update, system);
+    pub fn add_update_system(&mut self, system: impl System<(), (), W> + 'static) -> SystemId {
+        self.add_system(Stage::Update, system)
     }
 
-    pub fn run(&mut self, world: &mut World) {
+    /// Runs each stage in order. Within a stage, systems are grouped into
+    /// batches of non-conflicting systems (see `stage_batches`) whose
+    /// systems run in parallel; conflicting systems always run in the order
+    /// they were added.
+    pub fn run(&mut self, world: &mut W) {
+        for states in &mut self.states {
+            states.run_transitions(world);
+        }
         for (_stage, systems) in &mut self.stages {
-            for system in systems {
-                system.run(world);
-            }
+            run_batches(systems, world, self.flush_after_each_system);
         }
         world.flush_commands();
         world.tick();
     }
+
+    /// Names of the systems in `stage`, grouped into the batches `run` uses
+    pub fn stage_batches(&self, stage: Stage) -> Vec<Vec<&str>> {
+        batch_names(&self.stages, stage)
+    }
 }
 
-impl Default for Schedule {
+impl<W: WorldLike> Default for Schedule<W> {
     fn default() -> Self {
-        Self::new()
+        Self::empty()
     }
 }
 
-pub struct ParallelSchedule {
-    systems: Vec<Box<dyn System>>,
+/// Declarative construction of a `Schedule`, e.g.
+/// `Schedule::builder().system(Stage::Update, movement).build()`
+pub struct ScheduleBuilder<W: WorldLike = World> {
+    systems: Vec<(Stage, BoxedSystem<W>)>,
+    flush_after_each_system: bool,
 }
 
-impl ParallelSchedule {
+impl ScheduleBuilder {
     pub fn new() -> Self {
-        Self {
-            systems: Vec::new(),
-        }
+        Self::default()
     }
+}
 
-    pub fn add_system(&mut self, system: impl System + 'static) {
-        self.systems.push(Box::new(system));
+impl<W: WorldLike> ScheduleBuilder<W> {
+    pub fn flush_after_each_system(mut self, enabled: bool) -> Self {
+        self.flush_after_each_system = enabled;
+        self
     }
 
-    pub fn run(&mut self, world: &mut World) {
-        // Group systems by conflicts
-        let mut batches: Vec<Vec<usize>> = Vec::new();
-        let mut assigned = vec![false; self.systems.len()];
+    pub fn system(mut self, stage: Stage, system: impl System<(), (), W> + 'static) -> Self {
+        self.systems.push((stage, Box::new(system)));
+        self
+    }
 
-        for i in 0..self.systems.len() {
-            if assigned[i] {
-                continue;
-            }
+    pub fn build(self) -> Schedule<W> {
+        let mut schedule = Schedule::from_systems(self.systems);
+        schedule.flush_after_each_system(self.flush_after_each_system);
+        schedule
+    }
+}
+
+impl<W: WorldLike> Default for ScheduleBuilder<W> {
+    fn default() -> Self {
+        Self {
+            systems: Vec::new(),
+            flush_after_each_system: false,
+        }
+    }
+}
 
-            let mut batch = vec![i];
-            assigned[i] = true;
+/// Why a system of `ParallelSchedule` starts a later batch than the first:
+/// it conflicts with `after`, which sits in the batch just before it. See
+/// `ParallelSchedule::last_batch_diagnostics`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchDiagnostic {
+    pub system: String,
+    /// Index of the batch `system` landed in, within its stage
+    pub batch: usize,
+    pub after: String,
+    /// A component one of the two writes and the other accesses, or `None`
+    /// when either declares no access and so conflicts with everything
+    pub component: Option<TypeId>,
+}
 
-            for j in (i + 1)..self.systems.len() {
-                if assigned[j] {
-                    continue;
-                }
+/// Like `Schedule`, stages run in order and each stage's systems are
+/// batched by their declared access
+pub struct ParallelSchedule<W: WorldLike = World> {
+    stages: Stages<W>,
+    diagnostics: Vec<BatchDiagnostic>,
+}
 
-                let conflicts = batch
-                    .iter()
-                    .any(|&b| self.systems_conflict(&self.systems[b], &self.systems[j]));
+impl ParallelSchedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
 
-                if !conflicts {
-                    batch.push(j);
-                    assigned[j] = true;
-                }
-            }
+impl<W: WorldLike> ParallelSchedule<W> {
+    /// Adds `system` to `Stage::Update`
+    pub fn add_system(&mut self, system: impl System<(), (), W> + 'static) -> SystemId {
+        self.add_stage_system(Stage::Update, system)
+    }
 
-            batches.push(batch);
+    pub fn add_stage_system(
+        &mut self,
+        stage: Stage,
+        system: impl System<(), (), W> + 'static,
+    ) -> SystemId {
+        let id = SystemId::next();
+        if let Some((_, systems)) = self.stages.iter_mut().find(|(s, _)| *s == stage) {
+            systems.push((id, Box::new(system)));
         }
+        id
+    }
 
-        // Run each batch (systems in a batch could run in parallel)
-        for batch in batches {
-            for &system_index in &batch {
-                self.systems[system_index].run(world);
-            }
+    pub fn run(&mut self, world: &mut W) {
+        self.diagnostics.clear();
+        for (_stage, systems) in &mut self.stages {
+            self.diagnostics.extend(batch_diagnostics(systems));
+            run_batches(systems, world, false);
         }
-
         world.flush_commands();
         world.tick();
     }
 
-    fn systems_conflict(&self, a: &Box<dyn System>, b: &Box<dyn System>) -> bool {
-        let a_reads = a.reads();
-        let a_writes = a.writes();
-        let b_reads = b.reads();
-        let b_writes = b.writes();
+    /// Names of the systems in `stage`, grouped into the batches `run` uses
+    pub fn stage_batches(&self, stage: Stage) -> Vec<Vec<&str>> {
+        batch_names(&self.stages, stage)
+    }
 
-        // Write-
-        // Write-write conflict
-        for a_write in a_writes {
-            if b_writes.contains(a_write) {
-                return true;
-            }
+    /// For each system the last `run` placed after the first batch of its
+    /// stage, the conflict that put it there, to explain why systems
+    /// serialize unexpectedly
+    pub fn last_batch_diagnostics(&self) -> &[BatchDiagnostic] {
+        &self.diagnostics
+    }
+}
+
+fn batch_diagnostics<W: WorldLike>(systems: &[ScheduledSystem<W>]) -> Vec<BatchDiagnostic> {
+    batch_levels(systems)
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, (batch, blocker))| {
+            let (_, system) = &systems[i];
+            let (_, after) = &systems[blocker?];
+            Some(BatchDiagnostic {
+                system: system.name().to_string(),
+                batch,
+                after: after.name().to_string(),
+                component: conflict_component(after.as_ref(), system.as_ref()),
+            })
+        })
+        .collect()
+}
+
+/// Groups systems into batches that may run together. Each system lands one
+/// batch after the last batch holding a system it conflicts with, so batches
+/// preserve the relative order of every conflicting pair.
+fn compute_batches<W: WorldLike>(systems: &[ScheduledSystem<W>]) -> Vec<Vec<usize>> {
+    let mut batches: Vec<Vec<usize>> = Vec::new();
+    for (i, (level, _)) in batch_levels(systems).into_iter().enumerate() {
+        if level == batches.len() {
+            batches.push(Vec::new());
         }
+        batches[level].push(i);
+    }
+    batches
+}
 
-        // Read-write conflict
-        for a_read in a_reads {
-            if b_writes.contains(a_read) {
-                return true;
+// Each system's batch, and the earlier system in the batch before it that
+// pushed it there, `None` in the first batch
+fn batch_levels<W: WorldLike>(systems: &[ScheduledSystem<W>]) -> Vec<(usize, Option<usize>)> {
+    let mut levels: Vec<(usize, Option<usize>)> = Vec::with_capacity(systems.len());
+    for (i, (_, system)) in systems.iter().enumerate() {
+        let blocker = (0..i)
+            .filter(|&j| systems_conflict(systems[j].1.as_ref(), system.as_ref()))
+            .max_by_key(|&j| (levels[j].0, std::cmp::Reverse(j)));
+        levels.push(match blocker {
+            Some(j) => (levels[j].0 + 1, Some(j)),
+            None => (0, None),
+        });
+    }
+    levels
+}
+
+// Runs `run` with the world's writes attributed to `id`, then restores the
+// writer from before, also when `run` panics
+fn run_as<W: WorldLike, D: Deref<Target = W>, R>(
+    id: SystemId,
+    world: &mut D,
+    run: impl FnOnce(&mut D) -> R,
+) -> R {
+    let previous = world.set_current_system(id);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| run(world)));
+    world.set_current_system(previous);
+    result.unwrap_or_else(|payload| panic::resume_unwind(payload))
+}
+
+fn run_batches<W: WorldLike>(systems: &mut [ScheduledSystem<W>], world: &mut W, flush_each: bool) {
+    for batch in compute_batches(systems) {
+        // A batch's systems run concurrently; any that can't share the world
+        // run one at a time afterwards, which is fine as none conflict
+        let exclusive = if batch.len() > 1 {
+            // The systems reach the world only through `System::run_shared`,
+            // whose contract the batching upholds
+            let shared = &*world;
+            let exclusive: Vec<usize> = systems
+                .iter_mut()
+                .enumerate()
+                .filter(|(index, _)| batch.contains(index))
+                .collect::<Vec<_>>()
+                .into_par_iter()
+                .filter_map(|(index, (id, system))| {
+                    #[cfg(feature = "tracing")]
+                    let _span = tracing::info_span!("system", name = system.name()).entered();
+                    let mut world = shared;
+                    let ran = run_as(*id, &mut world, |world| unsafe {
+                        system.run_shared(*world)
+                    });
+                    (!ran).then_some(index)
+                })
+                .collect();
+            if flush_each && exclusive.len() < batch.len() {
+                world.flush_commands();
+            }
+            exclusive
+        } else {
+            batch
+        };
+
+        for system_index in exclusive {
+            let (id, system) = &mut systems[system_index];
+            #[cfg(feature = "tracing")]
+            let _span = tracing::info_span!("system", name = system.name()).entered();
+            let mut world = &mut *world;
+            run_as(*id, &mut world, |world| system.run(world));
+            if flush_each {
+                world.flush_commands();
             }
         }
+    }
+}
 
-        for b_read in b_reads {
-            if a_writes.contains(b_read) {
-                return true;
-            }
+fn systems_conflict<W: WorldLike>(a: &dyn System<(), (), W>, b: &dyn System<(), (), W>) -> bool {
+    let a_reads = a.reads();
+    let a_writes = a.writes();
+    let b_reads = b.reads();
+    let b_writes = b.writes();
+
+    // Systems that declare no access (e.g. plain `FnMut(&mut World)`) may
+    // touch anything, so they conflict with everything
+    if (a_reads.is_empty() && a_writes.is_empty()) || (b_reads.is_empty() && b_writes.is_empty()) {
+        return true;
+    }
+
+    // Write-write conflict
+    for a_write in a_writes {
+        if b_writes.contains(a_write) {
+            return true;
         }
+    }
 
-        false
+    // Read-write conflict
+    for a_read in a_reads {
+        if b_writes.contains(a_read) {
+            return true;
+        }
+    }
+
+    for b_read in b_reads {
+        if a_writes.contains(b_read) {
+            return true;
+        }
     }
+
+    false
 }
 
-impl Default for ParallelSchedule {
+// A component behind a conflict between `a` and `b`, `None` if either
+// declares no access
+fn conflict_component<W: WorldLike>(
+    a: &dyn System<(), (), W>,
+    b: &dyn System<(), (), W>,
+) -> Option<TypeId> {
+    let (a_reads, a_writes, b_reads, b_writes) = (a.reads(), a.writes(), b.reads(), b.writes());
+    a_writes
+        .iter()
+        .find(|&component| b_writes.contains(component) || b_reads.contains(component))
+        .or_else(|| {
+            b_writes
+                .iter()
+                .find(|&component| a_reads.contains(component))
+        })
+        .copied()
+}
+
+impl<W: WorldLike> Default for ParallelSchedule<W> {
     fn default() -> Self {
-        Self::new()
+        Self {
+            stages: empty_stages(),
+            diagnostics: Vec::new(),
+        }
     }
 }
@@ -0,0 +1,63 @@
+use crate::component::Component;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+type Caster<Trait> = Box<dyn Fn(*mut u8) -> *mut Trait + Send + Sync>;
+
+struct TraitCasters<Trait: ?Sized> {
+    casters: HashMap<TypeId, Caster<Trait>>,
+}
+
+/// Maps a trait object type to the concrete components that can be viewed as it
+pub struct TraitRegistry {
+    traits: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl TraitRegistry {
+    pub fn new() -> Self {
+        Self {
+            traits: HashMap::new(),
+        }
+    }
+
+    pub fn register<Trait: ?Sized + 'static, T: Component>(
+        &mut self,
+        cast: fn(&mut T) -> &mut Trait,
+    ) {
+        let entry = self.traits.entry(TypeId::of::<Trait>()).or_insert_with(|| {
+            Box::new(TraitCasters::<Trait> {
+                casters: HashMap::new(),
+            })
+        });
+
+        let casters = entry
+            .downcast_mut::<TraitCasters<Trait>>()
+            .expect("Trait registry entry has the wrong type");
+
+        casters.casters.insert(
+            TypeId::of::<T>(),
+            Box::new(move |ptr| cast(unsafe { &mut *(ptr as *mut T) }) as *mut Trait),
+        );
+    }
+
+    pub(crate) fn caster<Trait: ?Sized + 'static>(
+        &self,
+        component: TypeId,
+    ) -> Option<&Caster<Trait>> {
+        self.traits
+            .get(&TypeId::of::<Trait>())?
+            .downcast_ref::<TraitCasters<Trait>>()?
+            .casters
+            .get(&component)
+    }
+
+    pub fn is_registered<Trait: ?Sized + 'static>(&self, component: TypeId) -> bool {
+        self.caster::<Trait>(component).is_some()
+    }
+}
+
+impl Default for TraitRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
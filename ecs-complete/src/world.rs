@@ -1,42 +1,152 @@
-use crate::archetype::ArchetypeMap;
-use crate::command::Commands;
-use crate::component::{Bundle, Component, type_name};
-use crate::entity::{Entity, EntityInfo, EntityMeta};
+use crate::archetype::{ArchetypeMap, ArchetypeView};
+use crate::command::{Commands, EntityCommandBuffer};
+use crate::component::{
+    Bundle, Component, ComponentId, ComponentIndex, DynComponentId, DynValue, DynValueTag,
+    DynamicBundle, type_name,
+};
+use crate::entity::{Entity, EntityInfo, EntityMap, EntityMeta};
 use crate::error::{EcsError, Result};
-use crate::query::Query;
-use crate::resource::Resources;
-use slotmap::SlotMap;
-use std::any::TypeId;
+use crate::events::{CloneEventsFn, EventStorage, Events};
+use crate::hierarchy::{ChildPolicy, Children, Parent};
+use crate::prefab::{Prefab, PrefabEntity};
+use crate::query::{Disabled, Query, QueryFilter, ReadOnlyQuery, RowFilter};
+use crate::registry::{CloneIntoFn, ComponentRegistry};
+use crate::resource::{ResourceCheckpoint, Resources};
+use crate::system::SystemId;
+use crate::trait_query::TraitRegistry;
+use rayon::iter::plumbing::{Consumer, Producer, ProducerCallback, UnindexedConsumer, bridge};
+use rayon::prelude::*;
+use std::alloc::{GlobalAlloc, Layout};
+use std::any::{Any, TypeId};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+
+type ArchetypeChangeHook = Box<dyn Fn(&mut World, Entity, usize, usize) + Send + Sync>;
+
+/// A component registered at runtime, stored as raw bytes
+#[derive(Clone)]
+struct DynComponentInfo {
+    name: &'static str,
+    layout: Layout,
+    drop_fn: unsafe fn(*mut u8),
+}
+
+unsafe fn drop_nothing(_ptr: *mut u8) {}
+
+// Stands in for cloning `Children` in `clone_entity`: a copy starts childless
+unsafe fn write_no_children(_src: *const u8, dst: *mut u8) {
+    unsafe { (dst as *mut Children).write(Children::new()) }
+}
+
+// Dynamic components have no Rust type, so reflection sees them as `()`
+fn opaque_any(_ptr: *const u8) -> *const dyn Any {
+    &()
+}
 
 pub struct World {
-    entities: SlotMap<Entity, EntityLocation>,
+    entities: EntityMap<EntityLocation>,
     pub(crate) archetypes: ArchetypeMap,
     resources: Resources,
+    events: HashMap<TypeId, Box<dyn EventStorage>>,
+    // Event types `try_clone` can copy, see `register_clonable_event`
+    clonable_events: HashMap<TypeId, CloneEventsFn>,
+    traits: TraitRegistry,
+    registry: ComponentRegistry,
+    archetype_change_hooks: Vec<ArchetypeChangeHook>,
+    dynamic_components: Vec<DynComponentInfo>,
+    dyn_value_tags: Vec<&'static str>,
+    // Entities that lost a component this tick, by component type
+    removed: HashMap<TypeId, Vec<Entity>>,
     commands: Commands,
     tick: u64,
+    hierarchy_cleanup: bool,
+    child_policy: ChildPolicy,
+}
+
+/// Memory held by a world's entity and component storage
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// Bytes allocated for component columns (`capacity * item_size`)
+    pub column_bytes: usize,
+    /// Column bytes allocated but not holding a component
+    pub wasted_bytes: usize,
+    /// Bytes used to track entity locations and archetype membership
+    pub entity_metadata_bytes: usize,
+    pub archetype_count: usize,
+}
+
+/// An archetype's id, component type names and entity count, see
+/// `World::archetypes_info`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchetypeInfo {
+    pub id: usize,
+    pub type_names: Vec<&'static str>,
+    pub len: usize,
 }
 
 #[derive(Clone, Copy)]
 struct EntityLocation {
     archetype: usize,
     index: usize,
+    spawn_tick: u64,
 }
 
 impl World {
     pub fn new() -> Self {
         Self {
-            entities: SlotMap::with_key(),
+            entities: EntityMap::new(),
             archetypes: ArchetypeMap::new(),
             resources: Resources::new(),
+            events: HashMap::new(),
+            clonable_events: HashMap::new(),
+            traits: TraitRegistry::new(),
+            registry: ComponentRegistry::new(),
+            archetype_change_hooks: Vec::new(),
+            dynamic_components: Vec::new(),
+            dyn_value_tags: Vec::new(),
+            removed: HashMap::new(),
             commands: Commands::new(),
             tick: 0,
+            hierarchy_cleanup: false,
+            child_policy: ChildPolicy::Orphan,
+        }
+    }
+
+    /// A world whose component columns allocate from `allocator` instead of
+    /// the global allocator, e.g. an arena or bump allocator
+    pub fn with_allocator(allocator: impl GlobalAlloc + Send + Sync + 'static) -> Self {
+        Self {
+            archetypes: ArchetypeMap::with_allocator(Arc::new(allocator)),
+            ..Self::new()
         }
     }
 
+    /// A world whose archetypes are split into chunks of at most
+    /// `chunk_size` entities, see `component_chunks`. Each chunk is its own
+    /// allocation, so growing adds a chunk instead of reallocating and moving
+    /// every row.
+    pub fn with_chunk_size(chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "chunk size must be non-zero");
+        let mut world = Self::new();
+        world.archetypes.set_chunk_size(Some(chunk_size));
+        world
+    }
+
+    pub fn chunk_size(&self) -> Option<usize> {
+        self.archetypes.chunk_size()
+    }
+
     pub fn tick(&mut self) {
         self.tick += 1;
-        for archetype in self.archetypes.iter_mut() {
-            archetype.set_tick(self.tick);
+        self.archetypes.set_tick(self.tick);
+        for events in self.events.values_mut() {
+            events.update();
+        }
+        self.resources.set_tick(self.tick);
+        for entities in self.removed.values_mut() {
+            entities.clear();
         }
     }
 
@@ -44,15 +154,20 @@ impl World {
         self.tick
     }
 
+    /// The tick before the most recent `tick()` call
+    pub fn last_frame_tick(&self) -> u64 {
+        self.tick.saturating_sub(1)
+    }
+
     pub fn spawn<B: Bundle>(&mut self, bundle: B) -> Entity {
         let type_ids = B::type_ids();
         let type_names = B::type_names();
 
         let archetype_index = self.archetypes.get_or_create(type_ids, type_names);
-        let archetype = self.archetypes.get_mut(archetype_index).unwrap();
+        let mut archetype = self.archetypes.view_mut(archetype_index).unwrap();
 
         if archetype.is_empty() {
-            B::init_archetype(archetype);
+            B::init_archetype(&mut archetype);
         }
 
         let entity_index = archetype.len();
@@ -60,18 +175,164 @@ impl World {
         let entity = self.entities.insert(EntityLocation {
             archetype: archetype_index,
             index: entity_index,
+            spawn_tick: self.tick,
+        });
+
+        archetype.push_entity(entity);
+        bundle.insert_into(&mut archetype, entity_index);
+        self.archetypes.mark_structural_change();
+
+        entity
+    }
+
+    /// Spawns `bundle` at exactly `entity`, e.g. to restore a snapshot with
+    /// its original ids so stored `Entity` references stay valid. Fails if
+    /// `entity`'s slot is already held by a live entity.
+    pub fn spawn_at<B: Bundle>(&mut self, entity: Entity, bundle: B) -> Result<()> {
+        let archetype_index = self
+            .archetypes
+            .get_or_create(B::type_ids(), B::type_names());
+        let mut archetype = self.archetypes.view_mut(archetype_index).unwrap();
+
+        let location = EntityLocation {
+            archetype: archetype_index,
+            index: archetype.len(),
+            spawn_tick: self.tick,
+        };
+        if !self.entities.insert_at(entity, location) {
+            return Err(EcsError::InvalidOperation(format!(
+                "cannot spawn at {:?}, its slot is already in use",
+                entity
+            )));
+        }
+
+        if archetype.is_empty() {
+            B::init_archetype(&mut archetype);
+        }
+        archetype.push_entity(entity);
+        bundle.insert_into(&mut archetype, location.index);
+        self.archetypes.mark_structural_change();
+
+        Ok(())
+    }
+
+    /// Spawns the components staged in `bundle`, resolving the archetype from
+    /// their types at runtime
+    pub fn spawn_dynamic_bundle(&mut self, bundle: DynamicBundle) -> Entity {
+        let archetype_index = self
+            .archetypes
+            .get_or_create(bundle.type_ids(), bundle.type_names());
+        let mut archetype = self.archetypes.view_mut(archetype_index).unwrap();
+
+        if archetype.is_empty() {
+            bundle.init_archetype(&mut archetype);
+        }
+
+        let entity_index = archetype.len();
+        let entity = self.entities.insert(EntityLocation {
+            archetype: archetype_index,
+            index: entity_index,
+            spawn_tick: self.tick,
         });
 
         archetype.push_entity(entity);
-        bundle.insert_into(archetype, entity_index);
+        bundle.insert_into(&mut archetype, entity_index);
+        self.archetypes.mark_structural_change();
 
         entity
     }
 
+    /// Resolves `B`'s archetype once and returns a writer that spawns into it
+    pub fn archetype_writer<B: Bundle>(&mut self) -> ArchetypeWriter<'_, B> {
+        let archetype = self
+            .archetypes
+            .get_or_create(B::type_ids(), B::type_names());
+        let target = self.archetypes.get_mut(archetype).unwrap();
+        if target.is_empty() {
+            B::init_archetype(target);
+        }
+
+        ArchetypeWriter {
+            world: self,
+            archetype,
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    /// When on, `despawn` removes the entity from its parent's `Children` and
+    /// handles its own children per `set_child_policy`. Off by default.
+    pub fn set_hierarchy_cleanup(&mut self, enabled: bool) {
+        self.hierarchy_cleanup = enabled;
+    }
+
+    pub fn set_child_policy(&mut self, policy: ChildPolicy) {
+        self.child_policy = policy;
+    }
+
+    fn detach_hierarchy(&mut self, entity: Entity) {
+        self.detach_from_parent(entity);
+
+        // The entity is about to be despawned, so read its children in place
+        // rather than moving it to a new archetype without them
+        if let Some(Children(children)) = self.get::<Children>(entity).cloned() {
+            self.release_children(entity, children);
+        }
+    }
+
+    // Drops `entity` from its parent's `Children`, which moves no entity
+    fn detach_from_parent(&mut self, entity: Entity) {
+        if let Some(&Parent(parent)) = self.get::<Parent>(entity)
+            && let Some(children) = self.get_mut::<Children>(parent)
+        {
+            children.remove(entity);
+        }
+    }
+
+    // Orphans or despawns, per the child policy, those of `children` whose
+    // parent is still `entity`
+    fn release_children(&mut self, entity: Entity, children: Vec<Entity>) {
+        for child in children {
+            if self.get::<Parent>(child) != Some(&Parent(entity)) {
+                continue;
+            }
+            match self.child_policy {
+                ChildPolicy::Orphan => {
+                    let _ = self.remove::<Parent>(child);
+                }
+                ChildPolicy::Despawn => {
+                    self.despawn(child);
+                }
+            }
+        }
+    }
+
     pub fn despawn(&mut self, entity: Entity) -> bool {
+        if self.hierarchy_cleanup && self.is_alive(entity) {
+            self.detach_hierarchy(entity);
+            // A `Parent` cycle may have despawned it already
+            if !self.is_alive(entity) {
+                return true;
+            }
+        }
+        self.despawn_row(entity)
+    }
+
+    // Removes `entity` and its components, leaving the hierarchy as it is
+    fn despawn_row(&mut self, entity: Entity) -> bool {
         if let Some(location) = self.entities.remove(entity) {
+            let removed = &mut self.removed;
+            self.archetypes
+                .sparse_mut()
+                .remove_entity(entity, |type_id| {
+                    removed.entry(type_id).or_default().push(entity);
+                });
+
             let archetype = self.archetypes.get_mut(location.archetype).unwrap();
+            for &type_id in archetype.types() {
+                removed.entry(type_id).or_default().push(entity);
+            }
             let (removed_entity, swapped_entity) = archetype.remove_entity(location.index);
+            self.archetypes.mark_structural_change();
 
             if let Some(swapped) = swapped_entity {
                 if let Some(swapped_location) = self.entities.get_mut(swapped) {
@@ -85,32 +346,270 @@ impl World {
         }
     }
 
+    /// Despawns `entity` and returns what it looked like just before removal,
+    /// or `None` if it was already dead.
+    pub fn despawn_get_info(&mut self, entity: Entity) -> Option<EntityInfo> {
+        let info = self.entity_info(entity)?;
+        self.despawn(entity);
+        Some(info)
+    }
+
+    /// Despawns every live entity in `entities`, returning how many were removed.
+    /// Removals are grouped per archetype and applied from the highest row down,
+    /// so each swap-remove only ever pulls in a surviving entity. Hierarchy
+    /// cleanup runs first, as in `despawn`; children it despawns under
+    /// `ChildPolicy::Despawn` are not counted.
+    pub fn despawn_batch(&mut self, entities: &[Entity]) -> usize {
+        if self.hierarchy_cleanup {
+            for &entity in entities {
+                if self.is_alive(entity) {
+                    self.detach_hierarchy(entity);
+                }
+            }
+        }
+
+        let mut rows: HashMap<usize, Vec<usize>> = HashMap::new();
+        for &entity in entities {
+            if let Some(location) = self.entities.remove(entity) {
+                let removed = &mut self.removed;
+                self.archetypes
+                    .sparse_mut()
+                    .remove_entity(entity, |type_id| {
+                        removed.entry(type_id).or_default().push(entity);
+                    });
+                for &type_id in self.archetypes.get(location.archetype).unwrap().types() {
+                    removed.entry(type_id).or_default().push(entity);
+                }
+                rows.entry(location.archetype)
+                    .or_default()
+                    .push(location.index);
+            }
+        }
+
+        let mut despawned = 0;
+        for (archetype_index, mut indices) in rows {
+            indices.sort_unstable_by(|a, b| b.cmp(a));
+
+            let archetype = self.archetypes.get_mut(archetype_index).unwrap();
+            for index in indices {
+                let (_removed, swapped_entity) = archetype.remove_entity(index);
+
+                if let Some(swapped) = swapped_entity
+                    && let Some(swapped_location) = self.entities.get_mut(swapped)
+                {
+                    swapped_location.index = index;
+                }
+                despawned += 1;
+            }
+        }
+        if despawned > 0 {
+            self.archetypes.mark_structural_change();
+        }
+
+        despawned
+    }
+
+    /// Spawns an entity without components, e.g. to reserve an id and fill
+    /// it in later. It is alive but matches no query until something is
+    /// inserted.
+    pub fn spawn_empty(&mut self) -> Entity {
+        let archetype_index = self.archetypes.get_or_create(Vec::new(), Vec::new());
+        let archetype = self.archetypes.get_mut(archetype_index).unwrap();
+
+        let entity = self.entities.insert(EntityLocation {
+            archetype: archetype_index,
+            index: archetype.len(),
+            spawn_tick: self.tick,
+        });
+        archetype.push_entity(entity);
+        self.archetypes.mark_structural_change();
+        entity
+    }
+
+    /// Returns a handle to `entity`, creating it as an empty entity at exactly
+    /// that id if it isn't alive. Returns `None` if the id's slot is held by a
+    /// different generation.
+    pub fn get_or_spawn(&mut self, entity: Entity) -> Option<EntityMut<'_>> {
+        if !self.entities.contains_key(entity) {
+            let archetype_index = self.archetypes.get_or_create(Vec::new(), Vec::new());
+            let archetype = self.archetypes.get_mut(archetype_index).unwrap();
+
+            let location = EntityLocation {
+                archetype: archetype_index,
+                index: archetype.len(),
+                spawn_tick: self.tick,
+            };
+            if !self.entities.insert_at(entity, location) {
+                return None;
+            }
+            archetype.push_entity(entity);
+            self.archetypes.mark_structural_change();
+        }
+
+        Some(EntityMut {
+            world: self,
+            entity,
+        })
+    }
+
+    /// A handle to `entity`'s `T` that re-resolves on every read
+    pub fn track<T: Component>(&self, entity: Entity) -> ComponentHandle<T> {
+        ComponentHandle {
+            entity,
+            _marker: std::marker::PhantomData,
+        }
+    }
+
     pub fn is_alive(&self, entity: Entity) -> bool {
         self.entities.contains_key(entity)
     }
 
     pub fn get<T: Component>(&self, entity: Entity) -> Option<&T> {
         let location = self.entities.get(entity)?;
-        let archetype = self.archetypes.get(location.archetype)?;
+        let archetype = self.archetypes.view(location.archetype)?;
         archetype.get_component::<T>(location.index)
     }
 
     pub fn get_mut<T: Component>(&mut self, entity: Entity) -> Option<&mut T> {
         let location = self.entities.get(entity)?;
-        let archetype = self.archetypes.get_mut(location.archetype)?;
-        archetype.get_component_mut::<T>(location.index)
+        let archetype = self.archetypes.view(location.archetype)?;
+        // `&mut self` rules out any other borrow of the component
+        unsafe { archetype.get_component_mut::<T>(location.index) }
     }
 
     pub fn try_get<T: Component>(&self, entity: Entity) -> Result<&T> {
         self.get(entity).ok_or(EcsError::EntityNotFound(entity))
     }
 
+    /// Renumbers the live entities to dense slots `0..n` (in slot order, all
+    /// at the first generation) and returns the old to new mapping, e.g.
+    /// before saving. Queued commands are applied first, so none of them
+    /// refers to an old id. `Parent` and `Children` are rewritten, with links
+    /// to dead entities dropped (a `Parent` pointing at one is removed); other
+    /// components holding `Entity` ids must be fixed up by the caller with the
+    /// returned map.
+    pub fn compact(&mut self) -> HashMap<Entity, Entity> {
+        self.flush_commands();
+
+        let mut entities = EntityMap::new();
+        let mut map = HashMap::with_capacity(self.entities.len());
+        for (entity, &location) in self.entities.iter() {
+            map.insert(entity, entities.insert(location));
+        }
+        self.entities = entities;
+
+        for archetype in self.archetypes.iter_mut() {
+            archetype.remap_entities(|entity| map[&entity]);
+        }
+        self.archetypes.sparse_mut().remap(&map);
+        for removed in self.removed.values_mut() {
+            removed.retain_mut(|entity| match map.get(entity) {
+                Some(&new) => {
+                    *entity = new;
+                    true
+                }
+                None => false,
+            });
+        }
+
+        // Remapped in place without marking, as the ids change but not the
+        // links they describe
+        let mut orphans = Vec::new();
+        for archetype in self.archetypes.views() {
+            for index in 0..archetype.len() {
+                // `&mut self` rules out any other borrow
+                if let Some(parent) =
+                    unsafe { archetype.get_component_mut_unmarked::<Parent>(index) }
+                {
+                    match map.get(&parent.0) {
+                        Some(&new) => parent.0 = new,
+                        None => orphans.push(archetype.entities()[index]),
+                    }
+                }
+                if let Some(children) =
+                    unsafe { archetype.get_component_mut_unmarked::<Children>(index) }
+                {
+                    children.0 = children
+                        .0
+                        .iter()
+                        .filter_map(|child| map.get(child).copied())
+                        .collect();
+                }
+            }
+        }
+        self.archetypes.mark_structural_change();
+        for orphan in orphans {
+            let _ = self.remove::<Parent>(orphan);
+        }
+
+        map
+    }
+
+    /// Marks `entity`'s `T` changed in the current tick, for writes that
+    /// bypassed `get_mut`, e.g. from FFI through a raw pointer.
+    #[cfg(feature = "change_detection")]
+    pub fn mark_changed<T: Component>(&mut self, entity: Entity) -> Result<()> {
+        let location = *self
+            .entities
+            .get(entity)
+            .ok_or(EcsError::EntityNotFound(entity))?;
+        let marked = self
+            .archetypes
+            .get_mut(location.archetype)
+            .is_some_and(|archetype| archetype.mark_changed(TypeId::of::<T>(), location.index));
+        if marked
+            || self
+                .archetypes
+                .sparse_mut()
+                .get_mut::<T>()
+                .is_some_and(|set| set.get_mut(entity).is_some())
+        {
+            return Ok(());
+        }
+        Err(EcsError::ComponentNotFound {
+            entity,
+            type_name: type_name::<T>(),
+        })
+    }
+
+    /// Like `try_get`, but tells a dead entity (`EntityNotFound`) apart from
+    /// a live one without `T` (`ComponentNotFound`)
+    pub fn get_checked<T: Component>(&self, entity: Entity) -> Result<&T> {
+        if !self.is_alive(entity) {
+            return Err(EcsError::EntityNotFound(entity));
+        }
+        self.get(entity).ok_or(EcsError::ComponentNotFound {
+            entity,
+            type_name: type_name::<T>(),
+        })
+    }
+
     pub fn try_get_mut<T: Component>(&mut self, entity: Entity) -> Result<&mut T> {
         if !self.is_alive(entity) {
             return Err(EcsError::EntityNotFound(entity));
         }
-        self.get_mut(entity)
-            .ok_or(EcsError::ComponentNotFound(TypeId::of::<T>()))
+        self.get_mut(entity).ok_or(EcsError::ComponentNotFound {
+            entity,
+            type_name: type_name::<T>(),
+        })
+    }
+
+    /// Inserts a component computed from the entity's current components,
+    /// e.g. a bounding box derived from a mesh. `f` only gets shared access;
+    /// the insert happens once it returns.
+    pub fn insert_with<C: Component>(
+        &mut self,
+        entity: Entity,
+        f: impl FnOnce(EntityRef<'_>) -> C,
+    ) -> Result<()> {
+        if !self.is_alive(entity) {
+            return Err(EcsError::EntityNotFound(entity));
+        }
+        let component = f(EntityRef {
+            world: self,
+            entity,
+        });
+        self.insert(entity, component)
     }
 
     pub fn insert<C: Component>(&mut self, entity: Entity, component: C) -> Result<()> {
@@ -119,55 +618,231 @@ impl World {
             .get(entity)
             .ok_or(EcsError::EntityNotFound(entity))?;
 
+        if let Some(set) = self.archetypes.sparse_mut().get_mut::<C>() {
+            set.insert(entity, component);
+            return Ok(());
+        }
+
         let from_archetype = location.archetype;
         let component_type = TypeId::of::<C>();
 
         // Check if component already exists
         let from_arch = self.archetypes.get(from_archetype).unwrap();
         if from_arch.types().contains(&component_type) {
-            // Just update the component
+            // Overwrite in place; assigning drops the previous value
             let archetype = self.archetypes.get_mut(from_archetype).unwrap();
-            archetype.set_component(location.index, component);
+            *archetype.get_component_mut::<C>(location.index).unwrap() = component;
             return Ok(());
         }
 
-        // Find or create target archetype
-        let to_archetype = if let Some(to) = self
+        // Move entity to new archetype
+        let to_archetype = self.archetype_with_added::<C>(from_archetype);
+        self.move_entity_with_component(entity, from_archetype, to_archetype, component)?;
+
+        Ok(())
+    }
+
+    /// Finds or creates the archetype of `from_archetype` plus `C`
+    fn archetype_with_added<C: Component>(&mut self, from_archetype: usize) -> usize {
+        let component_type = TypeId::of::<C>();
+        if let Some(to) = self
             .archetypes
             .find_archetype_with_added(from_archetype, component_type)
         {
-            to
-        } else {
-            let to = self.archetypes.create_archetype_with_added(
-                from_archetype,
-                component_type,
-                type_name::<C>(),
+            return to;
+        }
+
+        let to = self.archetypes.create_archetype_with_added(
+            from_archetype,
+            component_type,
+            type_name::<C>(),
+        );
+
+        // Initialize columns in the new archetype
+        let (from_arch, to_arch) = self.archetypes.get_pair_mut(from_archetype, to).unwrap();
+
+        // Copy column structure from source
+        for column in &from_arch.columns {
+            to_arch.add_column_raw(column.id, column.layout, column.drop_fn, column.as_any_fn);
+        }
+
+        // Add column for the new component
+        to_arch.add_column::<C>();
+
+        to
+    }
+
+    /// Inserts many components at once. Entities leaving the same archetype
+    /// are moved together, copying each contiguous run of rows with one
+    /// memcpy per column. Fails before changing anything if an entity is
+    /// dead or listed twice.
+    pub fn insert_batch<C: Component>(
+        &mut self,
+        batch: impl IntoIterator<Item = (Entity, C)>,
+    ) -> Result<()> {
+        let batch: Vec<(Entity, C)> = batch.into_iter().collect();
+        let mut seen = HashSet::with_capacity(batch.len());
+        for &(entity, _) in &batch {
+            if !self.is_alive(entity) {
+                return Err(EcsError::EntityNotFound(entity));
+            }
+            if !seen.insert(entity) {
+                return Err(EcsError::InvalidOperation(format!(
+                    "{:?} appears twice in insert_batch",
+                    entity
+                )));
+            }
+        }
+
+        // Group the entities that actually change archetype by source
+        let mut groups: HashMap<usize, Vec<(usize, Entity, C)>> = HashMap::new();
+        for (entity, component) in batch {
+            let location = *self.entities.get(entity).unwrap();
+            let has_component = self
+                .archetypes
+                .get(location.archetype)
+                .unwrap()
+                .types()
+                .contains(&TypeId::of::<C>());
+            if self.archetypes.sparse().get::<C>().is_some() || has_component {
+                self.insert(entity, component)?;
+            } else {
+                groups.entry(location.archetype).or_default().push((
+                    location.index,
+                    entity,
+                    component,
+                ));
+            }
+        }
+
+        let mut moved = Vec::new();
+        for (from_archetype, mut rows) in groups {
+            let to_archetype = self.archetype_with_added::<C>(from_archetype);
+            rows.sort_unstable_by_key(|&(index, _, _)| index);
+            moved.extend(
+                rows.iter()
+                    .map(|&(_, entity, _)| (entity, from_archetype, to_archetype)),
             );
+            self.move_rows_with_component(from_archetype, to_archetype, rows);
+        }
+        self.archetypes.mark_structural_change();
 
-            // Initialize columns in the new archetype
-            let (from_arch, to_arch) = self.archetypes.get_pair_mut(from_archetype, to).unwrap();
+        // Hooks may restructure the world, so they only run once every
+        // group has moved
+        for (entity, from_archetype, to_archetype) in moved {
+            self.run_archetype_change_hooks(entity, from_archetype, to_archetype);
+        }
 
-            // Copy column structure from source
-            for col in 0..from_arch.columns.len() {
-                if to_arch.columns.len() <= col {
-                    let item_size = from_arch.columns[col].item_size;
-                    let drop_fn = from_arch.columns[col].drop_fn;
-                    to_arch.add_column_raw(item_size, drop_fn);
-                }
+        Ok(())
+    }
+
+    // Moves `rows` (sorted by source index) from one archetype to the
+    // other, writing each row's new component
+    fn move_rows_with_component<C: Component>(
+        &mut self,
+        from_archetype: usize,
+        to_archetype: usize,
+        rows: Vec<(usize, Entity, C)>,
+    ) {
+        let (from_arch, to_arch) = self
+            .archetypes
+            .get_pair_mut(from_archetype, to_archetype)
+            .unwrap();
+        let from_ids: Vec<ComponentId> = from_arch.component_ids().collect();
+        let to_start = to_arch.len();
+        to_arch.reserve(rows.len());
+        for &(_, entity, _) in &rows {
+            to_arch.push_entity(entity);
+        }
+
+        // Copy each run of consecutive source rows in one go
+        let mut run_start = 0;
+        for i in 1..=rows.len() {
+            if i < rows.len() && rows[i].0 == rows[i - 1].0 + 1 {
+                continue;
+            }
+            for &id in &from_ids {
+                assert!(
+                    to_arch.copy_run_from(
+                        to_start + run_start,
+                        from_arch,
+                        rows[run_start].0,
+                        i - run_start,
+                        id,
+                    ),
+                    "Component {:?} could not be moved from archetype {} to archetype {}",
+                    id,
+                    from_archetype,
+                    to_archetype
+                );
             }
+            run_start = i;
+        }
 
-            // Add column for the new component
-            to_arch.add_column::<C>();
+        // Remove back to front, so whatever is swapped into a freed row is
+        // never itself part of the batch
+        let from_indices: Vec<usize> = rows.iter().map(|&(index, _, _)| index).collect();
+        for (offset, (_, entity, component)) in rows.into_iter().enumerate() {
+            to_arch.set_component(to_start + offset, component);
+            let location = self.entities.get_mut(entity).unwrap();
+            location.archetype = to_archetype;
+            location.index = to_start + offset;
+        }
+        for &index in from_indices.iter().rev() {
+            let (_removed, swapped) = from_arch.remove_entity_moved(index);
+            if let Some(swapped) = swapped
+                && let Some(location) = self.entities.get_mut(swapped)
+            {
+                location.index = index;
+            }
+        }
+    }
 
-            to
-        };
+    /// Exchanges the `T` values of `a` and `b` in place, without cloning.
+    /// Both components are marked changed.
+    pub fn swap<T: Component>(&mut self, a: Entity, b: Entity) -> Result<()> {
+        for entity in [a, b] {
+            if !self.is_alive(entity) {
+                return Err(EcsError::EntityNotFound(entity));
+            }
+            if self.get::<T>(entity).is_none() {
+                return Err(EcsError::ComponentNotFound {
+                    entity,
+                    type_name: type_name::<T>(),
+                });
+            }
+        }
+        if a == b {
+            return Ok(());
+        }
 
-        // Move entity to new archetype
-        self.move_entity_with_component(entity, from_archetype, to_archetype, component)?;
+        if let Some(set) = self.archetypes.sparse_mut().get_mut::<T>() {
+            set.swap(a, b);
+            return Ok(());
+        }
 
+        // Both slots were just checked, and nothing moves between taking the
+        // two pointers, so they stay valid and never alias
+        let a_ptr = self.get_mut::<T>(a).unwrap() as *mut T;
+        let b_ptr = self.get_mut::<T>(b).unwrap() as *mut T;
+        unsafe { std::ptr::swap(a_ptr, b_ptr) };
         Ok(())
     }
 
+    /// Inserts `component` only if the entity doesn't already have a `C`.
+    /// Returns whether it was inserted; an existing value is left untouched.
+    pub fn insert_if_absent<C: Component>(&mut self, entity: Entity, component: C) -> Result<bool> {
+        if !self.is_alive(entity) {
+            return Err(EcsError::EntityNotFound(entity));
+        }
+        if self.get::<C>(entity).is_some() {
+            return Ok(false);
+        }
+
+        self.insert(entity, component)?;
+        Ok(true)
+    }
+
     fn move_entity_with_component<C: Component>(
         &mut self,
         entity: Entity,
@@ -182,13 +857,13 @@ impl World {
             .ok_or(EcsError::EntityNotFound(entity))?
             .index;
 
-        // Get the types from source archetype
-        let from_types: Vec<TypeId> = self
+        // Get the components of the source archetype
+        let from_ids: Vec<ComponentId> = self
             .archetypes
             .get(from_archetype)
             .ok_or(EcsError::ArchetypeNotFound(from_archetype))?
-            .types()
-            .to_vec();
+            .component_ids()
+            .collect();
 
         let to_index;
         let swapped_entity;
@@ -211,17 +886,16 @@ impl World {
             to_arch.push_entity(entity);
 
             // Copy all matching components from source to destination
-            for &type_id in &from_types {
-                to_arch.copy_component_from(to_index, from_arch, from_index, type_id);
-            }
+            to_arch.copy_components_from(to_index, from_arch, from_index, &from_ids);
 
             // Add the new component
             to_arch.set_component(to_index, new_component);
 
             // Remove entity from source archetype
-            let (_removed, swapped) = from_arch.remove_entity(from_index);
+            let (_removed, swapped) = from_arch.remove_entity_moved(from_index);
             swapped_entity = swapped;
         }
+        self.archetypes.mark_structural_change();
 
         // Update entity location
         let loc = self
@@ -238,14 +912,21 @@ impl World {
             }
         }
 
+        self.run_archetype_change_hooks(entity, from_archetype, to_archetype);
+
         Ok(())
     }
 
+    /// Moves `entity` to `to_archetype`, copying every destination component
+    /// except `added`, which the caller writes after the move. Panics if the
+    /// source can't supply one of them, since the slot would stay
+    /// uninitialized.
     fn move_entity(
         &mut self,
         entity: Entity,
         from_archetype: usize,
         to_archetype: usize,
+        added: &[ComponentId],
     ) -> Result<()> {
         // Get current location and verify entity exists
         let from_index = self
@@ -254,13 +935,14 @@ impl World {
             .ok_or(EcsError::EntityNotFound(entity))?
             .index;
 
-        // Get the types from target archetype (which is a subset of source)
-        let to_types: Vec<TypeId> = self
+        // Source components missing from the destination are being removed
+        let to_ids: Vec<ComponentId> = self
             .archetypes
             .get(to_archetype)
             .ok_or(EcsError::ArchetypeNotFound(to_archetype))?
-            .types()
-            .to_vec();
+            .component_ids()
+            .filter(|id| !added.contains(id))
+            .collect();
 
         let to_index;
         let swapped_entity;
@@ -283,14 +965,13 @@ impl World {
             to_arch.push_entity(entity);
 
             // Copy all components that exist in target archetype
-            for &type_id in &to_types {
-                to_arch.copy_component_from(to_index, from_arch, from_index, type_id);
-            }
+            to_arch.copy_components_from(to_index, from_arch, from_index, &to_ids);
 
             // Remove entity from source archetype
-            let (_removed, swapped) = from_arch.remove_entity(from_index);
+            let (_removed, swapped) = from_arch.remove_entity_moved(from_index);
             swapped_entity = swapped;
         }
+        self.archetypes.mark_structural_change();
 
         // Update entity location
         let loc = self
@@ -307,15 +988,49 @@ impl World {
             }
         }
 
+        self.run_archetype_change_hooks(entity, from_archetype, to_archetype);
+
         Ok(())
     }
 
+    /// Registers a hook called whenever an entity moves to a different
+    /// archetype, with the old and new archetype ids
+    pub fn register_on_archetype_change(
+        &mut self,
+        hook: impl Fn(&mut World, Entity, usize, usize) + Send + Sync + 'static,
+    ) {
+        self.archetype_change_hooks.push(Box::new(hook));
+    }
+
+    fn run_archetype_change_hooks(&mut self, entity: Entity, from: usize, to: usize) {
+        if self.archetype_change_hooks.is_empty() {
+            return;
+        }
+
+        // Hooks get the world mutably, so detach them while they run
+        let hooks = std::mem::take(&mut self.archetype_change_hooks);
+        for hook in &hooks {
+            hook(self, entity, from, to);
+        }
+        let registered_during_run = std::mem::replace(&mut self.archetype_change_hooks, hooks);
+        self.archetype_change_hooks.extend(registered_during_run);
+    }
+
     pub fn remove<C: Component>(&mut self, entity: Entity) -> Result<C> {
         let location = self
             .entities
             .get(entity)
             .ok_or(EcsError::EntityNotFound(entity))?;
 
+        if let Some(set) = self.archetypes.sparse_mut().get_mut::<C>() {
+            let component = set.remove(entity).ok_or(EcsError::ComponentNotFound {
+                entity,
+                type_name: type_name::<C>(),
+            })?;
+            self.record_removed(TypeId::of::<C>(), entity);
+            return Ok(component);
+        }
+
         let from_archetype = location.archetype;
         let component_type = TypeId::of::<C>();
 
@@ -324,7 +1039,10 @@ impl World {
             let archetype = self.archetypes.get_mut(from_archetype).unwrap();
             archetype
                 .take_component::<C>(location.index)
-                .ok_or(EcsError::ComponentNotFound(component_type))?
+                .ok_or(EcsError::ComponentNotFound {
+                    entity,
+                    type_name: type_name::<C>(),
+                })?
         };
 
         // Find or create target archetype
@@ -343,11 +1061,14 @@ impl World {
 
             if to_arch.columns.is_empty() {
                 // Copy column structure from source for all components except the removed one
-                for (col_idx, &type_id) in from_arch.types().iter().enumerate() {
-                    if type_id != component_type {
-                        let item_size = from_arch.columns[col_idx].item_size;
-                        let drop_fn = from_arch.columns[col_idx].drop_fn;
-                        to_arch.add_column_raw(item_size, drop_fn);
+                for column in &from_arch.columns {
+                    if column.id != ComponentId::Static(component_type) {
+                        to_arch.add_column_raw(
+                            column.id,
+                            column.layout,
+                            column.drop_fn,
+                            column.as_any_fn,
+                        );
                     }
                 }
             }
@@ -356,11 +1077,26 @@ impl World {
         };
 
         // Move entity to new archetype
-        self.move_entity(entity, from_archetype, to_archetype)?;
+        self.move_entity(entity, from_archetype, to_archetype, &[])?;
+        self.record_removed(component_type, entity);
 
         Ok(component)
     }
 
+    fn record_removed(&mut self, type_id: TypeId, entity: Entity) {
+        self.removed.entry(type_id).or_default().push(entity);
+    }
+
+    /// Entities that lost a `T` this tick through `remove` or `despawn`.
+    /// The record is cleared by `tick`.
+    pub fn removed<T: Component>(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.removed
+            .get(&TypeId::of::<T>())
+            .into_iter()
+            .flatten()
+            .copied()
+    }
+
     pub(crate) fn remove_by_id(&mut self, entity: Entity, type_id: TypeId) {
         // Simplified version for commands
         if let Some(location) = self.entities.get(entity) {
@@ -368,134 +1104,1868 @@ impl World {
             if let Some(to_archetype) = self
                 .archetypes
                 .find_archetype_with_removed(from_archetype, type_id)
+                && self
+                    .move_entity(entity, from_archetype, to_archetype, &[])
+                    .is_ok()
             {
-                let _ = self.move_entity(entity, from_archetype, to_archetype);
+                self.record_removed(type_id, entity);
             }
         }
     }
 
-    pub fn query<Q: Query>(&mut self) -> QueryIter<Q> {
-        QueryIter {
-            archetypes: &mut self.archetypes,
-            archetype_index: 0,
-            entity_index: 0,
-            _marker: std::marker::PhantomData,
+    pub fn query<Q: Query + 'static>(&mut self) -> QueryIter<'_, Q> {
+        self.query_filtered::<Q, ()>()
+    }
+
+    /// The only entity matching `Q`, or `None` if there are zero or several
+    pub fn query_single<Q: Query + 'static>(&mut self) -> Option<Q::Item<'_>> {
+        let mut iter = self.query::<Q>();
+        let item = iter.next()?;
+        if iter.next().is_some() {
+            return None;
         }
+        Some(item)
     }
 
-    pub fn entity_info(&self, entity: Entity) -> Option<EntityInfo> {
-        let location = self.entities.get(entity)?;
-        let archetype = self.archetypes.get(location.archetype)?;
+    /// Number of entities matching `Q`, counted from archetype sizes without
+    /// fetching any component. With sparse components registered, entities
+    /// are checked one by one instead.
+    pub fn query_count<Q: Query + 'static>(&mut self) -> usize {
+        self.query::<Q>().count()
+    }
 
-        Some(EntityInfo {
+    pub fn query_any<Q: Query + 'static>(&mut self) -> bool {
+        self.query_count::<Q>() > 0
+    }
+
+    /// Iterates `Q` alongside `data`, pairing the n-th matching entity with
+    /// `data[n]`. The order is the same as `query`'s, so a side array built
+    /// from one query lines up with the next as long as nothing spawns,
+    /// despawns or moves in between. Panics if the lengths differ.
+    pub fn query_zip<'w, 'd, Q: Query + 'static, T>(
+        &'w mut self,
+        data: &'d mut [T],
+    ) -> std::iter::Zip<QueryIter<'w, Q>, std::slice::IterMut<'d, T>> {
+        let len = self.query_count::<Q>();
+        let iter = self.query::<Q>();
+        assert_eq!(
+            len,
+            data.len(),
+            "query_zip: {len} matching entities but {} data elements",
+            data.len()
+        );
+        iter.zip(data.iter_mut())
+    }
+
+    /// Despawns every entity matching `Q` for which `pred` returns true, in a
+    /// single pass. Each archetype is walked back to front, so the swap-remove
+    /// of a despawn only moves an already visited entity into the freed slot.
+    /// With hierarchy cleanup on, each despawned entity leaves its parent's
+    /// `Children` right away, while the child policy is applied to its own
+    /// children once the pass ends, since orphaning or despawning them would
+    /// move entities the pass hasn't reached. Returns how many entities `pred`
+    /// despawned, not counting children.
+    pub fn scan_despawn<Q: Query + 'static>(
+        &mut self,
+        mut pred: impl FnMut(Entity, Q::Item<'_>) -> bool,
+    ) -> usize {
+        let matches = self
+            .archetypes
+            .matching_archetypes((TypeId::of::<(Q, ())>(), false), |archetype| {
+                query_matches::<Q>(archetype, false)
+            });
+
+        let mut despawned = 0;
+        let mut orphaned = Vec::new();
+        for &archetype_id in matches.iter() {
+            let mut index = self.archetypes.get(archetype_id).unwrap().len();
+            while index > 0 {
+                index -= 1;
+                let archetype = self.archetypes.view(archetype_id).unwrap();
+                if !row_matches::<Q>(archetype, index) {
+                    continue;
+                }
+                let entity = archetype.entities()[index];
+                if !pred(entity, unsafe { Q::fetch(archetype, index) }) {
+                    continue;
+                }
+                if self.hierarchy_cleanup {
+                    self.detach_from_parent(entity);
+                    if let Some(Children(children)) = self.get::<Children>(entity).cloned() {
+                        orphaned.push((entity, children));
+                    }
+                }
+                self.despawn_row(entity);
+                despawned += 1;
+            }
+        }
+
+        for (entity, children) in orphaned {
+            self.release_children(entity, children);
+        }
+        despawned
+    }
+
+    /// Queries matching entities, skipping any marked `Disabled`.
+    pub fn query_filtered<Q: Query + 'static, F: QueryFilter + 'static>(
+        &mut self,
+    ) -> QueryIter<'_, Q, F> {
+        QueryIter::new(&mut self.archetypes, false)
+    }
+
+    /// Like `query`, but fails instead of iterating when `Q` would alias a
+    /// component, e.g. `(&mut Position, &Position)`
+    pub fn query_checked<Q: Query + 'static>(&mut self) -> Result<QueryIter<'_, Q>> {
+        if crate::query::aliased_component::<Q>().is_some() {
+            return Err(EcsError::InvalidOperation(format!(
+                "query {} borrows a component mutably more than once",
+                std::any::type_name::<Q>()
+            )));
+        }
+        Ok(self.query::<Q>())
+    }
+
+    /// Parallel version of `query` for composing rayon adaptors. Only the
+    /// matching row ranges are gathered up front; rayon splits them between
+    /// threads and each fetches its own rows, every item borrowing a distinct
+    /// component slot. Indexed, so `zip`, `enumerate` and the like work too.
+    pub fn par_query<Q: Query + 'static>(
+        &mut self,
+    ) -> impl IndexedParallelIterator<Item = Q::Item<'_>>
+    where
+        for<'a> Q::Item<'a>: Send,
+    {
+        let matches = self
+            .archetypes
+            .matching_archetypes((TypeId::of::<(Q, ())>(), false), |archetype| {
+                query_matches::<Q>(archetype, false)
+            });
+
+        let mut runs = Vec::new();
+        for &archetype_id in matches.iter() {
+            let archetype = self.archetypes.view(archetype_id).unwrap();
+            let len = archetype.len();
+            if !archetype.checks_rows() {
+                if len > 0 {
+                    runs.push((archetype, 0..len));
+                }
+                continue;
+            }
+            // Entities may lack a sparse term, so keep only the matching runs
+            let mut index = 0;
+            while index < len {
+                if !row_matches::<Q>(archetype, index) {
+                    index += 1;
+                    continue;
+                }
+                let start = index;
+                while index < len && row_matches::<Q>(archetype, index) {
+                    index += 1;
+                }
+                runs.push((archetype, start..index));
+            }
+        }
+        ParQueryIter::<Q>::new(runs)
+    }
+
+    /// Read-only query through a shared borrow, e.g. for a renderer that only
+    /// holds `&World`. Skips `Disabled` entities like `query`, but doesn't use
+    /// the match cache since that needs `&mut self`.
+    pub fn query_ref<Q: ReadOnlyQuery>(&self) -> impl Iterator<Item = Q::Item<'_>> {
+        self.archetypes
+            .views()
+            .filter(|&archetype| query_matches::<Q>(archetype, false))
+            .flat_map(|archetype| {
+                (0..archetype.len())
+                    .filter(move |&i| row_matches::<Q>(archetype, i))
+                    .map(move |i| Q::fetch_ref(archetype, i))
+            })
+    }
+
+    /// Like `query`, but also yields entities marked `Disabled`.
+    pub fn query_including_disabled<Q: Query + 'static>(&mut self) -> QueryIter<'_, Q> {
+        QueryIter::new(&mut self.archetypes, true)
+    }
+
+    /// Hides `entity` from normal queries by adding the `Disabled` marker.
+    pub fn disable(&mut self, entity: Entity) -> Result<()> {
+        self.insert(entity, Disabled)
+    }
+
+    /// Makes a disabled entity visible to normal queries again. Returns
+    /// `false` if it was not disabled.
+    pub fn enable(&mut self, entity: Entity) -> bool {
+        self.remove::<Disabled>(entity).is_ok()
+    }
+
+    pub fn is_disabled(&self, entity: Entity) -> bool {
+        self.get::<Disabled>(entity).is_some()
+    }
+
+    /// Registers a component known only by its layout, e.g. one defined by a
+    /// scripting host. Values are stored aligned to `layout`; zero-sized layouts
+    /// never allocate. `drop_fn`, if given, runs on a value's bytes when it is
+    /// removed or overwritten.
+    pub fn register_dynamic_component(
+        &mut self,
+        name: &'static str,
+        layout: Layout,
+        drop_fn: Option<unsafe fn(*mut u8)>,
+    ) -> DynComponentId {
+        let id = DynComponentId(self.dynamic_components.len() as u32);
+        self.dynamic_components.push(DynComponentInfo {
+            name,
+            layout,
+            drop_fn: drop_fn.unwrap_or(drop_nothing),
+        });
+        id
+    }
+
+    pub fn dynamic_component_name(&self, id: DynComponentId) -> Option<&'static str> {
+        self.dynamic_components
+            .get(id.0 as usize)
+            .map(|info| info.name)
+    }
+
+    pub fn register_dyn_value_tag(&mut self, name: &'static str) -> DynValueTag {
+        let tag = DynValueTag(self.dyn_value_tags.len() as u32);
+        self.dyn_value_tags.push(name);
+        tag
+    }
+
+    pub fn dyn_value_tag_name(&self, tag: DynValueTag) -> Option<&'static str> {
+        self.dyn_value_tags.get(tag.0 as usize).copied()
+    }
+
+    /// Entities whose `DynValue` carries `tag`, skipping `Disabled` ones
+    pub fn query_tagged(&self, tag: DynValueTag) -> impl Iterator<Item = (Entity, &DynValue)> {
+        let dyn_value = TypeId::of::<DynValue>();
+        let disabled = TypeId::of::<Disabled>();
+
+        self.archetypes
+            .iter()
+            .filter(move |archetype| {
+                archetype.types().contains(&dyn_value) && !archetype.types().contains(&disabled)
+            })
+            .flat_map(move |archetype| {
+                archetype
+                    .entities()
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(index, &entity)| {
+                        let value = archetype.get_component::<DynValue>(index)?;
+                        (value.tag() == tag).then_some((entity, value))
+                    })
+            })
+    }
+
+    fn dynamic_info(&self, id: DynComponentId, bytes: &[u8]) -> Result<&DynComponentInfo> {
+        let info = self.dynamic_components.get(id.0 as usize).ok_or_else(|| {
+            EcsError::InvalidOperation(format!("Unknown dynamic component {:?}", id))
+        })?;
+        if bytes.len() != info.layout.size() {
+            return Err(EcsError::InvalidOperation(format!(
+                "Dynamic component {} expects {} bytes, got {}",
+                info.name,
+                info.layout.size(),
+                bytes.len()
+            )));
+        }
+        Ok(info)
+    }
+
+    /// Spawns an entity holding only dynamic components, each given as its raw bytes
+    pub fn spawn_dynamic(&mut self, components: &[(DynComponentId, &[u8])]) -> Result<Entity> {
+        for (i, &(id, bytes)) in components.iter().enumerate() {
+            self.dynamic_info(id, bytes)?;
+            if components[..i].iter().any(|&(other, _)| other == id) {
+                return Err(EcsError::InvalidOperation(format!(
+                    "Dynamic component {:?} given twice",
+                    id
+                )));
+            }
+        }
+
+        let entity = self.spawn_empty();
+        for &(id, bytes) in components {
+            self.insert_dynamic(entity, id, bytes)?;
+        }
+        Ok(entity)
+    }
+
+    /// Adds or overwrites the dynamic component `id` on `entity`
+    pub fn insert_dynamic(
+        &mut self,
+        entity: Entity,
+        id: DynComponentId,
+        bytes: &[u8],
+    ) -> Result<()> {
+        let location = *self
+            .entities
+            .get(entity)
+            .ok_or(EcsError::EntityNotFound(entity))?;
+        let info = self.dynamic_info(id, bytes)?;
+        let layout = info.layout;
+        let drop_fn = info.drop_fn;
+
+        let from_archetype = location.archetype;
+        let from_arch = self.archetypes.get(from_archetype).unwrap();
+
+        if from_arch.contains(ComponentId::Dynamic(id)) {
+            let archetype = self.archetypes.get_mut(from_archetype).unwrap();
+            let ptr = archetype.get_component_ptr_mut(id, location.index).unwrap();
+            unsafe {
+                drop_fn(ptr);
+                std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr, bytes.len());
+            }
+            return Ok(());
+        }
+
+        let types = from_arch.types().to_vec();
+        let type_names = from_arch.type_names().to_vec();
+        let mut dynamic = from_arch.dynamic_ids().to_vec();
+        dynamic.push(id);
+
+        let to_archetype = self
+            .archetypes
+            .get_or_create_with_dynamic(types, type_names, dynamic);
+        let (from_arch, to_arch) = self
+            .archetypes
+            .get_pair_mut(from_archetype, to_archetype)
+            .unwrap();
+        for column in &from_arch.columns {
+            to_arch.add_column_raw(column.id, column.layout, column.drop_fn, column.as_any_fn);
+        }
+        to_arch.add_column_raw(ComponentId::Dynamic(id), layout, drop_fn, opaque_any);
+
+        self.move_entity(
             entity,
-            archetype_id: archetype.id(),
-            component_types: archetype.type_names().to_vec(),
-        })
+            from_archetype,
+            to_archetype,
+            &[ComponentId::Dynamic(id)],
+        )?;
+
+        let location = *self.entities.get(entity).unwrap();
+        let archetype = self.archetypes.get_mut(location.archetype).unwrap();
+        let ptr = archetype.get_component_ptr_mut(id, location.index).unwrap();
+        unsafe {
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr, bytes.len());
+        }
+        Ok(())
     }
 
-    pub fn entity_meta(&self, entity: Entity) -> Option<EntityMeta> {
+    /// Raw bytes of the dynamic component `id` on `entity`
+    pub fn get_dynamic(&self, entity: Entity, id: DynComponentId) -> Option<&[u8]> {
+        let size = self.dynamic_components.get(id.0 as usize)?.layout.size();
         let location = self.entities.get(entity)?;
-        Some(EntityMeta {
-            generation: 0, // SlotMap handles generations internally
-            archetype: location.archetype,
-            index: location.index,
-        })
+        let archetype = self.archetypes.get(location.archetype)?;
+        let ptr = archetype.get_component_ptr(id, location.index)?;
+        Some(unsafe { std::slice::from_raw_parts(ptr, size) })
     }
 
-    pub fn insert_resource<T: Send + Sync + 'static>(&mut self, resource: T) {
-        self.resources.insert(resource);
+    /// Calls `visitor` with every component of every entity, sparse ones
+    /// included, for reflection-style inspection.
+    pub fn visit(&self, visitor: &mut dyn FnMut(Entity, &'static str, &dyn Any)) {
+        let sparse = self.archetypes.sparse();
+        for archetype in self.archetypes.iter() {
+            for (index, &entity) in archetype.entities().iter().enumerate() {
+                for (&type_id, &name) in archetype.types().iter().zip(archetype.type_names()) {
+                    if let Some(component) = archetype.get_component_any(type_id, index) {
+                        visitor(entity, name, component);
+                    }
+                }
+                for (_, name, component) in sparse.components_of(entity) {
+                    visitor(entity, name, component);
+                }
+            }
+        }
     }
 
-    pub fn get_resource<T: 'static>(&self) -> Option<crate::resource::Res<T>> {
-        self.resources.get()
+    /// Entities whose archetype has every component in `include` and none in
+    /// `exclude`. Like `query`, skips `Disabled` entities unless `Disabled` is
+    /// itself in `include`.
+    pub fn query_dynamic<'a>(
+        &'a self,
+        include: &'a [ComponentId],
+        exclude: &'a [ComponentId],
+    ) -> impl Iterator<Item = Entity> + 'a {
+        let disabled = ComponentId::Static(TypeId::of::<Disabled>());
+        let skip_disabled = !include.contains(&disabled);
+
+        self.archetypes
+            .iter()
+            .filter(move |archetype| {
+                include.iter().all(|&id| archetype.contains(id))
+                    && !exclude.iter().any(|&id| archetype.contains(id))
+                    && !(skip_disabled && archetype.contains(disabled))
+            })
+            .flat_map(|archetype| archetype.entities().iter().copied())
     }
 
-    pub fn get_resource_mut<T: 'static>(&self) -> Option<crate::resource::ResMut<T>> {
-        self.resources.get_mut()
+    /// Runs `f` for every entity matching `Q`, passing its id and a command
+    /// buffer for structural changes. Buffered commands are applied after the
+    /// whole pass, so the callback never observes its own despawns or inserts.
+    pub fn for_each_mut<Q: Query>(
+        &mut self,
+        mut f: impl FnMut(Entity, Q::Item<'_>, &mut EntityCommandBuffer),
+    ) {
+        let mut buffer = EntityCommandBuffer::new();
+
+        for archetype in self.archetypes.views() {
+            if !query_matches::<Q>(archetype, false) {
+                continue;
+            }
+
+            for index in 0..archetype.len() {
+                if !row_matches::<Q>(archetype, index) {
+                    continue;
+                }
+                let entity = archetype.entities()[index];
+                let item = unsafe { Q::fetch(archetype, index) };
+                f(entity, item, &mut buffer);
+            }
+        }
+
+        buffer.apply(self);
     }
 
-    pub fn remove_resource<T: 'static>(&mut self) -> Option<T> {
-        self.resources.remove()
+    /// Stores `T` in a sparse set instead of archetype columns, so inserting or
+    /// removing it never moves the entity. Register before any `T` is inserted.
+    /// Queries, bundles, `visit` and prefabs see sparse components like any
+    /// other; only where they are stored differs.
+    pub fn register_sparse<T: Component>(&mut self) {
+        self.archetypes.register_sparse::<T>();
     }
 
-    pub fn commands(&mut self) -> &mut Commands {
-        &mut self.commands
+    pub fn is_sparse<T: Component>(&self) -> bool {
+        self.archetypes.sparse().get::<T>().is_some()
     }
 
-    pub fn flush_commands(&mut self) {
-        let mut commands = std::mem::replace(&mut self.commands, Commands::new());
-        commands.apply(self);
-        self.commands = commands;
+    /// Iterates every entity holding the sparse component `T`
+    pub fn query_sparse<T: Component>(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.archetypes
+            .sparse()
+            .get::<T>()
+            .into_iter()
+            .flat_map(|set| set.iter())
     }
 
-    pub fn reserve(&mut self, additional: usize) {
-        self.entities.reserve(additional);
+    pub fn query_sparse_mut<T: Component>(&mut self) -> impl Iterator<Item = (Entity, &mut T)> {
+        self.archetypes
+            .sparse_mut()
+            .get_mut::<T>()
+            .into_iter()
+            .flat_map(|set| set.iter_mut())
     }
-}
 
-impl Default for World {
-    fn default() -> Self {
-        Self::new()
+    /// Joins the sparse component `T` with the query `Q`, yielding only
+    /// entities that hold both; the same as `query::<(Q, &T)>()`
+    pub fn query_with_sparse<Q: Query + 'static, T: Component>(
+        &mut self,
+    ) -> impl Iterator<Item = (Q::Item<'_>, &T)> {
+        self.query::<(Q, &T)>()
     }
-}
 
-pub struct QueryIter<'a, Q: Query> {
-    archetypes: &'a mut ArchetypeMap,
-    archetype_index: usize,
-    entity_index: usize,
-    _marker: std::marker::PhantomData<Q>,
-}
+    /// Yields every unique `K`-combination of entities matching the read-only
+    /// query `Q`, in index order, e.g. all pairs for `K = 2`. The
+    /// `ReadOnlyQuery` bound rejects `&mut T` terms at compile time.
+    pub fn query_combinations<Q: ReadOnlyQuery, const K: usize>(
+        &self,
+    ) -> impl Iterator<Item = [Q::Item<'_>; K]> {
+        let mut rows = Vec::new();
+        for archetype in self.archetypes.views() {
+            if !query_matches::<Q>(archetype, false) {
+                continue;
+            }
+            rows.extend(
+                (0..archetype.len())
+                    .filter(|&index| row_matches::<Q>(archetype, index))
+                    .map(|index| (archetype, index)),
+            );
+        }
 
-impl<'a, Q: Query> Iterator for QueryIter<'a, Q> {
-    type Item = Q::Item<'a>;
+        let n = rows.len();
+        let mut indices: Vec<usize> = (0..K).collect();
+        let mut done = K > n;
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let archetypes_ptr = self.archetypes as *mut ArchetypeMap;
+        std::iter::from_fn(move || {
+            if done {
+                return None;
+            }
 
-        loop {
-            let archetype_count = unsafe { (*archetypes_ptr).iter().count() };
+            let combination = std::array::from_fn(|i| {
+                let (archetype, index) = rows[indices[i]];
+                Q::fetch_ref(archetype, index)
+            });
+
+            // Advance to the next combination in lexicographic order
+            match (0..K).rev().find(|&i| indices[i] != i + n - K) {
+                Some(i) => {
+                    indices[i] += 1;
+                    for j in i + 1..K {
+                        indices[j] = indices[j - 1] + 1;
+                    }
+                }
+                None => done = true,
+            }
 
-            if self.archetype_index >= archetype_count {
-                return None;
+            Some(combination)
+        })
+    }
+
+    /// Overwrites every `T` in the world with a clone of `value`, marking each
+    /// one changed. Disabled entities are included.
+    pub fn fill<T: Component + Clone>(&mut self, value: T) {
+        if let Some(set) = self.archetypes.sparse_mut().get_mut::<T>() {
+            for (_, slot) in set.iter_mut() {
+                *slot = value.clone();
             }
+            return;
+        }
 
-            let archetype = unsafe {
-                (*archetypes_ptr)
-                    .iter_mut()
-                    .nth(self.archetype_index)
-                    .unwrap()
-            };
+        let type_id = TypeId::of::<T>();
+        for archetype in self.archetypes.iter_mut() {
+            if !archetype.types().contains(&type_id) {
+                continue;
+            }
+            for index in 0..archetype.len() {
+                *archetype.get_component_mut::<T>(index).unwrap() = value.clone();
+            }
+        }
+    }
 
-            if !Q::matches_archetype(archetype.types()) {
-                self.archetype_index += 1;
-                self.entity_index = 0;
+    /// Runs `Q` and pairs each item with `T` read from the entity's `Parent`,
+    /// or `None` when it has no parent or the parent lacks `T`. Panics if `Q`
+    /// writes `T`, since a parent may also be matched by `Q`.
+    pub fn query_with_parent<Q: Query, T: Component>(
+        &mut self,
+    ) -> impl Iterator<Item = (Q::Item<'_>, Option<&T>)> {
+        assert!(
+            !Q::write_types().contains(&TypeId::of::<T>()),
+            "query_with_parent cannot write the parent component {}",
+            type_name::<T>()
+        );
+
+        let mut rows = Vec::new();
+        for (archetype_index, archetype) in self.archetypes.views().enumerate() {
+            if !query_matches::<Q>(archetype, false) {
                 continue;
             }
 
-            if self.entity_index >= archetype.len() {
-                self.archetype_index += 1;
-                self.entity_index = 0;
+            for index in 0..archetype.len() {
+                if !row_matches::<Q>(archetype, index) {
+                    continue;
+                }
+                let parent = archetype
+                    .get_component::<Parent>(index)
+                    .and_then(|parent| self.get::<T>(parent.0))
+                    .map(|value| value as *const T);
+                rows.push((archetype_index, index, parent));
+            }
+        }
+
+        let mut items = Vec::with_capacity(rows.len());
+        for (archetype_index, index, parent) in rows {
+            // Rows are distinct and `Q` never writes `T`, so nothing aliases
+            let archetype = self.archetypes.view(archetype_index).unwrap();
+            let item = unsafe { Q::fetch(archetype, index) };
+            items.push((item, parent.map(|value| unsafe { &*value })));
+        }
+
+        items.into_iter()
+    }
+
+    /// Runs `Q` with every entity visited after its `Parent` chain, roots
+    /// first, e.g. for top-down transform propagation. A `Parent` cycle is
+    /// broken at its smallest entity, which is then treated as a root.
+    pub fn query_hierarchical<Q: Query>(&mut self) -> impl Iterator<Item = Q::Item<'_>> {
+        let mut rows = Vec::new();
+        for (archetype_index, archetype) in self.archetypes.views().enumerate() {
+            if !query_matches::<Q>(archetype, false) {
                 continue;
             }
+            for (index, &entity) in archetype.entities().iter().enumerate() {
+                if row_matches::<Q>(archetype, index) {
+                    rows.push((archetype_index, index, entity));
+                }
+            }
+        }
 
-            let item = unsafe { Q::fetch(archetype, self.entity_index) };
-            self.entity_index += 1;
+        let mut depths = HashMap::new();
+        for &(_, _, entity) in &rows {
+            self.hierarchy_depth(entity, &mut depths);
+        }
+        rows.sort_by_key(|&(_, _, entity)| depths[&entity]);
 
-            return Some(unsafe { std::mem::transmute(item) });
+        let mut items = Vec::with_capacity(rows.len());
+        for (archetype_index, index, _) in rows {
+            // Rows are distinct, so the fetched items never alias
+            let archetype = self.archetypes.view(archetype_index).unwrap();
+            items.push(unsafe { Q::fetch(archetype, index) });
         }
+
+        items.into_iter()
     }
 
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        let remaining: usize = unsafe {
-            let archetypes_ptr = self.archetypes as *const ArchetypeMap;
-            (*archetypes_ptr)
-                .iter()
-                .skip(self.archetype_index)
-                .filter(|a| Q::matches_archetype(a.types()))
-                .map(|a| a.len())
-                .sum()
+    // Number of `Parent` links above `entity`, memoized in `depths`
+    fn hierarchy_depth(&self, entity: Entity, depths: &mut HashMap<Entity, usize>) -> usize {
+        // Walk up until a root, a known depth or a repeat (a cycle)
+        let mut path = vec![entity];
+        let mut base = loop {
+            let current = *path.last().unwrap();
+            if let Some(&depth) = depths.get(&current) {
+                path.pop();
+                break depth + 1;
+            }
+            match self.get::<Parent>(current) {
+                Some(parent) if self.is_alive(parent.0) => {
+                    if let Some(start) = path.iter().position(|&e| e == parent.0) {
+                        // path[start..] is a cycle, each entry's parent being
+                        // the next; cut it above its smallest entity
+                        let cycle = path.split_off(start);
+                        let cut = (0..cycle.len()).min_by_key(|&i| cycle[i]).unwrap();
+                        for step in 0..cycle.len() {
+                            let i = (cut + cycle.len() - step) % cycle.len();
+                            depths.insert(cycle[i], step);
+                        }
+                        if path.is_empty() {
+                            return depths[&entity];
+                        }
+                        break depths[&cycle[0]] + 1;
+                    }
+                    path.push(parent.0);
+                }
+                _ => {
+                    path.pop();
+                    depths.insert(current, 0);
+                    break 1;
+                }
+            }
+        };
+
+        while let Some(e) = path.pop() {
+            depths.insert(e, base);
+            base += 1;
+        }
+        depths[&entity]
+    }
+
+    /// Gives `T` the next `ComponentIndex` unless it already has one. Types
+    /// are otherwise registered when first spawned, so registering up front
+    /// pins archetype column order regardless of spawn order.
+    pub fn register_component<T: Component>(&mut self) -> ComponentIndex {
+        self.archetypes.register_component(TypeId::of::<T>())
+    }
+
+    pub fn component_index<T: Component>(&self) -> Option<ComponentIndex> {
+        self.archetypes.component_index(TypeId::of::<T>())
+    }
+
+    /// Registers `T` as clonable so it can be copied into prefabs
+    pub fn register_clonable<T: Component + Clone>(&mut self) {
+        self.registry.register_clonable::<T>();
+    }
+
+    /// Copies `entity` (and its `Children` subtree when `recursive`) into a
+    /// prefab. Every component other than `Parent`/`Children` must be
+    /// registered with `register_clonable`.
+    pub fn export_prefab(&self, entity: Entity, recursive: bool) -> Result<Prefab> {
+        let mut prefab = Prefab::new();
+        let mut visited = HashSet::new();
+        let mut pending = vec![(entity, None)];
+        let clone_fns = |type_id, name| {
+            self.registry.clone_fns(type_id).ok_or_else(|| {
+                EcsError::InvalidOperation(format!(
+                    "Component {} is not registered as clonable",
+                    name
+                ))
+            })
         };
-        (remaining, Some(remaining))
+
+        while let Some((current, parent)) = pending.pop() {
+            if !visited.insert(current) {
+                continue;
+            }
+
+            let location = self
+                .entities
+                .get(current)
+                .ok_or(EcsError::EntityNotFound(current))?;
+            let archetype = self.archetypes.get(location.archetype).unwrap();
+
+            let mut components = Vec::new();
+            for (&type_id, &name) in archetype.types().iter().zip(archetype.type_names()) {
+                // Hierarchy links are rebuilt with fresh ids on instantiation
+                if type_id == TypeId::of::<Parent>() || type_id == TypeId::of::<Children>() {
+                    continue;
+                }
+
+                let fns = clone_fns(type_id, name)?;
+                let ptr = archetype
+                    .get_component_ptr(type_id, location.index)
+                    .unwrap();
+                components.push((type_id, name, unsafe { (fns.read)(ptr) }));
+            }
+            for (type_id, name, component) in self.archetypes.sparse().components_of(current) {
+                let fns = clone_fns(type_id, name)?;
+                let ptr = component as *const dyn Any as *const u8;
+                components.push((type_id, name, unsafe { (fns.read)(ptr) }));
+            }
+
+            let index = prefab.entities.len();
+            prefab.entities.push(PrefabEntity { parent, components });
+
+            if recursive && let Some(children) = archetype.get_component::<Children>(location.index)
+            {
+                for &child in children.0.iter().rev() {
+                    if self.is_alive(child) {
+                        pending.push((child, Some(index)));
+                    }
+                }
+            }
+        }
+
+        Ok(prefab)
+    }
+
+    /// Spawns a fresh copy of `prefab`, returning the new root entity. Each
+    /// entity is spawned with all of its components at once. Panics if a
+    /// component isn't registered with `register_clonable` in this world,
+    /// which can only happen for a prefab exported from another world.
+    pub fn spawn_prefab(&mut self, prefab: &Prefab) -> Entity {
+        let mut has_children = vec![false; prefab.len()];
+        for prefab_entity in &prefab.entities {
+            if let Some(parent_index) = prefab_entity.parent {
+                has_children[parent_index] = true;
+            }
+        }
+
+        let mut spawned: Vec<Entity> = Vec::with_capacity(prefab.len());
+        for (index, prefab_entity) in prefab.entities.iter().enumerate() {
+            let mut bundle = DynamicBundle::new();
+            for (type_id, name, value) in &prefab_entity.components {
+                let fns = self
+                    .registry
+                    .clone_fns(*type_id)
+                    .unwrap_or_else(|| panic!("Component {} is not registered as clonable", name));
+                (fns.stage)(value, &mut bundle);
+            }
+
+            // Parents come before their children, which join the parent's
+            // `Children` as they spawn
+            let parent = prefab_entity
+                .parent
+                .map(|parent_index| spawned[parent_index]);
+            if let Some(parent) = parent {
+                bundle.add(Parent(parent));
+            }
+            if has_children[index] {
+                bundle.add(Children::new());
+            }
+
+            let entity = self.spawn_dynamic_bundle(bundle);
+            if let Some(parent) = parent {
+                self.get_mut::<Children>(parent).unwrap().add(entity);
+            }
+            spawned.push(entity);
+        }
+
+        spawned[0]
+    }
+
+    /// Spawns a copy of `entity` into the same archetype, with every
+    /// component cloned in place. Every component must be registered with
+    /// `register_clonable`, except `Parent` and `Children`: the copy keeps
+    /// the parent and joins its `Children`, but starts with no children of
+    /// its own since those stay with `entity`.
+    pub fn clone_entity(&mut self, entity: Entity) -> Result<Entity> {
+        let location = *self
+            .entities
+            .get(entity)
+            .ok_or(EcsError::EntityNotFound(entity))?;
+        let archetype = self.archetypes.get(location.archetype).unwrap();
+        if !archetype.dynamic_ids().is_empty() {
+            return Err(EcsError::InvalidOperation(
+                "cannot clone entity: dynamic components can't be cloned".to_string(),
+            ));
+        }
+        let is_hierarchy =
+            |type_id| type_id == TypeId::of::<Parent>() || type_id == TypeId::of::<Children>();
+        let unclonable = archetype
+            .types()
+            .iter()
+            .copied()
+            .zip(archetype.type_names().iter().copied())
+            .chain(
+                self.archetypes
+                    .sparse()
+                    .components_of(entity)
+                    .map(|(type_id, name, _)| (type_id, name)),
+            )
+            .find(|&(type_id, _)| !is_hierarchy(type_id) && !self.registry.is_clonable(type_id));
+        if let Some((_, name)) = unclonable {
+            return Err(EcsError::InvalidOperation(format!(
+                "Component {} is not registered as clonable",
+                name
+            )));
+        }
+
+        let clone = self.entities.insert(EntityLocation {
+            archetype: location.archetype,
+            index: archetype.len(),
+            spawn_tick: self.tick,
+        });
+        let registry = &self.registry;
+        let clone_fn = |type_id: TypeId| -> Option<CloneIntoFn> {
+            if type_id == TypeId::of::<Parent>() {
+                Some(crate::registry::clone_into::<Parent>)
+            } else if type_id == TypeId::of::<Children>() {
+                Some(write_no_children)
+            } else {
+                registry.clone_fns(type_id).map(|fns| fns.clone_into)
+            }
+        };
+        let archetypes = &mut self.archetypes;
+        let cloned = panic::catch_unwind(AssertUnwindSafe(|| {
+            archetypes.get_mut(location.archetype).unwrap().clone_row(
+                location.index,
+                clone,
+                &|id| match id {
+                    ComponentId::Static(type_id) => clone_fn(type_id),
+                    ComponentId::Dynamic(_) => None,
+                },
+            );
+            archetypes
+                .sparse_mut()
+                .clone_components(entity, clone, &clone_fn);
+        }));
+        if let Err(payload) = cloned {
+            // A panicking `Clone` mustn't leave the id pointing at a row that
+            // was never pushed, or at half the clone's components
+            let reserved = self.entities.remove(clone).unwrap();
+            let archetype = self.archetypes.get_mut(reserved.archetype).unwrap();
+            if reserved.index < archetype.len() {
+                archetype.remove_entity(reserved.index);
+            }
+            self.archetypes.sparse_mut().remove_entity(clone, |_| {});
+            panic::resume_unwind(payload);
+        }
+        self.archetypes.mark_structural_change();
+
+        if let Some(&Parent(parent)) = self.get::<Parent>(clone)
+            && let Some(children) = self.get_mut::<Children>(parent)
+        {
+            children.add(clone);
+        }
+        Ok(clone)
+    }
+
+    /// Deep copy with the same entity ids, cloned components and resources,
+    /// and the same tick and settings, e.g. to simulate ahead without
+    /// touching `self`. Every component type must be registered with
+    /// `register_clonable` and every resource with
+    /// `register_clonable_resource`, and every event type still holding
+    /// events with `register_clonable_event`; dynamic components can't be
+    /// copied. Storage is copied as is, so change ticks, spawn ticks, freed
+    /// entity slots and sparse components all carry over. Hooks, trait casts
+    /// and queued commands do not.
+    pub fn try_clone(&self) -> Result<World> {
+        let unclonable = |name: &str| {
+            EcsError::InvalidOperation(format!(
+                "cannot clone world: {} is not registered as clonable",
+                name
+            ))
+        };
+        if let Some(name) = self.resources.unclonable_type_name() {
+            return Err(unclonable(name));
+        }
+        for archetype in self.archetypes.iter().filter(|a| !a.is_empty()) {
+            if !archetype.dynamic_ids().is_empty() {
+                return Err(EcsError::InvalidOperation(
+                    "cannot clone world: dynamic components can't be cloned".to_string(),
+                ));
+            }
+            for (&type_id, &name) in archetype.types().iter().zip(archetype.type_names()) {
+                if !self.registry.is_clonable(type_id) {
+                    return Err(unclonable(name));
+                }
+            }
+        }
+        for (type_id, name) in self.archetypes.sparse().occupied_types() {
+            if !self.registry.is_clonable(type_id) {
+                return Err(unclonable(name));
+            }
+        }
+
+        let mut events = HashMap::new();
+        for (&type_id, storage) in &self.events {
+            match self.clonable_events.get(&type_id) {
+                Some(clone) => {
+                    events.insert(type_id, clone(storage.as_ref()));
+                }
+                None if storage.is_drained() => {}
+                None => return Err(unclonable(storage.type_name())),
+            }
+        }
+
+        let clone_fn = |id: ComponentId| match id {
+            ComponentId::Static(type_id) => {
+                self.registry.clone_fns(type_id).map(|fns| fns.clone_into)
+            }
+            ComponentId::Dynamic(_) => None,
+        };
+        Ok(World {
+            entities: self.entities.clone(),
+            archetypes: self.archetypes.clone_with(&clone_fn),
+            resources: self.resources.clone_registered(),
+            events,
+            clonable_events: self.clonable_events.clone(),
+            registry: self.registry.clone(),
+            dynamic_components: self.dynamic_components.clone(),
+            dyn_value_tags: self.dyn_value_tags.clone(),
+            removed: self.removed.clone(),
+            tick: self.tick,
+            hierarchy_cleanup: self.hierarchy_cleanup,
+            child_policy: self.child_policy,
+            ..World::new()
+        })
+    }
+
+    /// Registers `T` as viewable through the trait object `Trait`, e.g.
+    /// `world.register_trait::<dyn Drawable, Sprite>(|sprite| sprite)`
+    pub fn register_trait<Trait: ?Sized + 'static, T: Component>(
+        &mut self,
+        cast: fn(&mut T) -> &mut Trait,
+    ) {
+        self.traits.register::<Trait, T>(cast);
+    }
+
+    /// Iterates every component registered for `Trait`, regardless of
+    /// concrete type, sparse ones included. `Disabled` entities are skipped
+    /// as in `query`, and like `&mut T` a component is only marked changed
+    /// once the iterator reaches it.
+    pub fn query_trait<Trait: ?Sized + 'static>(&mut self) -> impl Iterator<Item = &mut Trait> {
+        let traits = &self.traits;
+        let sparse: Vec<TypeId> = self
+            .archetypes
+            .sparse()
+            .occupied_types()
+            .map(|(type_id, _)| type_id)
+            .filter(|&type_id| traits.is_registered::<Trait>(type_id))
+            .collect();
+
+        let mut slots = Vec::new();
+        for archetype in self.archetypes.views() {
+            if archetype.types().contains(&TypeId::of::<Disabled>()) {
+                continue;
+            }
+            let types: Vec<TypeId> = archetype
+                .types()
+                .iter()
+                .copied()
+                .filter(|&type_id| traits.is_registered::<Trait>(type_id))
+                .chain(
+                    sparse
+                        .iter()
+                        .copied()
+                        .filter(|type_id| !archetype.types().contains(type_id)),
+                )
+                .collect();
+            for index in 0..archetype.len() {
+                slots.extend(types.iter().map(|&type_id| (archetype, type_id, index)));
+            }
+        }
+
+        // Each slot is a distinct component, fetched once, so the borrows
+        // don't alias
+        slots
+            .into_iter()
+            .filter_map(move |(archetype, type_id, index)| {
+                let cast = traits.caster::<Trait>(type_id)?;
+                let ptr = unsafe { archetype.get_component_ptr_mut(type_id, index)? };
+                Some(unsafe { &mut *cast(ptr) })
+            })
+    }
+
+    pub fn entity_info(&self, entity: Entity) -> Option<EntityInfo> {
+        let location = self.entities.get(entity)?;
+        let archetype = self.archetypes.get(location.archetype)?;
+
+        Some(EntityInfo {
+            entity,
+            archetype_id: archetype.id(),
+            component_types: archetype.type_names().to_vec(),
+            component_type_ids: archetype.types().to_vec(),
+        })
+    }
+
+    /// Ticks elapsed since `entity` was spawned
+    pub fn entity_age(&self, entity: Entity) -> Option<u64> {
+        let location = self.entities.get(entity)?;
+        Some(self.tick - location.spawn_tick)
+    }
+
+    pub fn entity_meta(&self, entity: Entity) -> Option<EntityMeta> {
+        let location = self.entities.get(entity)?;
+        Some(EntityMeta {
+            generation: 0, // SlotMap handles generations internally
+            archetype: location.archetype,
+            index: location.index,
+        })
+    }
+
+    /// Sends `event` to the world-owned `Events<T>`, creating it on first use.
+    /// Events stay available to `drain_events` for the tick they were sent in
+    /// and the following one.
+    pub fn send_event<T: Send + Sync + 'static>(&mut self, event: T) {
+        self.events
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(Events::<T>::new()))
+            .as_any_mut()
+            .downcast_mut::<Events<T>>()
+            .unwrap()
+            .send(event);
+    }
+
+    /// Registers event `T` to be copied by `try_clone`
+    pub fn register_clonable_event<T: Clone + Send + Sync + 'static>(&mut self) {
+        self.clonable_events
+            .insert(TypeId::of::<T>(), crate::events::clone_events::<T>);
+    }
+
+    /// Takes every pending `T` event, oldest first
+    pub fn drain_events<T: Send + Sync + 'static>(&mut self) -> Vec<T> {
+        match self.events.get_mut(&TypeId::of::<T>()) {
+            Some(events) => events
+                .as_any_mut()
+                .downcast_mut::<Events<T>>()
+                .unwrap()
+                .drain_all()
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn insert_resource<T: Send + Sync + 'static>(&mut self, resource: T) {
+        self.resources.insert(resource);
+    }
+
+    pub fn for_each_resource(&self, f: impl FnMut(TypeId, &'static str, &dyn Any)) {
+        self.resources.for_each(f);
+    }
+
+    pub fn replace_resource<T: Send + Sync + 'static>(&mut self, resource: T) -> Option<T> {
+        self.resources.replace(resource)
+    }
+
+    pub(crate) fn resources(&self) -> &Resources {
+        &self.resources
+    }
+
+    pub fn get_resource<T: 'static>(&self) -> Option<crate::resource::Res<T>> {
+        self.resources.get()
+    }
+
+    pub fn get_resource_mut<T: 'static>(&self) -> Option<crate::resource::ResMut<T>> {
+        self.resources.get_mut()
+    }
+
+    /// Mutable handles to two different resources, or `None` if either is
+    /// missing. Panics if `A` and `B` are the same type.
+    pub fn get_resource_mut2<A: 'static, B: 'static>(
+        &self,
+    ) -> Option<(
+        crate::resource::ResMut<'_, A>,
+        crate::resource::ResMut<'_, B>,
+    )> {
+        assert_ne!(
+            TypeId::of::<A>(),
+            TypeId::of::<B>(),
+            "get_resource_mut2 needs two different resource types, got {} twice",
+            std::any::type_name::<A>()
+        );
+        Some((self.resources.get_mut()?, self.resources.get_mut()?))
+    }
+
+    pub fn remove_resource<T: 'static>(&mut self) -> Option<T> {
+        self.resources.remove()
+    }
+
+    pub fn contains_resource<T: 'static>(&self) -> bool {
+        self.resources.contains::<T>()
+    }
+
+    /// Type names of every resource in the world, sorted, e.g. for an
+    /// inspector panel
+    pub fn resource_type_names(&self) -> Vec<&'static str> {
+        self.resources.type_names()
+    }
+
+    /// Whether resource `T` was inserted or written through `ResMut` this tick
+    pub fn is_resource_changed<T: 'static>(&self) -> bool {
+        self.resources.is_changed::<T>()
+    }
+
+    /// Registers resource `T` to be captured by `checkpoint_resources`
+    pub fn register_clonable_resource<T: Clone + Send + Sync + 'static>(&mut self) {
+        self.resources.register_clonable::<T>();
+    }
+
+    pub fn checkpoint_resources(&self) -> ResourceCheckpoint {
+        self.resources.snapshot()
+    }
+
+    pub fn restore_resources(&mut self, checkpoint: ResourceCheckpoint) {
+        self.resources.restore(checkpoint);
+    }
+
+    pub fn commands(&mut self) -> &mut Commands {
+        &mut self.commands
+    }
+
+    pub fn flush_commands(&mut self) {
+        let mut commands = std::mem::replace(&mut self.commands, Commands::new());
+        commands.apply(self);
+        self.commands = commands;
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.entities.reserve(additional);
+    }
+
+    /// Reserves room for `additional` more entities with exactly bundle `B`,
+    /// covering both entity metadata and the archetype's columns.
+    pub fn reserve_in<B: Bundle>(&mut self, additional: usize) {
+        self.entities.reserve(additional);
+
+        let archetype_index = self
+            .archetypes
+            .get_or_create(B::type_ids(), B::type_names());
+        let archetype = self.archetypes.get_mut(archetype_index).unwrap();
+        if archetype.is_empty() {
+            B::init_archetype(archetype);
+        }
+        archetype.reserve(additional);
+        self.archetypes.mark_structural_change();
+    }
+
+    pub fn memory_stats(&self) -> MemoryStats {
+        let mut stats = MemoryStats {
+            entity_metadata_bytes: self.entities.allocated_bytes(),
+            ..MemoryStats::default()
+        };
+
+        for archetype in self.archetypes.iter() {
+            stats.archetype_count += 1;
+            stats.entity_metadata_bytes +=
+                archetype.entity_capacity() * std::mem::size_of::<Entity>();
+
+            for column in &archetype.columns {
+                stats.column_bytes += column.capacity * column.item_size;
+                stats.wasted_bytes += (column.capacity - column.len) * column.item_size;
+            }
+        }
+
+        stats
+    }
+
+    /// Type names and entity count of every non-empty archetype, largest first
+    pub fn archetype_histogram(&self) -> Vec<(Vec<&'static str>, usize)> {
+        let mut histogram: Vec<_> = self
+            .archetypes
+            .iter()
+            .filter(|archetype| !archetype.is_empty())
+            .map(|archetype| (archetype.type_names().to_vec(), archetype.len()))
+            .collect();
+        histogram.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        histogram
+    }
+
+    /// Every archetype, including empty ones, in id order
+    pub fn archetypes_info(&self) -> Vec<ArchetypeInfo> {
+        self.archetypes
+            .iter()
+            .map(|archetype| ArchetypeInfo {
+                id: archetype.id(),
+                type_names: archetype.type_names().to_vec(),
+                len: archetype.len(),
+            })
+            .collect()
+    }
+
+    /// The `T` column of archetype `archetype_id` as one slice, for batch
+    /// math over struct-of-arrays data. Includes `Disabled` entities. None if
+    /// the archetype spans several chunks, see `component_chunks`.
+    pub fn component_slice<T: Component>(&self, archetype_id: usize) -> Option<&[T]> {
+        self.archetypes.get(archetype_id)?.column_slice::<T>()
+    }
+
+    pub fn component_slice_mut<T: Component>(&mut self, archetype_id: usize) -> Option<&mut [T]> {
+        self.archetypes
+            .get_mut(archetype_id)?
+            .column_slice_mut::<T>()
+    }
+
+    /// Calls `f` with every entity in archetype `archetype_id`, in storage
+    /// order. Unknown ids visit nothing.
+    pub fn for_each_in_archetype(&self, archetype_id: usize, mut f: impl FnMut(Entity)) {
+        if let Some(archetype) = self.archetypes.get(archetype_id) {
+            for &entity in archetype.entities() {
+                f(entity);
+            }
+        }
+    }
+
+    /// Like `for_each_in_archetype`, also passing each entity's `T`. Visits
+    /// nothing if the archetype doesn't store `T`.
+    pub fn for_each_component_in_archetype<T: Component>(
+        &self,
+        archetype_id: usize,
+        mut f: impl FnMut(Entity, &T),
+    ) {
+        let Some(archetype) = self.archetypes.get(archetype_id) else {
+            return;
+        };
+        if let Some(chunks) = archetype.column_chunks::<T>() {
+            for (&entity, component) in archetype.entities().iter().zip(chunks.flatten()) {
+                f(entity, component);
+            }
+        }
+    }
+
+    /// The `T` column of archetype `archetype_id` split into the archetype's
+    /// chunks, in entity order
+    pub fn component_chunks<T: Component>(
+        &self,
+        archetype_id: usize,
+    ) -> Option<impl Iterator<Item = &[T]> + '_> {
+        self.archetypes.get(archetype_id)?.column_chunks::<T>()
+    }
+
+    /// Non-empty archetypes per live entity; 0.0 for an empty world
+    pub fn fragmentation_ratio(&self) -> f64 {
+        let entity_count = self.entities.len();
+        if entity_count == 0 {
+            return 0.0;
+        }
+        let occupied = self.archetypes.iter().filter(|a| !a.is_empty()).count();
+        occupied as f64 / entity_count as f64
+    }
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct EntityMut<'w> {
+    world: &'w mut World,
+    entity: Entity,
+}
+
+impl<'w> EntityMut<'w> {
+    pub fn id(&self) -> Entity {
+        self.entity
+    }
+
+    pub fn get<T: Component>(&self) -> Option<&T> {
+        self.world.get(self.entity)
+    }
+
+    pub fn get_mut<T: Component>(&mut self) -> Option<&mut T> {
+        self.world.get_mut(self.entity)
+    }
+
+    pub fn insert<C: Component>(&mut self, component: C) -> Result<&mut Self> {
+        self.world.insert(self.entity, component)?;
+        Ok(self)
+    }
+
+    pub fn remove<C: Component>(&mut self) -> Result<C> {
+        self.world.remove(self.entity)
+    }
+}
+
+/// Long-lived reference to one entity's `T`, see `World::track`. Holds no
+/// borrow; every `get` looks the entity up again, so it survives archetype
+/// moves and yields `None` once the entity or its `T` is gone.
+pub struct ComponentHandle<T> {
+    entity: Entity,
+    _marker: std::marker::PhantomData<fn() -> T>,
+}
+
+impl<T: Component> ComponentHandle<T> {
+    pub fn entity(&self) -> Entity {
+        self.entity
+    }
+
+    pub fn get<'w>(&self, world: &'w World) -> Option<&'w T> {
+        world.get(self.entity)
+    }
+
+    pub fn get_mut<'w>(&self, world: &'w mut World) -> Option<&'w mut T> {
+        world.get_mut(self.entity)
+    }
+}
+
+impl<T> Clone for ComponentHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for ComponentHandle<T> {}
+
+/// Read-only view of one entity's components, see `World::insert_with`
+#[derive(Clone, Copy)]
+pub struct EntityRef<'w> {
+    world: &'w World,
+    entity: Entity,
+}
+
+impl<'w> EntityRef<'w> {
+    pub fn id(&self) -> Entity {
+        self.entity
+    }
+
+    pub fn get<T: Component>(&self) -> Option<&'w T> {
+        self.world.get(self.entity)
+    }
+
+    pub fn contains<T: Component>(&self) -> bool {
+        self.get::<T>().is_some()
+    }
+}
+
+/// Spawns bundles of type `B` into a pre-resolved archetype, see
+/// `World::archetype_writer`
+pub struct ArchetypeWriter<'w, B: Bundle> {
+    world: &'w mut World,
+    archetype: usize,
+    _marker: std::marker::PhantomData<fn(B)>,
+}
+
+impl<B: Bundle> ArchetypeWriter<'_, B> {
+    pub fn push(&mut self, bundle: B) -> Entity {
+        let mut archetype = self.world.archetypes.view_mut(self.archetype).unwrap();
+        let entity_index = archetype.len();
+
+        let entity = self.world.entities.insert(EntityLocation {
+            archetype: self.archetype,
+            index: entity_index,
+            spawn_tick: self.world.tick,
+        });
+
+        archetype.push_entity(entity);
+        bundle.insert_into(&mut archetype, entity_index);
+        self.world.archetypes.mark_structural_change();
+
+        entity
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.world.entities.reserve(additional);
+        self.world
+            .archetypes
+            .get_mut(self.archetype)
+            .unwrap()
+            .reserve(additional);
+        self.world.archetypes.mark_structural_change();
+    }
+}
+
+impl fmt::Debug for World {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Only report layout, component data isn't required to be Debug
+        let archetypes: Vec<_> = self
+            .archetypes
+            .iter()
+            .map(|archetype| (archetype.type_names(), archetype.len()))
+            .collect();
+
+        f.debug_struct("World")
+            .field("entity_count", &self.entities.len())
+            .field("archetype_count", &archetypes.len())
+            .field("archetypes", &archetypes)
+            .field("tick", &self.tick)
+            .finish()
+    }
+}
+
+// Whether `archetype` can hold entities yielded by `Q`. The empty archetype
+// of `spawn_empty` entities has nothing to fetch, so no query matches it.
+fn query_matches<Q: Query>(archetype: ArchetypeView<'_>, include_disabled: bool) -> bool {
+    archetype.has_components()
+        && Q::matches_archetype(&archetype.query_types())
+        && (include_disabled || !archetype.types().contains(&TypeId::of::<Disabled>()))
+}
+
+// Whether the entity at `index` of an archetype passing `query_matches` has
+// every component `Q` needs, which only sparse components leave open
+fn row_matches<Q: Query>(archetype: ArchetypeView<'_>, index: usize) -> bool {
+    !archetype.checks_rows() || Q::matches_row(archetype, index)
+}
+
+/// The world operations a `Schedule` needs, so it can drive world types
+/// other than `World`. Nothing here depends on how components are stored, so
+/// a world from another crate can implement it and run function systems;
+/// `QuerySystem` additionally needs `QueryWorld`. `Sync` so the systems of a
+/// batch can share it across threads.
+pub trait WorldLike: Sync + 'static {
+    fn flush_commands(&mut self);
+    fn tick(&mut self);
+
+    /// Called by schedules around each system run, on the thread running
+    /// it, so writes can be attributed to it. Returns the system it
+    /// replaces, which schedules restore once the run ends.
+    fn set_current_system(&self, _system: SystemId) -> SystemId {
+        SystemId::NONE
+    }
+}
+
+/// A `WorldLike` whose components live in this crate's archetypes, which is
+/// what `Query` terms fetch from, so `QuerySystem` can run against it. In
+/// practice that is `World` or a type wrapping one.
+pub trait QueryWorld: WorldLike {
+    fn query<Q: Query + 'static>(&mut self) -> impl Iterator<Item = Q::Item<'_>>;
+
+    /// Like `query`, through a shared borrow so the systems of one batch can
+    /// query from several threads at once. `None` if the world type doesn't
+    /// support it, and schedules then run the system on its own.
+    ///
+    /// # Safety
+    ///
+    /// While the iterator lives, nothing else may touch a component `Q`
+    /// writes or write one `Q` reads, and the world's structure must not
+    /// change.
+    unsafe fn query_shared<Q: Query + 'static>(&self) -> Option<impl Iterator<Item = Q::Item<'_>>> {
+        None::<std::iter::Empty<Q::Item<'_>>>
+    }
+}
+
+impl WorldLike for World {
+    fn flush_commands(&mut self) {
+        World::flush_commands(self);
+    }
+
+    fn tick(&mut self) {
+        World::tick(self);
+    }
+
+    fn set_current_system(&self, system: SystemId) -> SystemId {
+        SystemId(crate::archetype::set_current_writer(system.get()))
+    }
+}
+
+impl QueryWorld for World {
+    fn query<Q: Query + 'static>(&mut self) -> impl Iterator<Item = Q::Item<'_>> {
+        World::query::<Q>(self)
+    }
+
+    unsafe fn query_shared<Q: Query + 'static>(&self) -> Option<impl Iterator<Item = Q::Item<'_>>> {
+        // The query cache needs `&mut`, so match archetypes directly
+        let archetypes = self
+            .archetypes
+            .views()
+            .filter(|&archetype| !archetype.is_empty() && query_matches::<Q>(archetype, false))
+            .collect();
+        Some(SharedQueryIter::<Q> {
+            archetypes,
+            archetype: 0,
+            index: 0,
+            _marker: std::marker::PhantomData,
+        })
+    }
+}
+
+/// Parallel iterator behind `World::par_query`, over runs of matching rows
+struct ParQueryIter<'a, Q: Query> {
+    runs: Vec<(ArchetypeView<'a>, std::ops::Range<usize>)>,
+    // Position of each run's first row among all matching rows
+    starts: Vec<usize>,
+    len: usize,
+    _marker: std::marker::PhantomData<(&'a mut World, Q)>,
+}
+
+// Each row is fetched by exactly one thread, and the world is borrowed
+// mutably for the iterator's lifetime
+unsafe impl<Q: Query> Send for ParQueryIter<'_, Q> {}
+
+impl<'a, Q: Query> ParQueryIter<'a, Q> {
+    fn new(runs: Vec<(ArchetypeView<'a>, std::ops::Range<usize>)>) -> Self {
+        let mut len = 0;
+        let starts = runs
+            .iter()
+            .map(|(_, rows)| {
+                let start = len;
+                len += rows.len();
+                start
+            })
+            .collect();
+        Self {
+            runs,
+            starts,
+            len,
+            _marker: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<'a, Q: Query> ParallelIterator for ParQueryIter<'a, Q>
+where
+    Q::Item<'a>: Send,
+{
+    type Item = Q::Item<'a>;
+
+    fn drive_unindexed<C: UnindexedConsumer<Self::Item>>(self, consumer: C) -> C::Result {
+        bridge(self, consumer)
+    }
+
+    fn opt_len(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+impl<'a, Q: Query> IndexedParallelIterator for ParQueryIter<'a, Q>
+where
+    Q::Item<'a>: Send,
+{
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn drive<C: Consumer<Self::Item>>(self, consumer: C) -> C::Result {
+        bridge(self, consumer)
+    }
+
+    fn with_producer<CB: ProducerCallback<Self::Item>>(self, callback: CB) -> CB::Output {
+        callback.callback(RowProducer::<Q> {
+            runs: &self.runs,
+            starts: &self.starts,
+            rows: 0..self.len,
+            _marker: std::marker::PhantomData,
+        })
+    }
+}
+
+/// A slice of `ParQueryIter`'s matching rows, by position among all of
+/// them, which rayon splits and hands to threads
+struct RowProducer<'p, 'a, Q: Query> {
+    runs: &'p [(ArchetypeView<'a>, std::ops::Range<usize>)],
+    starts: &'p [usize],
+    rows: std::ops::Range<usize>,
+    _marker: std::marker::PhantomData<(&'a mut World, Q)>,
+}
+
+// Producers cover disjoint rows, see `ParQueryIter`
+unsafe impl<Q: Query> Send for RowProducer<'_, '_, Q> {}
+
+impl<'p, 'a, Q: Query> RowProducer<'p, 'a, Q> {
+    fn fetch(&self, position: usize) -> Q::Item<'a> {
+        let run = self.starts.partition_point(|&start| start <= position) - 1;
+        let (archetype, rows) = &self.runs[run];
+        let index = rows.start + (position - self.starts[run]);
+        unsafe { Q::fetch(*archetype, index) }
+    }
+}
+
+impl<'p, 'a, Q: Query> Producer for RowProducer<'p, 'a, Q>
+where
+    Q::Item<'a>: Send,
+{
+    type Item = Q::Item<'a>;
+    type IntoIter = Self;
+
+    fn into_iter(self) -> Self {
+        self
+    }
+
+    fn split_at(self, index: usize) -> (Self, Self) {
+        let mid = self.rows.start + index;
+        let left = RowProducer {
+            rows: self.rows.start..mid,
+            ..self
+        };
+        let right = RowProducer {
+            rows: mid..self.rows.end,
+            ..self
+        };
+        (left, right)
+    }
+}
+
+impl<'a, Q: Query> Iterator for RowProducer<'_, 'a, Q> {
+    type Item = Q::Item<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let position = self.rows.next()?;
+        Some(self.fetch(position))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.rows.size_hint()
+    }
+}
+
+impl<Q: Query> DoubleEndedIterator for RowProducer<'_, '_, Q> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let position = self.rows.next_back()?;
+        Some(self.fetch(position))
+    }
+}
+
+impl<Q: Query> ExactSizeIterator for RowProducer<'_, '_, Q> {}
+
+/// Iterator behind `World::query_shared`. It only borrows the archetypes
+/// shared, so one can be alive on each thread of a batch; each only writes
+/// through the column pointers of `Q`'s terms.
+struct SharedQueryIter<'a, Q: Query> {
+    archetypes: Vec<ArchetypeView<'a>>,
+    archetype: usize,
+    index: usize,
+    _marker: std::marker::PhantomData<(&'a World, Q)>,
+}
+
+impl<'a, Q: Query> Iterator for SharedQueryIter<'a, Q> {
+    type Item = Q::Item<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let archetype = *self.archetypes.get(self.archetype)?;
+            if self.index >= archetype.len() {
+                self.archetype += 1;
+                self.index = 0;
+                continue;
+            }
+            let index = self.index;
+            self.index += 1;
+            if !row_matches::<Q>(archetype, index) {
+                continue;
+            }
+            return Some(unsafe { Q::fetch(archetype, index) });
+        }
+    }
+}
+
+pub struct QueryIter<'a, Q: Query, F: QueryFilter = (), R: RowFilter = ()> {
+    archetypes: &'a ArchetypeMap,
+    matches: Arc<[usize]>,
+    match_index: usize,
+    entity_index: usize,
+    // Back cursor: matches[..back_match] remain, and the last of those ends
+    // at back_entity (exclusive)
+    back_match: usize,
+    back_entity: usize,
+    // Structure generation at creation, checked in debug builds so a world
+    // mutated behind the iterator's back panics instead of reading freed data
+    structure_generation: u64,
+    row_filter: R,
+    _marker: std::marker::PhantomData<(Q, F)>,
+}
+
+impl<'a, Q: Query + 'static, F: QueryFilter + 'static> QueryIter<'a, Q, F> {
+    fn new(archetypes: &'a mut ArchetypeMap, include_disabled: bool) -> Self {
+        let matches = archetypes.matching_archetypes(
+            (TypeId::of::<(Q, F)>(), include_disabled),
+            |archetype| {
+                query_matches::<Q>(archetype, include_disabled)
+                    && F::matches_archetype(&archetype.query_types())
+            },
+        );
+
+        let archetypes = &*archetypes;
+        #[cfg(debug_assertions)]
+        let matches = Self::skip_incomplete(archetypes, matches);
+
+        let back_match = matches.len();
+        let back_entity = matches
+            .last()
+            .map_or(0, |&id| archetypes.get(id).unwrap().len());
+
+        let structure_generation = archetypes.structure_generation();
+
+        Self {
+            archetypes,
+            matches,
+            match_index: 0,
+            entity_index: 0,
+            back_match,
+            back_entity,
+            structure_generation,
+            row_filter: (),
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    /// Narrows the query with a runtime filter such as `ChangedBy`. It is
+    /// checked before each row is fetched, so rejected rows are never
+    /// borrowed or marked changed.
+    pub fn filter_by<R: RowFilter>(self, filter: R) -> QueryIter<'a, Q, F, R> {
+        QueryIter {
+            archetypes: self.archetypes,
+            matches: self.matches,
+            match_index: self.match_index,
+            entity_index: self.entity_index,
+            back_match: self.back_match,
+            back_entity: self.back_entity,
+            structure_generation: self.structure_generation,
+            row_filter: filter,
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    // Debug builds leave out partially initialized archetypes instead of
+    // panicking inside `fetch`, so the size hint stays exact. With the
+    // `tracing` feature each one is reported as a warning.
+    #[cfg(debug_assertions)]
+    fn skip_incomplete(archetypes: &ArchetypeMap, matches: Arc<[usize]>) -> Arc<[usize]> {
+        let complete = |id: &usize| archetypes.get(*id).unwrap().is_complete();
+        if matches.iter().all(complete) {
+            return matches;
+        }
+        #[cfg(feature = "tracing")]
+        for &id in matches.iter().filter(|id| !complete(id)) {
+            tracing::warn!(
+                archetype = id,
+                query = std::any::type_name::<Q>(),
+                "skipping partially initialized archetype"
+            );
+        }
+        matches.iter().copied().filter(complete).collect()
+    }
+}
+
+impl<'a, Q: Query, F: QueryFilter, R: RowFilter> QueryIter<'a, Q, F, R> {
+    fn check_structure(&self) {
+        debug_assert_eq!(
+            self.archetypes.structure_generation(),
+            self.structure_generation,
+            "World was structurally modified (spawn, despawn, insert, remove or reserve) \
+             while a QueryIter over it was still alive"
+        );
+    }
+
+    // Whether the row passes the filters and, where sparse components make
+    // it a per-entity question, has every term of `Q`
+    fn row_passes(&self, archetype: ArchetypeView<'_>, index: usize) -> bool {
+        let checks_rows = archetype.checks_rows();
+        (F::ARCHETYPAL && !checks_rows || F::matches_component(archetype, index))
+            && (!checks_rows || Q::matches_row(archetype, index))
+            && (R::ALL || self.row_filter.matches_row(archetype, index))
+    }
+
+    // Moves the front cursor past the next passing row without fetching it
+    fn next_row(&mut self) -> Option<(ArchetypeView<'a>, usize)> {
+        self.check_structure();
+        loop {
+            if self.match_index >= self.back_match {
+                return None;
+            }
+            let archetype_id = self.matches[self.match_index];
+            let archetype = self.archetypes.view(archetype_id).unwrap();
+
+            if self.entity_index >= self.front_end(archetype.len()) {
+                self.match_index += 1;
+                self.entity_index = 0;
+                continue;
+            }
+
+            let index = self.entity_index;
+            self.entity_index += 1;
+            if self.row_passes(archetype, index) {
+                return Some((archetype, index));
+            }
+        }
+    }
+
+    // Exclusive end of the front cursor's archetype, clamped by the back cursor
+    fn front_end(&self, archetype_len: usize) -> usize {
+        if self.match_index + 1 == self.back_match {
+            self.back_entity
+        } else {
+            archetype_len
+        }
+    }
+}
+
+impl<'a, Q: Query, F: QueryFilter, R: RowFilter> Iterator for QueryIter<'a, Q, F, R> {
+    type Item = Q::Item<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (archetype, index) = self.next_row()?;
+        Some(unsafe { Q::fetch(archetype, index) })
+    }
+
+    // Sums the archetype sizes when every row of them matches, otherwise
+    // skips rows without fetching, so counting never borrows or marks them
+    fn count(mut self) -> usize {
+        if let (lower, Some(upper)) = self.size_hint()
+            && lower == upper
+        {
+            return lower;
+        }
+        let mut count = 0;
+        while self.next_row().is_some() {
+            count += 1;
+        }
+        count
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = if self.match_index >= self.back_match {
+            0
+        } else {
+            let last_len = self
+                .archetypes
+                .get(self.matches[self.back_match - 1])
+                .unwrap()
+                .len();
+            self.matches[self.match_index..self.back_match]
+                .iter()
+                .map(|&id| self.archetypes.get(id).unwrap().len())
+                .sum::<usize>()
+                .saturating_sub(self.entity_index + (last_len - self.back_entity))
+        };
+        if F::ARCHETYPAL && R::ALL && !self.archetypes.sparse().has_sets() {
+            (remaining, Some(remaining))
+        } else {
+            (0, Some(remaining))
+        }
+    }
+}
+
+impl<'a, Q: Query, F: QueryFilter, R: RowFilter> DoubleEndedIterator for QueryIter<'a, Q, F, R> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.check_structure();
+        loop {
+            if self.back_match <= self.match_index {
+                return None;
+            }
+            let archetype_id = self.matches[self.back_match - 1];
+            let archetype = self.archetypes.view(archetype_id).unwrap();
+
+            // Never step back past the front cursor in a shared archetype
+            let start = if self.back_match - 1 == self.match_index {
+                self.entity_index
+            } else {
+                0
+            };
+            if self.back_entity <= start {
+                self.back_match -= 1;
+                self.back_entity = match self.back_match.checked_sub(1) {
+                    Some(index) => self.archetypes.get(self.matches[index]).unwrap().len(),
+                    None => 0,
+                };
+                continue;
+            }
+
+            self.back_entity -= 1;
+            if !self.row_passes(archetype, self.back_entity) {
+                continue;
+            }
+
+            return Some(unsafe { Q::fetch(archetype, self.back_entity) });
+        }
     }
 }
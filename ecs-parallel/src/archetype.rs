@@ -11,6 +11,7 @@ pub(crate) struct Archetype {
 }
 
 struct Column {
+    type_id: TypeId,
     data: NonNull<u8>,
     len: usize,
     capacity: usize,
@@ -18,6 +19,10 @@ struct Column {
     drop_fn: unsafe fn(*mut u8),
 }
 
+// Columns are only created for `Send + Sync` components, see `add_column`
+unsafe impl Send for Column {}
+unsafe impl Sync for Column {}
+
 impl Archetype {
     pub fn new(types: Vec<TypeId>) -> Self {
         Self {
@@ -39,8 +44,14 @@ impl Archetype {
         &self.entities
     }
 
-    pub fn add_column<T: 'static>(&mut self) {
+    pub fn add_column<T: 'static + Send + Sync>(&mut self) {
+        let type_id = TypeId::of::<T>();
+        if self.columns.iter().any(|c| c.type_id == type_id) {
+            return;
+        }
+
         let column = Column {
+            type_id,
             data: NonNull::dangling(),
             len: 0,
             capacity: 0,
@@ -49,7 +60,10 @@ impl Archetype {
                 std::ptr::drop_in_place(ptr as *mut T);
             },
         };
-        self.columns.push(column);
+
+        // Keep columns sorted by TypeId so column i always stores types[i]
+        let position = self.columns.partition_point(|c| c.type_id < type_id);
+        self.columns.insert(position, column);
     }
 
     pub fn push_entity(&mut self, entity: Entity) {
@@ -63,7 +77,7 @@ impl Archetype {
         }
     }
 
-    pub fn set_component<T: 'static>(&mut self, index: usize, component: T) {
+    pub fn set_component<T: 'static + Send + Sync>(&mut self, index: usize, component: T) {
         let type_id = TypeId::of::<T>();
         let column_index = self
             .types
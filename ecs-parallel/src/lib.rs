@@ -6,7 +6,7 @@ pub mod world;
 
 pub use entity::Entity;
 pub use query::{Query, QueryBorrow};
-pub use system::{IntoSystem, ParallelSchedule, Schedule, Stage, System};
+pub use system::{BatchDiagnostic, IntoSystem, ParallelSchedule, Schedule, Stage, System};
 pub use world::World;
 
 #[cfg(test)]
@@ -283,4 +283,153 @@ mod tests {
         assert_eq!(system2.reads().len(), 0);
         assert_eq!(system2.writes().len(), 1);
     }
+
+    #[test]
+    fn test_query_five_tuple() {
+        #[derive(Debug, PartialEq)]
+        struct Mass(f64);
+
+        #[derive(Debug, PartialEq)]
+        struct Tag(u8);
+
+        let mut world = World::new();
+
+        for i in 0..3 {
+            world.spawn((
+                Position {
+                    x: i as f32,
+                    y: 0.0,
+                },
+                Velocity { dx: 1.0, dy: 2.0 },
+                Health(100),
+                Mass(i as f64 * 2.0),
+                Tag(i as u8),
+            ));
+        }
+        world.spawn((Position { x: 9.0, y: 9.0 }, Health(1)));
+
+        let mut count = 0;
+        for (pos, vel, health, mass, tag) in
+            world.query::<(&mut Position, &Velocity, &Health, &Mass, &Tag)>()
+        {
+            pos.x += vel.dx;
+            assert_eq!(health.0, 100);
+            assert_eq!(mass.0, tag.0 as f64 * 2.0);
+            count += 1;
+        }
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn test_batches_are_deterministic() {
+        fn build() -> (Schedule, ParallelSchedule) {
+            let mut schedule = Schedule::new();
+            let mut parallel = ParallelSchedule::new();
+            for _ in 0..4 {
+                schedule
+                    .add_update_system(QuerySystem::<&mut Position, _>::new(|_: &mut Position| {}));
+                schedule.add_update_system(QuerySystem::<&Velocity, _>::new(|_: &Velocity| {}));
+                schedule.add_update_system(QuerySystem::<&mut Health, _>::new(|_: &mut Health| {}));
+                parallel.add_system(QuerySystem::<&mut Position, _>::new(|_: &mut Position| {}));
+                parallel.add_system(QuerySystem::<&Velocity, _>::new(|_: &Velocity| {}));
+                parallel.add_system(QuerySystem::<&mut Health, _>::new(|_: &mut Health| {}));
+            }
+            (schedule, parallel)
+        }
+
+        let expected = vec![vec![0, 1, 2, 4, 7, 10], vec![3, 5], vec![6, 8], vec![9, 11]];
+        for _ in 0..8 {
+            let (schedule, parallel) = build();
+            assert_eq!(schedule.stage_batches(Stage::Update), expected.as_slice());
+            assert_eq!(parallel.batches(), expected);
+        }
+    }
+
+    #[test]
+    fn test_batch_diagnostics_name_self_conflicting_system() {
+        use std::any::TypeId;
+
+        struct Confused;
+
+        impl System for Confused {
+            fn run(&mut self, _world: &mut World) {}
+
+            fn reads(&self) -> Vec<TypeId> {
+                vec![TypeId::of::<Position>()]
+            }
+
+            fn writes(&self) -> Vec<TypeId> {
+                vec![TypeId::of::<Position>()]
+            }
+
+            fn name(&self) -> &str {
+                "confused"
+            }
+        }
+
+        let mut world = World::new();
+        let mut schedule = ParallelSchedule::new();
+        schedule.add_system(QuerySystem::<&Velocity, _>::new(|_: &Velocity| {}));
+        schedule.add_system(Confused);
+        schedule.add_system(QuerySystem::<&mut Health, _>::new(|_: &mut Health| {}));
+
+        schedule.run(&mut world);
+        assert_eq!(schedule.batches(), vec![vec![0, 2], vec![1]]);
+        assert_eq!(
+            schedule.last_batch_diagnostics(),
+            &[BatchDiagnostic {
+                system: "confused".to_string(),
+                component: TypeId::of::<Position>(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_ecs_complete_schedule_drives_world() {
+        use ecs_complete::{IntoSystem, ScheduleBuilder, Stage, WorldLike};
+
+        // `ecs-complete`'s schedule only needs `WorldLike`, which doesn't
+        // depend on its storage, so a thin wrapper lets it drive this world
+        struct Scheduled {
+            world: World,
+            ticks: u32,
+        }
+
+        impl WorldLike for Scheduled {
+            fn flush_commands(&mut self) {}
+
+            fn tick(&mut self) {
+                self.ticks += 1;
+            }
+        }
+
+        let mut scheduled = Scheduled {
+            world: World::new(),
+            ticks: 0,
+        };
+        let entity = scheduled
+            .world
+            .spawn((Position { x: 0.0, y: 0.0 }, Velocity { dx: 1.0, dy: 2.0 }));
+
+        let mut schedule = ScheduleBuilder::<Scheduled>::default()
+            .system(
+                Stage::Update,
+                (|scheduled: &mut Scheduled| {
+                    for (pos, vel) in scheduled.world.query::<(&mut Position, &Velocity)>() {
+                        pos.x += vel.dx;
+                        pos.y += vel.dy;
+                    }
+                })
+                .into_system(),
+            )
+            .build();
+        schedule.run(&mut scheduled);
+        schedule.run(&mut scheduled);
+
+        assert_eq!(
+            scheduled.world.get::<Position>(entity),
+            Some(&Position { x: 2.0, y: 4.0 })
+        );
+        assert_eq!(scheduled.ticks, 2);
+    }
 }
@@ -66,108 +66,42 @@ impl<T: 'static + Send> Query for &mut T {
 }
 
 // Implement Query for tuples of queries
-impl<Q1: Query, Q2: Query> Query for (Q1, Q2) {
-    type Item<'a> = (Q1::Item<'a>, Q2::Item<'a>);
-
-    fn matches_archetype(types: &[TypeId]) -> bool {
-        Q1::matches_archetype(types) && Q2::matches_archetype(types)
-    }
-
-    unsafe fn fetch<'a>(
-        archetype: &'a mut crate::archetype::Archetype,
-        index: usize,
-    ) -> Self::Item<'a> {
-        let ptr = archetype as *mut crate::archetype::Archetype;
-        unsafe { (Q1::fetch(&mut *ptr, index), Q2::fetch(&mut *ptr, index)) }
-    }
-
-    fn read_types() -> Vec<TypeId> {
-        let mut types = Q1::read_types();
-        types.extend(Q2::read_types());
-        types
-    }
-
-    fn write_types() -> Vec<TypeId> {
-        let mut types = Q1::write_types();
-        types.extend(Q2::write_types());
-        types
-    }
-}
-
-impl<Q1: Query, Q2: Query, Q3: Query> Query for (Q1, Q2, Q3) {
-    type Item<'a> = (Q1::Item<'a>, Q2::Item<'a>, Q3::Item<'a>);
-
-    fn matches_archetype(types: &[TypeId]) -> bool {
-        Q1::matches_archetype(types) && Q2::matches_archetype(types) && Q3::matches_archetype(types)
-    }
-
-    unsafe fn fetch<'a>(
-        archetype: &'a mut crate::archetype::Archetype,
-        index: usize,
-    ) -> Self::Item<'a> {
-        let ptr = archetype as *mut crate::archetype::Archetype;
-        unsafe {
-            (
-                Q1::fetch(&mut *ptr, index),
-                Q2::fetch(&mut *ptr, index),
-                Q3::fetch(&mut *ptr, index),
-            )
+macro_rules! impl_query_tuple {
+    ($($q:ident),+) => {
+        impl<$($q: Query),+> Query for ($($q,)+) {
+            type Item<'a> = ($($q::Item<'a>,)+);
+
+            fn matches_archetype(types: &[TypeId]) -> bool {
+                $($q::matches_archetype(types))&&+
+            }
+
+            unsafe fn fetch<'a>(
+                archetype: &'a mut crate::archetype::Archetype,
+                index: usize,
+            ) -> Self::Item<'a> {
+                let ptr = archetype as *mut crate::archetype::Archetype;
+                unsafe { ($($q::fetch(&mut *ptr, index),)+) }
+            }
+
+            fn read_types() -> Vec<TypeId> {
+                let mut types = Vec::new();
+                $(types.extend($q::read_types());)+
+                types
+            }
+
+            fn write_types() -> Vec<TypeId> {
+                let mut types = Vec::new();
+                $(types.extend($q::write_types());)+
+                types
+            }
         }
-    }
-
-    fn read_types() -> Vec<TypeId> {
-        let mut types = Q1::read_types();
-        types.extend(Q2::read_types());
-        types.extend(Q3::read_types());
-        types
-    }
-
-    fn write_types() -> Vec<TypeId> {
-        let mut types = Q1::write_types();
-        types.extend(Q2::write_types());
-        types.extend(Q3::write_types());
-        types
-    }
+    };
 }
 
-impl<Q1: Query, Q2: Query, Q3: Query, Q4: Query> Query for (Q1, Q2, Q3, Q4) {
-    type Item<'a> = (Q1::Item<'a>, Q2::Item<'a>, Q3::Item<'a>, Q4::Item<'a>);
-
-    fn matches_archetype(types: &[TypeId]) -> bool {
-        Q1::matches_archetype(types)
-            && Q2::matches_archetype(types)
-            && Q3::matches_archetype(types)
-            && Q4::matches_archetype(types)
-    }
-
-    unsafe fn fetch<'a>(
-        archetype: &'a mut crate::archetype::Archetype,
-        index: usize,
-    ) -> Self::Item<'a> {
-        let ptr = archetype as *mut crate::archetype::Archetype;
-        unsafe {
-            (
-                Q1::fetch(&mut *ptr, index),
-                Q2::fetch(&mut *ptr, index),
-                Q3::fetch(&mut *ptr, index),
-                Q4::fetch(&mut *ptr, index),
-            )
-        }
-    }
-
-    fn read_types() -> Vec<TypeId> {
-        let mut types = Q1::read_types();
-        types.extend(Q2::read_types());
-        types.extend(Q3::read_types());
-        types.extend(Q4::read_types());
-        types
-    }
-
-    fn write_types() -> Vec<TypeId> {
-        let mut types = Q1::write_types();
-        types.extend(Q2::write_types());
-        types.extend(Q3::write_types());
-        types.extend(Q4::write_types());
-        types
-    }
-}
+impl_query_tuple!(Q1, Q2);
+impl_query_tuple!(Q1, Q2, Q3);
+impl_query_tuple!(Q1, Q2, Q3, Q4);
+impl_query_tuple!(Q1, Q2, Q3, Q4, Q5);
+impl_query_tuple!(Q1, Q2, Q3, Q4, Q5, Q6);
+impl_query_tuple!(Q1, Q2, Q3, Q4, Q5, Q6, Q7);
+impl_query_tuple!(Q1, Q2, Q3, Q4, Q5, Q6, Q7, Q8);
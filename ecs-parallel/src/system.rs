@@ -184,6 +184,14 @@ impl Schedule {
         }
     }
 
+    /// Indices (in insertion order) of the systems in `stage`, grouped into
+    /// the batches `run` executes
+    pub fn stage_batches(&self, stage: Stage) -> &[Vec<usize>] {
+        self.stages
+            .get(&stage)
+            .map_or(&[], |executor| &executor.batches)
+    }
+
     /// Run a specific stage
     pub fn run_stage(&mut self, stage: Stage, world: &mut World) {
         if let Some(executor) = self.stages.get_mut(&stage) {
@@ -219,47 +227,12 @@ impl StageExecutor {
 
     /// Rebuild parallel execution batches based on system dependencies
     fn rebuild_batches(&mut self) {
-        self.batches.clear();
-
-        let mut remaining: HashSet<usize> = (0..self.systems.len()).collect();
-
-        while !remaining.is_empty() {
-            let mut batch = Vec::new();
-            let mut batch_reads = HashSet::new();
-            let mut batch_writes = HashSet::new();
-
-            let remaining_vec: Vec<usize> = remaining.iter().copied().collect();
-
-            for &idx in &remaining_vec {
-                let system = &self.systems[idx];
-                let reads = system.reads();
-                let writes = system.writes();
-
-                // Check if this system conflicts with the current batch
-                let has_conflict = writes
-                    .iter()
-                    .any(|w| batch_reads.contains(w) || batch_writes.contains(w))
-                    || reads.iter().any(|r| batch_writes.contains(r));
-
-                if !has_conflict {
-                    batch.push(idx);
-                    batch_reads.extend(reads);
-                    batch_writes.extend(writes);
-                    remaining.remove(&idx);
-                }
-            }
-
-            if !batch.is_empty() {
-                self.batches.push(batch);
-            } else {
-                // If we couldn't add anything, there might be a deadlock
-                // Just add the first remaining system to break it
-                if let Some(&idx) = remaining.iter().next() {
-                    self.batches.push(vec![idx]);
-                    remaining.remove(&idx);
-                }
-            }
-        }
+        let access: Vec<_> = self
+            .systems
+            .iter()
+            .map(|system| (system.reads(), system.writes()))
+            .collect();
+        self.batches = compute_batches(&access).0;
     }
 
     fn run(&mut self, world: &mut World) {
@@ -280,10 +253,21 @@ impl StageExecutor {
     }
 }
 
+/// A system the batcher could not place normally and ran on its own, see
+/// `ParallelSchedule::last_batch_diagnostics`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchDiagnostic {
+    pub system: String,
+    /// Component the system reports as both read and written, which makes it
+    /// conflict with any batch, even an empty one
+    pub component: TypeId,
+}
+
 /// Parallel schedule that can execute non-conflicting systems in parallel
 pub struct ParallelSchedule {
     systems: Vec<Box<dyn System>>,
     dependency_graph: DependencyGraph,
+    diagnostics: Vec<BatchDiagnostic>,
 }
 
 impl ParallelSchedule {
@@ -291,6 +275,7 @@ impl ParallelSchedule {
         Self {
             systems: Vec::new(),
             dependency_graph: DependencyGraph::new(),
+            diagnostics: Vec::new(),
         }
     }
 
@@ -305,9 +290,27 @@ impl ParallelSchedule {
         self
     }
 
+    /// Indices of the systems grouped into the batches `run` executes
+    pub fn batches(&self) -> Vec<Vec<usize>> {
+        self.dependency_graph.compute_batches().0
+    }
+
+    /// Systems the last `run` had to force into a batch of their own,
+    /// explaining why they serialize unexpectedly
+    pub fn last_batch_diagnostics(&self) -> &[BatchDiagnostic] {
+        &self.diagnostics
+    }
+
     /// Execute systems in parallel where possible
     pub fn run(&mut self, world: &mut World) {
-        let batches = self.dependency_graph.compute_batches();
+        let (batches, stalls) = self.dependency_graph.compute_batches();
+        self.diagnostics = stalls
+            .into_iter()
+            .map(|(idx, component)| BatchDiagnostic {
+                system: self.systems[idx].name().to_string(),
+                component,
+            })
+            .collect();
 
         for batch in batches {
             // For true parallelism, we'd need to split World access
@@ -347,49 +350,75 @@ impl DependencyGraph {
     }
 
     /// Compute batches of systems that can run in parallel
-    fn compute_batches(&self) -> Vec<Vec<usize>> {
-        let mut batches = Vec::new();
-        let mut remaining: HashSet<usize> = (0..self.systems.len()).collect();
-
-        while !remaining.is_empty() {
-            let mut batch = Vec::new();
-            let mut batch_reads = HashSet::new();
-            let mut batch_writes = HashSet::new();
+    fn compute_batches(&self) -> (Vec<Vec<usize>>, Vec<(usize, TypeId)>) {
+        let access: Vec<_> = self
+            .systems
+            .iter()
+            .map(|node| (node.reads.clone(), node.writes.clone()))
+            .collect();
+        compute_batches(&access)
+    }
+}
 
-            let remaining_vec: Vec<usize> = remaining.iter().copied().collect();
+/// Greedily packs systems, given as `(reads, writes)`, into batches of
+/// non-conflicting systems. Candidates are always visited in index order, so
+/// the same systems added in the same order always batch the same way. Also
+/// returns each system the deadlock fallback had to place, with the component
+/// that stalled it.
+fn compute_batches(
+    access: &[(Vec<TypeId>, Vec<TypeId>)],
+) -> (Vec<Vec<usize>>, Vec<(usize, TypeId)>) {
+    let mut batches = Vec::new();
+    let mut stalls = Vec::new();
+    let mut assigned = vec![false; access.len()];
+    let mut remaining = access.len();
+
+    while remaining > 0 {
+        let mut batch = Vec::new();
+        let mut batch_reads = HashSet::new();
+        let mut batch_writes = HashSet::new();
+
+        for (idx, (reads, writes)) in access.iter().enumerate() {
+            if assigned[idx] {
+                continue;
+            }
 
-            for &idx in &remaining_vec {
-                let node = &self.systems[idx];
+            // Check for conflicts
+            let has_write_conflict = writes
+                .iter()
+                .any(|w| batch_reads.contains(w) || batch_writes.contains(w));
 
-                // Check for conflicts
-                let has_write_conflict = node
-                    .writes
-                    .iter()
-                    .any(|w| batch_reads.contains(w) || batch_writes.contains(w));
+            let has_read_conflict = reads.iter().any(|r| batch_writes.contains(r));
 
-                let has_read_conflict = node.reads.iter().any(|r| batch_writes.contains(r));
+            // A system that reads what it writes conflicts with itself
+            let has_self_conflict = writes.iter().any(|w| reads.contains(w));
 
-                if !has_write_conflict && !has_read_conflict {
-                    batch.push(idx);
-                    batch_reads.extend(node.reads.iter().copied());
-                    batch_writes.extend(node.writes.iter().copied());
-                    remaining.remove(&idx);
-                }
+            if !has_write_conflict && !has_read_conflict && !has_self_conflict {
+                batch.push(idx);
+                batch_reads.extend(reads.iter().copied());
+                batch_writes.extend(writes.iter().copied());
+                assigned[idx] = true;
+                remaining -= 1;
             }
+        }
 
-            if !batch.is_empty() {
-                batches.push(batch);
-            } else {
-                // Break potential deadlock
-                if let Some(&idx) = remaining.iter().next() {
-                    batches.push(vec![idx]);
-                    remaining.remove(&idx);
-                }
+        if !batch.is_empty() {
+            batches.push(batch);
+        } else {
+            // Break potential deadlock with the lowest unassigned system.
+            // Nothing else fit into an empty batch, so it conflicts with itself.
+            let idx = assigned.iter().position(|&done| !done).unwrap();
+            let (reads, writes) = &access[idx];
+            if let Some(&component) = writes.iter().find(|w| reads.contains(w)) {
+                stalls.push((idx, component));
             }
+            batches.push(vec![idx]);
+            assigned[idx] = true;
+            remaining -= 1;
         }
-
-        batches
     }
+
+    (batches, stalls)
 }
 
 /// Helper macro to create query systems more easily
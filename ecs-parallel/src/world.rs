@@ -113,80 +113,33 @@ pub trait ComponentBundle {
     fn insert_into(self, archetype: &mut crate::archetype::Archetype, index: usize);
 }
 
-// Implement ComponentBundle for single components
-impl<T: 'static> ComponentBundle for (T,) {
-    fn type_ids() -> Vec<TypeId> {
-        vec![TypeId::of::<T>()]
-    }
-
-    fn init_archetype(archetype: &mut crate::archetype::Archetype) {
-        archetype.add_column::<T>();
-    }
-
-    fn insert_into(self, archetype: &mut crate::archetype::Archetype, index: usize) {
-        archetype.set_component(index, self.0);
-    }
-}
-
-// Implement ComponentBundle for tuples of 2-4 components
-impl<T1: 'static, T2: 'static> ComponentBundle for (T1, T2) {
-    fn type_ids() -> Vec<TypeId> {
-        vec![TypeId::of::<T1>(), TypeId::of::<T2>()]
-    }
-
-    fn init_archetype(archetype: &mut crate::archetype::Archetype) {
-        archetype.add_column::<T1>();
-        archetype.add_column::<T2>();
-    }
-
-    fn insert_into(self, archetype: &mut crate::archetype::Archetype, index: usize) {
-        archetype.set_component(index, self.0);
-        archetype.set_component(index, self.1);
-    }
-}
-
-impl<T1: 'static, T2: 'static, T3: 'static> ComponentBundle for (T1, T2, T3) {
-    fn type_ids() -> Vec<TypeId> {
-        vec![TypeId::of::<T1>(), TypeId::of::<T2>(), TypeId::of::<T3>()]
-    }
+// Implement ComponentBundle for tuples of 1-8 components
+macro_rules! impl_component_bundle {
+    ($($t:ident: $idx:tt),+) => {
+        impl<$($t: 'static + Send + Sync),+> ComponentBundle for ($($t,)+) {
+            fn type_ids() -> Vec<TypeId> {
+                vec![$(TypeId::of::<$t>()),+]
+            }
 
-    fn init_archetype(archetype: &mut crate::archetype::Archetype) {
-        archetype.add_column::<T1>();
-        archetype.add_column::<T2>();
-        archetype.add_column::<T3>();
-    }
+            fn init_archetype(archetype: &mut crate::archetype::Archetype) {
+                $(archetype.add_column::<$t>();)+
+            }
 
-    fn insert_into(self, archetype: &mut crate::archetype::Archetype, index: usize) {
-        archetype.set_component(index, self.0);
-        archetype.set_component(index, self.1);
-        archetype.set_component(index, self.2);
-    }
+            fn insert_into(self, archetype: &mut crate::archetype::Archetype, index: usize) {
+                $(archetype.set_component(index, self.$idx);)+
+            }
+        }
+    };
 }
 
-impl<T1: 'static, T2: 'static, T3: 'static, T4: 'static> ComponentBundle for (T1, T2, T3, T4) {
-    fn type_ids() -> Vec<TypeId> {
-        vec![
-            TypeId::of::<T1>(),
-            TypeId::of::<T2>(),
-            TypeId::of::<T3>(),
-            TypeId::of::<T4>(),
-        ]
-    }
-
-    fn init_archetype(archetype: &mut crate::archetype::Archetype) {
-        archetype.add_column::<T1>();
-        archetype.add_column::<T2>();
-        archetype.add_column::<T3>();
-        archetype.add_column::<T4>();
-    }
-
-    fn insert_into(self, archetype: &mut crate::archetype::Archetype, index: usize) {
-        archetype.set_component(index, self.0);
-        archetype.set_component(index, self.1);
-        archetype.set_component(index, self.2);
-        archetype.set_component(index, self.3);
-    }
-}
+impl_component_bundle!(T1: 0);
+impl_component_bundle!(T1: 0, T2: 1);
+impl_component_bundle!(T1: 0, T2: 1, T3: 2);
+impl_component_bundle!(T1: 0, T2: 1, T3: 2, T4: 3);
+impl_component_bundle!(T1: 0, T2: 1, T3: 2, T4: 3, T5: 4);
+impl_component_bundle!(T1: 0, T2: 1, T3: 2, T4: 3, T5: 4, T6: 5);
+impl_component_bundle!(T1: 0, T2: 1, T3: 2, T4: 3, T5: 4, T6: 5, T7: 6);
+impl_component_bundle!(T1: 0, T2: 1, T3: 2, T4: 3, T5: 4, T6: 5, T7: 6, T8: 7);
 
 pub struct QueryIter<'a, Q: Query> {
     archetypes: &'a mut ArchetypeMap,
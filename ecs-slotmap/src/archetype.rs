@@ -11,6 +11,7 @@ pub(crate) struct Archetype {
 }
 
 struct Column {
+    type_id: TypeId,
     data: NonNull<u8>,
     len: usize,
     capacity: usize,
@@ -40,7 +41,13 @@ impl Archetype {
     }
 
     pub fn add_column<T: 'static>(&mut self) {
+        let type_id = TypeId::of::<T>();
+        if self.columns.iter().any(|c| c.type_id == type_id) {
+            return;
+        }
+
         let column = Column {
+            type_id,
             data: NonNull::dangling(),
             len: 0,
             capacity: 0,
@@ -49,7 +56,10 @@ impl Archetype {
                 std::ptr::drop_in_place(ptr as *mut T);
             },
         };
-        self.columns.push(column);
+
+        // Keep columns sorted by TypeId so column i always stores types[i]
+        let position = self.columns.partition_point(|c| c.type_id < type_id);
+        self.columns.insert(position, column);
     }
 
     pub fn push_entity(&mut self, entity: Entity) {
@@ -223,4 +223,40 @@ mod tests {
 
         assert_eq!(count, 1);
     }
+
+    #[test]
+    fn test_query_five_tuple() {
+        #[derive(Debug, PartialEq)]
+        struct Mass(f64);
+
+        #[derive(Debug, PartialEq)]
+        struct Tag(u8);
+
+        let mut world = World::new();
+
+        for i in 0..3 {
+            world.spawn((
+                Position {
+                    x: i as f32,
+                    y: 0.0,
+                },
+                Velocity { dx: 1.0, dy: 2.0 },
+                Health(100),
+                Mass(i as f64 * 2.0),
+                Tag(i as u8),
+            ));
+        }
+        world.spawn((Position { x: 9.0, y: 9.0 }, Health(1)));
+
+        let mut count = 0;
+        for (pos, vel, health, mass, tag) in
+            world.query::<(&mut Position, &Velocity, &Health, &Mass, &Tag)>()
+        {
+            pos.x += vel.dx;
+            assert_eq!(health.0, 100);
+            assert_eq!(mass.0, tag.0 as f64 * 2.0);
+            count += 1;
+        }
+        assert_eq!(count, 3);
+    }
 }